@@ -0,0 +1,33 @@
+#ifndef LAS_TERRAIN_GENERATOR_H
+#define LAS_TERRAIN_GENERATOR_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TERRAIN_OK 0
+#define TERRAIN_INVALID_CONFIG 1
+#define TERRAIN_FAILED 2
+/* Another generate_terrain call is still running in this process */
+#define TERRAIN_BUSY 3
+
+/* Status is one of downloading, downloaded, postponed, missing, failed, computed */
+typedef void (*terrain_tile_callback)(int32_t tile_x, int32_t tile_y, const char *status,
+                                      void *user_data);
+
+/* config_json holds the command line arguments of a run: {"args": ["-p", "401,101", ...]}.
+ * The callback may be NULL and is always invoked on the calling thread. One run at a time per
+ * process, overlapping calls from other threads return TERRAIN_BUSY. */
+int32_t generate_terrain(const char *config_json, terrain_tile_callback callback,
+                         void *user_data);
+
+/* Message of the last failed call on this thread or NULL, owned by the library */
+const char *terrain_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
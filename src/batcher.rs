@@ -0,0 +1,370 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    num::NonZero,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Instant,
+};
+
+use clap::{Parser, error::ContextKind};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{self, Cli},
+    splatter, storage,
+    stylizer::Style,
+};
+
+// A batch file lists independent jobs, each given as the arguments of a regular generate run,
+// and the styles their --stylize names:
+// { "jobs": [ { "name": "bled", "args": ["-p", "430,136", "-r", "1", ...] } ], "styles": { ... } }
+#[derive(Serialize, Deserialize)]
+struct BatchFile {
+    jobs: Vec<BatchJob>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    styles: BTreeMap<String, Style>,
+}
+
+// Keys of batch files and jobs, other keys are ignored by batch and reported by config validate
+const BATCH_FILE_KEYS: [&str; 2] = ["jobs", "styles"];
+const BATCH_JOB_KEYS: [&str; 2] = ["name", "args"];
+
+#[derive(Serialize, Deserialize)]
+struct BatchJob {
+    name: String,
+    args: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JobReport {
+    name: String,
+    succeeded: bool,
+    error: Option<String>,
+    tiles: usize,
+    pending_tiles: usize,
+    duration_s: f64,
+}
+
+#[derive(Serialize)]
+struct BatchReport {
+    succeeded: usize,
+    failed: usize,
+    tiles: usize,
+    duration_s: f64,
+    jobs: Vec<JobReport>,
+}
+
+pub fn run_batch(
+    batch_file: &str,
+    parallel_jobs: NonZero<usize>,
+    cache_dir: Option<&str>,
+    report: Option<&str>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let batch: BatchFile = serde_json::from_str(&fs::read_to_string(batch_file)?)?;
+    let started = Instant::now();
+
+    let next_job = AtomicUsize::new(0);
+    let job_reports = Mutex::new(Vec::with_capacity(batch.jobs.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..parallel_jobs.get().min(batch.jobs.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next_job.fetch_add(1, Ordering::SeqCst);
+
+                    if index >= batch.jobs.len() {
+                        break;
+                    }
+
+                    let job_report = run_job(&batch.jobs[index], &batch.styles, cache_dir);
+                    job_reports.lock().unwrap().push((index, job_report));
+                }
+            });
+        }
+    });
+
+    let mut job_reports = job_reports.into_inner().unwrap();
+    job_reports.sort_by_key(|(index, _)| *index);
+
+    let jobs = job_reports
+        .into_iter()
+        .map(|(_, job_report)| job_report)
+        .collect::<Vec<JobReport>>();
+
+    let batch_report = BatchReport {
+        succeeded: jobs.iter().filter(|job| job.succeeded).count(),
+        failed: jobs.iter().filter(|job| !job.succeeded).count(),
+        tiles: jobs.iter().map(|job| job.tiles).sum(),
+        duration_s: started.elapsed().as_secs_f64(),
+        jobs,
+    };
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>10}  {}",
+        "job", "tiles", "pending", "time [s]", "status"
+    );
+
+    for job in batch_report.jobs.iter() {
+        println!(
+            "{:<24} {:>8} {:>8} {:>10.1}  {}",
+            job.name,
+            job.tiles,
+            job.pending_tiles,
+            job.duration_s,
+            job.error.as_deref().unwrap_or("ok")
+        );
+    }
+
+    println!(
+        "Batch finished in {:.1} s: {} succeeded, {} failed, {} tiles",
+        batch_report.duration_s, batch_report.succeeded, batch_report.failed, batch_report.tiles
+    );
+
+    if let Some(report) = report {
+        storage::write_atomically(
+            Path::new(report),
+            serde_json::to_string_pretty(&batch_report)?.as_bytes(),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Batch file holding a single job, as written by init
+pub fn write_single_job(
+    batch_file: &str,
+    name: &str,
+    args: Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let batch = BatchFile {
+        jobs: vec![BatchJob {
+            name: name.to_string(),
+            args,
+        }],
+        styles: BTreeMap::new(),
+    };
+
+    storage::write_atomically(
+        Path::new(batch_file),
+        serde_json::to_string_pretty(&batch)?.as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+// Checks a batch file, or a TOML splat rules file, without running anything
+pub fn validate_config(file: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !file.ends_with(".toml") {
+        return validate_batch(file);
+    }
+
+    let table: toml::Table = toml::from_str(&fs::read_to_string(file)?)
+        .map_err(|error| format!("{}: {}", file, error))?;
+
+    if !table.contains_key("rule") {
+        return Err(format!("{} holds no [[rule]] tables", file).into());
+    }
+
+    splatter::parse_splat_rules(file)?;
+
+    println!("{} is valid", file);
+
+    Ok(())
+}
+
+// Checks the structure of a batch file and the arguments of every job without running them.
+// Errors name the line of the field or argument they were found at.
+pub fn validate_batch(batch_file: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let content = fs::read_to_string(batch_file)?;
+    let batch: BatchFile =
+        serde_json::from_str(&content).map_err(|error| format!("{}: {}", batch_file, error))?;
+    let mut invalid_jobs = 0;
+
+    // Unknown keys are ignored when running the batch, likely typos of the known ones. Keys of a
+    // job are looked up after the name of the job before it.
+    let document: serde_json::Value = serde_json::from_str(&content)?;
+    let mut unknown_keys = 0;
+    let mut report_unknown_keys = |value: &serde_json::Value, known: &[&str], start: usize| {
+        for key in value
+            .as_object()
+            .into_iter()
+            .flat_map(|object| object.keys())
+        {
+            if !known.contains(&key.as_str()) {
+                unknown_keys += 1;
+                println!(
+                    "Field {}{} is not known, expected one of {}",
+                    key,
+                    format_line(find_line(&content, start, &quote(key))),
+                    known.join(", ")
+                );
+            }
+        }
+    };
+
+    report_unknown_keys(&document, &BATCH_FILE_KEYS, 0);
+
+    let mut previous_job_line = None;
+
+    for job in document["jobs"].as_array().into_iter().flatten() {
+        report_unknown_keys(job, &BATCH_JOB_KEYS, previous_job_line.unwrap_or(0));
+        previous_job_line = job["name"]
+            .as_str()
+            .and_then(|name| get_job_line(&content, name));
+    }
+
+    for (index, job) in batch.jobs.iter().enumerate() {
+        let job_line = get_job_line(&content, &job.name);
+        let line = format_line(job_line);
+        let result = parse_job(job, &batch.styles, None).and_then(|cli| {
+            core::read_config_from_cli(&cli)?;
+            Ok(())
+        });
+
+        if batch.jobs[..index]
+            .iter()
+            .any(|other| other.name == job.name)
+        {
+            println!("Job {}{} reuses the name of an earlier job", job.name, line);
+        }
+
+        match result {
+            Ok(()) => println!("Job {}{} is valid", job.name, line),
+            Err(value) => {
+                invalid_jobs += 1;
+                // Only the message, clap appends the usage to its errors
+                let message = value.to_string();
+                let argument_line = get_argument_line(&content, job_line, value.as_ref());
+                println!(
+                    "Job {}{} is invalid: {}",
+                    job.name,
+                    format_line(argument_line.or(job_line)),
+                    message.lines().next().unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    if invalid_jobs > 0 {
+        return Err(format!(
+            "{} of {} jobs in {} are invalid",
+            invalid_jobs,
+            batch.jobs.len(),
+            batch_file
+        )
+        .into());
+    }
+
+    if unknown_keys > 0 {
+        return Err(format!("{} has {} unknown fields", batch_file, unknown_keys).into());
+    }
+
+    println!("{} jobs in {} are valid", batch.jobs.len(), batch_file);
+
+    Ok(())
+}
+
+// Line of the "name" field holding the job name, counted from 1
+fn get_job_line(content: &str, name: &str) -> Option<usize> {
+    let quoted_name = quote(name);
+
+    content
+        .lines()
+        .position(|line| line.contains("\"name\"") && line.contains(&quoted_name))
+        .map(|index| index + 1)
+}
+
+// Line of the argument clap rejected, searched from the job's name onwards. Values are looked
+// up before flags, the flag of an invalid value may have been given earlier.
+fn get_argument_line(
+    content: &str,
+    job_line: Option<usize>,
+    error: &(dyn Error + Send + Sync + 'static),
+) -> Option<usize> {
+    let error = error.downcast_ref::<clap::Error>()?;
+    let start = job_line?.saturating_sub(1);
+
+    [ContextKind::InvalidValue, ContextKind::InvalidArg]
+        .into_iter()
+        .filter_map(|kind| error.get(kind))
+        .filter_map(|value| {
+            let argument = value.to_string();
+            // Flags are described with their value name, e.g. "--style <STYLE>"
+            let argument = argument.split_whitespace().next()?.to_string();
+            find_line(content, start, &quote(&argument))
+        })
+        .next()
+}
+
+fn find_line(content: &str, start: usize, text: &str) -> Option<usize> {
+    content
+        .lines()
+        .skip(start)
+        .position(|line| line.contains(text))
+        .map(|index| start + index + 1)
+}
+
+fn format_line(line: Option<usize>) -> String {
+    line.map(|line| format!(" at line {}", line))
+        .unwrap_or_default()
+}
+
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn run_job(job: &BatchJob, styles: &BTreeMap<String, Style>, cache_dir: Option<&str>) -> JobReport {
+    println!("Starting job {}", job.name);
+
+    let started = Instant::now();
+    let result = parse_job(job, styles, cache_dir).and_then(|cli| Ok(crate::generate(&cli)?));
+
+    let (summary, error) = match result {
+        Ok(summary) => (Some(summary), None),
+        Err(value) => {
+            println!("Job {} failed: {}", job.name, value);
+            (None, Some(value.to_string()))
+        }
+    };
+
+    JobReport {
+        name: job.name.clone(),
+        succeeded: error.is_none(),
+        error,
+        tiles: summary.as_ref().map(|summary| summary.tiles).unwrap_or(0),
+        pending_tiles: summary.map(|summary| summary.pending_tiles).unwrap_or(0),
+        duration_s: started.elapsed().as_secs_f64(),
+    }
+}
+
+fn parse_job(
+    job: &BatchJob,
+    styles: &BTreeMap<String, Style>,
+    cache_dir: Option<&str>,
+) -> Result<Cli, Box<dyn Error + Send + Sync>> {
+    let mut args = vec![env!("CARGO_PKG_NAME").to_string()];
+    args.extend(job.args.iter().cloned());
+
+    if let Some(cache_dir) = cache_dir {
+        if !job.args.iter().any(|arg| arg == "--cache-dir") {
+            args.push("--cache-dir".to_string());
+            args.push(cache_dir.to_string());
+        }
+    }
+
+    let mut cli = Cli::try_parse_from(args)?;
+
+    if cli.command.is_some() {
+        return Err("Batch jobs can not contain subcommands".into());
+    }
+
+    cli.styles = styles.clone();
+
+    Ok(cli)
+}
@@ -0,0 +1,260 @@
+use std::{error::Error, thread, time::Instant};
+
+use rand::Rng;
+
+use crate::{
+    computer,
+    core::{HttpConfig, Point},
+    gridder::{NeighbourSearch, Precision, SpatialIndex},
+    meterer::TileTimings,
+    projection::Crs,
+    requester::{self, LazData, PointAttributes, TilePermit},
+    spiller::{PointStore, Reservation},
+    writer::{BigTiffMode, OutputFormat, TileRaster},
+};
+
+pub struct BenchConfig {
+    pub tiles: usize,
+    pub tile_size_m: f64,
+    pub density: f64,
+    pub resolution: usize,
+    pub sample_size: usize,
+    pub blur_kernel_size: u32,
+    pub threads: Vec<usize>,
+    pub formats: Vec<OutputFormat>,
+    pub ann: bool,
+    pub index: SpatialIndex,
+    pub internal_precision: Precision,
+    pub http_url: Option<String>,
+    pub http_requests: usize,
+    pub http: HttpConfig,
+}
+
+struct StageTimes {
+    threads: usize,
+    gridding_s: f64,
+    blur_s: f64,
+    encode_s: f64,
+}
+
+pub fn run_bench(bench: &BenchConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if bench.tiles == 0 || bench.sample_size == 0 || bench.threads.iter().any(|t| *t == 0) {
+        return Err("Tiles, sample size and thread counts must be positive".into());
+    }
+
+    let points_per_tile = (bench.tile_size_m * bench.tile_size_m * bench.density) as usize;
+
+    println!(
+        "Generating {} synthetic tiles of {} m with {} points each",
+        bench.tiles, bench.tile_size_m, points_per_tile
+    );
+
+    let tiles = (0..bench.tiles)
+        .map(|index| create_synthetic_tile(index, bench.tile_size_m, points_per_tile))
+        .collect::<Vec<LazData>>();
+
+    let mut results = vec![];
+
+    for threads in bench.threads.iter() {
+        println!("Running with {} threads", threads);
+        results.push(time_stages(bench, &tiles, *threads)?);
+    }
+
+    println!(
+        "{:>8} {:>12} {:>10} {:>10} {:>10} {:>8}",
+        "threads", "gridding [s]", "blur [s]", "encode [s]", "total [s]", "speedup"
+    );
+
+    let baseline = results
+        .first()
+        .map(|times| times.gridding_s + times.blur_s + times.encode_s)
+        .unwrap_or(0.0);
+
+    for times in results.iter() {
+        let total = times.gridding_s + times.blur_s + times.encode_s;
+
+        println!(
+            "{:>8} {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>7.2}x",
+            times.threads,
+            times.gridding_s,
+            times.blur_s,
+            times.encode_s,
+            total,
+            baseline / total
+        );
+    }
+
+    if let Some(url) = &bench.http_url {
+        compare_clients(url, bench.http_requests, &bench.http)?;
+    }
+
+    Ok(())
+}
+
+fn compare_clients(
+    url: &str,
+    requests: usize,
+    http: &HttpConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if requests == 0 {
+        return Err("HTTP requests must be positive".into());
+    }
+
+    println!("Requesting {} {} times per client setup", url, requests);
+
+    let (fresh, shared) = requester::compare_clients(url, requests, http)?;
+
+    println!("{:>8} {:>10} {:>12}", "client", "total [s]", "average [ms]");
+
+    for (name, elapsed) in [("fresh", fresh), ("shared", shared)] {
+        println!(
+            "{:>8} {:>10.2} {:>12.1}",
+            name,
+            elapsed.as_secs_f64(),
+            elapsed.as_secs_f64() * 1000.0 / requests as f64
+        );
+    }
+
+    println!(
+        "Connection reuse saves {:.1} ms per request",
+        (fresh.as_secs_f64() - shared.as_secs_f64()) * 1000.0 / requests as f64
+    );
+
+    Ok(())
+}
+
+// Rolling terrain with some noise, dense enough to resemble an airborne survey
+fn create_synthetic_tile(index: usize, tile_size_m: f64, point_count: usize) -> LazData {
+    let mut rng = rand::thread_rng();
+    let origin = (index as f64 * tile_size_m, 0.0);
+
+    let points = (0..point_count)
+        .map(|_| {
+            let x = origin.0 + rng.gen_range(0.0..tile_size_m);
+            let y = origin.1 + rng.gen_range(0.0..tile_size_m);
+            let z = 300.0
+                + 50.0 * (x / 150.0).sin()
+                + 30.0 * (y / 90.0).cos()
+                + rng.gen_range(-0.5..0.5);
+
+            [x, y, z]
+        })
+        .collect::<Vec<[f64; 3]>>();
+
+    LazData {
+        tile: Point(index as i32, 0),
+        offset_from_center: (index as i32, 0),
+        file_stem: format!("img_{}_0", index),
+        bounds_min: (origin.0, origin.1, 210.0),
+        bounds_max: (origin.0 + tile_size_m, origin.1 + tile_size_m, 390.0),
+        points: PointStore::Memory(points, Reservation::default()),
+        attributes: PointAttributes::default(),
+        fallback: false,
+        survey: None,
+        orthophoto: None,
+        timings: TileTimings::default(),
+        permit: TilePermit::default(),
+    }
+}
+
+fn time_stages(
+    bench: &BenchConfig,
+    tiles: &[LazData],
+    threads: usize,
+) -> Result<StageTimes, Box<dyn Error + Send + Sync>> {
+    let work_amount = tiles.len().div_ceil(threads);
+    let (min_height, max_height) = (210.0, 390.0);
+
+    let started = Instant::now();
+    let mut buffers = run_parallel(tiles.chunks(work_amount), |tile| {
+        let index = computer::create_tile_index(
+            tile,
+            bench.resolution,
+            bench.resolution,
+            bench.sample_size,
+            NeighbourSearch::new(bench.ann, bench.index),
+            bench.internal_precision,
+        );
+        let heights = tile
+            .points
+            .iter()
+            .map(|point| ((point[2] - min_height) / (max_height - min_height)) as f32)
+            .collect::<Vec<f32>>();
+
+        Ok(index.interpolate(bench.sample_size, &heights))
+    })?;
+    let gridding_s = started.elapsed().as_secs_f64();
+
+    let started = Instant::now();
+    run_parallel(buffers.chunks_mut(work_amount), |buffer| {
+        computer::blur_image(
+            bench.blur_kernel_size,
+            bench.resolution,
+            bench.resolution,
+            buffer,
+        )
+    })?;
+    let blur_s = started.elapsed().as_secs_f64();
+
+    let started = Instant::now();
+    let tiles_with_buffers = tiles.iter().zip(buffers.iter()).collect::<Vec<_>>();
+    run_parallel(tiles_with_buffers.chunks(work_amount), |(tile, buffer)| {
+        let raster = TileRaster {
+            bounds_min: (tile.bounds_min.0, tile.bounds_min.1),
+            bounds_max: (tile.bounds_max.0, tile.bounds_max.1),
+            crs: Crs::D96Tm,
+            dim_x: bench.resolution,
+            dim_y: bench.resolution,
+            heights: buffer,
+            min_height,
+            max_height,
+        };
+
+        for format in bench.formats.iter() {
+            format.writer(BigTiffMode::Auto, None).encode(&raster)?;
+        }
+
+        Ok(())
+    })?;
+    let encode_s = started.elapsed().as_secs_f64();
+
+    Ok(StageTimes {
+        threads,
+        gridding_s,
+        blur_s,
+        encode_s,
+    })
+}
+
+// Runs one thread per chunk of items and returns the results in item order
+fn run_parallel<I, T, R>(
+    chunks: I,
+    task: impl Fn(T) -> Result<R, Box<dyn Error + Send + Sync>> + Sync,
+) -> Result<Vec<R>, Box<dyn Error + Send + Sync>>
+where
+    I: Iterator,
+    I::Item: IntoIterator<Item = T> + Send,
+    R: Send,
+{
+    let task = &task;
+
+    thread::scope(|scope| {
+        let handles = chunks
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(task)
+                        .collect::<Result<Vec<R>, Box<dyn Error + Send + Sync>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = vec![];
+        for handle in handles {
+            results.extend(handle.join().unwrap()?);
+        }
+
+        Ok(results)
+    })
+}
@@ -0,0 +1,181 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    core::Config,
+    deriver::{DerivedMap, TileMaps},
+    error::TerrainError,
+    gridder::TileIndex,
+    requester::LazData,
+    storage::OutputSink,
+    writer::{self, OutputWriter, PngWriter, RawWriter, TileRaster},
+};
+
+// Class groups painted into the R, G, B and A channel of the splat map: ground, vegetation,
+// buildings and water. Unclassified pixels fall back to ground.
+const SPLAT_CLASSES: [(&str, &[u8]); 4] = [
+    ("ground", &[2]),
+    ("vegetation", &[3, 4, 5]),
+    ("building", &[6]),
+    ("water", &[9]),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineBundle {
+    // Raw 16 bit heightmap as taken by the import dialog of the HTerrain plugin
+    Godot,
+    // 16 bit PNG heightmap as loaded by the Bevy terrain crates
+    Bevy,
+}
+
+#[derive(Serialize)]
+struct BundleDescriptor<'a> {
+    engine: EngineBundle,
+    heightmap: String,
+    normal_map: &'a str,
+    splat_map: &'a str,
+    splat_channels: [String; 4],
+    resolution: usize,
+    size_m: (f64, f64),
+    min_height: f64,
+    max_height: f64,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    crs: String,
+}
+
+// Writes everything an engine needs to import a tile into {file_stem}_{engine}/: the heightmap,
+// a tangent space normal map, an RGBA splat map and terrain.json describing
+// how to scale them
+pub fn write_bundle(
+    config: &Config,
+    sink: &dyn OutputSink,
+    file_stem: &str,
+    raster: &TileRaster,
+    maps: &mut TileMaps,
+    index: &TileIndex,
+    data: &LazData,
+) -> Result<(), TerrainError> {
+    let engine = match config.engine_bundle {
+        Some(engine) => engine,
+        None => return Ok(()),
+    };
+
+    let folder = format!(
+        "{}_{}",
+        file_stem,
+        engine.to_possible_value().unwrap().get_name()
+    );
+    let height_writer: Box<dyn OutputWriter> = match engine {
+        EngineBundle::Godot => Box::new(RawWriter),
+        EngineBundle::Bevy => Box::new(PngWriter),
+    };
+    let heightmap = format!("heightmap.{}", height_writer.extension());
+
+    sink.put(
+        &format!("{}/{}", folder, heightmap),
+        &height_writer.encode(raster).map_err(TerrainError::Output)?,
+    )
+    .map_err(TerrainError::Output)?;
+
+    let mut normal_channels: [Vec<f32>; 4] = Default::default();
+    for (channel, map) in normal_channels.iter_mut().zip([
+        DerivedMap::NormalX,
+        DerivedMap::NormalY,
+        DerivedMap::NormalZ,
+        DerivedMap::One,
+    ]) {
+        *channel = maps
+            .get(map)?
+            .iter()
+            .map(|value| map.to_unit(*value))
+            .collect();
+    }
+
+    sink.put(
+        &format!("{}/normal.png", folder),
+        &writer::encode_packed_png(raster.dim_x, raster.dim_y, &normal_channels)
+            .map_err(TerrainError::Output)?,
+    )
+    .map_err(TerrainError::Output)?;
+
+    // Rules given with --splat-rules replace the default class groups
+    let (splat, splat_channels) = match &config.splat_rules {
+        Some(rules) => (
+            rules.compute(
+                maps,
+                index,
+                data,
+                config.sample_size as usize,
+                (raster.min_height, raster.max_height),
+            )?,
+            rules.channel_names(),
+        ),
+        None => (
+            create_class_splat(config, index, data),
+            SPLAT_CLASSES.map(|(name, _)| name.to_string()),
+        ),
+    };
+
+    sink.put(
+        &format!("{}/splat.png", folder),
+        &writer::encode_packed_png(raster.dim_x, raster.dim_y, &splat)
+            .map_err(TerrainError::Output)?,
+    )
+    .map_err(TerrainError::Output)?;
+
+    let descriptor = BundleDescriptor {
+        engine,
+        heightmap,
+        normal_map: "normal.png",
+        splat_map: "splat.png",
+        splat_channels,
+        resolution: raster.dim_x,
+        size_m: (
+            raster.bounds_max.0 - raster.bounds_min.0,
+            raster.bounds_max.1 - raster.bounds_min.1,
+        ),
+        min_height: raster.min_height,
+        max_height: raster.max_height,
+        bounds_min: raster.bounds_min,
+        bounds_max: raster.bounds_max,
+        crs: raster.crs.to_string(),
+    };
+
+    sink.put(
+        &format!("{}/terrain.json", folder),
+        serde_json::to_string_pretty(&descriptor)?.as_bytes(),
+    )
+    .map_err(TerrainError::Output)?;
+
+    Ok(())
+}
+
+// Grids a 0/1 indicator per class group and normalizes the four weights to sum to one
+fn create_class_splat(config: &Config, index: &TileIndex, data: &LazData) -> [Vec<f32>; 4] {
+    let mut channels: [Vec<f32>; 4] = SPLAT_CLASSES.map(|(_, classes)| {
+        let indicators = data
+            .attributes
+            .classes
+            .iter()
+            .map(|class| if classes.contains(class) { 1.0 } else { 0.0 })
+            .collect::<Vec<f32>>();
+
+        index.interpolate(config.sample_size as usize, &indicators)
+    });
+
+    for pixel in 0..channels[0].len() {
+        let total = channels.iter().map(|channel| channel[pixel]).sum::<f32>();
+
+        for (ind, channel) in channels.iter_mut().enumerate() {
+            channel[pixel] = match (total > 0.0, ind) {
+                (true, _) => channel[pixel] / total,
+                (false, 0) => 1.0,
+                (false, _) => 0.0,
+            };
+        }
+    }
+
+    channels
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// All files the cache keeps for one tile: the point data, its source validator and survey name
+struct CachedTile {
+    files: Vec<PathBuf>,
+    size: u64,
+    last_used: SystemTime,
+}
+
+// Marks a cached file as used now, the modification time is what eviction orders by
+pub fn touch(path: &Path) {
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+pub fn print_cache_info(folder: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let tiles = list_cached_tiles(folder)?;
+    let size = tiles.iter().map(|tile| tile.size).sum::<u64>();
+    let compressed = tiles
+        .iter()
+        .filter(|tile| {
+            tile.files
+                .iter()
+                .any(|file| file.extension().is_some_and(|extension| extension == "zst"))
+        })
+        .count();
+
+    println!("Cache {}", folder);
+    println!(
+        "Tiles: {} ({} compressed with zstd)",
+        tiles.len(),
+        compressed
+    );
+    println!("Size: {} MB", size >> 20);
+
+    let now = SystemTime::now();
+    let days_ago =
+        |time: SystemTime| now.duration_since(time).unwrap_or_default().as_secs() / (24 * 60 * 60);
+    if let (Some(oldest), Some(newest)) = (
+        tiles.iter().map(|tile| tile.last_used).min(),
+        tiles.iter().map(|tile| tile.last_used).max(),
+    ) {
+        println!(
+            "Last used: oldest {} days ago, newest {} days ago",
+            days_ago(oldest),
+            days_ago(newest)
+        );
+    }
+
+    Ok(())
+}
+
+// Evicts the least recently used tiles until the cache is no larger than max_size
+pub fn prune_cache(folder: &str, max_size: u64) -> io::Result<()> {
+    let mut tiles = list_cached_tiles(folder)?;
+    let mut size = tiles.iter().map(|tile| tile.size).sum::<u64>();
+
+    if size <= max_size {
+        return Ok(());
+    }
+
+    tiles.sort_by_key(|tile| tile.last_used);
+
+    let mut evicted = 0;
+    for tile in tiles {
+        if size <= max_size {
+            break;
+        }
+
+        for file in tile.files.iter() {
+            fs::remove_file(file)?;
+        }
+
+        size -= tile.size;
+        evicted += 1;
+    }
+
+    println!(
+        "Evicted {} tiles from the cache, {} MB remain",
+        evicted,
+        size >> 20
+    );
+
+    Ok(())
+}
+
+// Groups the files of the cache by tile, TMR_x_y.laz and TMR_x_y.json belong together. Partial
+// files of running downloads are left alone.
+fn list_cached_tiles(folder: &str) -> io::Result<Vec<CachedTile>> {
+    let mut tiles = BTreeMap::<String, CachedTile>::new();
+
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if !file_name.starts_with("TMR_") || file_name.ends_with(".part") {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let stem = file_name.split('.').next().unwrap_or_default().to_string();
+        let modified = metadata.modified()?;
+        let tile = tiles.entry(stem).or_insert(CachedTile {
+            files: vec![],
+            size: 0,
+            last_used: SystemTime::UNIX_EPOCH,
+        });
+
+        tile.files.push(entry.path());
+        tile.size += metadata.len();
+        tile.last_used = tile.last_used.max(modified);
+    }
+
+    Ok(tiles.into_values().collect())
+}
@@ -1,30 +1,259 @@
-use std::{error::Error, fs, num::NonZero, thread};
+use std::{
+    fs,
+    num::NonZero,
+    sync::{Mutex, mpsc},
+    thread,
+    time::Instant,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
-use exr::{
-    image::{Encoding, Image, Layer, SpecificChannels},
-    math::Vec2,
-    prelude::{ChannelDescription, LayerAttributes, WritableImage},
+use crate::{
+    bundler,
+    conditioner::{self, HydroCondition},
+    core::{Config, CorePoint, NamingScheme, Point, Shard, TileLayout, TilePolygon, TileRectangle},
+    deriver::{DerivedMap, EqualizationTable, TileMaps},
+    draper,
+    error::TerrainError,
+    flattener, footprinter,
+    global_constants::TILE_SIZE_M,
+    grader::{self, RoadGrading},
+    gridder::{self, NeighbourSearch, Precision, SpatialIndex, TileIndex},
+    invoker::{self, RecordingSink},
+    meterer::{self, Stage, TileTimings},
+    mosaic::{self, Mosaic, MosaicTile},
+    pauser, pipeliner,
+    previewer::{self, PreviewTile},
+    progress::{self, TileStatus},
+    projection::{self, ReprojectedRaster},
+    requester::{self, DownloadResult, LazData},
+    spiller::{RasterStore, Reservation, Spiller},
+    storage::{self, OutputSink},
+    tiler,
+    tuner::TuneReport,
+    validator::{self, TileErrors},
+    verifier::{self, ChecksumSink},
+    writer::{self, IntegerHeights, OutputFormat, OutputWriter, PngWriter, TileRaster},
+    zarrer,
 };
-use kiddo::{ImmutableKdTree, SquaredEuclidean};
-use libblur::{AnisotropicRadius, BlurImageMut, EdgeMode, EdgeMode2D, ThreadingPolicy};
-use serde::Serialize;
 
-use crate::{core::Config, requester::LazData};
+// Upper bound on the number of elevations sorted to find normalization percentiles
+const MAX_PERCENTILE_SAMPLES: usize = 4_000_000;
 
-#[derive(Serialize)]
-struct ComputeConfig {
-    texture_resolution: u16,
-    max_height: f64,
-    min_height: f64,
-    real_world_dimensions_m: f64,
+#[derive(Serialize, Deserialize)]
+pub struct ComputeConfig {
+    pub texture_resolution: u16,
+    pub max_height: f64,
+    pub min_height: f64,
+    pub real_world_dimensions_m: f64,
+    // CRS of the written heightmaps
+    #[serde(default)]
+    pub crs: String,
+    // CRS of the tile bounds and the grid, differs from crs when the heightmaps were reprojected
+    #[serde(default)]
+    pub source_crs: String,
+    #[serde(default)]
+    pub grid: GridLayout,
+    #[serde(default)]
+    pub tiles: Vec<TileMetadata>,
+    #[serde(default)]
+    pub parameters: ComputeParameters,
+    // Settings chosen by --auto-tune
+    #[serde(default)]
+    pub auto_tune: Option<TuneReport>,
+    // 16 bit code of voids in PNG, raw and integer heightmaps, missing in runs that wrote voids
+    // as the lowest height
+    #[serde(default)]
+    pub nodata: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct GridLayout {
+    pub columns: u16,
+    pub rows: u16,
+    pub min_offset: (i32, i32),
+    pub max_offset: (i32, i32),
+    pub tile_count: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TileMetadata {
+    pub file_stem: String,
+    pub offset_from_center: (i32, i32),
+    pub bounds_min: (f64, f64, f64),
+    pub bounds_max: (f64, f64, f64),
+    // Set for tiles filled from the fallback DEM instead of the survey
+    #[serde(default)]
+    pub fallback: bool,
+    // Points per square meter of the source data
+    #[serde(default)]
+    pub point_density: f64,
+    // ARSO block or other survey the tile was read from, when the source reports it
+    #[serde(default)]
+    pub survey: Option<String>,
+    // Differs from texture_resolution only with adaptive resolution
+    #[serde(default)]
+    pub resolution: u16,
+    // Height errors at held-out points, measured with --validate
+    #[serde(default)]
+    pub validation: Option<TileErrors>,
+    // Only written to report.json of the run that computed the tile
+    #[serde(skip)]
+    pub timings: TileTimings,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ComputeParameters {
+    pub core_points: Vec<CorePoint>,
+    pub rectangles: Vec<TileRectangle>,
+    pub polygons: Vec<TilePolygon>,
+    pub route: Option<String>,
+    pub corridor_width_m: f64,
+    pub possible_blocks: Vec<u8>,
+    pub blur_kernel_size: u8,
+    pub blur_levels: Vec<u8>,
+    pub blur_target: BlurTarget,
+    pub void_dilation: u8,
+    pub max_neighbour_distance_m: Option<f64>,
+    pub sample_size: u8,
+    pub resolution: u16,
+    pub adaptive_resolution: bool,
+    pub formats: Vec<OutputFormat>,
+    pub z_scale: f64,
+    pub clamp_min: Option<f64>,
+    pub clamp_max: Option<f64>,
+    pub normalize_percentiles: Option<(f64, f64)>,
+    pub equalized: bool,
+    pub chunk_size: Option<u32>,
+    pub chunk_size_m: Option<f64>,
+    pub ann: bool,
+    pub index: SpatialIndex,
+    pub internal_precision: Precision,
+    pub preview: bool,
+    pub low_density: f64,
+    pub shard: Option<Shard>,
+    pub naming: NamingScheme,
+    pub layout: TileLayout,
+    pub integer_heights: Option<IntegerHeights>,
+    pub stylized: bool,
+    pub hydro_condition: Option<HydroCondition>,
+}
+
+impl From<&Config> for ComputeParameters {
+    fn from(value: &Config) -> Self {
+        ComputeParameters {
+            core_points: value.core_points.clone(),
+            rectangles: value.rectangles.clone(),
+            polygons: value.polygons.clone(),
+            route: value.route.clone(),
+            corridor_width_m: value.corridor_width_m,
+            possible_blocks: value.possible_blocks.clone(),
+            blur_kernel_size: value.blur_kernel_size,
+            blur_levels: value.blur_levels.clone(),
+            blur_target: value.blur_target,
+            void_dilation: value.void_dilation,
+            max_neighbour_distance_m: value.max_neighbour_distance_m,
+            sample_size: value.sample_size,
+            resolution: value.resolution,
+            adaptive_resolution: value.adaptive_resolution,
+            formats: value.formats.clone(),
+            z_scale: value.z_scale,
+            clamp_min: value.clamp_min,
+            clamp_max: value.clamp_max,
+            normalize_percentiles: value.normalize_percentiles,
+            equalized: value.equalized,
+            chunk_size: value.chunk_size,
+            chunk_size_m: value.chunk_size_m,
+            ann: value.ann,
+            index: value.index,
+            internal_precision: value.internal_precision,
+            preview: value.preview,
+            low_density: value.low_density,
+            shard: value.shard,
+            naming: value.naming,
+            layout: value.layout,
+            integer_heights: value.integer_heights,
+            stylized: value.style.is_some(),
+            hydro_condition: value.hydro_condition,
+        }
+    }
+}
+
+struct TileOutput {
+    heights: RasterStore,
+    thumbnail: Option<Vec<u8>>,
+    errors: Option<TileErrors>,
+    timings: TileTimings,
+}
+
+struct ComputedTile {
+    heights: Vec<f32>,
+    thumbnail: Option<Vec<u8>>,
+    errors: Option<TileErrors>,
+    timings: TileTimings,
+}
+
+// Written when a run stops before all tiles were downloaded. The normalization bounds are kept
+// so that tiles generated by the resumed run share the vertical scale of the earlier ones.
+#[derive(Serialize, Deserialize)]
+pub struct ResumeState {
+    pub pending_points: Vec<Point>,
+    pub min_height: f64,
+    pub max_height: f64,
+}
+
+pub fn read_resume_state(config: &Config) -> Result<ResumeState, TerrainError> {
+    let json = fs::read_to_string(storage::get_local_path(
+        &config.destination_folder,
+        &config.get_run_file_name("resume.json"),
+    ))?;
+
+    Ok(serde_json::from_str(&json)?)
+}
+
+pub fn read_compute_config(folder: &str) -> Result<ComputeConfig, TerrainError> {
+    read_compute_config_file(folder, "config.json")
+}
+
+// config.json of a shard has the shard suffix, see Config::get_run_file_name
+pub fn read_compute_config_file(
+    folder: &str,
+    file_name: &str,
+) -> Result<ComputeConfig, TerrainError> {
+    let json = fs::read_to_string(storage::get_local_path(folder, file_name))?;
+
+    Ok(serde_json::from_str(&json)?)
 }
 
 pub fn compute_textures_parallel(
     config: &Config,
     cpus: NonZero<usize>,
-    data: Vec<LazData>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let (min_height, max_height) = get_height_bounds(&data)?;
+    download: DownloadResult,
+    resume_state: Option<ResumeState>,
+) -> Result<(), TerrainError> {
+    let data = download.data;
+    let (min_height, max_height) = match &resume_state {
+        Some(resume_state) => (resume_state.min_height, resume_state.max_height),
+        None => get_height_bounds(&data, config)?,
+    };
+    let output_sink =
+        storage::create_output_sink(&config.destination_folder).map_err(TerrainError::Output)?;
+    let checksums = ChecksumSink::new(output_sink.as_ref());
+    let sink: &dyn OutputSink = &checksums;
+
+    let equalization_table = if config.equalized {
+        Some(EqualizationTable::from_heights(
+            sample_heights(&data, config)
+                .into_iter()
+                .map(|height| ((height - min_height) / (max_height - min_height)) as f32),
+        ))
+    } else {
+        None
+    };
+    let equalization_table = equalization_table.as_ref();
+
     let work_amount = data.len() / cpus + 1;
 
     println!("Number of data elements: {}", data.len());
@@ -34,196 +263,1074 @@ pub fn compute_textures_parallel(
         cpus, work_amount
     );
 
-    thread::scope(|scope| -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Tile rasters are only kept around when they have to be assembled into chunks or web map
+    // tiles afterwards. Past --max-memory they are spilled to disk like the point arrays.
+    let keep_buffers = mosaic::get_chunk_dimensions(config).is_some()
+        || config.zarr
+        || config.layout == TileLayout::Zxy;
+    let raster_spiller = &Spiller::new(config.max_memory, config.internal_precision);
+    let dim = config.resolution as usize;
+
+    let tile_outputs = thread::scope(|scope| -> Result<Vec<Option<TileOutput>>, TerrainError> {
         let mut results = vec![];
         for (_id, chunk) in data.chunks(work_amount).enumerate() {
-            let result = scope.spawn(move || -> Result<(), Box<dyn Error + Send + Sync>> {
+            let result = scope.spawn(move || -> Result<Vec<Option<TileOutput>>, TerrainError> {
+                let mut outputs = vec![];
+
                 for data in chunk {
-                    create_texture(config, data, min_height, max_height)?;
+                    let computed = match compute_tile(
+                        config,
+                        sink,
+                        data,
+                        min_height,
+                        max_height,
+                        equalization_table,
+                    ) {
+                        Ok(output) => output,
+                        // A full disk fails only this tile, it is left pending for --resume
+                        Err(value) if value.is_storage_full() => {
+                            println!(
+                                "Out of disk space writing tile {}:{}: {}",
+                                data.tile.0, data.tile.1, value
+                            );
+                            progress::report(data.tile, TileStatus::Failed);
+                            outputs.push(None);
+                            continue;
+                        }
+                        Err(value) => return Err(value),
+                    };
+
+                    outputs.push(Some(TileOutput {
+                        heights: if keep_buffers {
+                            raster_spiller
+                                .store_raster(computed.heights)
+                                .map_err(TerrainError::Spill)?
+                        } else {
+                            RasterStore::Memory(vec![], Reservation::default())
+                        },
+                        thumbnail: computed.thumbnail,
+                        errors: computed.errors,
+                        timings: computed.timings,
+                    }));
                 }
 
-                Ok(())
+                Ok(outputs)
             });
 
             results.push(result);
         }
 
+        let mut tile_outputs = vec![];
         for result in results {
-            result.join().unwrap();
+            tile_outputs.extend(result.join().unwrap()?);
         }
 
-        Ok(())
+        Ok(tile_outputs)
     })?;
 
+    let mut out_of_space_points = vec![];
+    let (data, tile_outputs): (Vec<LazData>, Vec<TileOutput>) = data
+        .into_iter()
+        .zip(tile_outputs)
+        .filter_map(|(data, output)| match output {
+            Some(output) => Some((data, output)),
+            None => {
+                out_of_space_points.push(data.tile);
+                None
+            }
+        })
+        .unzip();
+
+    if keep_buffers {
+        let mosaic = Mosaic::new(
+            data.iter()
+                .zip(tile_outputs.iter())
+                .map(|(data, output)| MosaicTile {
+                    data,
+                    heights: output.heights.as_slice(),
+                })
+                .collect(),
+            dim,
+            dim,
+            config.seam_blend_m,
+        );
+
+        mosaic::write_chunks(config, cpus, sink, &mosaic, min_height, max_height)
+            .map_err(TerrainError::Output)?;
+
+        if config.zarr {
+            zarrer::write_zarr(
+                cpus,
+                sink,
+                &mosaic,
+                config.source_crs,
+                TILE_SIZE_M / config.resolution as f64,
+                min_height,
+                max_height,
+            )
+            .map_err(TerrainError::Output)?;
+        }
+
+        if config.layout == TileLayout::Zxy {
+            tiler::write_tiles(
+                config,
+                cpus,
+                sink,
+                &mosaic,
+                TILE_SIZE_M / config.resolution as f64,
+                min_height,
+                max_height,
+            )
+            .map_err(TerrainError::Output)?;
+        }
+    }
+
+    if config.preview {
+        let preview_tiles = data
+            .iter()
+            .zip(tile_outputs.iter())
+            .filter_map(|(data, output)| {
+                Some(PreviewTile {
+                    file_stem: data.file_stem.clone(),
+                    bounds_min: (data.bounds_min.0, data.bounds_min.1),
+                    bounds_max: (data.bounds_max.0, data.bounds_max.1),
+                    thumbnail: output.thumbnail.clone()?,
+                })
+            })
+            .collect::<Vec<PreviewTile>>();
+
+        println!("Writing preview map.");
+        previewer::write_preview(sink, &preview_tiles, config.source_crs)
+            .map_err(TerrainError::Output)?;
+    }
+
+    if config.pdal_pipeline {
+        println!("Writing PDAL pipeline.");
+        pipeliner::write_pipeline(config, sink, &data).map_err(TerrainError::Output)?;
+    }
+
+    if config.footprints {
+        println!("Writing tile footprints.");
+        footprinter::write_footprints(
+            sink,
+            &footprinter::get_footprints(&data, &download.missing_points, config.low_density),
+            config.source_crs,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    let tiles = data
+        .iter()
+        .zip(tile_outputs.iter())
+        .map(|(tile, output)| get_tile_metadata(config, tile, output.errors, output.timings))
+        .collect::<Vec<TileMetadata>>();
+
+    let out_of_space_tiles = out_of_space_points.len();
+    let mut pending_points = download.pending_points;
+    pending_points.extend(out_of_space_points);
+
+    write_run_files(
+        config,
+        &checksums,
+        tiles,
+        pending_points,
+        resume_state.as_ref(),
+        (min_height, max_height),
+    )?;
+
+    if out_of_space_tiles > 0 {
+        return Err(TerrainError::DiskSpace(format!(
+            "{} tiles could not be written for lack of disk space. Free space and rerun with --resume.",
+            out_of_space_tiles
+        )));
+    }
+
+    Ok(())
+}
+
+// Computes the tiles while they are downloaded, with at most max_in_flight tiles alive at once,
+// counting those being downloaded, waiting and computed. The vertical range has to be known up
+// front, from --clamp-min and --clamp-max or the state of an earlier run. Returns the computed
+// and pending tile counts.
+pub fn compute_textures_streaming(
+    config: &Config,
+    cpus: NonZero<usize>,
+    max_in_flight: usize,
+    resume_state: Option<ResumeState>,
+) -> Result<(usize, usize), TerrainError> {
+    let (min_height, max_height) = match (&resume_state, config.clamp_min, config.clamp_max) {
+        (Some(resume_state), _, _) => (resume_state.min_height, resume_state.max_height),
+        // Point heights are normalized after --z-scale, as by get_height_bounds
+        (None, Some(clamp_min), Some(clamp_max)) => (
+            config.adjust_height(clamp_min),
+            config.adjust_height(clamp_max),
+        ),
+        _ => {
+            return Err(TerrainError::Settings(
+                "Streaming needs --clamp-min and --clamp-max to fix the height range".to_string(),
+            ));
+        }
+    };
+    let output_sink =
+        storage::create_output_sink(&config.destination_folder).map_err(TerrainError::Output)?;
+    let checksums = ChecksumSink::new(output_sink.as_ref());
+    let sink: &dyn OutputSink = &checksums;
+
+    println!("Area min height {}, max height {}", min_height, max_height);
+    println!(
+        "Number of CPUs: {}, decoded tiles in flight: {}",
+        cpus, max_in_flight
+    );
+
+    // A failing compute thread drops the receiver, so the download stops instead of blocking on
+    // a full channel
+    let (tx, rx) = mpsc::sync_channel::<LazData>(max_in_flight);
+    let rx = Mutex::new(Some(rx));
+
+    let (download, computed) = thread::scope(
+        |scope| -> Result<(DownloadResult, Vec<StreamedTile>), TerrainError> {
+            let mut results = vec![];
+            for _ in 0..cpus.get() {
+                let rx = &rx;
+                results.push(scope.spawn(move || {
+                    compute_streamed_tiles(config, sink, rx, (min_height, max_height))
+                }));
+            }
+
+            let resume_points = resume_state
+                .as_ref()
+                .map(|state| state.pending_points.as_slice());
+            let download = requester::stream_laz_data(cpus, config, resume_points, |data| {
+                tx.send(data).map_err(|_| TerrainError::Interrupted)
+            });
+
+            // Closing the channel lets the compute threads finish once it is drained
+            drop(tx);
+
+            let mut computed = vec![];
+            for result in results {
+                computed.extend(result.join().unwrap()?);
+            }
+
+            Ok((download?, computed))
+        },
+    )?;
+
+    let (tile_count, pending_count) = (computed.len(), download.pending_points.len());
+    let (tiles, preview_tiles): (Vec<TileMetadata>, Vec<Option<PreviewTile>>) =
+        computed.into_iter().unzip();
+
+    if config.preview {
+        println!("Writing preview map.");
+        previewer::write_preview(
+            sink,
+            &preview_tiles
+                .into_iter()
+                .flatten()
+                .collect::<Vec<PreviewTile>>(),
+            config.source_crs,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    write_run_files(
+        config,
+        &checksums,
+        tiles,
+        download.pending_points,
+        resume_state.as_ref(),
+        (min_height, max_height),
+    )?;
+
+    Ok((tile_count, pending_count))
+}
+
+type StreamedTile = (TileMetadata, Option<PreviewTile>);
+
+fn compute_streamed_tiles(
+    config: &Config,
+    sink: &dyn OutputSink,
+    rx: &Mutex<Option<mpsc::Receiver<LazData>>>,
+    (min_height, max_height): (f64, f64),
+) -> Result<Vec<StreamedTile>, TerrainError> {
+    let mut computed = vec![];
+
+    loop {
+        // The lock is only held while waiting, the tile is computed after its release
+        let received = match rx.lock().unwrap().as_ref() {
+            Some(rx) => rx.recv(),
+            None => break,
+        };
+        let data = match received {
+            Ok(data) => data,
+            Err(_) => break,
+        };
+
+        let tile = match compute_tile(config, sink, &data, min_height, max_height, None) {
+            Ok(tile) => tile,
+            Err(value) => {
+                rx.lock().unwrap().take();
+                return Err(value);
+            }
+        };
+
+        let preview_tile = tile.thumbnail.map(|thumbnail| PreviewTile {
+            file_stem: data.file_stem.clone(),
+            bounds_min: (data.bounds_min.0, data.bounds_min.1),
+            bounds_max: (data.bounds_max.0, data.bounds_max.1),
+            thumbnail,
+        });
+        computed.push((
+            get_tile_metadata(config, &data, tile.errors, tile.timings),
+            preview_tile,
+        ));
+    }
+
+    Ok(computed)
+}
+
+fn get_tile_metadata(
+    config: &Config,
+    tile: &LazData,
+    errors: Option<TileErrors>,
+    timings: TileTimings,
+) -> TileMetadata {
+    TileMetadata {
+        file_stem: tile.file_stem.clone(),
+        offset_from_center: tile.offset_from_center,
+        bounds_min: tile.bounds_min,
+        bounds_max: tile.bounds_max,
+        fallback: tile.fallback,
+        point_density: tile.density(),
+        survey: tile.survey.clone(),
+        resolution: config.tile_resolution(tile.density()),
+        validation: errors,
+        timings,
+    }
+}
+
+#[derive(Serialize)]
+struct TileReport<'a> {
+    tile: &'a str,
+    #[serde(flatten)]
+    timings: TileTimings,
+}
+
+#[derive(Serialize)]
+struct RunReport<'a> {
+    tiles: Vec<TileReport<'a>>,
+    total: TileTimings,
+}
+
+// Writes the stage timings of the tiles computed by this run to report.json and, with --profile,
+// as folded stacks for flamegraph tools to profile.folded
+fn write_report(
+    config: &Config,
+    sink: &dyn OutputSink,
+    tiles: &[TileMetadata],
+) -> Result<(), TerrainError> {
+    let report = RunReport {
+        tiles: tiles
+            .iter()
+            .map(|tile| TileReport {
+                tile: &tile.file_stem,
+                timings: tile.timings,
+            })
+            .collect(),
+        total: tiles.iter().fold(TileTimings::default(), |total, tile| {
+            total.merge(tile.timings)
+        }),
+    };
+
+    sink.put(
+        &config.get_run_file_name("report.json"),
+        serde_json::to_string_pretty(&report)?.as_bytes(),
+    )
+    .map_err(TerrainError::Output)?;
+
+    if config.profile {
+        let total = report.total;
+        println!(
+            "Stage totals: download {:.0} ms, decode {:.0} ms, compute {:.0} ms (index {:.0} ms, \
+             gridding {:.0} ms, blur {:.0} ms, encode {:.0} ms)",
+            total.download_ms,
+            total.decode_ms,
+            total.compute_ms,
+            total.index_ms,
+            total.gridding_ms,
+            total.blur_ms,
+            total.encode_ms
+        );
+
+        let folded = tiles
+            .iter()
+            .map(|tile| tile.timings.to_folded(&tile.file_stem))
+            .collect::<String>();
+        sink.put(
+            &config.get_run_file_name("profile.folded"),
+            folded.as_bytes(),
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    Ok(())
+}
+
+// Writes config.json, resume.json when tiles are pending and the manifest of everything written
+fn write_run_files(
+    config: &Config,
+    checksums: &ChecksumSink,
+    mut tiles: Vec<TileMetadata>,
+    pending_points: Vec<Point>,
+    resume_state: Option<&ResumeState>,
+    (min_height, max_height): (f64, f64),
+) -> Result<(), TerrainError> {
+    let sink: &dyn OutputSink = checksums;
+
+    write_report(config, sink, &tiles)?;
+
+    validator::report_errors(
+        &tiles
+            .iter()
+            .filter_map(|tile| Some((tile.file_stem.clone(), tile.validation?)))
+            .collect::<Vec<(String, TileErrors)>>(),
+    );
+
+    if resume_state.is_some() {
+        if let Ok(previous) = read_compute_config_file(
+            &config.destination_folder,
+            &config.get_run_file_name("config.json"),
+        ) {
+            for tile in previous.tiles {
+                if !tiles
+                    .iter()
+                    .any(|new_tile| new_tile.file_stem == tile.file_stem)
+                {
+                    tiles.push(tile);
+                }
+            }
+        }
+    }
+
     let cfg = ComputeConfig {
         texture_resolution: config.resolution,
         max_height: max_height,
         min_height: min_height,
-        real_world_dimensions_m: 1000.0,
+        real_world_dimensions_m: get_real_world_dimensions(&tiles),
+        crs: config.target_crs.to_string(),
+        source_crs: config.source_crs.to_string(),
+        grid: get_grid_layout(&tiles)?,
+        tiles,
+        parameters: ComputeParameters::from(config),
+        auto_tune: config.tuning.clone(),
+        nodata: Some(writer::HEIGHT_NODATA),
     };
 
     let json = serde_json::to_string_pretty(&cfg)?;
 
     println!("Writing meta data.");
-    fs::write(format!("{}/config.json", config.destination_folder), json)?;
+    sink.put(&config.get_run_file_name("config.json"), json.as_bytes())
+        .map_err(TerrainError::Output)?;
+
+    let resume_pending = !pending_points.is_empty();
+    if resume_pending {
+        println!(
+            "{} tiles are pending. Rerun with --resume to continue.",
+            pending_points.len()
+        );
+
+        let state = ResumeState {
+            pending_points,
+            min_height,
+            max_height,
+        };
+        sink.put(
+            &config.get_run_file_name("resume.json"),
+            serde_json::to_string_pretty(&state)?.as_bytes(),
+        )
+        .map_err(TerrainError::Output)?;
+    } else if config.resume {
+        fs::remove_file(storage::get_local_path(
+            &config.destination_folder,
+            &config.get_run_file_name("resume.json"),
+        ))?;
+    }
+
+    // Tiles an update or resume did not recompute keep their entries of the previous manifest, so
+    // an unreadable one fails the run instead of dropping them
+    let manifest_name = config.get_run_file_name(verifier::MANIFEST_NAME);
+    let previous_manifest = if resume_state.is_some()
+        && storage::get_local_path(&config.destination_folder, &manifest_name).exists()
+    {
+        Some(
+            verifier::read_manifest_file(&config.destination_folder, &manifest_name)
+                .map_err(TerrainError::Input)?,
+        )
+    } else {
+        None
+    };
+    checksums
+        .write_manifest(config, previous_manifest, resume_pending)
+        .map_err(TerrainError::Output)?;
 
     Ok(())
 }
 
+// Runs --post-tile-hook on the files create_texture wrote for the tile and measures its errors
+// for --validate
+fn compute_tile(
+    config: &Config,
+    sink: &dyn OutputSink,
+    data: &LazData,
+    min_height: f64,
+    max_height: f64,
+    equalization_table: Option<&EqualizationTable>,
+) -> Result<ComputedTile, TerrainError> {
+    pauser::wait_while_paused(config);
+
+    // Drops stages left over on this thread from work outside of a tile
+    meterer::take_tile_timings();
+
+    let start = Instant::now();
+    let (heights, thumbnail) = match &config.post_tile_hook {
+        Some(hook) => {
+            let recording = RecordingSink::new(sink);
+            let output = create_texture(
+                config,
+                &recording,
+                data,
+                min_height,
+                max_height,
+                equalization_table,
+            )?;
+
+            invoker::run_tile_hook(
+                hook,
+                config,
+                data,
+                (min_height, max_height),
+                &recording.into_files(),
+            )
+            .map_err(TerrainError::Hook)?;
+
+            output
+        }
+        None => create_texture(
+            config,
+            sink,
+            data,
+            min_height,
+            max_height,
+            equalization_table,
+        )?,
+    };
+    meterer::record_stage(Stage::Compute, start.elapsed());
+    let timings = data.timings.merge(meterer::take_tile_timings());
+
+    if config.nice {
+        thread::sleep(start.elapsed());
+    }
+
+    let errors = if config.validate {
+        validator::validate_tile(
+            config,
+            data,
+            config.tile_resolution(data.density()) as usize,
+        )?
+    } else {
+        None
+    };
+
+    Ok(ComputedTile {
+        heights,
+        thumbnail,
+        errors,
+        timings,
+    })
+}
+
 fn create_texture(
     config: &Config,
+    sink: &dyn OutputSink,
     data: &LazData,
     min_height: f64,
     max_height: f64,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    equalization_table: Option<&EqualizationTable>,
+) -> Result<(Vec<f32>, Option<Vec<u8>>), TerrainError> {
     let (min_x, min_y, max_x, max_y) = (
         data.bounds_min.0,
         data.bounds_min.1,
         data.bounds_max.0,
         data.bounds_max.1,
     );
-    let (delta_x, delta_y) = (max_x - min_x, max_y - min_y);
+    let resolution = config.tile_resolution(data.density()) as usize;
+    let (dim_x, dim_y) = (resolution, resolution);
 
-    let channel_num = 3;
-    let (dim_x, dim_y) = (config.resolution as usize, config.resolution as usize);
-    let dim_x_adapted = dim_x * channel_num;
-
-    let point_data = data
+    let index_start = Instant::now();
+    let index = create_tile_index(
+        data,
+        dim_x,
+        dim_y,
+        config.sample_size as usize,
+        NeighbourSearch::new(config.ann, config.index),
+        config.internal_precision,
+    );
+    meterer::record_stage(Stage::Index, index_start.elapsed());
+    let heights = data
         .points
         .iter()
         .map(|point| {
-            let point = point;
-
-            [
-                point.x,
-                point.y,
-                (point.z - min_height) / (max_height - min_height),
-            ]
+            ((config.adjust_height(point[2]) - min_height) / (max_height - min_height))
+                .clamp(0.0, 1.0) as f32
         })
-        .collect::<Vec<[f64; 3]>>();
+        .collect::<Vec<f32>>();
 
-    let point_data_xy: Vec<[f64; 2]> = point_data
-        .iter()
-        .map(|point| [point[0], point[1]])
-        .collect();
+    let gridding_start = Instant::now();
+    let mut buffer_f32 = index.interpolate_within(
+        config.sample_size as usize,
+        &heights,
+        config.max_neighbour_distance_m,
+    );
+    meterer::record_stage(Stage::Gridding, gridding_start.elapsed());
 
-    let kdtree = ImmutableKdTree::<f64, 2>::new_from_slice(&point_data_xy[..]);
-    let neighbours_n = config.sample_size as usize;
-    let nearest_neighbours_n = NonZero::new(neighbours_n).unwrap();
+    let normalize_height = |elevation: f64| {
+        ((config.adjust_height(elevation) - min_height) / (max_height - min_height)).clamp(0.0, 1.0)
+            as f32
+    };
+    flattener::flatten_footprints(
+        &config.flatten_footprints,
+        (min_x, min_y),
+        (max_x, max_y),
+        dim_x,
+        dim_y,
+        &mut buffer_f32,
+        normalize_height,
+    );
+    if let Some(sea_level) = config.sea_level {
+        flattener::flatten_sea(
+            &config.sea_polygons,
+            normalize_height(sea_level),
+            (min_x, min_y),
+            (max_x, max_y),
+            dim_x,
+            dim_y,
+            &mut buffer_f32,
+        );
+    }
+    grader::grade_roads(
+        &config.roads,
+        &RoadGrading {
+            width_m: config.road_width_m,
+            cross_slope: config.road_cross_slope,
+            meter_height: config.z_scale / (max_height - min_height),
+        },
+        (min_x, min_y),
+        (max_x, max_y),
+        dim_x,
+        dim_y,
+        &mut buffer_f32,
+    );
+    let file_stem = data.file_stem.clone();
+    gridder::dilate_into_voids(&mut buffer_f32, dim_x, dim_y, config.void_dilation as usize);
 
-    let mut buffer_f32: Vec<f32> = vec![0f32; dim_x_adapted * dim_y];
+    // Spot elevations are set after gridding and again after every blur, which would smooth them
+    let apply_spots = |heights: &mut [f32]| {
+        if let Some(spot_elevations) = &config.spot_elevations {
+            spot_elevations.apply(
+                (min_x, min_y),
+                (max_x, max_y),
+                (dim_x, dim_y),
+                normalize_height,
+                heights,
+            );
+        }
+    };
+    apply_spots(&mut buffer_f32);
 
-    for linear_index in (0..(dim_x_adapted * dim_y)).step_by(channel_num) {
-        let (ind_x, ind_y) = (linear_index % dim_x_adapted, linear_index / dim_x_adapted);
-        let (ind_x, ind_y) = (ind_x, dim_y - ind_y);
+    // Further smoothing levels share the interpolation and only repeat the blur
+    for kernel_size in config.blur_levels.iter() {
+        let mut level = buffer_f32.clone();
+        blur_image(*kernel_size as u32, dim_x, dim_y, &mut level)?;
+        apply_spots(&mut level);
 
-        let (geo_x, geo_y) = (
-            (ind_x as f64 / dim_x_adapted as f64) * delta_x as f64 + min_x,
-            (ind_y as f64 / dim_y as f64) * delta_y as f64 + min_y,
-        );
+        let reprojected = reproject_for_output(config, &level, (dim_x, dim_y), data);
+        let (bounds_min, bounds_max, output_heights) = match &reprojected {
+            Some(reprojected) => (
+                reprojected.bounds_min,
+                reprojected.bounds_max,
+                reprojected.heights.as_slice(),
+            ),
+            None => ((min_x, min_y), (max_x, max_y), level.as_slice()),
+        };
 
-        let nearest_neighbours =
-            kdtree.nearest_n::<SquaredEuclidean>(&[geo_x, geo_y], nearest_neighbours_n);
-        let mut height_result = 0f32;
+        write_formats(
+            config,
+            sink,
+            &format!("{}_blur{}", file_stem, kernel_size),
+            &TileRaster {
+                bounds_min,
+                bounds_max,
+                crs: config.target_crs,
+                dim_x,
+                dim_y,
+                heights: output_heights,
+                min_height,
+                max_height,
+            },
+        )?;
+    }
 
-        for neighbour in nearest_neighbours {
-            height_result += point_data[neighbour.item as usize][2] as f32;
+    // Visualizations get their own buffer when only one of data and visualizations is blurred
+    let kernel_size = config.blur_kernel_size as u32;
+    let viz_f32 = match config.blur_target {
+        BlurTarget::Both => {
+            blur_image(kernel_size, dim_x, dim_y, &mut buffer_f32)?;
+            apply_spots(&mut buffer_f32);
+            None
         }
+        BlurTarget::Data => {
+            let viz_f32 = buffer_f32.clone();
+            blur_image(kernel_size, dim_x, dim_y, &mut buffer_f32)?;
+            apply_spots(&mut buffer_f32);
+            Some(viz_f32)
+        }
+        BlurTarget::Viz => {
+            let mut viz_f32 = buffer_f32.clone();
+            blur_image(kernel_size, dim_x, dim_y, &mut viz_f32)?;
+            apply_spots(&mut viz_f32);
+            Some(viz_f32)
+        }
+    };
+    let viz_heights = viz_f32.as_deref().unwrap_or(&buffer_f32);
 
-        let height_result = height_result / neighbours_n as f32;
-
-        buffer_f32[linear_index] = height_result;
-        buffer_f32[linear_index + 1] = height_result;
-        buffer_f32[linear_index + 2] = height_result;
-    }
+    // The returned buffer stays in the source CRS for chunking and previews, only the written
+    // rasters are resampled
+    let reprojected = reproject_for_output(config, &buffer_f32, (dim_x, dim_y), data);
+    let (bounds_min, bounds_max, output_heights) = match &reprojected {
+        Some(reprojected) => (
+            reprojected.bounds_min,
+            reprojected.bounds_max,
+            reprojected.heights.as_slice(),
+        ),
+        None => ((min_x, min_y), (max_x, max_y), buffer_f32.as_slice()),
+    };
 
-    blur_image(
-        config.blur_kernel_size as u32,
+    let raster = TileRaster {
+        bounds_min,
+        bounds_max,
+        crs: config.target_crs,
         dim_x,
         dim_y,
-        &mut buffer_f32,
-    )?;
+        heights: output_heights,
+        min_height,
+        max_height,
+    };
+
+    // Chunked runs write their rasters per chunk instead of per survey tile
+    if mosaic::get_chunk_dimensions(config).is_none() {
+        write_formats(config, sink, &file_stem, &raster)?;
+    }
+
+    // Layers and packed textures stay on the source grid, the EXR attributes record its CRS
+    let source_raster = TileRaster {
+        bounds_min: (min_x, min_y),
+        bounds_max: (max_x, max_y),
+        crs: config.source_crs,
+        heights: &buffer_f32,
+        ..raster
+    };
 
-    let image = create_image(channel_num, dim_x, dim_y, dim_x_adapted, &buffer_f32);
+    if let Some(style) = &config.style {
+        let stylized = style
+            .apply(
+                &buffer_f32,
+                dim_x,
+                dim_y,
+                source_raster.pixel_size().0,
+                config.z_scale / (max_height - min_height),
+            )
+            .map_err(TerrainError::Interpolation)?;
+        let reprojected = reproject_for_output(config, &stylized, (dim_x, dim_y), data);
+        let stylized_raster = match &reprojected {
+            Some(reprojected) => TileRaster {
+                heights: reprojected.heights.as_slice(),
+                ..raster
+            },
+            None => TileRaster {
+                heights: &stylized,
+                ..source_raster
+            },
+        };
 
-    let file_coord_name_x = get_coordinate_name(data.offset_from_center.0);
-    let file_coord_name_y = get_coordinate_name(data.offset_from_center.1);
-    let file_path = format!(
-        "{}/img_{}_{}.exr",
-        config.destination_folder, file_coord_name_x, file_coord_name_y
+        write_formats(
+            config,
+            sink,
+            &format!("{}_stylized", file_stem),
+            &stylized_raster,
+        )?;
+    }
+
+    if let Some(method) = config.hydro_condition {
+        let conditioned = conditioner::condition(&buffer_f32, dim_x, dim_y, method);
+        let reprojected = reproject_for_output(config, &conditioned, (dim_x, dim_y), data);
+        let conditioned_raster = match &reprojected {
+            Some(reprojected) => TileRaster {
+                heights: reprojected.heights.as_slice(),
+                ..raster
+            },
+            None => TileRaster {
+                heights: &conditioned,
+                ..source_raster
+            },
+        };
+
+        write_formats(
+            config,
+            sink,
+            &format!("{}_conditioned", file_stem),
+            &conditioned_raster,
+        )?;
+    }
+
+    draper::drape_orthophoto(sink, data, &file_stem, dim_x, dim_y)?;
+
+    let mut maps = TileMaps::new(
+        config,
+        data,
+        &index,
+        viz_heights,
+        (dim_x, dim_y),
+        source_raster.pixel_size(),
+        (min_height, max_height),
     );
 
-    image.write().to_file(file_path)?;
+    if config.layered_exr {
+        let layers = [
+            ("height.Y", DerivedMap::Height),
+            ("normal.X", DerivedMap::NormalX),
+            ("normal.Y", DerivedMap::NormalY),
+            ("normal.Z", DerivedMap::NormalZ),
+            ("intensity.Y", DerivedMap::Intensity),
+            ("mask.Y", DerivedMap::Mask),
+        ]
+        .into_iter()
+        .map(|(name, map)| Ok((name, maps.get(map)?.to_vec())))
+        .collect::<Result<Vec<(&str, Vec<f32>)>, TerrainError>>()?;
 
-    Ok(())
-}
+        sink.put(
+            &format!("{}_layers.exr", file_stem),
+            &writer::encode_layered_exr(&source_raster, layers).map_err(TerrainError::Output)?,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    if config.ao {
+        let ao = maps.get(DerivedMap::Ao)?;
+        sink.put(
+            &format!("{}_ao.png", file_stem),
+            &writer::encode_gray_png(dim_x, dim_y, ao, config.ao_depth)
+                .map_err(TerrainError::Output)?,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    // Relief visualizations are written as 16 bit grayscale: the sky-view factor as is, openness
+    // in degrees divided by 180 and local relief centered on mid gray
+    let mut relief_maps = vec![];
+    if config.svf {
+        relief_maps.push(("svf", DerivedMap::SkyView));
+    }
+    if config.openness {
+        relief_maps.push(("openness_pos", DerivedMap::OpennessPositive));
+        relief_maps.push(("openness_neg", DerivedMap::OpennessNegative));
+    }
+    if config.lrm {
+        relief_maps.push(("lrm", DerivedMap::LocalRelief));
+    }
+
+    for (name, map) in relief_maps {
+        let values = maps
+            .get(map)?
+            .iter()
+            .map(|value| map.to_unit(*value))
+            .collect::<Vec<f32>>();
 
-fn get_coordinate_name(value: i16) -> String {
-    if value < 0 {
-        "n".to_string() + &value.abs().to_string()
+        sink.put(
+            &format!("{}_{}.png", file_stem, name),
+            &writer::encode_gray_png(dim_x, dim_y, &values, 16).map_err(TerrainError::Output)?,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    for recipe in config.packing_recipes.iter() {
+        let mut channels: [Vec<f32>; 4] = Default::default();
+        for (channel, map) in channels.iter_mut().zip(recipe.channels) {
+            *channel = maps
+                .get(map)?
+                .iter()
+                .map(|value| map.to_unit(*value))
+                .collect();
+        }
+
+        sink.put(
+            &format!("{}_{}.png", file_stem, recipe.name),
+            &writer::encode_packed_png(dim_x, dim_y, &channels).map_err(TerrainError::Output)?,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    if let Some(splat_rules) = &config.splat_rules {
+        let channels = splat_rules.compute(
+            &mut maps,
+            &index,
+            data,
+            config.sample_size as usize,
+            (min_height, max_height),
+        )?;
+
+        sink.put(
+            &format!("{}_splat.png", file_stem),
+            &writer::encode_packed_png(dim_x, dim_y, &channels).map_err(TerrainError::Output)?,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    bundler::write_bundle(
+        config,
+        sink,
+        &file_stem,
+        &source_raster,
+        &mut maps,
+        &index,
+        data,
+    )?;
+
+    if let Some(equalization_table) = equalization_table {
+        let viz_reprojected = viz_f32
+            .as_ref()
+            .and_then(|viz_f32| reproject_for_output(config, viz_f32, (dim_x, dim_y), data));
+        let viz_output_heights = match (&viz_reprojected, &viz_f32) {
+            (Some(reprojected), _) => reprojected.heights.as_slice(),
+            (None, Some(viz_f32)) => viz_f32.as_slice(),
+            (None, None) => output_heights,
+        };
+
+        let equalized = equalization_table.apply(viz_output_heights);
+        let equalized_raster = TileRaster {
+            heights: &equalized,
+            ..raster
+        };
+
+        sink.put(
+            &format!("{}_equalized.png", file_stem),
+            &PngWriter
+                .encode(&equalized_raster)
+                .map_err(TerrainError::Output)?,
+        )
+        .map_err(TerrainError::Output)?;
+    }
+
+    let thumbnail = if config.preview {
+        Some(previewer::create_thumbnail(viz_heights, dim_x, dim_y).map_err(TerrainError::Output)?)
     } else {
-        value.to_string()
+        None
+    };
+
+    progress::report(data.tile, TileStatus::Computed);
+
+    Ok((buffer_f32, thumbnail))
+}
+
+fn write_formats(
+    config: &Config,
+    sink: &dyn OutputSink,
+    file_stem: &str,
+    raster: &TileRaster,
+) -> Result<(), TerrainError> {
+    for format in config.formats.iter() {
+        let writer = format.writer(config.big_tiff, config.integer_heights);
+        let file_name = format!("{}.{}", file_stem, writer.extension());
+
+        let start = Instant::now();
+        let bytes = writer.encode(raster).map_err(TerrainError::Output)?;
+        meterer::record_stage(Stage::Encode, start.elapsed());
+
+        sink.put(&file_name, &bytes).map_err(TerrainError::Output)?;
     }
+
+    Ok(())
 }
 
-fn create_image<'a>(
-    channel_num: usize,
-    dim_x: usize,
-    dim_y: usize,
-    dim_x_adapted: usize,
-    buffer_f32: &'a Vec<f32>,
-) -> Image<
-    Layer<
-        SpecificChannels<
-            impl Fn(Vec2<usize>) -> (f32, f32, f32),
-            (ChannelDescription, ChannelDescription, ChannelDescription),
-        >,
-    >,
-> {
-    let channels = SpecificChannels::rgb(move |position: Vec2<usize>| {
-        let linear_index = position.0 * channel_num + position.1 * dim_x_adapted;
-        let data = buffer_f32[linear_index];
-
-        (data, data, data)
-    });
-
-    let image = exr::prelude::Image::from_layer(exr::prelude::Layer::new(
-        (dim_x, dim_y),
-        LayerAttributes::named("main-rgb-layer"),
-        Encoding::SMALL_LOSSLESS,
-        channels,
-    ));
+// Resamples a tile raster into the target CRS, None when the output stays in the source CRS
+fn reproject_for_output(
+    config: &Config,
+    heights: &[f32],
+    dim: (usize, usize),
+    data: &LazData,
+) -> Option<ReprojectedRaster> {
+    (config.target_crs != config.source_crs).then(|| {
+        projection::reproject_raster(
+            config.source_crs,
+            config.target_crs,
+            heights,
+            dim,
+            (data.bounds_min.0, data.bounds_min.1),
+            (data.bounds_max.0, data.bounds_max.1),
+        )
+    })
+}
 
-    image
+// Which outputs the -b blur is applied to: the written heightmaps, the derived visualizations
+// (layers, relief maps, packed textures, equalized PNGs and previews) or both
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlurTarget {
+    Data,
+    Viz,
+    #[default]
+    Both,
 }
 
-fn blur_image(
+pub fn blur_image(
     kernel_size: u32,
     dim_x: usize,
     dim_y: usize,
     buffer_f32: &mut Vec<f32>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut blured_image = BlurImageMut::borrow(
-        buffer_f32,
-        dim_x as u32,
-        dim_y as u32,
-        libblur::FastBlurChannels::Channels3,
-    );
-
-    libblur::fast_gaussian_f32(
-        &mut blured_image,
-        AnisotropicRadius {
-            x_axis: kernel_size,
-            y_axis: kernel_size,
-        },
-        ThreadingPolicy::Adaptive,
-        EdgeMode2D::anisotropy(EdgeMode::Clamp, EdgeMode::Clamp),
-    )?;
+) -> Result<(), TerrainError> {
+    let start = Instant::now();
+    gridder::blur_image(kernel_size, dim_x, dim_y, buffer_f32)
+        .map_err(TerrainError::Interpolation)?;
+    meterer::record_stage(Stage::Blur, start.elapsed());
 
     Ok(())
 }
 
-fn get_height_bounds(data: &[LazData]) -> Result<(f64, f64), Box<dyn Error + Send + Sync>> {
+// Spatial index of one tile's points, built once and shared by every raster derived from the tile
+pub fn create_tile_index(
+    data: &LazData,
+    dim_x: usize,
+    dim_y: usize,
+    neighbours_n: usize,
+    search: NeighbourSearch,
+    precision: Precision,
+) -> TileIndex {
+    TileIndex::new(
+        data.points.iter(),
+        (data.bounds_min.0, data.bounds_min.1),
+        (data.bounds_max.0, data.bounds_max.1),
+        dim_x,
+        dim_y,
+        neighbours_n,
+        search,
+        precision,
+    )
+}
+
+fn get_height_bounds(data: &[LazData], config: &Config) -> Result<(f64, f64), TerrainError> {
+    if let Some(percentiles) = config.normalize_percentiles {
+        return get_percentile_height_bounds(data, config, percentiles);
+    }
+
     let (mut min_height, mut max_height) = (f64::MAX, f64::MIN);
 
     for sector in data {
@@ -236,5 +1343,87 @@ fn get_height_bounds(data: &[LazData]) -> Result<(f64, f64), Box<dyn Error + Sen
         }
     }
 
-    Ok((min_height, max_height))
+    Ok((
+        config.adjust_height(min_height),
+        config.adjust_height(max_height),
+    ))
+}
+
+fn get_percentile_height_bounds(
+    data: &[LazData],
+    config: &Config,
+    percentiles: (f64, f64),
+) -> Result<(f64, f64), TerrainError> {
+    let point_count: usize = data.iter().map(|sector| sector.points.len()).sum();
+
+    if point_count == 0 {
+        return Err(TerrainError::Interpolation(
+            "No points available to compute elevation percentiles".to_string(),
+        ));
+    }
+
+    let mut heights = sample_heights(data, config);
+    heights.sort_unstable_by(|a, b| a.total_cmp(b));
+
+    let percentile_height = |percentile: f64| {
+        heights[((percentile / 100.0) * (heights.len() - 1) as f64).round() as usize]
+    };
+
+    Ok((
+        percentile_height(percentiles.0),
+        percentile_height(percentiles.1),
+    ))
+}
+
+// Adjusted elevations of an evenly strided subset of all points, at most MAX_PERCENTILE_SAMPLES
+fn sample_heights(data: &[LazData], config: &Config) -> Vec<f64> {
+    let point_count: usize = data.iter().map(|sector| sector.points.len()).sum();
+    let stride = (point_count / MAX_PERCENTILE_SAMPLES).max(1);
+
+    data.iter()
+        .flat_map(|sector| sector.points.iter())
+        .step_by(stride)
+        .map(|point| config.adjust_height(point[2]))
+        .collect()
+}
+
+pub fn get_real_world_dimensions(tiles: &[TileMetadata]) -> f64 {
+    tiles.iter().fold(0f64, |dimension, sector| {
+        dimension
+            .max(sector.bounds_max.0 - sector.bounds_min.0)
+            .max(sector.bounds_max.1 - sector.bounds_min.1)
+    })
+}
+
+pub fn get_grid_layout(tiles: &[TileMetadata]) -> Result<GridLayout, TerrainError> {
+    if tiles.is_empty() {
+        return Ok(GridLayout::default());
+    }
+
+    let (mut min_offset, mut max_offset) = ((i32::MAX, i32::MAX), (i32::MIN, i32::MIN));
+
+    for sector in tiles {
+        min_offset.0 = min_offset.0.min(sector.offset_from_center.0);
+        min_offset.1 = min_offset.1.min(sector.offset_from_center.1);
+        max_offset.0 = max_offset.0.max(sector.offset_from_center.0);
+        max_offset.1 = max_offset.1.max(sector.offset_from_center.1);
+    }
+
+    let get_count = |min: i32, max: i32| {
+        u16::try_from(max as i64 - min as i64 + 1).map_err(|_| {
+            TerrainError::Settings(format!(
+                "The tiles span {} columns or rows, at most {} fit the grid layout",
+                max as i64 - min as i64 + 1,
+                u16::MAX
+            ))
+        })
+    };
+
+    Ok(GridLayout {
+        columns: get_count(min_offset.0, max_offset.0)?,
+        rows: get_count(min_offset.1, max_offset.1)?,
+        min_offset,
+        max_offset,
+        tile_count: tiles.len(),
+    })
 }
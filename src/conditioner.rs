@@ -0,0 +1,189 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+// How --hydro-condition removes depressions: raising them to their spill level or carving a
+// descending path from their bottom to the outlet
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HydroCondition {
+    Fill,
+    Breach,
+}
+
+#[derive(PartialEq)]
+struct Cell {
+    height: f32,
+    index: usize,
+}
+
+impl Eq for Cell {}
+
+// Reversed so the binary heap pops the lowest cell first
+impl Ord for Cell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .height
+            .total_cmp(&self.height)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Cell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Conditioned copy of a heightmap in which every cell drains to the tile edge or a void. Based on
+// Priority-Flood (Barnes et al. 2014), growing inwards from the edges and voids lowest cell first.
+// Filled flats and carved paths step by the next representable height, as the epsilon variant of
+// the paper does, so the rise stays as small as the heights allow at any elevation.
+pub fn condition(heights: &[f32], dim_x: usize, dim_y: usize, method: HydroCondition) -> Vec<f32> {
+    let mut conditioned = heights.to_vec();
+    let mut visited = heights
+        .iter()
+        .map(|height| height.is_nan())
+        .collect::<Vec<bool>>();
+    // Cell each cell was reached from, which is the next cell on its way to the outlet
+    let mut parents = vec![None; heights.len()];
+    let mut order = Vec::with_capacity(heights.len());
+    let mut queue = BinaryHeap::new();
+
+    let get_neighbours = |index: usize| {
+        let (column, row) = (index % dim_x, index / dim_x);
+        [
+            (column > 0).then(|| index - 1),
+            (column + 1 < dim_x).then(|| index + 1),
+            (row > 0).then(|| index - dim_x),
+            (row + 1 < dim_y).then(|| index + dim_x),
+        ]
+        .into_iter()
+        .flatten()
+    };
+
+    for index in 0..heights.len() {
+        let (column, row) = (index % dim_x, index / dim_x);
+        let on_edge = column == 0 || row == 0 || column + 1 == dim_x || row + 1 == dim_y;
+
+        if !visited[index]
+            && (on_edge || get_neighbours(index).any(|neighbour| heights[neighbour].is_nan()))
+        {
+            visited[index] = true;
+            queue.push(Cell {
+                height: heights[index],
+                index,
+            });
+        }
+    }
+
+    while let Some(Cell { height, index }) = queue.pop() {
+        order.push(index);
+
+        for neighbour in get_neighbours(index) {
+            if visited[neighbour] {
+                continue;
+            }
+            visited[neighbour] = true;
+            parents[neighbour] = Some(index);
+
+            if method == HydroCondition::Fill && conditioned[neighbour] <= height {
+                conditioned[neighbour] = height.next_up();
+            }
+
+            queue.push(Cell {
+                height: conditioned[neighbour],
+                index: neighbour,
+            });
+        }
+    }
+
+    if method == HydroCondition::Breach {
+        // Cells that can not drain to the cell they were reached from lower it below themselves.
+        // A cell pops after the cell it was reached from, so in reverse pop order every cell is
+        // final once it is reached and the whole path to the outlet is lowered in a single pass.
+        // Normalized heights end at 0, where the path stays flat.
+        for index in order.into_iter().rev() {
+            if let Some(parent) = parents[index] {
+                conditioned[parent] =
+                    conditioned[parent].min(conditioned[index].next_down().max(0.0));
+            }
+        }
+    }
+
+    conditioned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIM: usize = 5;
+
+    // Tile edge at 1, the inner 3x3 cells at 0.5 with a pit of 0.2 in the middle
+    fn create_pit() -> Vec<f32> {
+        (0..DIM * DIM)
+            .map(|index| {
+                let (column, row) = (index % DIM, index / DIM);
+                match (column, row) {
+                    (2, 2) => 0.2,
+                    (1..=3, 1..=3) => 0.5,
+                    _ => 1.0,
+                }
+            })
+            .collect()
+    }
+
+    // Whether following the lowest lower neighbour leads from every cell to the tile edge
+    fn drains_to_edge(heights: &[f32]) -> bool {
+        (0..heights.len()).all(|start| {
+            let mut index = start;
+
+            for _ in 0..heights.len() {
+                let (column, row) = (index % DIM, index / DIM);
+                if column == 0 || row == 0 || column + 1 == DIM || row + 1 == DIM {
+                    return true;
+                }
+
+                let lowest = [index - 1, index + 1, index - DIM, index + DIM]
+                    .into_iter()
+                    .min_by(|a, b| heights[*a].total_cmp(&heights[*b]))
+                    .unwrap();
+                if heights[lowest] >= heights[index] {
+                    return false;
+                }
+
+                index = lowest;
+            }
+
+            false
+        })
+    }
+
+    #[test]
+    fn fill_raises_the_pit_above_its_spill_level() {
+        let heights = create_pit();
+        let conditioned = condition(&heights, DIM, DIM, HydroCondition::Fill);
+
+        assert!(!drains_to_edge(&heights));
+        assert!(drains_to_edge(&conditioned));
+        assert!(conditioned[2 * DIM + 2] > 1.0);
+        assert_eq!(conditioned[0], 1.0);
+    }
+
+    #[test]
+    fn breach_carves_a_path_from_the_pit() {
+        let conditioned = condition(&create_pit(), DIM, DIM, HydroCondition::Breach);
+
+        assert!(drains_to_edge(&conditioned));
+        assert_eq!(conditioned[2 * DIM + 2], 0.2);
+    }
+
+    #[test]
+    fn breach_does_not_go_below_zero() {
+        let conditioned = condition(&[0.0; DIM * DIM], DIM, DIM, HydroCondition::Breach);
+
+        assert!(conditioned.iter().all(|height| *height >= 0.0));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::BTreeSet, error::Error, fs};
+
+use crate::{
+    computer,
+    merger::{self, get_crs},
+    storage::{self, OutputSink},
+    verifier::{self, ChecksumSink},
+    writer::{self, BigTiffMode, OutputFormat, TileRaster},
+};
+
+// Formats heights are read from when a folder holds several, most precise first. Integer EXRs
+// hold no scale and offset, the TIFF next to them does.
+const SOURCE_PREFERENCE: [OutputFormat; 4] = [
+    OutputFormat::Exr,
+    OutputFormat::Tiff,
+    OutputFormat::Png,
+    OutputFormat::Raw,
+];
+
+// Writes the heightmaps of a generated folder in other formats, scaled with the height range and
+// integer height settings of its config.json. Without a destination the converted heightmaps are
+// added to the folder, otherwise the destination gets them along with the other tile files.
+pub fn convert_folder(
+    folder: &str,
+    formats: &[OutputFormat],
+    destination_folder: Option<&str>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut config = computer::read_compute_config(folder)?;
+    let parameters = &config.parameters;
+
+    let source_format = SOURCE_PREFERENCE
+        .into_iter()
+        .filter(|format| *format != OutputFormat::Exr || parameters.integer_heights.is_none())
+        .find(|format| parameters.formats.contains(format))
+        .ok_or_else(|| format!("{} holds no heightmaps that can be converted", folder))?;
+    let source_extension = source_format.writer(BigTiffMode::Auto, None).extension();
+
+    let destination_folder = destination_folder.unwrap_or(folder);
+    let in_place =
+        storage::resolve_local_folder(destination_folder) == storage::resolve_local_folder(folder);
+    let sink = storage::create_output_sink(destination_folder)?;
+    let checksums = ChecksumSink::new(sink.as_ref());
+    let crs = get_crs(&config)?;
+    let mut converted = 0;
+
+    for tile in config.tiles.iter() {
+        let heightmap_stems = merger::get_heightmap_stems(parameters, &tile.file_stem);
+
+        // Heightmaps in the formats that were not asked for stay behind, the others are written
+        // below or, for the source format, copied
+        if !in_place {
+            let dropped_names = heightmap_stems
+                .iter()
+                .flat_map(|stem| {
+                    parameters
+                        .formats
+                        .iter()
+                        .filter(|format| {
+                            **format != source_format || !formats.contains(&source_format)
+                        })
+                        .map(move |format| {
+                            format!(
+                                "{}.{}",
+                                stem,
+                                format.writer(BigTiffMode::Auto, None).extension()
+                            )
+                        })
+                })
+                .collect::<BTreeSet<String>>();
+
+            for file_name in merger::list_tile_files(folder, &tile.file_stem)? {
+                if dropped_names.contains(&file_name) {
+                    continue;
+                }
+
+                let bytes = fs::read(storage::get_local_path(folder, &file_name))?;
+                checksums.put(&file_name, &bytes)?;
+            }
+        }
+
+        for stem in heightmap_stems.iter() {
+            let path = storage::get_local_path(folder, &format!("{}.{}", stem, source_extension));
+            let (dim_x, dim_y, heights) =
+                writer::read_heightmap(&path, (config.min_height, config.max_height))?;
+            let (bounds_min, bounds_max) = merger::get_raster_bounds(
+                &config,
+                (tile.bounds_min.0, tile.bounds_min.1),
+                (tile.bounds_max.0, tile.bounds_max.1),
+            )?;
+            let raster = TileRaster {
+                bounds_min,
+                bounds_max,
+                crs,
+                dim_x,
+                dim_y,
+                heights: &heights,
+                min_height: config.min_height,
+                max_height: config.max_height,
+            };
+
+            for format in formats.iter().filter(|format| **format != source_format) {
+                let writer = format.writer(BigTiffMode::Auto, parameters.integer_heights);
+                checksums.put(
+                    &format!("{}.{}", stem, writer.extension()),
+                    &writer.encode(&raster)?,
+                )?;
+            }
+
+            converted += 1;
+        }
+    }
+
+    // In place the folder keeps its earlier formats, a destination only holds the converted ones
+    // and the source heightmaps when they were asked for
+    if in_place {
+        for format in formats.iter() {
+            if !config.parameters.formats.contains(format) {
+                config.parameters.formats.push(*format);
+            }
+        }
+    } else {
+        config.parameters.formats = formats.to_vec();
+    }
+
+    checksums.put(
+        "config.json",
+        serde_json::to_string_pretty(&config)?.as_bytes(),
+    )?;
+    let previous_manifest = in_place
+        .then(|| verifier::read_manifest(destination_folder).ok())
+        .flatten();
+    checksums.write_merged_manifest(config.parameters, previous_manifest)?;
+
+    println!(
+        "Converted {} heightmaps from {} to {}",
+        converted,
+        source_extension,
+        formats
+            .iter()
+            .map(|format| format.writer(BigTiffMode::Auto, None).extension())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    );
+
+    Ok(())
+}
@@ -1,6 +1,31 @@
-use std::{error::Error, fmt::Display, fs, path::PathBuf, str::FromStr};
+use std::{
+    collections::BTreeMap, error::Error, fmt::Display, fs, io, num::NonZero, path::PathBuf,
+    str::FromStr, time::Duration,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bundler::EngineBundle,
+    computer::BlurTarget,
+    conditioner::HydroCondition,
+    deriver::{DerivedMap, PackingRecipe},
+    excluder::{self, Area, Polygons},
+    global_constants::{MIN_ADAPTIVE_RESOLUTION, TILE_SIZE_M},
+    grader::{self, Roads},
+    gridder::{Precision, SpatialIndex},
+    projection::Crs,
+    requester::SurveySelection,
+    splatter::{self, SplatRules},
+    spotter::{self, SpotElevations},
+    storage,
+    stylizer::{self, Style},
+    tuner::TuneReport,
+    writer::{BigTiffMode, IntegerHeights, OutputFormat},
+};
 
 #[derive(Clone, Copy, Debug)]
 pub enum CommandlineParsingErrors {
@@ -28,63 +53,94 @@ impl Display for CommandlineParsingErrors {
 impl Error for CommandlineParsingErrors {}
 
 pub struct CorePointIterator {
-    start_position: (i16, i16),
-    current_position_index: (u8, u8),
-    side_dimension: u8,
+    start_position: (i32, i32),
+    current_position_index: (u32, u32),
+    side_dimension: u32,
+    radius: u16,
+    shape: AreaShape,
 }
 
 impl Iterator for CorePointIterator {
     type Item = Point;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current_position_index.0 >= self.side_dimension
-            || self.current_position_index.1 >= self.side_dimension
-        {
-            return None;
-        }
+        loop {
+            if self.current_position_index.0 >= self.side_dimension
+                || self.current_position_index.1 >= self.side_dimension
+            {
+                return None;
+            }
 
-        let current_point = Point(
-            self.start_position.0 + self.current_position_index.0 as i16,
-            self.start_position.1 + self.current_position_index.1 as i16,
-        );
+            let (index_x, index_y) = self.current_position_index;
+            let current_point = Point(
+                self.start_position.0 + index_x as i32,
+                self.start_position.1 + index_y as i32,
+            );
 
-        self.current_position_index.0 += 1;
-        if self.current_position_index.0 % self.side_dimension == 0 {
-            self.current_position_index.0 = 0;
-            self.current_position_index.1 += 1;
-        }
+            self.current_position_index.0 += 1;
+            if self.current_position_index.0 % self.side_dimension == 0 {
+                self.current_position_index.0 = 0;
+                self.current_position_index.1 += 1;
+            }
+
+            if self.shape == AreaShape::Circle {
+                // Keep tiles whose center lies within half a tile of the circle
+                let radius = self.radius as i64;
+                let (delta_x, delta_y) = (index_x as i64 - radius, index_y as i64 - radius);
 
-        Some(current_point)
+                if delta_x * delta_x + delta_y * delta_y > radius * radius + radius {
+                    continue;
+                }
+            }
+
+            return Some(current_point);
+        }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AreaShape {
+    #[default]
+    Square,
+    Circle,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct CorePoint {
     center: Point,
-    radius: u8,
+    radius: u16,
+    #[serde(default)]
+    shape: AreaShape,
 }
 
 impl CorePoint {
-    pub fn new(center: Point, radius: u8) -> Self {
-        CorePoint { center, radius }
+    pub fn new(center: Point, radius: u16, shape: AreaShape) -> Self {
+        CorePoint {
+            center,
+            radius,
+            shape,
+        }
+    }
+
+    pub fn center(&self) -> Point {
+        self.center
     }
 
     pub fn get_all_points_in_area(&self) -> CorePointIterator {
-        let side_dimension: u8 = (self.radius as u8)
-            .checked_mul(2)
-            .and_then(|prod| prod.checked_add(1))
-            .or(Some(255))
-            .unwrap();
+        let side_dimension = self.radius as u32 * 2 + 1;
 
         let (start_x, start_y) = (
-            self.center.0 as i16 - self.radius as i16,
-            self.center.1 as i16 - self.radius as i16,
+            self.center.0 - self.radius as i32,
+            self.center.1 - self.radius as i32,
         );
 
         CorePointIterator {
             start_position: (start_x, start_y),
-            current_position_index: (0u8, 0u8),
+            current_position_index: (0u32, 0u32),
             side_dimension,
+            radius: self.radius,
+            shape: self.shape,
         }
     }
 }
@@ -96,16 +152,38 @@ impl TryFrom<&Cli> for Vec<CorePoint> {
         let mut result = Vec::with_capacity(value.points.len());
 
         for i in 0..value.points.len() {
-            let point = Point::from_str(&value.points[i])?;
-            result.push(CorePoint::new(point, value.radius[i]));
+            // A radius given inline as "x,y:r" takes precedence over the -r list
+            let (point, radius) = match value.points[i].split_once(':') {
+                Some((point, radius)) => (
+                    point,
+                    radius.trim().parse::<u16>().map_err(|_err| {
+                        CommandlineParsingErrors::IntegerParsing(
+                            "Inline radius could not be converted to u16",
+                        )
+                    })?,
+                ),
+                None if value.radius.len() == value.points.len() => {
+                    (value.points[i].as_str(), value.radius[i])
+                }
+                None => (
+                    value.points[i].as_str(),
+                    *value.radius.first().ok_or(
+                        CommandlineParsingErrors::NumberOfPointsAndRadius(
+                            "Points without an inline radius need a default radius (-r)",
+                        ),
+                    )?,
+                ),
+            };
+
+            result.push(CorePoint::new(Point::from_str(point)?, radius, value.shape));
         }
 
         Ok(result)
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
-pub struct Point(pub i16, pub i16);
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Serialize, Deserialize)]
+pub struct Point(pub i32, pub i32);
 
 impl FromStr for Point {
     type Err = CommandlineParsingErrors;
@@ -132,92 +210,1573 @@ impl FromStr for Point {
             ));
         }
 
-        let first_number = first_number.unwrap().parse::<i16>().map_err(|_err| {
-            CommandlineParsingErrors::IntegerParsing("First argument could not be converted to u16")
+        let first_number = first_number.unwrap().parse::<i32>().map_err(|_err| {
+            CommandlineParsingErrors::IntegerParsing("First argument could not be converted to i32")
         })?;
-        let second_number = second_number.unwrap().parse::<i16>().map_err(|_err| {
-            CommandlineParsingErrors::IntegerParsing("First argument could not be converted to u16")
+        let second_number = second_number.unwrap().parse::<i32>().map_err(|_err| {
+            CommandlineParsingErrors::IntegerParsing(
+                "Second argument could not be converted to i32",
+            )
         })?;
 
         Ok(Point(first_number, second_number))
     }
 }
 
+// Tiles of a width x height block, starting at its south-west tile
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TileRectangle {
+    corner: Point,
+    width: u16,
+    height: u16,
+}
+
+impl TileRectangle {
+    pub fn get_all_points_in_area(&self) -> impl Iterator<Item = Point> + use<> {
+        let (corner, width) = (self.corner, self.width as i32);
+
+        (0..self.height as i32)
+            .flat_map(move |y| (0..width).map(move |x| Point(corner.0 + x, corner.1 + y)))
+    }
+}
+
+// Area in meters of the source CRS, as taken by `recompute --bbox`
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+}
+
+impl BoundingBox {
+    // Every tile whose extent overlaps the box
+    pub fn get_all_points_in_area(&self) -> impl Iterator<Item = Point> + use<> {
+        let (min_x, min_y) = (
+            (self.min.0 / TILE_SIZE_M).floor() as i32,
+            (self.min.1 / TILE_SIZE_M).floor() as i32,
+        );
+        let (max_x, max_y) = (
+            (self.max.0 / TILE_SIZE_M).ceil() as i32,
+            (self.max.1 / TILE_SIZE_M).ceil() as i32,
+        );
+
+        (min_y..max_y).flat_map(move |y| (min_x..max_x).map(move |x| Point(x, y)))
+    }
+}
+
+// One of count disjoint parts of the tile list, index counts from 1. Every tile belongs to exactly
+// one shard, decided from its coordinates alone, so hosts agree on the split without talking to
+// each other and regardless of how the area was given.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    pub fn contains(&self, point: &Point) -> bool {
+        // SplitMix64 finalizer, neighbouring tiles land on different shards so each host gets
+        // an even share of dense and sparse regions
+        let mut hash = ((point.0 as u32 as u64) << 32) | point.1 as u32 as u64;
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+
+        hash % self.count as u64 == (self.index - 1) as u64
+    }
+
+    // Run files of the shard next to those of the others, config.json becomes
+    // config.shard-2-of-4.json
+    pub fn get_file_name(&self, name: &str) -> String {
+        match name.rsplit_once('.') {
+            Some((stem, extension)) => format!(
+                "{}.shard-{}-of-{}.{}",
+                stem, self.index, self.count, extension
+            ),
+            None => format!("{}.shard-{}-of-{}", name, self.index, self.count),
+        }
+    }
+}
+
+impl Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+// Scheme of the tile file names: offsets from the first tile of the area with negative ones
+// written as n1 as in all earlier runs, the same offsets with their sign, offsets from the
+// south-west corner of the area zero padded so they sort, or tile indices of the grid
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamingScheme {
+    #[default]
+    Legacy,
+    Signed,
+    Offset,
+    Absolute,
+}
+
+// Layout of the outputs: the survey tiles only, or also a slippy map pyramid of Web Mercator
+// tiles in {z}/{x}/{y} folders that static tile servers can serve as they are
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileLayout {
+    #[default]
+    Flat,
+    Zxy,
+}
+
+// Names the tiles of one area, the corners are taken from the full area so resumed runs and
+// shards name their tiles the same way
+#[derive(Clone, Copy, Debug)]
+pub struct TileNaming {
+    pub scheme: NamingScheme,
+    pub origin: Point,
+    pub area_min: Point,
+}
+
+impl TileNaming {
+    pub fn get_file_stem(&self, tile: Point) -> String {
+        match self.scheme {
+            NamingScheme::Legacy => format!(
+                "img_{}_{}",
+                get_coordinate_name(tile.0 - self.origin.0),
+                get_coordinate_name(tile.1 - self.origin.1)
+            ),
+            NamingScheme::Signed => {
+                format!("img_{}_{}", tile.0 - self.origin.0, tile.1 - self.origin.1)
+            }
+            NamingScheme::Offset => format!(
+                "img_{:03}_{:03}",
+                tile.0 - self.area_min.0,
+                tile.1 - self.area_min.1
+            ),
+            NamingScheme::Absolute => format!("img_{}_{}", tile.0, tile.1),
+        }
+    }
+}
+
+fn get_coordinate_name(value: i32) -> String {
+    if value < 0 {
+        "n".to_string() + &value.abs().to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// Tiles whose centers lie inside a polygon given by tile indices
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TilePolygon {
+    vertices: Vec<Point>,
+}
+
+impl TilePolygon {
+    pub fn get_all_points_in_area(&self) -> Vec<Point> {
+        let min_x = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.0)
+            .min()
+            .unwrap_or(0);
+        let max_x = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.0)
+            .max()
+            .unwrap_or(0);
+        let min_y = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.1)
+            .min()
+            .unwrap_or(0);
+        let max_y = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.1)
+            .max()
+            .unwrap_or(0);
+
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| Point(x, y)))
+            .filter(|point| self.contains(point.0 as f64 + 0.5, point.1 as f64 + 0.5))
+            .collect()
+    }
+
+    // Even-odd rule ray casting
+    fn contains(&self, x: f64, y: f64) -> bool {
+        let mut inside = false;
+        let mut previous = self.vertices[self.vertices.len() - 1];
+
+        for vertex in self.vertices.iter() {
+            let (x_a, y_a) = (vertex.0 as f64, vertex.1 as f64);
+            let (x_b, y_b) = (previous.0 as f64, previous.1 as f64);
+
+            if (y_a > y) != (y_b > y) && x < (x_b - x_a) * (y - y_a) / (y_b - y_a) + x_a {
+                inside = !inside;
+            }
+
+            previous = *vertex;
+        }
+
+        inside
+    }
+}
+
 pub struct Config {
     pub core_points: Vec<CorePoint>,
+    pub rectangles: Vec<TileRectangle>,
+    pub polygons: Vec<TilePolygon>,
+    pub route: Option<String>,
+    pub corridor_width_m: f64,
     pub possible_blocks: Vec<u8>,
+    pub survey: SurveySelection,
     pub blur_kernel_size: u8,
+    pub blur_levels: Vec<u8>,
+    pub blur_target: BlurTarget,
+    pub void_dilation: u8,
+    pub max_neighbour_distance_m: Option<f64>,
     pub sample_size: u8,
+    // With adaptive resolution this is the maximum any tile is gridded at
     pub resolution: u16,
+    pub adaptive_resolution: bool,
     pub destination_folder: String,
+    pub formats: Vec<OutputFormat>,
+    pub source: String,
+    pub http: HttpConfig,
+    pub mirrors: Vec<String>,
+    pub max_bandwidth: Option<u64>,
+    pub max_total_download: Option<u64>,
+    pub max_memory: Option<u64>,
+    pub resume: bool,
+    pub update: bool,
+    pub offline: bool,
+    pub cache_dir: Option<String>,
+    pub pack_cache: bool,
+    pub compress_cache: bool,
+    pub nearest_first: bool,
+    pub shard: Option<Shard>,
+    pub naming: NamingScheme,
+    pub layout: TileLayout,
+    pub max_cpu_percent: Option<u8>,
+    pub nice: bool,
+    pub profile: bool,
+    pub max_cache_size: Option<u64>,
+    pub z_scale: f64,
+    pub clamp_min: Option<f64>,
+    pub clamp_max: Option<f64>,
+    pub normalize_percentiles: Option<(f64, f64)>,
+    pub equalized: bool,
+    pub chunk_size: Option<u32>,
+    pub chunk_size_m: Option<f64>,
+    pub ann: bool,
+    pub index: SpatialIndex,
+    pub internal_precision: Precision,
+    pub preview: bool,
+    pub footprints: bool,
+    pub low_density: f64,
+    pub min_density: Option<f64>,
+    pub big_tiff: BigTiffMode,
+    pub integer_heights: Option<IntegerHeights>,
+    pub zarr: bool,
+    pub seam_blend_m: f64,
+    pub export_points: Option<String>,
+    pub pdal_pipeline: bool,
+    pub fallback_dem: Option<String>,
+    pub fallback_dem_resolution: f64,
+    pub orthophoto: Option<String>,
+    pub spot_elevations: Option<SpotElevations>,
+    pub exclusion_areas: Vec<Area>,
+    pub flatten_footprints: Vec<Area>,
+    pub sea_level: Option<f64>,
+    pub sea_polygons: Vec<Area>,
+    pub roads: Vec<Vec<(f64, f64)>>,
+    pub road_width_m: f64,
+    pub road_cross_slope: f64,
+    pub source_crs: Crs,
+    pub target_crs: Crs,
+    pub layered_exr: bool,
+    pub packing_recipes: Vec<PackingRecipe>,
+    pub ao: bool,
+    pub ao_radius_m: f64,
+    pub ao_depth: u8,
+    pub svf: bool,
+    pub openness: bool,
+    pub svf_radius_m: f64,
+    pub lrm: bool,
+    pub lrm_radius_m: f64,
+    pub engine_bundle: Option<EngineBundle>,
+    pub splat_rules: Option<SplatRules>,
+    pub style: Option<Style>,
+    pub hydro_condition: Option<HydroCondition>,
+    pub post_tile_hook: Option<String>,
+    pub post_run_hook: Option<String>,
+    pub max_in_flight_tiles: Option<usize>,
+    pub validate: bool,
+    pub auto_tune: bool,
+    pub metrics_address: Option<String>,
+    pub skip_space_check: bool,
+    // Filled in by the auto-tune search once the tiles are downloaded
+    pub tuning: Option<TuneReport>,
 }
 
 impl Config {
-    fn new(
-        core_points: Vec<CorePoint>,
-        possible_blocks: Vec<u8>,
-        blur_kernel_size: u8,
-        sample_size: u8,
-        resolution: u16,
-        destination_folder: String,
-    ) -> Self {
-        Config {
-            core_points,
-            possible_blocks,
-            blur_kernel_size,
-            sample_size,
-            resolution,
-            destination_folder,
+    // Intensities and classes are only kept alongside the coordinates when an output uses them
+    pub fn needs_point_attributes(&self) -> bool {
+        self.layered_exr
+            || self.engine_bundle.is_some()
+            || self
+                .splat_rules
+                .as_ref()
+                .is_some_and(|rules| rules.needs_point_attributes())
+            || self.packing_recipes.iter().any(|recipe| {
+                recipe
+                    .channels
+                    .iter()
+                    .any(|map| map.needs_point_attributes())
+            })
+    }
+
+    // Name of a run file such as config.json, with the shard suffix when the run is a shard
+    pub fn get_run_file_name(&self, name: &str) -> String {
+        match &self.shard {
+            Some(shard) => shard.get_file_name(name),
+            None => name.to_string(),
+        }
+    }
+
+    // Workers to run on the available cores, reduced by --max-cpu-percent
+    pub fn worker_count(&self, available: NonZero<usize>) -> NonZero<usize> {
+        match self.max_cpu_percent {
+            Some(percent) => NonZero::new(available.get() * percent as usize / 100)
+                .unwrap_or(NonZero::<usize>::MIN),
+            None => available,
+        }
+    }
+
+    // Output resolution of a tile with the given point density. Adaptive resolution aims for one
+    // point per pixel, so sparse tiles are not upsampled into blur.
+    pub fn tile_resolution(&self, density: f64) -> u16 {
+        if !self.adaptive_resolution {
+            return self.resolution;
+        }
+
+        ((density.sqrt() * TILE_SIZE_M).round() as u16).clamp(
+            MIN_ADAPTIVE_RESOLUTION.min(self.resolution),
+            self.resolution,
+        )
+    }
+
+    // Elevation as it enters normalization: clipped to the requested range, then scaled
+    pub fn adjust_height(&self, z: f64) -> f64 {
+        let z = z
+            .max(self.clamp_min.unwrap_or(f64::MIN))
+            .min(self.clamp_max.unwrap_or(f64::MAX));
+
+        z * self.z_scale
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct HttpConfig {
+    pub proxy: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub basic_auth: Option<(String, String)>,
+    pub user_agent: Option<String>,
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+}
+
+impl TryFrom<&Cli> for HttpConfig {
+    type Error = CommandlineParsingErrors;
+
+    fn try_from(value: &Cli) -> Result<Self, Self::Error> {
+        let mut headers = Vec::with_capacity(value.headers.len());
+
+        for header in value.headers.iter() {
+            let (name, header_value) = header.split_once(':').ok_or(
+                CommandlineParsingErrors::IncorrectArgumentStructure(
+                    "Header is not correctly structured. Structure should be 'Name: value'",
+                ),
+            )?;
+            headers.push((name.trim().to_string(), header_value.trim().to_string()));
         }
+
+        let basic_auth = match &value.basic_auth {
+            Some(credentials) => {
+                let (user, password) = credentials.split_once(':').ok_or(
+                    CommandlineParsingErrors::IncorrectArgumentStructure(
+                        "Basic auth is not correctly structured. Structure should be 'user:password'",
+                    ),
+                )?;
+                Some((user.to_string(), password.to_string()))
+            }
+            None => None,
+        };
+
+        Ok(HttpConfig {
+            proxy: value.proxy.clone(),
+            headers,
+            basic_auth,
+            user_agent: value.user_agent.clone(),
+            connect_timeout: Duration::from_secs(value.connect_timeout),
+            request_timeout: Duration::from_secs(value.request_timeout),
+        })
+    }
+}
+
+fn get_style(value: &Cli) -> Result<Option<Style>, CommandlineParsingErrors> {
+    let Some(name) = &value.stylize else {
+        return Ok(None);
+    };
+
+    // --style-file takes precedence over the styles of the batch file the job runs from
+    let style = value
+        .style_file
+        .as_ref()
+        .and_then(|styles| styles.get(name))
+        .or_else(|| value.styles.get(name))
+        .ok_or(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Style of --stylize is not defined in --style-file or the styles of the batch file",
+        ))?;
+
+    if style.is_empty() {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Style of --stylize contains no filters",
+        ));
     }
+
+    Ok(Some(style.clone()))
 }
 
 impl TryFrom<&Cli> for Config {
     type Error = CommandlineParsingErrors;
 
     fn try_from(value: &Cli) -> Result<Self, Self::Error> {
-        Ok(Config::new(
-            Vec::<CorePoint>::try_from(value)?,
-            value.possible_blocks.clone(),
-            value.blur_kernel_size,
-            value.sample_size,
-            value.resolution,
-            value.destination_folder.clone(),
-        ))
+        Ok(Config {
+            core_points: Vec::<CorePoint>::try_from(value)?,
+            rectangles: value.rectangles.clone(),
+            polygons: value.polygons.clone(),
+            route: value.route.clone(),
+            corridor_width_m: value.corridor_width_m,
+            possible_blocks: value.possible_blocks.clone(),
+            survey: value.survey,
+            blur_kernel_size: value.blur_kernel_size[0],
+            blur_levels: value
+                .blur_kernel_size
+                .iter()
+                .skip(1)
+                .copied()
+                .unique()
+                .filter(|kernel_size| *kernel_size != value.blur_kernel_size[0])
+                .collect(),
+            sample_size: value.sample_size,
+            resolution: match (value.gsd, value.resolution) {
+                (Some(gsd), _) => (TILE_SIZE_M / gsd).round() as u16,
+                (None, ResolutionSetting::Fixed(resolution)) => resolution,
+                (None, ResolutionSetting::Auto) => value.max_resolution,
+            },
+            adaptive_resolution: value.gsd.is_none()
+                && matches!(value.resolution, ResolutionSetting::Auto),
+            destination_folder: value.destination_folder.clone(),
+            formats: value.format.iter().map(|e| *e).unique().collect(),
+            source: value.source.clone(),
+            http: HttpConfig::try_from(value)?,
+            mirrors: value.mirrors.clone(),
+            max_bandwidth: value.max_bandwidth,
+            max_total_download: value.max_total_download,
+            max_memory: value.max_memory,
+            resume: value.resume,
+            update: value.update,
+            offline: value.offline,
+            cache_dir: value.cache_dir.clone(),
+            pack_cache: value.pack_cache,
+            compress_cache: value.compress_cache,
+            nearest_first: value.nearest_first,
+            shard: value.shard,
+            naming: value.naming,
+            layout: value.layout,
+            max_cpu_percent: value.max_cpu_percent,
+            nice: value.nice,
+            profile: value.profile,
+            max_cache_size: value.max_cache_size,
+            z_scale: value.z_scale,
+            clamp_min: value.clamp_min,
+            clamp_max: value.clamp_max,
+            normalize_percentiles: value.normalize_percentiles,
+            equalized: value.equalized,
+            chunk_size: value.chunk_size,
+            chunk_size_m: value.chunk_size_m,
+            ann: value.ann,
+            index: value.index,
+            internal_precision: value.internal_precision,
+            preview: value.preview,
+            footprints: value.footprints,
+            low_density: value.low_density,
+            min_density: value.min_density,
+            big_tiff: value.bigtiff,
+            integer_heights: value.integer_heights.then_some(IntegerHeights {
+                scale: value.height_scale,
+                offset: value.height_offset,
+            }),
+            seam_blend_m: value.seam_blend_m,
+            blur_target: value.blur_target,
+            void_dilation: value.void_dilation,
+            max_neighbour_distance_m: value.max_neighbour_distance_m,
+            zarr: value.zarr,
+            export_points: value.export_points.clone(),
+            pdal_pipeline: value.pdal_pipeline,
+            fallback_dem: value.fallback_dem.clone(),
+            fallback_dem_resolution: value.fallback_dem_resolution,
+            orthophoto: value.orthophoto.clone(),
+            spot_elevations: value.spot_elevations.clone(),
+            exclusion_areas: value
+                .exclude_polygons
+                .as_ref()
+                .map(|polygons| polygons.project(value.source_crs))
+                .unwrap_or_default(),
+            flatten_footprints: value
+                .flatten_footprints
+                .as_ref()
+                .map(|polygons| polygons.project(value.source_crs))
+                .unwrap_or_default(),
+            sea_level: value.sea_level,
+            sea_polygons: value
+                .sea_polygons
+                .as_ref()
+                .map(|polygons| polygons.project(value.source_crs))
+                .unwrap_or_default(),
+            roads: value
+                .roads
+                .as_ref()
+                .map(|roads| roads.project(value.source_crs))
+                .unwrap_or_default(),
+            road_width_m: value.road_width_m,
+            road_cross_slope: value.road_cross_slope,
+            source_crs: value.source_crs,
+            target_crs: value.target_crs.unwrap_or(value.source_crs),
+            layered_exr: value.layered_exr,
+            packing_recipes: value.pack_channels.clone(),
+            ao: value.ao,
+            ao_radius_m: value.ao_radius_m,
+            ao_depth: value.ao_depth,
+            svf: value.svf,
+            openness: value.openness,
+            svf_radius_m: value.svf_radius_m,
+            lrm: value.lrm,
+            lrm_radius_m: value.lrm_radius_m,
+            engine_bundle: value.engine_bundle,
+            splat_rules: value.splat_rules.clone(),
+            style: get_style(value)?,
+            hydro_condition: value.hydro_condition,
+            post_tile_hook: value.post_tile_hook.clone(),
+            post_run_hook: value.post_run_hook.clone(),
+            max_in_flight_tiles: value.max_in_flight_tiles,
+            validate: value.validate,
+            auto_tune: value.auto_tune,
+            metrics_address: value.metrics_address.clone(),
+            skip_space_check: value.skip_space_check,
+            tuning: None,
+        })
     }
 }
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Cli {
-    #[arg(short = 'p', required = true, value_delimiter = ' ', num_args = 1..)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Core tiles as x,y tile indices, each optionally with its own radius as x,y:r
+    #[arg(
+        short = 'p',
+        required_unless_present_any = ["rectangles", "polygons", "route"],
+        value_delimiter = ' ',
+        num_args = 1..
+    )]
     points: Vec<String>,
 
-    #[arg(short = 'r', required = true, value_delimiter = ' ', num_args = 1..)]
-    radius: Vec<u8>,
+    /// Tiles taken around every core point in each direction, one radius for all points or one per
+    /// point
+    #[arg(short = 'r', value_delimiter = ' ', num_args = 1..)]
+    radius: Vec<u16>,
 
+    /// Shape of the area around every core point, a square or a circle of the radius
+    #[arg(long, value_enum, default_value = "square")]
+    shape: AreaShape,
+
+    /// Block of tiles as x,y,width,height from its south-west tile, may be repeated
+    #[arg(long = "rect", value_parser = parse_rectangle)]
+    rectangles: Vec<TileRectangle>,
+
+    /// Tiles whose centers lie inside the polygon of tile indices x,y;x,y;x,y, may be repeated
+    #[arg(long = "polygon", value_parser = parse_polygon)]
+    polygons: Vec<TilePolygon>,
+
+    /// GPX or GeoJSON track, every tile within half the corridor width of it is generated
+    #[arg(long)]
+    route: Option<String>,
+
+    /// Width in meters of the corridor along --route
+    #[arg(long, default_value = "1000")]
+    corridor_width_m: f64,
+
+    /// Survey blocks searched for every tile, e.g. --possible-blocks 21 35
     #[arg(long, required = true, value_delimiter = ' ', num_args = 1..)]
     possible_blocks: Vec<u8>,
 
-    #[arg(short = 'b', default_value = "10")]
-    blur_kernel_size: u8,
+    /// Copy used where several blocks hold a tile: first, newest, oldest or block:<n>
+    #[arg(long, default_value = "first", value_parser = parse_survey)]
+    survey: SurveySelection,
+
+    /// Kernel size in pixels of the blur applied after gridding. Every further size writes another
+    /// blurred copy of the heightmap.
+    #[arg(short = 'b', value_delimiter = ' ', num_args = 1.., default_value = "10")]
+    blur_kernel_size: Vec<u8>,
 
+    /// Nearest points averaged into every pixel
     #[arg(short = 's', default_value = "3")]
     sample_size: u8,
 
-    #[arg(long, default_value = "1024")]
-    resolution: u16,
+    /// Pixels per tile side, or 'auto' to derive it per tile from the point density
+    #[arg(long, default_value = "1024", value_parser = parse_resolution)]
+    resolution: ResolutionSetting,
+
+    /// Upper bound of the per tile resolution picked by '--resolution auto'
+    #[arg(long, default_value = "2048")]
+    max_resolution: u16,
 
+    /// Meters per pixel, sets the resolution from the tile size instead of --resolution
+    #[arg(long, conflicts_with = "resolution")]
+    gsd: Option<f64>,
+
+    /// Output folder, a local path or an s3:// or gs:// location
     #[arg(short = 'd', required = true)]
     destination_folder: String,
+
+    /// Heightmap formats to write: exr, png, tiff and raw
+    #[arg(long, value_delimiter = ' ', num_args = 1.., default_value = "exr")]
+    format: Vec<OutputFormat>,
+
+    /// Where the tiles come from: arso: with an optional base URL, file:<folder> of local LAZ
+    /// tiles, or an s3:// or gs:// bucket
+    #[arg(long, default_value = "arso:")]
+    source: String,
+
+    /// Base URLs mirroring the ARSO layout, tried in order when the source is unreachable
+    #[arg(long = "mirror")]
+    mirrors: Vec<String>,
+
+    /// Proxy URL every download goes through
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Extra request header as 'Name: value', may be repeated
+    #[arg(long = "header")]
+    headers: Vec<String>,
+
+    /// Basic authentication of the downloads as user:password
+    #[arg(long)]
+    basic_auth: Option<String>,
+
+    /// User-Agent header sent with every request
+    #[arg(long)]
+    user_agent: Option<String>,
+
+    /// Seconds allowed for establishing a connection
+    #[arg(long, default_value = "30")]
+    connect_timeout: u64,
+
+    /// Seconds allowed for a whole request, from sending it until the body is read
+    #[arg(long, default_value = "300")]
+    request_timeout: u64,
+
+    /// Download rate limit per second of all workers combined, e.g. 10M
+    #[arg(long, value_parser = parse_byte_size)]
+    max_bandwidth: Option<u64>,
+
+    /// Downloaded bytes after which no further tiles are fetched, e.g. 2G
+    #[arg(long, value_parser = parse_byte_size)]
+    max_total_download: Option<u64>,
+
+    /// Memory held by tile points, e.g. 8G. Points beyond it are spilled to files in the temp
+    /// directory.
+    #[arg(long, value_parser = parse_byte_size)]
+    max_memory: Option<u64>,
+
+    /// Continues an interrupted run in the destination folder, computing only the tiles it has not
+    /// written yet with the height range of the first attempt
+    #[arg(long)]
+    resume: bool,
+
+    /// Regenerates only the tiles that are new or changed at the source since they were cached,
+    /// keeping the height range of the previous run
+    #[arg(long, conflicts_with = "resume", requires = "cache_dir")]
+    update: bool,
+
+    /// Reads tiles only from the cache or local sources and fails when any tile of the area is
+    /// missing
+    #[arg(long, conflicts_with = "update")]
+    offline: bool,
+
+    /// Folder keeping downloaded tiles between runs
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// Stores newly cached tiles as packed point arrays, which load without LAZ decompression
+    #[arg(long, requires = "cache_dir")]
+    pack_cache: bool,
+
+    /// Stores newly cached tiles zstd compressed, mostly worth it together with --pack-cache
+    #[arg(long, requires = "cache_dir")]
+    compress_cache: bool,
+
+    /// Share of the CPU cores used for download and compute workers, at least one worker remains
+    #[arg(long)]
+    max_cpu_percent: Option<u8>,
+
+    /// Download and compute workers pause after every tile for as long as it took to fetch and
+    /// decode or to compute, so a background run leaves the machine responsive
+    #[arg(long)]
+    nice: bool,
+
+    /// Prints the time spent per stage and writes profile.folded for flamegraph tools next to the
+    /// per tile timings of report.json
+    #[arg(long)]
+    profile: bool,
+
+    /// Downloads tiles in order of their distance from the first core point, or from the middle of
+    /// the area without one, so the center is ready first
+    #[arg(long)]
+    nearest_first: bool,
+
+    /// Computes only shard i of N of the tiles, e.g. 2/4, so several hosts can split a large area
+    /// into one shared destination. Run files get a shard suffix, merge combines them afterward.
+    #[arg(long, value_parser = parse_shard)]
+    shard: Option<Shard>,
+
+    /// Tile file names: offsets from the first tile (img_n1_2), the same with a sign (img_-1_2),
+    /// zero padded offsets from the south-west corner of the area (img_000_002) or absolute tile
+    /// indices (img_462_101)
+    #[arg(long, value_enum, default_value = "legacy")]
+    naming: NamingScheme,
+
+    /// Also writes the area as 256 px EPSG:3857 web map tiles to {z}/{x}/{y} folders, from the
+    /// zoom level matching the resolution down to the one holding the whole area in one tile,
+    /// with a tiles.json describing them
+    #[arg(long, value_enum, default_value = "flat")]
+    layout: TileLayout,
+
+    /// Evicts the least recently used tiles after the download until the cache fits this size
+    #[arg(long, requires = "cache_dir", value_parser = parse_byte_size)]
+    max_cache_size: Option<u64>,
+
+    /// Factor the heights are multiplied by after clamping
+    #[arg(long, default_value = "1.0")]
+    z_scale: f64,
+
+    /// Heights below this many meters are raised to it before normalizing
+    #[arg(long, allow_negative_numbers = true)]
+    clamp_min: Option<f64>,
+
+    /// Heights above this many meters are lowered to it before normalizing
+    #[arg(long, allow_negative_numbers = true)]
+    clamp_max: Option<f64>,
+
+    /// Normalizes between these percentiles of all heights instead of the lowest and highest point,
+    /// as low,high, e.g. 1,99
+    #[arg(long, value_parser = parse_percentiles)]
+    normalize_percentiles: Option<(f64, f64)>,
+
+    /// Also writes a histogram equalized PNG per tile, equalized over the heights of the whole area
+    #[arg(long)]
+    equalized: bool,
+
+    /// Also writes the area cut into square chunks of this many pixels, independent of the survey
+    /// tiles
+    #[arg(long)]
+    chunk_size: Option<u32>,
+
+    /// Side of the chunks in meters, together with --chunk-size or keeping the pixel size of the
+    /// tiles
+    #[arg(long)]
+    chunk_size_m: Option<f64>,
+
+    /// Approximate neighbour search, faster but a chosen neighbour may lie one bucket width (about
+    /// 0.5 m) farther away than the exact one
+    #[arg(long)]
+    ann: bool,
+
+    /// Neighbour index of the exact search, grid buckets the points per output pixel
+    #[arg(long, value_enum, default_value = "kdtree")]
+    index: SpatialIndex,
+
+    /// Float type of the stored tile points and the neighbour index, f32 halves their memory
+    #[arg(long, value_enum, default_value = "f64")]
+    internal_precision: Precision,
+
+    /// Writes preview.html, a Leaflet map with a thumbnail of every tile over OpenStreetMap
+    #[arg(long)]
+    preview: bool,
+
+    /// Writes the tile outlines with their density status as GeoJSON and KML
+    #[arg(long)]
+    footprints: bool,
+
+    /// Points per square meter below which a tile is reported as low density
+    #[arg(long, default_value = "2.0")]
+    low_density: f64,
+
+    /// Points per square meter below which a surveyed tile fails the run
+    #[arg(long)]
+    min_density: Option<f64>,
+
+    /// Writes TIFFs as BigTIFF when they would exceed 4 GiB (auto), always or never
+    #[arg(long, value_enum, default_value = "auto")]
+    bigtiff: BigTiffMode,
+
+    /// Writes EXR and TIFF heights as 16 bit integers, height = value * scale + offset, with the
+    /// scale and offset in the file metadata. 0 marks voids, as in PNG and raw heightmaps. EXR
+    /// has no 16 bit integer type, its single Y channel holds them as u32.
+    #[arg(long)]
+    integer_heights: bool,
+
+    /// Meters per integer step, by default the height range divided by 65534
+    #[arg(long, requires = "integer_heights")]
+    height_scale: Option<f64>,
+
+    /// Height of the integer 1, the lowest after the void code 0, by default the lowest height of
+    /// the run
+    #[arg(long, requires = "integer_heights", allow_negative_numbers = true)]
+    height_offset: Option<f64>,
+
+    /// Applies -b to the heightmap data, to the visualizations derived from it or to both
+    #[arg(long, value_enum, default_value = "both")]
+    blur_target: BlurTarget,
+
+    /// Fills NaN voids up to this many pixels from valid heights before blurring, closing
+    /// speckles and fringes of masked or sparse areas. 0 keeps every void.
+    #[arg(long, default_value = "0")]
+    void_dilation: u8,
+
+    /// Leaves pixels farther than this many meters from every point as NaN voids instead of
+    /// spreading the nearest points over them, e.g. over water without returns
+    #[arg(long)]
+    max_neighbour_distance_m: Option<f64>,
+
+    /// Also writes the area as a Zarr v2 store with downsampled levels for xarray and Dask
+    #[arg(long)]
+    zarr: bool,
+
+    /// Width in meters of the band over which chunked and Zarr outputs blend seams between tiles
+    /// of different surveys, 0 disables blending
+    #[arg(long, default_value = "0")]
+    seam_blend_m: f64,
+
+    /// Writes the points of all tiles to one LAS file at this path, compressed when it ends in .laz
+    #[arg(long)]
+    export_points: Option<String>,
+
+    /// Writes pipeline.json, a PDAL pipeline reproducing the gridding of every tile
+    #[arg(long)]
+    pdal_pipeline: bool,
+
+    /// URL template of a coarse GeoTIFF DEM for tiles missing from the survey, with {minx}, {miny},
+    /// {maxx}, {maxy}, {width} and {height} replaced per tile
+    #[arg(long)]
+    fallback_dem: Option<String>,
+
+    /// Meters per pixel requested from --fallback-dem
+    #[arg(long, default_value = "30.0")]
+    fallback_dem_resolution: f64,
+
+    /// URL template of an orthophoto service such as a WMS GetMap request, with {minx}, {miny},
+    /// {maxx}, {maxy}, {width} and {height} replaced per tile. The PNG or TIFF answer is written
+    /// as an RGB texture on the pixel grid of the heightmap.
+    #[arg(long)]
+    orthophoto: Option<String>,
+
+    /// CSV of x,y,z surveyed heights in the source CRS. The pixel nearest to each spot takes its
+    /// height exactly after gridding and blurring, the pixels around it are blended towards it.
+    #[arg(long, value_parser = spotter::parse_spot_elevations)]
+    spot_elevations: Option<SpotElevations>,
+
+    /// GeoJSON of polygons whose points are dropped before gridding, the gaps are filled from the
+    /// surrounding terrain of the tile
+    #[arg(long, value_parser = excluder::parse_polygons)]
+    exclude_polygons: Option<Polygons>,
+
+    /// GeoJSON of building footprints flattened to the ground level around them, or to the
+    /// elevation property of their feature
+    #[arg(long, value_parser = excluder::parse_polygons)]
+    flatten_footprints: Option<Polygons>,
+
+    /// Elevation in meters below which the surface is flattened to the sea level
+    #[arg(long, allow_negative_numbers = true)]
+    sea_level: Option<f64>,
+
+    /// GeoJSON of sea polygons flattened to the sea level regardless of their elevation
+    #[arg(long, value_parser = excluder::parse_polygons)]
+    sea_polygons: Option<Polygons>,
+
+    /// GPX or GeoJSON road centerlines, the surface is smoothed along them and falls off by the
+    /// cross slope towards the road edges
+    #[arg(long, value_parser = grader::parse_roads)]
+    roads: Option<Roads>,
+
+    /// Width in meters of the roads of --roads
+    #[arg(long, default_value = "8.0")]
+    road_width_m: f64,
+
+    /// Fall of the road surface per meter from the centerline towards the edges
+    #[arg(long, default_value = "0.02")]
+    road_cross_slope: f64,
+
+    /// CRS of the survey tiles. Only EPSG:3794 (D96/TM), EPSG:32633 (UTM 33N), EPSG:3857 (Web
+    /// Mercator) and EPSG:4326 (WGS84) are supported.
+    #[arg(long, value_parser = parse_crs, default_value = "EPSG:3794")]
+    source_crs: Crs,
+
+    /// CRS the heightmaps are resampled into, one of the --source-crs codes. config.json records
+    /// it as crs next to source_crs, the CRS of the tile bounds and grid.
+    ///
+    /// Coordinates are converted with a built-in transverse Mercator, not PROJ, and ETRS89 is
+    /// taken as WGS84 without a datum shift. The two datums are about 0.9 m apart (2025).
+    #[arg(long, value_parser = parse_crs)]
+    target_crs: Option<Crs>,
+
+    /// Also writes an EXR per tile with height, normal, intensity and ground mask layers
+    #[arg(long)]
+    layered_exr: bool,
+
+    /// Packs derived maps into the channels of an RGBA PNG per tile, as
+    /// name:r=map,g=map,b=map,a=map with the maps height, normal_x, normal_y, normal_z, slope,
+    /// intensity, mask, ao, svf, openness_pos, openness_neg, lrm, zero and one. May be repeated.
+    #[arg(long, value_parser = parse_packing_recipe)]
+    pack_channels: Vec<PackingRecipe>,
+
+    /// Writes an ambient occlusion PNG per tile
+    #[arg(long)]
+    ao: bool,
+
+    /// Distance in meters searched for the horizon by --ao
+    #[arg(long, default_value = "50.0")]
+    ao_radius_m: f64,
+
+    /// Bit depth of the --ao PNG, 8 or 16
+    #[arg(long, default_value = "16")]
+    ao_depth: u8,
+
+    /// Writes a sky-view factor PNG per tile
+    #[arg(long)]
+    svf: bool,
+
+    /// Writes positive and negative openness PNGs per tile
+    #[arg(long)]
+    openness: bool,
+
+    /// Distance in meters searched for the horizon by --svf and --openness
+    #[arg(long, default_value = "10.0")]
+    svf_radius_m: f64,
+
+    /// Writes a local relief model PNG per tile, the heights minus a smoothed copy of them
+    #[arg(long)]
+    lrm: bool,
+
+    /// Radius in meters of the smoothing subtracted by --lrm
+    #[arg(long, default_value = "20.0")]
+    lrm_radius_m: f64,
+
+    /// Writes a per-tile folder with heightmap, normal map, splat map and descriptor ready to be
+    /// imported into the given engine
+    #[arg(long, value_enum)]
+    engine_bundle: Option<EngineBundle>,
+
+    /// TOML file of slope, height and class rules painting an RGBA splat map per tile
+    #[arg(long, value_parser = splatter::parse_splat_rules)]
+    splat_rules: Option<SplatRules>,
+
+    /// Name of a style in --style-file or in the "styles" of the batch file, whose terrace,
+    /// ridge and erosion filters are written to a stylized copy of the heightmap
+    #[arg(long)]
+    stylize: Option<String>,
+
+    /// JSON file of named styles for --stylize, in the format of the "styles" of batch files
+    #[arg(long, value_parser = stylizer::parse_style_file, requires = "stylize")]
+    style_file: Option<BTreeMap<String, Style>>,
+
+    /// Styles of the batch file the job runs from, set by batch
+    #[arg(skip)]
+    pub styles: BTreeMap<String, Style>,
+
+    /// Writes a copy of the heightmap without depressions, filled to their spill level or
+    /// breached towards their outlet, for flow routing and water simulations
+    #[arg(long, value_enum)]
+    hydro_condition: Option<HydroCondition>,
+
+    /// Shell command run for every file a tile produced, "{file}" is replaced by its quoted path
+    /// (also in LTG_FILE) and the tile is described in LTG_* environment variables
+    #[arg(long)]
+    post_tile_hook: Option<String>,
+
+    /// Shell command run once the run completed, "{folder}" is replaced by the quoted destination
+    /// (also in LTG_DESTINATION)
+    #[arg(long)]
+    post_run_hook: Option<String>,
+
+    /// Computes tiles while they download, holding at most this many tiles in memory at once,
+    /// from the start of their download until they are computed
+    #[arg(long)]
+    max_in_flight_tiles: Option<usize>,
+
+    /// Grids every tile again without a held-out subset of its points and reports the height
+    /// errors in meters at those points. Small tiles are gridded once per fold so that every point
+    /// is held out once.
+    #[arg(long)]
+    validate: bool,
+
+    /// Picks the sample size, neighbour search and blur with the lowest held-out error on a few
+    /// downloaded tiles, replacing -s, --ann and the first -b
+    #[arg(long)]
+    auto_tune: bool,
+
+    /// Serves tile counts, downloaded bytes and stage durations for Prometheus on this address,
+    /// e.g. 0.0.0.0:9464
+    #[arg(long)]
+    metrics_address: Option<String>,
+
+    /// Starts even when the estimated output and cache size exceed the free disk space
+    #[arg(long)]
+    skip_space_check: bool,
+}
+
+// Parses "name:r=height,g=mask,b=slope,a=one". Channels left out are zero, alpha defaults to one.
+fn parse_packing_recipe(value: &str) -> Result<PackingRecipe, String> {
+    let (name, channels) = value
+        .split_once(':')
+        .ok_or("Expected a packing recipe as 'name:r=map,g=map,b=map,a=map'")?;
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "Packing recipe name '{}' must be alphanumeric",
+            name
+        ));
+    }
+
+    let mut recipe = PackingRecipe {
+        name: name.to_string(),
+        channels: [
+            DerivedMap::Zero,
+            DerivedMap::Zero,
+            DerivedMap::Zero,
+            DerivedMap::One,
+        ],
+    };
+
+    for assignment in channels.split(',') {
+        let (channel, map) = assignment
+            .split_once('=')
+            .ok_or(format!("Expected 'channel=map', got '{}'", assignment))?;
+        let channel = match channel.trim().to_lowercase().as_str() {
+            "r" => 0,
+            "g" => 1,
+            "b" => 2,
+            "a" => 3,
+            _ => return Err(format!("Unknown channel '{}'", channel)),
+        };
+
+        recipe.channels[channel] = map.parse::<DerivedMap>()?;
+    }
+
+    Ok(recipe)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ResolutionSetting {
+    Fixed(u16),
+    Auto,
+}
+
+fn parse_resolution(value: &str) -> Result<ResolutionSetting, String> {
+    if value.trim().eq_ignore_ascii_case("auto") {
+        return Ok(ResolutionSetting::Auto);
+    }
+
+    match value.trim().parse::<u16>() {
+        Ok(resolution) if resolution > 0 => Ok(ResolutionSetting::Fixed(resolution)),
+        _ => Err(format!(
+            "'{}' should be a positive number of pixels or 'auto'",
+            value
+        )),
+    }
+}
+
+fn parse_survey(value: &str) -> Result<SurveySelection, String> {
+    match value.trim().to_lowercase().as_str() {
+        "first" => Ok(SurveySelection::First),
+        "newest" => Ok(SurveySelection::Newest),
+        "oldest" => Ok(SurveySelection::Oldest),
+        selection => selection
+            .strip_prefix("block:")
+            .and_then(|block_number| block_number.parse::<u8>().ok())
+            .map(SurveySelection::Block)
+            .ok_or(format!(
+                "'{}' should be one of first, newest, oldest or block:<n>",
+                value
+            )),
+    }
 }
 
-pub fn read_config_from_cli() -> Result<Config, CommandlineParsingErrors> {
-    let arguments = Cli::parse();
+fn parse_crs(value: &str) -> Result<Crs, String> {
+    value
+        .trim()
+        .to_uppercase()
+        .strip_prefix("EPSG:")
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(Crs::from_epsg)
+        .ok_or(format!(
+            "Unsupported CRS '{}', expected one of EPSG:3794, EPSG:32633, EPSG:3857, EPSG:4326",
+            value
+        ))
+}
+
+fn parse_percentiles(value: &str) -> Result<(f64, f64), String> {
+    let (low, high) = value
+        .split_once(',')
+        .ok_or_else(|| format!("'{}' should be structured as 'low,high'", value))?;
+
+    let low = low
+        .trim()
+        .parse::<f64>()
+        .map_err(|_err| format!("'{}' is not a valid percentile", low))?;
+    let high = high
+        .trim()
+        .parse::<f64>()
+        .map_err(|_err| format!("'{}' is not a valid percentile", high))?;
 
-    if arguments.points.len() != arguments.radius.len() {
+    if is_negative(low) || high.is_nan() || low >= high || high > 100.0 {
+        return Err("Percentiles must satisfy 0 <= low < high <= 100".to_string());
+    }
+
+    Ok((low, high))
+}
+
+fn parse_rectangle(value: &str) -> Result<TileRectangle, String> {
+    let parts = value
+        .split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|_err| format!("'{}' should be structured as 'x,y,width,height'", value))?;
+
+    match parts[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(TileRectangle {
+            corner: Point(x, y),
+            width: u16::try_from(width).map_err(|_err| "Rectangle is too wide".to_string())?,
+            height: u16::try_from(height).map_err(|_err| "Rectangle is too tall".to_string())?,
+        }),
+        _ => Err(format!(
+            "'{}' should be structured as 'x,y,width,height' with a positive size",
+            value
+        )),
+    }
+}
+
+fn parse_bounding_box(value: &str) -> Result<BoundingBox, String> {
+    let parts = value
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_err| {
+            format!(
+                "'{}' should be structured as 'min_x,min_y,max_x,max_y'",
+                value
+            )
+        })?;
+
+    match parts[..] {
+        [min_x, min_y, max_x, max_y] if min_x < max_x && min_y < max_y => Ok(BoundingBox {
+            min: (min_x, min_y),
+            max: (max_x, max_y),
+        }),
+        _ => Err(format!(
+            "'{}' should be structured as 'min_x,min_y,max_x,max_y' with min below max",
+            value
+        )),
+    }
+}
+
+fn parse_shard(value: &str) -> Result<Shard, String> {
+    let parts = value.split_once('/').and_then(|(index, count)| {
+        Some((
+            index.trim().parse::<u32>().ok()?,
+            count.trim().parse::<u32>().ok()?,
+        ))
+    });
+
+    match parts {
+        Some((index, count)) if index >= 1 && index <= count => Ok(Shard { index, count }),
+        _ => Err(format!(
+            "'{}' should be structured as 'i/N' with i between 1 and N",
+            value
+        )),
+    }
+}
+
+fn parse_polygon(value: &str) -> Result<TilePolygon, String> {
+    let vertices = value
+        .split(';')
+        .map(|vertex| Point::from_str(vertex).map_err(|err| err.to_string()))
+        .collect::<Result<Vec<Point>, String>>()?;
+
+    if vertices.len() < 3 {
+        return Err("A polygon needs at least three vertices, e.g. 'x,y;x,y;x,y'".to_string());
+    }
+
+    Ok(TilePolygon { vertices })
+}
+
+fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let unit_start = value
+        .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+
+    let number = number
+        .parse::<f64>()
+        .map_err(|_err| format!("'{}' is not a valid size", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return Err(format!("Unknown size unit in '{}'", value)),
+    };
+
+    // Casting saturates, sizes past u64 would silently become u64::MAX
+    let bytes = number * multiplier as f64;
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("'{}' is too large", value));
+    }
+
+    Ok(bytes as u64)
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Compare two generated terrains and write per-tile difference rasters
+    Diff {
+        /// Folder of the first terrain
+        dir_a: String,
+
+        /// Folder of the second terrain, differences are its heights minus those of the first
+        dir_b: String,
+
+        /// Folder receiving diff.json and the difference rasters
+        #[arg(short = 'd', required = true)]
+        destination_folder: String,
+    },
+    /// Combine the tiles of several generated folders, e.g. shards, into one terrain
+    Merge {
+        /// Generated folders to combine
+        #[arg(required = true, num_args = 1..)]
+        folders: Vec<String>,
+
+        /// Folder receiving the combined terrain
+        #[arg(short = 'd', required = true)]
+        destination_folder: String,
+
+        /// Also writes mosaic.exr and mosaic.png of all tiles at this many pixels per tile
+        #[arg(long)]
+        mosaic: Option<usize>,
+    },
+    /// Rewrite the heightmaps of a generated folder to a new height range without gridding again
+    Renormalize {
+        /// Generated folder
+        folder: String,
+
+        /// Height in meters mapped to 0
+        #[arg(long, allow_negative_numbers = true)]
+        min_height: f64,
+
+        /// Height in meters mapped to 1
+        #[arg(long, allow_negative_numbers = true)]
+        max_height: f64,
+
+        /// Writes the result here instead of over the folder
+        #[arg(short = 'd')]
+        destination_folder: Option<String>,
+    },
+    /// Re-encode the heightmaps of a generated folder into other formats, scaled with the height
+    /// range stored in its config.json
+    Convert {
+        /// Generated folder
+        folder: String,
+
+        /// Formats to write, e.g. --format png tiff
+        #[arg(long, value_delimiter = ' ', num_args = 1.., required = true)]
+        format: Vec<OutputFormat>,
+
+        /// Writes the result here instead of adding it to the folder
+        #[arg(short = 'd')]
+        destination_folder: Option<String>,
+    },
+    /// Check a generated folder against the checksums in its manifest.json
+    Verify {
+        /// Generated folder with a manifest.json
+        folder: String,
+    },
+    /// Grid the tiles of a sub-area again from cached point data, e.g. after changing the blur,
+    /// and keep all other tiles of the destination
+    Recompute {
+        /// min_x,min_y,max_x,max_y in meters of the source CRS
+        #[arg(long, value_parser = parse_bounding_box)]
+        bbox: BoundingBox,
+
+        /// Generate arguments of the earlier run with the changed settings, e.g.
+        /// -- -p 430,136 -r 2 -d out --cache-dir cache -b 5
+        #[arg(last = true)]
+        generate_args: Vec<String>,
+    },
+    /// Show or shrink a tile download cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+    /// Report the height jumps between adjacent tiles of a generated folder in seams.json
+    Seams {
+        /// Generated folder
+        folder: String,
+
+        /// Largest allowed jump in meters along a seam before it is flagged
+        #[arg(long, default_value = "0.5")]
+        tolerance_m: f64,
+    },
+    /// Print a shell completion script, e.g. for ~/.bashrc: source <(las-terrain-generator
+    /// completions bash)
+    Completions {
+        /// Shell the script is written for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print ready to copy invocations for common scenarios
+    Examples,
+    /// Answer a few questions and get a batch file for a first run
+    Init,
+    /// Check configuration files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Run every job listed in a JSON batch file, sharing one download cache
+    Batch {
+        /// JSON batch file listing the jobs
+        batch_file: String,
+
+        /// Jobs run at the same time
+        #[arg(long, default_value = "1")]
+        parallel_jobs: NonZero<usize>,
+
+        /// Tile cache shared by the jobs that do not set their own --cache-dir
+        #[arg(long)]
+        cache_dir: Option<String>,
+
+        /// Writes the outcome, tile count and duration of every job to this JSON file
+        #[arg(long)]
+        report: Option<String>,
+    },
+    /// Time gridding, blur and encoding on synthetic tiles across thread counts
+    Bench {
+        /// Synthetic tiles gridded per thread count
+        #[arg(long, default_value = "8")]
+        tiles: usize,
+
+        /// Side of a synthetic tile in meters
+        #[arg(long, default_value = "500")]
+        tile_size_m: f64,
+
+        /// Points per square meter of the synthetic tiles
+        #[arg(long, default_value = "10")]
+        density: f64,
+
+        /// Pixels per tile side
+        #[arg(long, default_value = "1024")]
+        resolution: u16,
+
+        /// Nearest points averaged into every pixel
+        #[arg(short = 's', default_value = "3")]
+        sample_size: u8,
+
+        /// Kernel size in pixels of the blur
+        #[arg(short = 'b', default_value = "10")]
+        blur_kernel_size: u8,
+
+        /// Thread counts timed
+        #[arg(long, value_delimiter = ' ', num_args = 1.., default_values_t = [1, 2, 4])]
+        threads: Vec<usize>,
+
+        /// Formats encoded
+        #[arg(long, value_delimiter = ' ', num_args = 1.., default_value = "exr")]
+        format: Vec<OutputFormat>,
+
+        /// Times the approximate neighbour search
+        #[arg(long)]
+        ann: bool,
+
+        /// Neighbour index of the exact search
+        #[arg(long, value_enum, default_value = "kdtree")]
+        index: SpatialIndex,
+
+        /// Float type of the tile points and the neighbour index
+        #[arg(long, value_enum, default_value = "f64")]
+        internal_precision: Precision,
+
+        /// Also downloads this URL with a fresh client per request and with one shared client
+        #[arg(long)]
+        http_url: Option<String>,
+
+        /// Requests made with each kind of client for --http-url
+        #[arg(long, default_value = "10")]
+        http_requests: usize,
+    },
+    /// Pick tiles on an interactive grid and watch the run progress
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Tile at the center of the grid as x,y
+        center: String,
+
+        /// Tiles shown around the center in each direction
+        #[arg(long, default_value = "5")]
+        view_radius: u16,
+
+        /// Regular generate arguments except the area, e.g. -- -d out --possible-blocks 21 35
+        #[arg(last = true)]
+        generate_args: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check a JSON batch file with its styles and the arguments of its jobs, or a TOML splat
+    /// rules file, without running them
+    Validate {
+        /// Batch file or TOML splat rules file
+        file: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Print the number of cached tiles, their size and when they were last used
+    Info {
+        /// Tile cache folder
+        cache_dir: String,
+    },
+    /// Evict the least recently used tiles until the cache fits the given size
+    Prune {
+        /// Tile cache folder
+        cache_dir: String,
+
+        /// Size the cache is shrunk to, e.g. 20G
+        #[arg(long, value_parser = parse_byte_size)]
+        max_size: u64,
+    },
+}
+
+pub fn read_cli() -> Cli {
+    Cli::parse()
+}
+
+pub fn check_folder_exists(folder: &str) -> Result<(), CommandlineParsingErrors> {
+    let metadata = match fs::metadata(folder) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+                "Destination folder does not exist",
+            ));
+        }
+        Err(_) => {
+            return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+                "Issue with the destination folder",
+            ));
+        }
+    };
+
+    if !metadata.is_dir() {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Destination folder is not a directory",
+        ));
+    }
+
+    if metadata.permissions().readonly() {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Destination folder is read only",
+        ));
+    }
+
+    Ok(())
+}
+
+// Lengths and factors given on the command line, NaN fails every comparison so it is caught here
+fn is_not_positive(value: f64) -> bool {
+    value.is_nan() || value <= 0.0
+}
+
+fn is_negative(value: f64) -> bool {
+    value.is_nan() || value < 0.0
+}
+
+pub fn read_config_from_cli(arguments: &Cli) -> Result<Config, CommandlineParsingErrors> {
+    if arguments.points.is_empty()
+        && arguments.rectangles.is_empty()
+        && arguments.polygons.is_empty()
+        && arguments.route.is_none()
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "At least one area must be given",
+        ));
+    }
+
+    if arguments.sea_polygons.is_some() && arguments.sea_level.is_none() {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Sea polygons need a sea level",
+        ));
+    }
+
+    if is_not_positive(arguments.road_width_m) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Road width has to be positive",
+        ));
+    }
+
+    if is_negative(arguments.road_cross_slope) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Road cross slope can not be negative",
+        ));
+    }
+
+    if is_negative(arguments.corridor_width_m) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Corridor width can not be negative",
+        ));
+    }
+
+    if arguments.radius.len() > 1 && arguments.points.len() != arguments.radius.len() {
+        return Err(CommandlineParsingErrors::NumberOfPointsAndRadius(
+            "Give either one default radius or one radius per point",
+        ));
+    }
+
+    if arguments.radius.is_empty() && arguments.points.iter().any(|point| !point.contains(':')) {
         return Err(CommandlineParsingErrors::NumberOfPointsAndRadius(
-            "Number of points must equal number of radius-es",
+            "Points without an inline radius ('x,y:r') need a default radius (-r)",
         ));
     }
 
@@ -227,20 +1786,312 @@ pub fn read_config_from_cli() -> Result<Config, CommandlineParsingErrors> {
         ));
     }
 
-    match fs::exists(&arguments.destination_folder) {
-        Ok(val) => {
-            if !val {
-                return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
-                    "Destination folder does not exist",
-                ));
-            }
+    if is_not_positive(arguments.z_scale) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Vertical scale factor must be positive",
+        ));
+    }
+
+    if let (Some(clamp_min), Some(clamp_max)) = (arguments.clamp_min, arguments.clamp_max) {
+        if clamp_min >= clamp_max {
+            return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+                "Clamp minimum must be lower than clamp maximum",
+            ));
         }
-        Err(_) => {
+    }
+
+    if let Some(gsd) = arguments.gsd {
+        let resolution = TILE_SIZE_M / gsd;
+
+        if is_not_positive(gsd) || resolution.round() < 1.0 || resolution.round() > u16::MAX as f64
+        {
             return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
-                "Issue with the destination folder",
+                "Ground sample distance must yield between 1 and 65535 pixels per tile",
             ));
         }
     }
 
-    Config::try_from(&arguments)
+    if is_negative(arguments.seam_blend_m) || arguments.seam_blend_m > TILE_SIZE_M / 2.0 {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Seam blend width must be between 0 and half a tile",
+        ));
+    }
+
+    if arguments.max_resolution == 0 {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Maximum resolution must be positive",
+        ));
+    }
+
+    if matches!(arguments.resolution, ResolutionSetting::Auto)
+        && arguments.gsd.is_none()
+        && (arguments.chunk_size.is_some()
+            || arguments.chunk_size_m.is_some()
+            || arguments.zarr
+            || arguments.layout == TileLayout::Zxy)
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Adaptive resolution can not be combined with chunked, Zarr or z/x/y outputs",
+        ));
+    }
+
+    if arguments.chunk_size == Some(0) || arguments.chunk_size_m.is_some_and(is_not_positive) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Chunk sizes must be positive",
+        ));
+    }
+
+    if arguments
+        .max_neighbour_distance_m
+        .is_some_and(is_not_positive)
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Maximum neighbour distance must be positive",
+        ));
+    }
+
+    if is_not_positive(arguments.fallback_dem_resolution) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Fallback DEM resolution must be positive",
+        ));
+    }
+
+    if arguments.ao_depth != 8 && arguments.ao_depth != 16 {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Ambient occlusion bit depth must be 8 or 16",
+        ));
+    }
+
+    if is_not_positive(arguments.ao_radius_m)
+        || is_not_positive(arguments.svf_radius_m)
+        || is_not_positive(arguments.lrm_radius_m)
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Horizon search radii must be positive",
+        ));
+    }
+
+    if is_negative(arguments.low_density) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Low density threshold can not be negative",
+        ));
+    }
+
+    if !arguments.mirrors.is_empty() && arguments.source.split(':').next() != Some("arso") {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Mirrors can only be used with an arso: source",
+        ));
+    }
+
+    if arguments.min_density.is_some_and(is_not_positive) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Minimum density must be positive",
+        ));
+    }
+
+    if arguments.connect_timeout == 0 || arguments.request_timeout == 0 {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Connect and request timeouts must be at least one second",
+        ));
+    }
+
+    if !storage::is_object_storage(&arguments.destination_folder) {
+        check_folder_exists(&arguments.destination_folder)?;
+    } else if arguments.resume || arguments.update {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Resuming and updating require a local destination folder",
+        ));
+    }
+
+    if arguments.source_crs.is_geographic() {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "The source CRS must be projected, the tile grid is laid out in meters",
+        ));
+    }
+
+    if arguments
+        .target_crs
+        .is_some_and(|target_crs| target_crs != arguments.source_crs)
+        && (arguments.chunk_size.is_some() || arguments.chunk_size_m.is_some() || arguments.zarr)
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Chunked and Zarr outputs are written in the source CRS, drop --target-crs",
+        ));
+    }
+
+    if arguments.blur_kernel_size.len() > 1
+        && (arguments.chunk_size.is_some() || arguments.chunk_size_m.is_some())
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Several blur levels can not be combined with chunked outputs",
+        ));
+    }
+
+    if arguments.update
+        && (arguments.chunk_size.is_some()
+            || arguments.chunk_size_m.is_some()
+            || arguments.zarr
+            || arguments.layout == TileLayout::Zxy)
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Chunked, Zarr and z/x/y outputs can not be updated incrementally",
+        ));
+    }
+
+    // Each shard only sees its own tiles, outputs spanning the whole area are built after merging
+    if arguments.shard.is_some()
+        && (arguments.chunk_size.is_some()
+            || arguments.chunk_size_m.is_some()
+            || arguments.zarr
+            || arguments.layout == TileLayout::Zxy
+            || arguments.equalized
+            || arguments.preview
+            || arguments.footprints
+            || arguments.pdal_pipeline
+            || arguments.engine_bundle.is_some())
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Shards can not write outputs covering the whole area, build them from the merged shards",
+        ));
+    }
+
+    if arguments.height_scale.is_some_and(is_not_positive) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Height scale must be positive",
+        ));
+    }
+
+    if matches!(arguments.max_cpu_percent, Some(percent) if percent == 0 || percent > 100) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "CPU percentage must be between 1 and 100",
+        ));
+    }
+
+    if arguments.max_in_flight_tiles == Some(0) {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Tiles in flight must be positive",
+        ));
+    }
+
+    // Streamed tiles are computed and dropped one by one, outputs needing all of them at once
+    // can not be written
+    if arguments.max_in_flight_tiles.is_some()
+        && (arguments.chunk_size.is_some()
+            || arguments.chunk_size_m.is_some()
+            || arguments.zarr
+            || arguments.layout == TileLayout::Zxy
+            || arguments.equalized
+            || arguments.normalize_percentiles.is_some()
+            || arguments.export_points.is_some()
+            || arguments.pdal_pipeline
+            || arguments.footprints
+            || arguments.min_density.is_some()
+            || arguments.auto_tune)
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Tiles in flight can not be combined with outputs that need all tiles at once",
+        ));
+    }
+
+    if arguments.max_in_flight_tiles.is_some()
+        && !arguments.resume
+        && !arguments.update
+        && (arguments.clamp_min.is_none() || arguments.clamp_max.is_none())
+    {
+        return Err(CommandlineParsingErrors::IncorrectArgumentStructure(
+            "Tiles in flight need --clamp-min and --clamp-max to fix the height range",
+        ));
+    }
+
+    Config::try_from(arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shards_partition_the_tiles() {
+        let tiles = (-20..20)
+            .flat_map(|y| (-20..20).map(move |x| Point(x, y)))
+            .collect::<Vec<Point>>();
+
+        for count in 1..=5 {
+            let mut sizes = vec![0; count as usize];
+
+            for tile in tiles.iter() {
+                let shards = (1..=count)
+                    .filter(|index| {
+                        Shard {
+                            index: *index,
+                            count,
+                        }
+                        .contains(tile)
+                    })
+                    .collect::<Vec<u32>>();
+
+                assert_eq!(shards.len(), 1, "{:?} is in shards {:?}", tile, shards);
+                sizes[shards[0] as usize - 1] += 1;
+            }
+
+            // Every shard gets a share of the area
+            assert!(sizes.iter().all(|size| *size > 0), "{:?}", sizes);
+        }
+    }
+
+    #[test]
+    fn byte_sizes_parse_their_units() {
+        assert_eq!(parse_byte_size("512"), Ok(512));
+        assert_eq!(parse_byte_size("512B"), Ok(512));
+        assert_eq!(parse_byte_size("4k"), Ok(4 << 10));
+        assert_eq!(parse_byte_size("1.5MB"), Ok(3 << 19));
+        assert_eq!(parse_byte_size(" 2 gb "), Ok(2 << 30));
+        assert_eq!(parse_byte_size("3T"), Ok(3 << 40));
+        assert!(parse_byte_size("1X").is_err());
+        assert!(parse_byte_size("-1G").is_err());
+        assert!(parse_byte_size("G").is_err());
+    }
+
+    #[test]
+    fn byte_sizes_past_u64_are_rejected() {
+        assert_eq!(parse_byte_size("16777215T"), Ok(16777215 << 40));
+        assert!(parse_byte_size("16777216T").is_err());
+        assert!(parse_byte_size("99999999999999999999").is_err());
+    }
+
+    fn create_polygon(vertices: &[(i32, i32)]) -> TilePolygon {
+        TilePolygon {
+            vertices: vertices.iter().map(|(x, y)| Point(*x, *y)).collect(),
+        }
+    }
+
+    #[test]
+    fn polygon_contains_its_lower_and_left_edges() {
+        let square = create_polygon(&[(0, 0), (4, 0), (4, 4), (0, 4)]);
+
+        assert!(square.contains(0.0, 2.0));
+        assert!(square.contains(2.0, 0.0));
+        assert!(square.contains(0.0, 0.0));
+        assert!(!square.contains(4.0, 2.0));
+        assert!(!square.contains(2.0, 4.0));
+        assert!(!square.contains(4.0, 4.0));
+    }
+
+    #[test]
+    fn polygons_sharing_an_edge_split_its_tiles() {
+        let square = create_polygon(&[(0, 0), (4, 0), (4, 4), (0, 4)]);
+        // The tile centers on the diagonal lie on the shared edge of both triangles
+        let lower = create_polygon(&[(0, 0), (4, 0), (4, 4)]);
+        let upper = create_polygon(&[(0, 0), (4, 4), (0, 4)]);
+
+        let mut halves = lower.get_all_points_in_area();
+        halves.extend(upper.get_all_points_in_area());
+        halves.sort();
+
+        let mut tiles = square.get_all_points_in_area();
+        tiles.sort();
+
+        assert_eq!(tiles.len(), 16);
+        assert_eq!(halves, tiles);
+    }
 }
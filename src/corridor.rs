@@ -0,0 +1,256 @@
+use std::fs;
+
+use serde_json::Value;
+
+use crate::{core::Point, global_constants::TILE_SIZE_M, projection::Crs, writer::InputError};
+
+// Tiles within half the corridor width of any track read from a GPX or GeoJSON file. Both
+// formats carry WGS84 coordinates, which are projected onto the tile grid of the source CRS.
+pub fn get_route_points(
+    path: &str,
+    corridor_width_m: f64,
+    crs: Crs,
+) -> Result<Vec<Point>, InputError> {
+    let lines = read_lines(path)?
+        .into_iter()
+        .map(|line| {
+            line.into_iter()
+                .map(|(lat, lon)| crs.from_wgs84(lat, lon))
+                .collect::<Vec<(f64, f64)>>()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<Vec<(f64, f64)>>>();
+
+    if lines.is_empty() {
+        return Err(InputError::Format(format!(
+            "No track found in route file {}",
+            path
+        )));
+    }
+
+    let half_width = corridor_width_m / 2.0;
+    let mut points = vec![];
+
+    for line in lines.iter() {
+        let segments = line
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .chain(if line.len() == 1 {
+                Some((line[0], line[0]))
+            } else {
+                None
+            });
+
+        for (start, end) in segments {
+            let min_x = ((start.0.min(end.0) - half_width) / TILE_SIZE_M).floor() as i32;
+            let max_x = ((start.0.max(end.0) + half_width) / TILE_SIZE_M).floor() as i32;
+            let min_y = ((start.1.min(end.1) - half_width) / TILE_SIZE_M).floor() as i32;
+            let max_y = ((start.1.max(end.1) + half_width) / TILE_SIZE_M).floor() as i32;
+
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    let tile_min = (x as f64 * TILE_SIZE_M, y as f64 * TILE_SIZE_M);
+                    let tile_max = (tile_min.0 + TILE_SIZE_M, tile_min.1 + TILE_SIZE_M);
+
+                    if get_segment_tile_distance(start, end, tile_min, tile_max) <= half_width {
+                        points.push(Point(x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    points.sort();
+    points.dedup();
+
+    Ok(points)
+}
+
+// WGS84 latitude, longitude lines of a GPX or GeoJSON file
+pub fn read_lines(path: &str) -> Result<Vec<Vec<(f64, f64)>>, InputError> {
+    let content = fs::read_to_string(path)?;
+
+    if path.to_lowercase().ends_with(".gpx") {
+        Ok(read_gpx(&content))
+    } else {
+        read_geojson(&content)
+    }
+}
+
+// Track points of all <trkpt> and <rtept> elements, one line per track segment or route
+fn read_gpx(content: &str) -> Vec<Vec<(f64, f64)>> {
+    let mut lines = vec![];
+
+    for (line_tag, point_tag) in [("trkseg", "trkpt"), ("rte", "rtept")] {
+        for (start, _) in get_start_tags(content, line_tag) {
+            let end = content[start..]
+                .find(&format!("</{}>", line_tag))
+                .map(|end| start + end)
+                .unwrap_or(content.len());
+
+            let line = get_start_tags(&content[start..end], point_tag)
+                .into_iter()
+                .filter_map(|(_, element)| {
+                    Some((
+                        get_xml_attribute(element, "lat")?,
+                        get_xml_attribute(element, "lon")?,
+                    ))
+                })
+                .collect::<Vec<(f64, f64)>>();
+
+            lines.push(line);
+        }
+    }
+
+    lines
+}
+
+// Positions and attribute text of all start tags with exactly the given name
+fn get_start_tags<'a>(content: &'a str, tag: &str) -> Vec<(usize, &'a str)> {
+    let pattern = format!("<{}", tag);
+
+    content
+        .match_indices(&pattern)
+        .filter_map(|(index, _)| {
+            let rest = &content[index + pattern.len()..];
+
+            if !rest.starts_with(|ch: char| ch == '>' || ch == '/' || ch.is_whitespace()) {
+                return None;
+            }
+
+            Some((index, &rest[..rest.find('>').unwrap_or(rest.len())]))
+        })
+        .collect()
+}
+
+fn get_xml_attribute(element: &str, name: &str) -> Option<f64> {
+    for quote in ['"', '\''] {
+        let pattern = format!("{}={}", name, quote);
+
+        if let Some(start) = element
+            .match_indices(&pattern)
+            .map(|(index, _)| index)
+            .find(|index| *index == 0 || element[..*index].ends_with(char::is_whitespace))
+        {
+            let value = &element[start + pattern.len()..];
+            return value[..value.find(quote)?].trim().parse().ok();
+        }
+    }
+
+    None
+}
+
+// LineString and MultiLineString geometries anywhere in the document
+fn read_geojson(content: &str) -> Result<Vec<Vec<(f64, f64)>>, InputError> {
+    let document: Value = serde_json::from_str(content)?;
+    let mut lines = vec![];
+
+    collect_geojson_lines(&document, &mut lines);
+
+    Ok(lines)
+}
+
+fn collect_geojson_lines(value: &Value, lines: &mut Vec<Vec<(f64, f64)>>) {
+    let to_line = |coordinates: &Value| {
+        coordinates
+            .as_array()
+            .map(|positions| {
+                positions
+                    .iter()
+                    .filter_map(|position| {
+                        // GeoJSON positions are ordered longitude, latitude
+                        Some((position.get(1)?.as_f64()?, position.get(0)?.as_f64()?))
+                    })
+                    .collect::<Vec<(f64, f64)>>()
+            })
+            .unwrap_or_default()
+    };
+
+    match value.get("type").and_then(Value::as_str) {
+        Some("LineString") => lines.push(to_line(&value["coordinates"])),
+        Some("MultiLineString") => {
+            if let Some(parts) = value["coordinates"].as_array() {
+                lines.extend(parts.iter().map(to_line));
+            }
+        }
+        Some("Feature") => collect_geojson_lines(&value["geometry"], lines),
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                collect_geojson_lines(feature, lines);
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                collect_geojson_lines(geometry, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn get_segment_tile_distance(
+    start: (f64, f64),
+    end: (f64, f64),
+    tile_min: (f64, f64),
+    tile_max: (f64, f64),
+) -> f64 {
+    let inside = |point: (f64, f64)| {
+        point.0 >= tile_min.0
+            && point.0 <= tile_max.0
+            && point.1 >= tile_min.1
+            && point.1 <= tile_max.1
+    };
+
+    if inside(start) || inside(end) {
+        return 0.0;
+    }
+
+    let corners = [
+        tile_min,
+        (tile_max.0, tile_min.1),
+        tile_max,
+        (tile_min.0, tile_max.1),
+    ];
+
+    let mut distance = f64::MAX;
+
+    for index in 0..4 {
+        let (corner_a, corner_b) = (corners[index], corners[(index + 1) % 4]);
+
+        if segments_intersect(start, end, corner_a, corner_b) {
+            return 0.0;
+        }
+
+        distance = distance
+            .min(get_point_segment_distance(corner_a, start, end))
+            .min(get_point_segment_distance(start, corner_a, corner_b))
+            .min(get_point_segment_distance(end, corner_a, corner_b));
+    }
+
+    distance
+}
+
+fn get_point_segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (delta_x, delta_y) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = delta_x * delta_x + delta_y * delta_y;
+
+    let t = if length_squared > 0.0 {
+        (((point.0 - start.0) * delta_x + (point.1 - start.1) * delta_y) / length_squared)
+            .clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    ((start.0 + t * delta_x - point.0).powi(2) + (start.1 + t * delta_y - point.1).powi(2)).sqrt()
+}
+
+fn segments_intersect(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    let orientation = |p: (f64, f64), q: (f64, f64), r: (f64, f64)| {
+        (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)
+    };
+
+    let (o1, o2) = (orientation(a, b, c), orientation(a, b, d));
+    let (o3, o4) = (orientation(c, d, a), orientation(c, d, b));
+
+    o1 * o2 < 0.0 && o3 * o4 < 0.0
+}
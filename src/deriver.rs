@@ -0,0 +1,446 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    computer::{self, TileIndex},
+    core::Config,
+    error::TerrainError,
+    requester::LazData,
+};
+
+const EQUALIZATION_BINS: usize = 4096;
+// ASPRS class of ground returns, used for the ground mask
+const GROUND_CLASS: u8 = 2;
+// Directions searched for the horizon around every pixel by the horizon based maps
+const HORIZON_DIRECTIONS: usize = 16;
+// Local relief in meters that maps to black and white in texture channels
+const LRM_DISPLAY_RANGE_M: f32 = 2.0;
+
+// Cumulative distribution of normalized heights over the whole area. Building it once from all
+// tiles keeps the equalized previews of neighbouring tiles consistent with each other.
+pub struct EqualizationTable {
+    cdf: Vec<f32>,
+}
+
+impl EqualizationTable {
+    pub fn from_heights(heights: impl Iterator<Item = f32>) -> Self {
+        let mut histogram = vec![0u64; EQUALIZATION_BINS];
+
+        for height in heights {
+            histogram[to_bin(height)] += 1;
+        }
+
+        let total = histogram.iter().sum::<u64>().max(1) as f32;
+        let mut cumulative = 0u64;
+        let cdf = histogram
+            .iter()
+            .map(|count| {
+                cumulative += count;
+                cumulative as f32 / total
+            })
+            .collect();
+
+        EqualizationTable { cdf }
+    }
+
+    pub fn apply(&self, heights: &[f32]) -> Vec<f32> {
+        heights
+            .iter()
+            .map(|height| self.cdf[to_bin(*height)])
+            .collect()
+    }
+}
+
+fn to_bin(height: f32) -> usize {
+    ((height.clamp(0.0, 1.0) * (EQUALIZATION_BINS - 1) as f32).round()) as usize
+}
+
+// Unit surface normals of a height raster as separate x (east), y (north) and z channels. Heights
+// are denormalized first so the slopes are true to scale; border pixels use one-sided differences.
+pub fn compute_normals(
+    heights: &[f32],
+    dim: (usize, usize),
+    pixel_size: (f64, f64),
+    height_range: (f64, f64),
+) -> [Vec<f32>; 3] {
+    let (dim_x, dim_y) = dim;
+    let height_at = |column: usize, row: usize| {
+        heights[row * dim_x + column] as f64 * (height_range.1 - height_range.0)
+    };
+
+    let mut normals = [
+        vec![0f32; dim_x * dim_y],
+        vec![0f32; dim_x * dim_y],
+        vec![0f32; dim_x * dim_y],
+    ];
+
+    for row in 0..dim_y {
+        for column in 0..dim_x {
+            let (west, east) = (column.saturating_sub(1), (column + 1).min(dim_x - 1));
+            let (north, south) = (row.saturating_sub(1), (row + 1).min(dim_y - 1));
+
+            // Row 0 lies on the northern edge, so north is towards lower rows
+            let gradient_x = (height_at(east, row) - height_at(west, row))
+                / ((east - west).max(1) as f64 * pixel_size.0);
+            let gradient_y = (height_at(column, north) - height_at(column, south))
+                / ((south - north).max(1) as f64 * pixel_size.1);
+
+            let length = (gradient_x * gradient_x + gradient_y * gradient_y + 1.0).sqrt();
+            let index = row * dim_x + column;
+
+            normals[0][index] = (-gradient_x / length) as f32;
+            normals[1][index] = (-gradient_y / length) as f32;
+            normals[2][index] = (1.0 / length) as f32;
+        }
+    }
+
+    normals
+}
+
+// Scans the elevation angle of the horizon in every direction around each pixel, marching up to
+// the radius with a stride that grows with distance, and reduces the angles to one value per
+// pixel. An inverted scan looks at the surface from below, as negative openness requires.
+fn scan_horizons(
+    heights: &[f32],
+    dim: (usize, usize),
+    pixel_size: (f64, f64),
+    height_range: (f64, f64),
+    radius_m: f64,
+    inverted: bool,
+    reduce: impl Fn(&[f64]) -> f64,
+) -> Vec<f32> {
+    let (dim_x, dim_y) = dim;
+    let sign = if inverted { -1.0 } else { 1.0 };
+    let height_at = |column: usize, row: usize| {
+        sign * heights[row * dim_x + column] as f64 * (height_range.1 - height_range.0)
+    };
+    let directions = (0..HORIZON_DIRECTIONS)
+        .map(|index| {
+            let angle = index as f64 * std::f64::consts::TAU / HORIZON_DIRECTIONS as f64;
+            (angle.cos(), angle.sin())
+        })
+        .collect::<Vec<(f64, f64)>>();
+    let pixel_step = pixel_size.0.min(pixel_size.1);
+    let mut angles = [0f64; HORIZON_DIRECTIONS];
+
+    (0..dim_x * dim_y)
+        .map(|index| {
+            let (column, row) = (index % dim_x, index / dim_x);
+            let height = height_at(column, row);
+
+            for (angle, (direction_x, direction_y)) in angles.iter_mut().zip(directions.iter()) {
+                let mut horizon = f64::NEG_INFINITY;
+                let mut distance = pixel_step;
+
+                while distance <= radius_m {
+                    let sample_column = column as f64 + direction_x * distance / pixel_size.0;
+                    let sample_row = row as f64 - direction_y * distance / pixel_size.1;
+
+                    if sample_column < 0.0
+                        || sample_row < 0.0
+                        || sample_column > (dim_x - 1) as f64
+                        || sample_row > (dim_y - 1) as f64
+                    {
+                        break;
+                    }
+
+                    let rise =
+                        height_at(sample_column.round() as usize, sample_row.round() as usize)
+                            - height;
+                    horizon = horizon.max(rise.atan2(distance));
+                    distance += pixel_step * (1.0 + distance / (8.0 * pixel_step)).floor();
+                }
+
+                // Directions leaving the tile right away see a flat horizon
+                *angle = if horizon.is_finite() { horizon } else { 0.0 };
+            }
+
+            reduce(&angles) as f32
+        })
+        .collect()
+}
+
+// Cosine-weighted ambient occlusion: the sky below a horizon at angle g blocks sin²(g) of the
+// irradiance arriving from that direction
+pub fn compute_ambient_occlusion(
+    heights: &[f32],
+    dim: (usize, usize),
+    pixel_size: (f64, f64),
+    height_range: (f64, f64),
+    radius_m: f64,
+) -> Vec<f32> {
+    scan_horizons(
+        heights,
+        dim,
+        pixel_size,
+        height_range,
+        radius_m,
+        false,
+        |angles| {
+            1.0 - angles
+                .iter()
+                .map(|angle| angle.max(0.0).sin().powi(2))
+                .sum::<f64>()
+                / angles.len() as f64
+        },
+    )
+}
+
+// Sky-view factor after Zakšek et al. (2011), the visible part of the sky hemisphere in 0..1
+pub fn compute_sky_view_factor(
+    heights: &[f32],
+    dim: (usize, usize),
+    pixel_size: (f64, f64),
+    height_range: (f64, f64),
+    radius_m: f64,
+) -> Vec<f32> {
+    scan_horizons(
+        heights,
+        dim,
+        pixel_size,
+        height_range,
+        radius_m,
+        false,
+        |angles| {
+            1.0 - angles.iter().map(|angle| angle.max(0.0).sin()).sum::<f64>() / angles.len() as f64
+        },
+    )
+}
+
+// Topographic openness after Yokoyama et al. (2002), the mean zenith angle of the horizon in
+// degrees. Negative openness is the same measure taken on the inverted surface.
+pub fn compute_openness(
+    heights: &[f32],
+    dim: (usize, usize),
+    pixel_size: (f64, f64),
+    height_range: (f64, f64),
+    radius_m: f64,
+    negative: bool,
+) -> Vec<f32> {
+    scan_horizons(
+        heights,
+        dim,
+        pixel_size,
+        height_range,
+        radius_m,
+        negative,
+        |angles| {
+            angles
+                .iter()
+                .map(|angle| 90.0 - angle.to_degrees())
+                .sum::<f64>()
+                / angles.len() as f64
+        },
+    )
+}
+
+// Rasters that can be derived for a tile besides the height itself
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum DerivedMap {
+    Height,
+    NormalX,
+    NormalY,
+    NormalZ,
+    Slope,
+    Intensity,
+    Mask,
+    Ao,
+    SkyView,
+    OpennessPositive,
+    OpennessNegative,
+    LocalRelief,
+    Zero,
+    One,
+}
+
+impl FromStr for DerivedMap {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "height" => Ok(DerivedMap::Height),
+            "normal_x" => Ok(DerivedMap::NormalX),
+            "normal_y" => Ok(DerivedMap::NormalY),
+            "normal_z" => Ok(DerivedMap::NormalZ),
+            "slope" => Ok(DerivedMap::Slope),
+            "intensity" => Ok(DerivedMap::Intensity),
+            "mask" => Ok(DerivedMap::Mask),
+            "ao" => Ok(DerivedMap::Ao),
+            "svf" => Ok(DerivedMap::SkyView),
+            "openness_pos" => Ok(DerivedMap::OpennessPositive),
+            "openness_neg" => Ok(DerivedMap::OpennessNegative),
+            "lrm" => Ok(DerivedMap::LocalRelief),
+            "zero" => Ok(DerivedMap::Zero),
+            "one" => Ok(DerivedMap::One),
+            _ => Err(format!("Unknown map '{}'", value)),
+        }
+    }
+}
+
+impl DerivedMap {
+    pub fn needs_point_attributes(&self) -> bool {
+        matches!(self, DerivedMap::Intensity | DerivedMap::Mask)
+    }
+
+    // Maps a raw value into 0..1 for storage in a texture channel
+    pub fn to_unit(&self, value: f32) -> f32 {
+        match self {
+            DerivedMap::NormalX | DerivedMap::NormalY | DerivedMap::NormalZ => value * 0.5 + 0.5,
+            DerivedMap::Slope => value / 90.0,
+            DerivedMap::Intensity => value / u16::MAX as f32,
+            DerivedMap::OpennessPositive | DerivedMap::OpennessNegative => value / 180.0,
+            DerivedMap::LocalRelief => value / (2.0 * LRM_DISPLAY_RANGE_M) + 0.5,
+            _ => value,
+        }
+    }
+}
+
+// Which map goes into the R, G, B and A channel of a packed texture
+#[derive(Clone, Debug)]
+pub struct PackingRecipe {
+    pub name: String,
+    pub channels: [DerivedMap; 4],
+}
+
+// Derived rasters of one tile, computed on first use and shared by every output that needs them
+pub struct TileMaps<'a> {
+    config: &'a Config,
+    data: &'a LazData,
+    index: &'a TileIndex,
+    heights: &'a [f32],
+    dim: (usize, usize),
+    pixel_size: (f64, f64),
+    height_range: (f64, f64),
+    maps: HashMap<DerivedMap, Vec<f32>>,
+}
+
+impl<'a> TileMaps<'a> {
+    pub fn new(
+        config: &'a Config,
+        data: &'a LazData,
+        index: &'a TileIndex,
+        heights: &'a [f32],
+        dim: (usize, usize),
+        pixel_size: (f64, f64),
+        height_range: (f64, f64),
+    ) -> Self {
+        TileMaps {
+            config,
+            data,
+            index,
+            heights,
+            dim,
+            pixel_size,
+            height_range,
+            maps: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, map: DerivedMap) -> Result<&[f32], TerrainError> {
+        if !self.maps.contains_key(&map) {
+            let values = self.compute(map)?;
+            self.maps.insert(map, values);
+        }
+
+        Ok(&self.maps[&map])
+    }
+
+    fn compute(&mut self, map: DerivedMap) -> Result<Vec<f32>, TerrainError> {
+        let size = self.dim.0 * self.dim.1;
+
+        let values = match map {
+            DerivedMap::Height => self.heights.to_vec(),
+            DerivedMap::NormalX | DerivedMap::NormalY | DerivedMap::NormalZ => {
+                let [normal_x, normal_y, normal_z] =
+                    compute_normals(self.heights, self.dim, self.pixel_size, self.height_range);
+
+                // All three components come out of one pass, so the other two are kept as well
+                let mut requested = vec![];
+                for (component, values) in [
+                    (DerivedMap::NormalX, normal_x),
+                    (DerivedMap::NormalY, normal_y),
+                    (DerivedMap::NormalZ, normal_z),
+                ] {
+                    if component == map {
+                        requested = values;
+                    } else {
+                        self.maps.insert(component, values);
+                    }
+                }
+
+                requested
+            }
+            DerivedMap::Slope => self
+                .get(DerivedMap::NormalZ)?
+                .iter()
+                .map(|normal_z| normal_z.clamp(-1.0, 1.0).acos().to_degrees())
+                .collect(),
+            DerivedMap::Intensity => {
+                let intensities = self
+                    .data
+                    .attributes
+                    .intensities
+                    .iter()
+                    .map(|intensity| *intensity as f32)
+                    .collect::<Vec<f32>>();
+
+                self.index
+                    .interpolate(self.config.sample_size as usize, &intensities)
+            }
+            DerivedMap::Mask => {
+                let ground = self
+                    .data
+                    .attributes
+                    .classes
+                    .iter()
+                    .map(|class| if *class == GROUND_CLASS { 1.0 } else { 0.0 })
+                    .collect::<Vec<f32>>();
+
+                self.index
+                    .interpolate(self.config.sample_size as usize, &ground)
+            }
+            DerivedMap::Ao => compute_ambient_occlusion(
+                self.heights,
+                self.dim,
+                self.pixel_size,
+                self.height_range,
+                self.config.ao_radius_m,
+            ),
+            DerivedMap::SkyView => compute_sky_view_factor(
+                self.heights,
+                self.dim,
+                self.pixel_size,
+                self.height_range,
+                self.config.svf_radius_m,
+            ),
+            DerivedMap::OpennessPositive | DerivedMap::OpennessNegative => compute_openness(
+                self.heights,
+                self.dim,
+                self.pixel_size,
+                self.height_range,
+                self.config.svf_radius_m,
+                map == DerivedMap::OpennessNegative,
+            ),
+            DerivedMap::LocalRelief => {
+                // Radius in pixels of the low-pass that the surface is compared against
+                let radius = (self.config.lrm_radius_m / self.pixel_size.0.min(self.pixel_size.1))
+                    .round()
+                    .max(1.0) as u32;
+                let mut smoothed = self.heights.to_vec();
+                computer::blur_image(radius, self.dim.0, self.dim.1, &mut smoothed)?;
+
+                self.heights
+                    .iter()
+                    .zip(smoothed)
+                    .map(|(height, smoothed)| {
+                        ((*height - smoothed) as f64 * (self.height_range.1 - self.height_range.0))
+                            as f32
+                    })
+                    .collect()
+            }
+            DerivedMap::Zero => vec![0.0; size],
+            DerivedMap::One => vec![1.0; size],
+        };
+
+        Ok(values)
+    }
+}
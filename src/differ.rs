@@ -0,0 +1,176 @@
+use std::{collections::BTreeSet, io::Cursor};
+
+use exr::prelude::{LayerAttributes, WritableImage};
+use serde::Serialize;
+
+use crate::{
+    computer::{self, ComputeConfig},
+    error::TerrainError,
+    merger, storage, writer,
+};
+
+#[derive(Serialize)]
+struct TileDiff {
+    tile: String,
+    rmse: f64,
+    max_delta: f64,
+}
+
+#[derive(Serialize)]
+struct DiffSummary {
+    rmse: f64,
+    max_delta: f64,
+    tiles: Vec<TileDiff>,
+    only_in_a: Vec<String>,
+    only_in_b: Vec<String>,
+}
+
+pub fn diff_terrains(
+    dir_a: &str,
+    dir_b: &str,
+    destination_folder: &str,
+) -> Result<(), TerrainError> {
+    let config_a = computer::read_compute_config(dir_a)?;
+    let config_b = computer::read_compute_config(dir_b)?;
+
+    let tiles_a = list_tiles(dir_a, &config_a);
+    let tiles_b = list_tiles(dir_b, &config_b);
+
+    let mut tile_diffs = vec![];
+    let (mut squared_sum, mut sample_count, mut max_delta) = (0f64, 0usize, 0f64);
+
+    for tile in tiles_a.intersection(&tiles_b) {
+        let (dim_x, dim_y, buffer_a) = writer::read_texture(storage::get_local_path(dir_a, tile))
+            .map_err(TerrainError::Input)?;
+        let (dim_x_b, dim_y_b, buffer_b) =
+            writer::read_texture(storage::get_local_path(dir_b, tile))
+                .map_err(TerrainError::Input)?;
+
+        if (dim_x, dim_y) != (dim_x_b, dim_y_b) {
+            println!(
+                "Tile {} has different resolutions ({}x{} vs {}x{}), skipping",
+                tile, dim_x, dim_y, dim_x_b, dim_y_b
+            );
+            continue;
+        }
+
+        let deltas = buffer_a
+            .iter()
+            .zip(buffer_b.iter())
+            .map(|(a, b)| denormalize(*b, &config_b) - denormalize(*a, &config_a))
+            .collect::<Vec<f64>>();
+
+        // Voids of either terrain stay NaN in the diff image but are left out of the statistics
+        let valid_deltas = deltas
+            .iter()
+            .filter(|delta| !delta.is_nan())
+            .collect::<Vec<&f64>>();
+        let tile_squared_sum: f64 = valid_deltas.iter().map(|delta| *delta * *delta).sum();
+        let tile_max_delta = valid_deltas
+            .iter()
+            .fold(0f64, |max, delta| max.max(delta.abs()));
+
+        squared_sum += tile_squared_sum;
+        sample_count += valid_deltas.len();
+        max_delta = max_delta.max(tile_max_delta);
+
+        let tile_diff = TileDiff {
+            tile: tile.clone(),
+            rmse: if valid_deltas.is_empty() {
+                0.0
+            } else {
+                (tile_squared_sum / valid_deltas.len() as f64).sqrt()
+            },
+            max_delta: tile_max_delta,
+        };
+
+        println!(
+            "Tile {}: rmse {:.3} m, max delta {:.3} m",
+            tile_diff.tile, tile_diff.rmse, tile_diff.max_delta
+        );
+
+        write_diff_image(destination_folder, tile, dim_x, dim_y, &deltas)?;
+        tile_diffs.push(tile_diff);
+    }
+
+    let summary = DiffSummary {
+        rmse: if sample_count > 0 {
+            (squared_sum / sample_count as f64).sqrt()
+        } else {
+            0.0
+        },
+        max_delta,
+        tiles: tile_diffs,
+        only_in_a: tiles_a.difference(&tiles_b).cloned().collect(),
+        only_in_b: tiles_b.difference(&tiles_a).cloned().collect(),
+    };
+
+    println!(
+        "Compared {} tiles: rmse {:.3} m, max delta {:.3} m",
+        summary.tiles.len(),
+        summary.rmse,
+        summary.max_delta
+    );
+    println!(
+        "Tiles only in {}: {}, tiles only in {}: {}",
+        dir_a,
+        summary.only_in_a.len(),
+        dir_b,
+        summary.only_in_b.len()
+    );
+
+    let json = serde_json::to_string_pretty(&summary)?;
+    storage::write_atomically(
+        &storage::get_local_path(destination_folder, "diff.json"),
+        json.as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+fn denormalize(value: f32, config: &ComputeConfig) -> f64 {
+    value as f64 * (config.max_height - config.min_height) + config.min_height
+}
+
+// EXR heightmaps of the tiles in config.json. Layered EXRs and other maps named after a tile are
+// not heightmaps and left out.
+fn list_tiles(folder: &str, config: &ComputeConfig) -> BTreeSet<String> {
+    config
+        .tiles
+        .iter()
+        .flat_map(|tile| merger::get_heightmap_stems(&config.parameters, &tile.file_stem))
+        .map(|file_stem| format!("{}.exr", file_stem))
+        .filter(|file_name| storage::get_local_path(folder, file_name).is_file())
+        .collect()
+}
+
+fn write_diff_image(
+    destination_folder: &str,
+    tile: &str,
+    dim_x: usize,
+    dim_y: usize,
+    deltas: &[f64],
+) -> Result<(), TerrainError> {
+    let buffer_f32 = deltas
+        .iter()
+        .map(|delta| *delta as f32)
+        .collect::<Vec<f32>>();
+
+    let image = writer::create_image(
+        dim_x,
+        dim_y,
+        &buffer_f32,
+        LayerAttributes::named("diff-rgb-layer"),
+    );
+    let mut bytes = vec![];
+    image
+        .write()
+        .to_buffered(Cursor::new(&mut bytes))
+        .map_err(|error| TerrainError::Output(error.into()))?;
+    storage::write_atomically(
+        &storage::get_local_path(destination_folder, &format!("diff_{}", tile)),
+        &bytes,
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::{error::Error, io};
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+
+use crate::core::Cli;
+
+// Ready to copy invocations of common scenarios, printed by the examples subcommand
+const EXAMPLES: [(&str, &str); 6] = [
+    (
+        "Single tile around a point as an EXR heightmap",
+        "las-terrain-generator -p 430,136 -r 0 --possible-blocks 21 35 -d out",
+    ),
+    (
+        "Square of tiles within a radius of two tiles, blurred twice as strongly",
+        "las-terrain-generator -p 430,136 -r 2 -b 20 --possible-blocks 21 35 -d out",
+    ),
+    (
+        "Bounding box of 4 by 3 tiles starting at its south-west corner",
+        "las-terrain-generator --rect 430,136,4,3 --possible-blocks 21 35 -d out",
+    ),
+    (
+        "Tiles from a local folder of LAZ files, without network access",
+        "las-terrain-generator -p 430,136 -r 1 --source file://./laz --offline \\\n    --possible-blocks 21 35 -d out",
+    ),
+    (
+        "Unreal Engine landscape import: 16 bit PNG and RAW at a landscape friendly size",
+        "las-terrain-generator -p 430,136 -r 1 --format png raw --resolution 1009 \\\n    --possible-blocks 21 35 -d out",
+    ),
+    (
+        "Run again with the cached downloads and only a different blur",
+        "las-terrain-generator -p 430,136 -r 1 -b 5 --cache-dir cache \\\n    --possible-blocks 21 35 -d out_sharp",
+    ),
+];
+
+pub fn print_examples() -> Result<(), Box<dyn Error + Send + Sync>> {
+    for (index, (description, invocation)) in EXAMPLES.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        println!("# {}", description);
+        println!("{}", invocation);
+    }
+
+    Ok(())
+}
+
+// Completion script of the shell written to stdout, e.g. to be sourced from the shell profile
+pub fn print_completions(shell: Shell) -> Result<(), Box<dyn Error + Send + Sync>> {
+    clap_complete::generate(
+        shell,
+        &mut Cli::command(),
+        env!("CARGO_PKG_NAME"),
+        &mut io::stdout(),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use std::io::Cursor;
+
+use tiff::{
+    ColorType,
+    decoder::{Decoder, DecodingResult},
+};
+
+use crate::{
+    error::TerrainError, packer::DecodeError, requester::LazData, storage::OutputSink,
+    writer::OutputError,
+};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Writes the orthophoto fetched with the tile as an RGB PNG on the pixel grid of the heightmap.
+// Tiles without an orthophoto or with one that does not decode are reported and skipped.
+pub fn drape_orthophoto(
+    sink: &dyn OutputSink,
+    data: &LazData,
+    file_stem: &str,
+    dim_x: usize,
+    dim_y: usize,
+) -> Result<(), TerrainError> {
+    let Some(image_bytes) = &data.orthophoto else {
+        return Ok(());
+    };
+
+    let (width, height, pixels) = match decode_rgb(image_bytes) {
+        Ok(decoded) => decoded,
+        Err(value) => {
+            println!("Err: {}", value);
+            println!(
+                "Decoding the orthophoto was not successful. Skipping point {}:{}",
+                data.tile.0, data.tile.1
+            );
+            return Ok(());
+        }
+    };
+
+    // The image is requested at the full resolution, adaptive tiles and services that ignore the
+    // requested size are resampled to the heightmap by nearest pixel
+    let resampled = (0..dim_x * dim_y)
+        .map(|index| {
+            let column = (index % dim_x) * width / dim_x;
+            let row = (index / dim_x) * height / dim_y;
+            pixels[row * width + column]
+        })
+        .collect::<Vec<[u8; 3]>>();
+
+    sink.put(
+        &format!("{}_ortho.png", file_stem),
+        &encode_rgb_png(dim_x, dim_y, &resampled).map_err(TerrainError::Output)?,
+    )
+    .map_err(TerrainError::Output)?;
+
+    Ok(())
+}
+
+// 8 bit PNG or TIFF in gray, gray with alpha, RGB or RGBA
+fn decode_rgb(bytes: &[u8]) -> Result<(usize, usize, Vec<[u8; 3]>), DecodeError> {
+    let (width, height, channels, samples) = if bytes.starts_with(&PNG_SIGNATURE) {
+        let mut decoder = png::Decoder::new(Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut samples = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut samples)?;
+        samples.truncate(info.buffer_size());
+
+        (
+            info.width as usize,
+            info.height as usize,
+            info.color_type.samples(),
+            samples,
+        )
+    } else {
+        let mut decoder = Decoder::new(Cursor::new(bytes))?;
+        let (width, height) = decoder.dimensions()?;
+        let channels = match decoder.colortype()? {
+            ColorType::Gray(8) => 1,
+            ColorType::GrayA(8) => 2,
+            ColorType::RGB(8) => 3,
+            ColorType::RGBA(8) => 4,
+            color_type => {
+                return Err(DecodeError::Malformed(format!(
+                    "Unsupported orthophoto color type {:?}",
+                    color_type
+                )));
+            }
+        };
+        let DecodingResult::U8(samples) = decoder.read_image()? else {
+            return Err(DecodeError::Malformed(
+                "Unsupported orthophoto sample format".to_string(),
+            ));
+        };
+
+        (width as usize, height as usize, channels, samples)
+    };
+
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|pixel| match channels {
+            1 | 2 => [pixel[0]; 3],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        })
+        .collect::<Vec<[u8; 3]>>();
+
+    if width == 0 || height == 0 || pixels.len() < width * height {
+        return Err(DecodeError::Malformed(
+            "The orthophoto is empty or truncated".to_string(),
+        ));
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn encode_rgb_png(dim_x: usize, dim_y: usize, pixels: &[[u8; 3]]) -> Result<Vec<u8>, OutputError> {
+    let mut png_bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut png_bytes, dim_x as u32, dim_y as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels.as_flattened())?;
+    writer.finish()?;
+
+    Ok(png_bytes)
+}
@@ -0,0 +1,135 @@
+use std::{io, process::ExitStatus};
+
+use thiserror::Error;
+
+use crate::{
+    core::CommandlineParsingErrors,
+    packer::DecodeError,
+    requester::BudgetExhausted,
+    writer::{InputError, OutputError},
+};
+
+// Failure classes of downloading and computing terrain. The classes that wrap a helper's error
+// keep it as their source, so callers can still tell a full disk from a rejected upload.
+#[derive(Debug, Error)]
+pub enum TerrainError {
+    #[error("Network request failed: {0}")]
+    Network(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    BudgetExhausted(#[from] BudgetExhausted),
+
+    #[error("Could not decode point data: {0}")]
+    LazDecode(#[source] DecodeError),
+
+    #[error("Interpolation failed: {0}")]
+    Interpolation(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("{url} responded with status {status}")]
+    HttpStatus { url: String, status: u16 },
+
+    // Settings that do not work together or with the chosen source
+    #[error("{0}")]
+    Settings(String),
+
+    #[error("Could not open the tile source: {0}")]
+    Source(#[source] StorageError),
+
+    // Tiles that have to be fetched but can not be, e.g. in offline mode
+    #[error("{0}")]
+    Unavailable(String),
+
+    #[error("{0}")]
+    TooSparse(String),
+
+    #[error("{0}")]
+    DiskSpace(String),
+
+    #[error("Could not read the input: {0}")]
+    Input(#[source] InputError),
+
+    #[error("Could not write the output: {0}")]
+    Output(#[source] OutputError),
+
+    #[error("Could not spill to disk: {0}")]
+    Spill(#[source] io::Error),
+
+    #[error("Hook failed: {0}")]
+    Hook(#[source] HookError),
+
+    #[error("Could not serve metrics on {address}: {source}")]
+    Metrics {
+        address: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Compute threads stopped early")]
+    Interrupted,
+}
+
+impl TerrainError {
+    // Whether a write failed because the volume is full
+    pub fn is_storage_full(&self) -> bool {
+        match self {
+            TerrainError::Io(error)
+            | TerrainError::Output(OutputError::Io(error))
+            | TerrainError::Spill(error) => error.kind() == io::ErrorKind::StorageFull,
+            _ => false,
+        }
+    }
+}
+
+impl From<las::Error> for TerrainError {
+    fn from(error: las::Error) -> Self {
+        TerrainError::LazDecode(error.into())
+    }
+}
+
+impl From<CommandlineParsingErrors> for TerrainError {
+    fn from(error: CommandlineParsingErrors) -> Self {
+        TerrainError::Settings(error.to_string())
+    }
+}
+
+impl From<clap::Error> for TerrainError {
+    fn from(error: clap::Error) -> Self {
+        TerrainError::Settings(error.to_string())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[cfg(feature = "object-storage")]
+    #[error(transparent)]
+    S3(#[from] s3::error::S3Error),
+
+    #[cfg(feature = "object-storage")]
+    #[error(transparent)]
+    Credentials(#[from] s3::creds::error::CredentialsError),
+
+    #[error("Object storage responded to {key} with status {status}")]
+    Status { key: String, status: u16 },
+
+    #[error("Object storage locations require the 'object-storage' feature")]
+    Unsupported,
+}
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error("Could not run hook '{command}': {source}")]
+    Spawn {
+        command: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Hook '{command}' failed with {status}")]
+    Failed { command: String, status: ExitStatus },
+}
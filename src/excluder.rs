@@ -0,0 +1,210 @@
+use std::fs;
+
+use serde_json::Value;
+
+use crate::projection::Crs;
+
+// Polygon in source CRS coordinates. The first ring is the outline and the others are holes, the
+// even-odd rule treats both the same way.
+#[derive(Clone, Debug)]
+pub struct Area {
+    rings: Vec<Vec<(f64, f64)>>,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    // Elevation property of the GeoJSON feature, in meters
+    pub elevation: Option<f64>,
+}
+
+impl Area {
+    fn new(rings: Vec<Vec<(f64, f64)>>, elevation: Option<f64>) -> Self {
+        let (mut bounds_min, mut bounds_max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+
+        for (x, y) in rings.iter().flatten() {
+            bounds_min = (bounds_min.0.min(*x), bounds_min.1.min(*y));
+            bounds_max = (bounds_max.0.max(*x), bounds_max.1.max(*y));
+        }
+
+        Area {
+            rings,
+            bounds_min,
+            bounds_max,
+            elevation,
+        }
+    }
+
+    pub fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        (self.bounds_min, self.bounds_max)
+    }
+
+    pub fn overlaps(&self, bounds_min: (f64, f64), bounds_max: (f64, f64)) -> bool {
+        self.bounds_min.0 <= bounds_max.0
+            && self.bounds_max.0 >= bounds_min.0
+            && self.bounds_min.1 <= bounds_max.1
+            && self.bounds_max.1 >= bounds_min.1
+    }
+
+    // Well-known text of the polygon with closed rings, as PDAL and GDAL take it
+    pub fn to_wkt(&self) -> String {
+        let rings = self
+            .rings
+            .iter()
+            .map(|ring| {
+                let vertices = ring
+                    .iter()
+                    .chain(ring.first().filter(|first| ring.last() != Some(*first)))
+                    .map(|(x, y)| format!("{} {}", x, y))
+                    .collect::<Vec<String>>();
+
+                format!("({})", vertices.join(", "))
+            })
+            .collect::<Vec<String>>();
+
+        format!("POLYGON ({})", rings.join(", "))
+    }
+
+    // Even-odd rule ray casting over all rings
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        if x < self.bounds_min.0
+            || x > self.bounds_max.0
+            || y < self.bounds_min.1
+            || y > self.bounds_max.1
+        {
+            return false;
+        }
+
+        let mut inside = false;
+
+        for ring in self.rings.iter() {
+            let mut previous = ring[ring.len() - 1];
+
+            for vertex in ring.iter() {
+                let (x_a, y_a) = *vertex;
+                let (x_b, y_b) = previous;
+
+                if (y_a > y) != (y_b > y) && x < (x_b - x_a) * (y - y_a) / (y_b - y_a) + x_a {
+                    inside = !inside;
+                }
+
+                previous = *vertex;
+            }
+        }
+
+        inside
+    }
+}
+
+// Polygon and MultiPolygon geometries of a GeoJSON file in WGS84, each with the elevation property
+// of its feature
+#[derive(Clone, Debug, Default)]
+pub struct Polygons {
+    polygons: Vec<(Vec<Vec<(f64, f64)>>, Option<f64>)>,
+}
+
+impl Polygons {
+    // Areas projected from WGS84 onto the source CRS
+    pub fn project(&self, crs: Crs) -> Vec<Area> {
+        self.polygons
+            .iter()
+            .map(|(rings, elevation)| {
+                let rings = rings
+                    .iter()
+                    .map(|ring| {
+                        ring.iter()
+                            .map(|(lat, lon)| crs.from_wgs84(*lat, *lon))
+                            .collect::<Vec<(f64, f64)>>()
+                    })
+                    .collect::<Vec<Vec<(f64, f64)>>>();
+
+                Area::new(rings, *elevation)
+            })
+            .collect()
+    }
+}
+
+pub fn parse_polygons(path: &str) -> Result<Polygons, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read polygons '{}': {}", path, error))?;
+    let document: Value = serde_json::from_str(&contents)
+        .map_err(|error| format!("Invalid GeoJSON '{}': {}", path, error))?;
+    let mut polygons = vec![];
+
+    collect_geojson_polygons(&document, None, &mut polygons);
+
+    let polygons = polygons
+        .into_iter()
+        .map(|(rings, elevation)| {
+            let rings = rings
+                .into_iter()
+                .filter(|ring| ring.len() >= 3)
+                .collect::<Vec<Vec<(f64, f64)>>>();
+
+            (rings, elevation)
+        })
+        .filter(|(rings, _)| !rings.is_empty())
+        .collect::<Vec<(Vec<Vec<(f64, f64)>>, Option<f64>)>>();
+
+    if polygons.is_empty() {
+        return Err(format!("No polygon found in '{}'", path));
+    }
+
+    Ok(Polygons { polygons })
+}
+
+// Whether a point falls into any of the areas, which should be prefiltered to the tile
+pub fn is_excluded(areas: &[&Area], x: f64, y: f64) -> bool {
+    areas.iter().any(|area| area.contains(x, y))
+}
+
+fn collect_geojson_polygons(
+    value: &Value,
+    elevation: Option<f64>,
+    polygons: &mut Vec<(Vec<Vec<(f64, f64)>>, Option<f64>)>,
+) {
+    let to_ring = |coordinates: &Value| {
+        coordinates
+            .as_array()
+            .map(|positions| {
+                positions
+                    .iter()
+                    .filter_map(|position| {
+                        // GeoJSON positions are ordered longitude, latitude
+                        Some((position.get(1)?.as_f64()?, position.get(0)?.as_f64()?))
+                    })
+                    .collect::<Vec<(f64, f64)>>()
+            })
+            .unwrap_or_default()
+    };
+    let to_polygon = |coordinates: &Value| {
+        let rings = coordinates
+            .as_array()
+            .map(|rings| rings.iter().map(to_ring).collect::<Vec<Vec<(f64, f64)>>>())
+            .unwrap_or_default();
+
+        (rings, elevation)
+    };
+
+    match value.get("type").and_then(Value::as_str) {
+        Some("Polygon") => polygons.push(to_polygon(&value["coordinates"])),
+        Some("MultiPolygon") => {
+            if let Some(parts) = value["coordinates"].as_array() {
+                polygons.extend(parts.iter().map(to_polygon));
+            }
+        }
+        Some("Feature") => collect_geojson_polygons(
+            &value["geometry"],
+            value["properties"]["elevation"].as_f64(),
+            polygons,
+        ),
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                collect_geojson_polygons(feature, elevation, polygons);
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                collect_geojson_polygons(geometry, elevation, polygons);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,55 @@
+use las::{Builder, Transform, Vector, Writer};
+
+use crate::{requester::LazData, writer::OutputError};
+
+const EXPORT_SCALE: f64 = 0.001;
+
+// Writes the points the surfaces were gridded from as one merged cloud. A .laz extension
+// compresses the output, anything else is written as plain LAS.
+pub fn export_points(path: &str, data: &[LazData]) -> Result<(), OutputError> {
+    let origin = data
+        .iter()
+        .fold((f64::MAX, f64::MAX, f64::MAX), |origin, tile| {
+            (
+                origin.0.min(tile.bounds_min.0),
+                origin.1.min(tile.bounds_min.1),
+                origin.2.min(tile.bounds_min.2),
+            )
+        });
+
+    // Offsets at the data minimum keep the scaled integer coordinates well within i32
+    let transform = |offset: f64| Transform {
+        scale: EXPORT_SCALE,
+        offset: if data.is_empty() { 0.0 } else { offset.floor() },
+    };
+
+    let mut builder = Builder::from((1, 4));
+    builder.point_format.is_compressed = path.to_lowercase().ends_with(".laz");
+    builder.transforms = Vector {
+        x: transform(origin.0),
+        y: transform(origin.1),
+        z: transform(origin.2),
+    };
+
+    let mut writer = Writer::from_path(path, builder.into_header()?)?;
+    let mut count = 0;
+
+    for tile in data {
+        for point in tile.points.iter() {
+            writer.write_point(las::Point {
+                x: point[0],
+                y: point[1],
+                z: point[2],
+                ..Default::default()
+            })?;
+        }
+
+        count += tile.points.len();
+    }
+
+    writer.close()?;
+
+    println!("Exported {} points to {}", count, path);
+
+    Ok(())
+}
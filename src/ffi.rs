@@ -0,0 +1,164 @@
+use std::{
+    cell::RefCell,
+    error::Error,
+    ffi::{CStr, CString, c_char, c_void},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::{
+    core::Cli,
+    progress::{self, TileEvent, TileStatus},
+};
+
+// Result codes of generate_terrain
+const TERRAIN_OK: i32 = 0;
+const TERRAIN_INVALID_CONFIG: i32 = 1;
+const TERRAIN_FAILED: i32 = 2;
+const TERRAIN_BUSY: i32 = 3;
+
+// Tile events of a run go to a single process wide observer, so only one run at a time can tell
+// its tiles apart from those of another
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Lets the next run start once the current one returned, also when it panicked
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+// Called for every tile status change with the tile indices, the status name and the user data
+// pointer passed to generate_terrain
+pub type TileCallback =
+    Option<extern "C" fn(tile_x: i32, tile_y: i32, status: *const c_char, user_data: *mut c_void)>;
+
+// The configuration takes the command line arguments of a generate run, the same as a batch job
+#[derive(Deserialize)]
+struct FfiConfig {
+    args: Vec<String>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs the generator for a JSON configuration such as `{"args": ["-p", "401,101", "-r", "1",
+/// "--possible-blocks", "35", "-d", "out"]}` and returns 0 on success, 1 for an invalid
+/// configuration, 2 when the run failed and 3 when another call is still running in the process.
+/// Runs do not overlap: tile events are reported process wide, so a second run started from
+/// another thread is rejected instead of receiving the tiles of the first. The callback is invoked
+/// on the calling thread.
+///
+/// # Safety
+///
+/// `config_json` must be null or point to a NUL terminated UTF-8 string that stays valid for the
+/// duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn generate_terrain(
+    config_json: *const c_char,
+    callback: TileCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    if config_json.is_null() {
+        set_last_error("Configuration is a null pointer");
+        return TERRAIN_INVALID_CONFIG;
+    }
+
+    // SAFETY: checked for null above, validity is the caller's contract
+    let config_json = unsafe { CStr::from_ptr(config_json) };
+    let cli = match parse_config(config_json) {
+        Ok(cli) => cli,
+        Err(value) => {
+            set_last_error(&value.to_string());
+            return TERRAIN_INVALID_CONFIG;
+        }
+    };
+
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        set_last_error("Another generate_terrain call is running in this process");
+        return TERRAIN_BUSY;
+    }
+    let _guard = RunGuard;
+
+    let events = progress::observe();
+    let run = thread::spawn(move || crate::generate(&cli).map(|_| ()));
+
+    // The user data pointer is not shareable, so events are forwarded from this thread only
+    let forward = |event: TileEvent| {
+        if let Some(callback) = callback {
+            let status = CString::new(get_status_name(event.status)).unwrap();
+            callback(event.tile.0, event.tile.1, status.as_ptr(), user_data);
+        }
+    };
+
+    while !run.is_finished() {
+        if let Ok(event) = events.recv_timeout(Duration::from_millis(50)) {
+            forward(event);
+        }
+    }
+    events.try_iter().for_each(forward);
+
+    match run.join() {
+        Ok(Ok(())) => TERRAIN_OK,
+        Ok(Err(value)) => {
+            set_last_error(&value.to_string());
+            TERRAIN_FAILED
+        }
+        Err(_) => {
+            set_last_error("Terrain generation panicked");
+            TERRAIN_FAILED
+        }
+    }
+}
+
+/// Message of the last failed generate_terrain call on this thread, or null. The string is owned
+/// by the library and stays valid until the next call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn terrain_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+fn parse_config(config_json: &CStr) -> Result<Cli, Box<dyn Error + Send + Sync>> {
+    let config: FfiConfig = serde_json::from_str(config_json.to_str()?)?;
+
+    let mut args = vec![env!("CARGO_PKG_NAME").to_string()];
+    args.extend(config.args);
+
+    let cli = Cli::try_parse_from(args)?;
+
+    if cli.command.is_some() {
+        return Err("The configuration can not contain subcommands".into());
+    }
+
+    Ok(cli)
+}
+
+fn get_status_name(status: TileStatus) -> &'static str {
+    match status {
+        TileStatus::Downloading => "downloading",
+        TileStatus::Downloaded => "downloaded",
+        TileStatus::Postponed => "postponed",
+        TileStatus::Missing => "missing",
+        TileStatus::Failed => "failed",
+        TileStatus::Computed => "computed",
+    }
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
@@ -0,0 +1,133 @@
+use crate::{excluder::Area, gridder};
+
+// Pixels around a footprint whose heights make up its ground level
+const GROUND_RING_PIXELS: usize = 2;
+
+// Flattens the gridded heights under every footprint into a pad. The pad takes the elevation of
+// the footprint when it has one, otherwise the median of the pixels around it, so the building
+// sits on the surrounding ground.
+pub fn flatten_footprints(
+    footprints: &[Area],
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    dim_x: usize,
+    dim_y: usize,
+    buffer_f32: &mut [f32],
+    normalize: impl Fn(f64) -> f32,
+) {
+    let extent = (bounds_max.0 - bounds_min.0, bounds_max.1 - bounds_min.1);
+    let get_column = |x: f64| ((x - bounds_min.0) / extent.0 * dim_x as f64).floor();
+    let get_row = |y: f64| (dim_y as f64 - (y - bounds_min.1) / extent.1 * dim_y as f64).floor();
+
+    for footprint in footprints
+        .iter()
+        .filter(|footprint| footprint.overlaps(bounds_min, bounds_max))
+    {
+        let (footprint_min, footprint_max) = footprint.bounds();
+        let ring = GROUND_RING_PIXELS as f64;
+        let clamp_column = |column: f64| column.clamp(0.0, dim_x as f64 - 1.0) as usize;
+        let clamp_row = |row: f64| row.clamp(0.0, dim_y as f64 - 1.0) as usize;
+        let (column_start, column_end) = (
+            clamp_column(get_column(footprint_min.0) - ring),
+            clamp_column(get_column(footprint_max.0) + ring + 1.0),
+        );
+        let (row_start, row_end) = (
+            clamp_row(get_row(footprint_max.1) - ring),
+            clamp_row(get_row(footprint_min.1) + ring + 1.0),
+        );
+        let window_x = column_end - column_start + 1;
+        let window_y = row_end - row_start + 1;
+
+        let inside = (0..window_x * window_y)
+            .map(|index| {
+                let (x, y) = gridder::get_pixel_position(
+                    bounds_min,
+                    bounds_max,
+                    (dim_x, dim_y),
+                    column_start + index % window_x,
+                    row_start + index / window_x,
+                );
+                footprint.contains(x, y)
+            })
+            .collect::<Vec<bool>>();
+
+        if !inside.contains(&true) {
+            continue;
+        }
+
+        let level = match footprint.elevation {
+            Some(elevation) => normalize(elevation),
+            None => {
+                let mut ground = vec![];
+
+                for index in (0..window_x * window_y).filter(|index| !inside[*index]) {
+                    let (column, row) = (index % window_x, index / window_x);
+                    let near_footprint = (row.saturating_sub(GROUND_RING_PIXELS)
+                        ..=(row + GROUND_RING_PIXELS).min(window_y - 1))
+                        .any(|near_row| {
+                            (column.saturating_sub(GROUND_RING_PIXELS)
+                                ..=(column + GROUND_RING_PIXELS).min(window_x - 1))
+                                .any(|near_column| inside[near_row * window_x + near_column])
+                        });
+                    let value = buffer_f32[(row_start + row) * dim_x + column_start + column];
+
+                    if near_footprint && !value.is_nan() {
+                        ground.push(value);
+                    }
+                }
+
+                if ground.is_empty() {
+                    continue;
+                }
+
+                let middle = ground.len() / 2;
+                *ground
+                    .select_nth_unstable_by(middle, |a, b| a.total_cmp(b))
+                    .1
+            }
+        };
+
+        for index in (0..window_x * window_y).filter(|index| inside[*index]) {
+            let (column, row) = (index % window_x, index / window_x);
+            buffer_f32[(row_start + row) * dim_x + column_start + column] = level;
+        }
+    }
+}
+
+// Sets the pixels below the sea level and every pixel inside a sea polygon to the sea level, which
+// removes the noisy returns off the coast
+pub fn flatten_sea(
+    sea_polygons: &[Area],
+    sea_level: f32,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    dim_x: usize,
+    dim_y: usize,
+    buffer_f32: &mut [f32],
+) {
+    let tile_polygons = sea_polygons
+        .iter()
+        .filter(|polygon| polygon.overlaps(bounds_min, bounds_max))
+        .collect::<Vec<&Area>>();
+
+    for (index, value) in buffer_f32.iter_mut().enumerate() {
+        if *value < sea_level {
+            *value = sea_level;
+            continue;
+        }
+
+        if !tile_polygons.is_empty() {
+            let (x, y) = gridder::get_pixel_position(
+                bounds_min,
+                bounds_max,
+                (dim_x, dim_y),
+                index % dim_x,
+                index / dim_x,
+            );
+
+            if tile_polygons.iter().any(|polygon| polygon.contains(x, y)) {
+                *value = sea_level;
+            }
+        }
+    }
+}
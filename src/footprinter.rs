@@ -0,0 +1,201 @@
+use serde_json::{Value, json};
+
+use crate::{
+    core::Point, global_constants::TILE_SIZE_M, projection::Crs, requester::LazData,
+    storage::OutputSink, writer::OutputError,
+};
+
+pub struct Footprint {
+    pub tile: Point,
+    pub file_stem: Option<String>,
+    pub point_count: usize,
+    pub status: FootprintStatus,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FootprintStatus {
+    Ok,
+    Missing,
+    LowDensity,
+    Fallback,
+}
+
+impl FootprintStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            FootprintStatus::Ok => "ok",
+            FootprintStatus::Missing => "missing",
+            FootprintStatus::LowDensity => "low-density",
+            FootprintStatus::Fallback => "fallback",
+        }
+    }
+
+    // KML colors are written as aabbggrr
+    fn kml_color(&self) -> &'static str {
+        match self {
+            FootprintStatus::Ok => "7f00c000",
+            FootprintStatus::Missing => "7f0000ff",
+            FootprintStatus::LowDensity => "7f00a5ff",
+            FootprintStatus::Fallback => "7fc0c000",
+        }
+    }
+}
+
+pub fn get_footprints(
+    data: &[LazData],
+    missing_points: &[Point],
+    low_density: f64,
+) -> Vec<Footprint> {
+    let mut footprints = data
+        .iter()
+        .map(|data| {
+            let density = data.density();
+
+            Footprint {
+                tile: data.tile,
+                file_stem: Some(data.file_stem.clone()),
+                point_count: data.points.len(),
+                status: if data.fallback {
+                    FootprintStatus::Fallback
+                } else if density < low_density {
+                    FootprintStatus::LowDensity
+                } else {
+                    FootprintStatus::Ok
+                },
+            }
+        })
+        .chain(missing_points.iter().map(|point| Footprint {
+            tile: *point,
+            file_stem: None,
+            point_count: 0,
+            status: FootprintStatus::Missing,
+        }))
+        .collect::<Vec<Footprint>>();
+
+    footprints.sort_by_key(|footprint| footprint.tile);
+
+    footprints
+}
+
+// Writes the footprints as GeoJSON and KML. Both formats expect WGS84, so the tile corners are
+// projected back from the source CRS.
+pub fn write_footprints(
+    sink: &dyn OutputSink,
+    footprints: &[Footprint],
+    crs: Crs,
+) -> Result<(), OutputError> {
+    let features = footprints
+        .iter()
+        .map(|footprint| {
+            json!({
+                "type": "Feature",
+                "properties": {
+                    "tile": format!("{}_{}", footprint.tile.0, footprint.tile.1),
+                    "file_stem": footprint.file_stem,
+                    "status": footprint.status.name(),
+                    "point_count": footprint.point_count,
+                    "density": footprint.point_count as f64 / (TILE_SIZE_M * TILE_SIZE_M),
+                },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [get_ring(footprint.tile, crs)
+                        .iter()
+                        .map(|(lat, lon)| json!([lon, lat]))
+                        .collect::<Vec<Value>>()],
+                },
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    let geojson = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    sink.put(
+        "footprints.geojson",
+        serde_json::to_string_pretty(&geojson)?.as_bytes(),
+    )?;
+
+    let styles = [
+        FootprintStatus::Ok,
+        FootprintStatus::Missing,
+        FootprintStatus::LowDensity,
+        FootprintStatus::Fallback,
+    ]
+    .iter()
+    .map(|status| {
+        format!(
+            "    <Style id=\"{}\"><LineStyle><color>ff{}</color></LineStyle><PolyStyle><color>{}</color></PolyStyle></Style>",
+            status.name(),
+            &status.kml_color()[2..],
+            status.kml_color()
+        )
+    })
+    .collect::<Vec<String>>()
+    .join("\n");
+
+    let placemarks = footprints
+        .iter()
+        .map(|footprint| {
+            let coordinates = get_ring(footprint.tile, crs)
+                .iter()
+                .map(|(lat, lon)| format!("{:.7},{:.7}", lon, lat))
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            format!(
+                r#"    <Placemark>
+      <name>{}_{}</name>
+      <styleUrl>#{}</styleUrl>
+      <ExtendedData>
+        <Data name="status"><value>{}</value></Data>
+        <Data name="file_stem"><value>{}</value></Data>
+        <Data name="point_count"><value>{}</value></Data>
+      </ExtendedData>
+      <Polygon><outerBoundaryIs><LinearRing><coordinates>{}</coordinates></LinearRing></outerBoundaryIs></Polygon>
+    </Placemark>"#,
+                footprint.tile.0,
+                footprint.tile.1,
+                footprint.status.name(),
+                footprint.status.name(),
+                footprint.file_stem.as_deref().unwrap_or(""),
+                footprint.point_count,
+                coordinates
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let kml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+  <Document>
+    <name>Tile footprints</name>
+{}
+{}
+  </Document>
+</kml>
+"#,
+        styles, placemarks
+    );
+    sink.put("footprints.kml", kml.as_bytes())?;
+
+    Ok(())
+}
+
+// Closed ring of the tile's grid cell as (lat, lon), counter-clockwise
+fn get_ring(tile: Point, crs: Crs) -> Vec<(f64, f64)> {
+    let (min_x, min_y) = (tile.0 as f64 * TILE_SIZE_M, tile.1 as f64 * TILE_SIZE_M);
+    let (max_x, max_y) = (min_x + TILE_SIZE_M, min_y + TILE_SIZE_M);
+
+    [
+        (min_x, min_y),
+        (max_x, min_y),
+        (max_x, max_y),
+        (min_x, max_y),
+        (min_x, min_y),
+    ]
+    .iter()
+    .map(|(x, y)| crs.to_wgs84(*x, *y))
+    .collect()
+}
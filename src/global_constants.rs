@@ -1,2 +1,5 @@
-pub const MIN_POINT_DIM: i16 = 0;
-pub const MAX_POINT_DIM: i16 = 800;
+pub const ARSO_MIN_TILE_INDEX: i32 = 0;
+pub const ARSO_MAX_TILE_INDEX: i32 = 800;
+pub const ARSO_BASE_URL: &str = "https://gis.arso.gov.si/lidar/otr/laz";
+pub const TILE_SIZE_M: f64 = 1000.0;
+pub const MIN_ADAPTIVE_RESOLUTION: u16 = 64;
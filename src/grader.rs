@@ -0,0 +1,229 @@
+use crate::{corridor, gridder, projection::Crs};
+
+// Road centerlines of a GPX or GeoJSON file in WGS84
+#[derive(Clone, Debug, Default)]
+pub struct Roads {
+    lines: Vec<Vec<(f64, f64)>>,
+}
+
+impl Roads {
+    // Centerlines projected from WGS84 onto the source CRS
+    pub fn project(&self, crs: Crs) -> Vec<Vec<(f64, f64)>> {
+        self.lines
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|(lat, lon)| crs.from_wgs84(*lat, *lon))
+                    .collect::<Vec<(f64, f64)>>()
+            })
+            .collect()
+    }
+}
+
+pub fn parse_roads(path: &str) -> Result<Roads, String> {
+    let lines = corridor::read_lines(path)
+        .map_err(|error| format!("Could not read roads '{}': {}", path, error))?
+        .into_iter()
+        .filter(|line| line.len() >= 2)
+        .collect::<Vec<Vec<(f64, f64)>>>();
+
+    if lines.is_empty() {
+        return Err(format!("No road centerline found in '{}'", path));
+    }
+
+    Ok(Roads { lines })
+}
+
+pub struct RoadGrading {
+    pub width_m: f64,
+    pub cross_slope: f64,
+    // Normalized height of one meter of elevation
+    pub meter_height: f64,
+}
+
+// Grades the gridded heights in a corridor around every centerline. The height along the
+// centerline is averaged over one road width to remove the survey noise, the road falls off by the
+// cross slope towards both edges and a shoulder of half the road width blends it into the terrain.
+pub fn grade_roads(
+    roads: &[Vec<(f64, f64)>],
+    grading: &RoadGrading,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    dim_x: usize,
+    dim_y: usize,
+    buffer_f32: &mut [f32],
+) {
+    if roads.is_empty() {
+        return;
+    }
+
+    let extent = (bounds_max.0 - bounds_min.0, bounds_max.1 - bounds_min.1);
+    let pixel_size = (extent.0 / dim_x as f64).min(extent.1 / dim_y as f64);
+    let half_width = grading.width_m / 2.0;
+    let reach = half_width * 2.0;
+
+    let get_pixel = |(x, y): (f64, f64)| {
+        let column = ((x - bounds_min.0) / extent.0 * dim_x as f64).round();
+        let row = (dim_y as f64 - (y - bounds_min.1) / extent.1 * dim_y as f64).round();
+
+        (column >= 0.0 && column < dim_x as f64 && row >= 0.0 && row < dim_y as f64)
+            .then(|| row as usize * dim_x + column as usize)
+    };
+
+    // Distance to the nearest centerline and the graded height there, per pixel
+    let mut nearest = vec![(f64::MAX, 0f32); dim_x * dim_y];
+
+    for line in roads.iter().filter(|line| {
+        line.iter().any(|(x, y)| {
+            *x >= bounds_min.0 - reach
+                && *x <= bounds_max.0 + reach
+                && *y >= bounds_min.1 - reach
+                && *y <= bounds_max.1 + reach
+        })
+    }) {
+        let stations = get_stations(line, pixel_size);
+        let samples = stations
+            .iter()
+            .map(|(position, _)| {
+                get_pixel(*position)
+                    .map(|index| buffer_f32[index])
+                    .filter(|value| !value.is_nan())
+            })
+            .collect::<Vec<Option<f32>>>();
+        let profile = smooth_profile(&stations, &samples, grading.width_m);
+
+        for (station_index, pair) in stations.windows(2).enumerate() {
+            let (start, end) = (pair[0].0, pair[1].0);
+            let (Some(start_height), Some(end_height)) =
+                (profile[station_index], profile[station_index + 1])
+            else {
+                continue;
+            };
+
+            let to_column = |x: f64| (x - bounds_min.0) / extent.0 * dim_x as f64;
+            let to_row = |y: f64| dim_y as f64 - (y - bounds_min.1) / extent.1 * dim_y as f64;
+            let reach_x = reach / extent.0 * dim_x as f64;
+            let reach_y = reach / extent.1 * dim_y as f64;
+            let column_start = (to_column(start.0.min(end.0)) - reach_x).floor().max(0.0) as usize;
+            let column_end = ((to_column(start.0.max(end.0)) + reach_x).ceil() as usize).min(dim_x);
+            let row_start = (to_row(start.1.max(end.1)) - reach_y).floor().max(0.0) as usize;
+            let row_end = ((to_row(start.1.min(end.1)) + reach_y).ceil() as usize).min(dim_y);
+
+            for row in row_start..row_end {
+                for column in column_start..column_end {
+                    let position = gridder::get_pixel_position(
+                        bounds_min,
+                        bounds_max,
+                        (dim_x, dim_y),
+                        column,
+                        row,
+                    );
+                    let (distance, along) = get_segment_distance(position, start, end);
+                    let pixel = &mut nearest[row * dim_x + column];
+
+                    if distance < pixel.0 {
+                        *pixel = (
+                            distance,
+                            start_height + (end_height - start_height) * along as f32,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for (value, (distance, height)) in buffer_f32.iter_mut().zip(nearest) {
+        if distance > reach || value.is_nan() {
+            continue;
+        }
+
+        let graded =
+            height - (grading.cross_slope * distance.min(half_width) * grading.meter_height) as f32;
+        let weight = if distance <= half_width {
+            1.0
+        } else {
+            (1.0 - (distance - half_width) / (reach - half_width)) as f32
+        };
+
+        *value += (graded - *value) * weight;
+    }
+}
+
+// Points every step meters along the line with their distance from its start
+fn get_stations(line: &[(f64, f64)], step: f64) -> Vec<((f64, f64), f64)> {
+    let mut stations = vec![(line[0], 0.0)];
+    let mut travelled = 0.0;
+
+    for pair in line.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+        let count = (length / step).ceil().max(1.0) as usize;
+
+        for index in 1..=count {
+            let along = index as f64 / count as f64;
+            stations.push((
+                (
+                    start.0 + (end.0 - start.0) * along,
+                    start.1 + (end.1 - start.1) * along,
+                ),
+                travelled + length * along,
+            ));
+        }
+
+        travelled += length;
+    }
+
+    stations
+}
+
+// Mean of the sampled heights within half a window of every station
+fn smooth_profile(
+    stations: &[((f64, f64), f64)],
+    samples: &[Option<f32>],
+    window_m: f64,
+) -> Vec<Option<f32>> {
+    let half_window = window_m / 2.0;
+    let mut first = 0;
+    let mut last = 0;
+    let (mut sum, mut count) = (0f64, 0usize);
+
+    stations
+        .iter()
+        .map(|(_, distance)| {
+            while last < stations.len() && stations[last].1 <= distance + half_window {
+                if let Some(sample) = samples[last] {
+                    sum += sample as f64;
+                    count += 1;
+                }
+                last += 1;
+            }
+            while stations[first].1 < distance - half_window {
+                if let Some(sample) = samples[first] {
+                    sum -= sample as f64;
+                    count -= 1;
+                }
+                first += 1;
+            }
+
+            (count > 0).then(|| (sum / count as f64) as f32)
+        })
+        .collect()
+}
+
+// Distance of a point to a segment and how far along the segment its projection lies
+fn get_segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> (f64, f64) {
+    let (delta_x, delta_y) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = delta_x * delta_x + delta_y * delta_y;
+    let along = if length_squared > 0.0 {
+        (((point.0 - start.0) * delta_x + (point.1 - start.1) * delta_y) / length_squared)
+            .clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (closest_x, closest_y) = (start.0 + delta_x * along, start.1 + delta_y * along);
+
+    (
+        ((point.0 - closest_x).powi(2) + (point.1 - closest_y).powi(2)).sqrt(),
+        along,
+    )
+}
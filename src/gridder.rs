@@ -0,0 +1,676 @@
+use std::num::NonZero;
+
+use clap::ValueEnum;
+use kiddo::{ImmutableKdTree, SquaredEuclidean};
+use libblur::{AnisotropicRadius, BlurImageMut, EdgeMode, EdgeMode2D, ThreadingPolicy};
+use serde::{Deserialize, Serialize};
+
+// The gridding and blur path has no thread or file system assumptions, so it also builds for
+// wasm32 where the blur has to stay on the calling thread
+#[cfg(not(target_arch = "wasm32"))]
+const THREADING_POLICY: ThreadingPolicy = ThreadingPolicy::Adaptive;
+#[cfg(target_arch = "wasm32")]
+const THREADING_POLICY: ThreadingPolicy = ThreadingPolicy::Single;
+
+// Index behind the exact neighbour search, chosen with --index
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpatialIndex {
+    #[default]
+    Kdtree,
+    // Uniform grid with cells of one output pixel. Faster to build and query than the kd-tree
+    // for evenly spread LiDAR points.
+    Grid,
+}
+
+// Float type of the coordinates held by the neighbour index and the stored tile points, chosen
+// with --internal-precision. f32 takes half the memory of f64.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    F32,
+    #[default]
+    F64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NeighbourSearch {
+    Exact(SpatialIndex),
+    Approximate,
+}
+
+impl NeighbourSearch {
+    pub fn new(approximate: bool, index: SpatialIndex) -> Self {
+        if approximate {
+            NeighbourSearch::Approximate
+        } else {
+            NeighbourSearch::Exact(index)
+        }
+    }
+}
+
+// Spatial index of a set of points, built once and shared by every raster gridded from them.
+// It works in coordinates relative to bounds_min: D96/TM coordinates of 4-5 * 10^5 m leave f32
+// only centimeters of resolution, tile local ones a fraction of a millimeter. The rasters keep
+// their absolute bounds for georeferencing.
+pub struct TileIndex {
+    lookup: NeighbourLookup,
+    extent: (f64, f64),
+    dim_x: usize,
+    dim_y: usize,
+}
+
+enum NeighbourLookup {
+    KdTree(ImmutableKdTree<f64, 2>),
+    KdTreeF32(ImmutableKdTree<f32, 2>),
+    Buckets(BucketGrid),
+}
+
+enum IndexPoints {
+    F64(Vec<[f64; 2]>),
+    F32(Vec<[f32; 2]>),
+}
+
+impl IndexPoints {
+    fn new(
+        points: impl Iterator<Item = [f64; 3]>,
+        origin: (f64, f64),
+        precision: Precision,
+    ) -> Self {
+        match precision {
+            Precision::F64 => IndexPoints::F64(
+                points
+                    .map(|point| [point[0] - origin.0, point[1] - origin.1])
+                    .collect(),
+            ),
+            Precision::F32 => IndexPoints::F32(
+                points
+                    .map(|point| [(point[0] - origin.0) as f32, (point[1] - origin.1) as f32])
+                    .collect(),
+            ),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            IndexPoints::F64(points) => points.len(),
+            IndexPoints::F32(points) => points.len(),
+        }
+    }
+
+    fn get(&self, item: usize) -> (f64, f64) {
+        match self {
+            IndexPoints::F64(points) => (points[item][0], points[item][1]),
+            IndexPoints::F32(points) => (points[item][0] as f64, points[item][1] as f64),
+        }
+    }
+}
+
+impl TileIndex {
+    pub fn new(
+        points: impl Iterator<Item = [f64; 3]>,
+        bounds_min: (f64, f64),
+        bounds_max: (f64, f64),
+        dim_x: usize,
+        dim_y: usize,
+        neighbours_n: usize,
+        search: NeighbourSearch,
+        precision: Precision,
+    ) -> Self {
+        let point_data_xy = IndexPoints::new(points, bounds_min, precision);
+        let extent = (bounds_max.0 - bounds_min.0, bounds_max.1 - bounds_min.1);
+
+        let lookup = match search {
+            NeighbourSearch::Exact(SpatialIndex::Kdtree) => match &point_data_xy {
+                IndexPoints::F64(points) => {
+                    NeighbourLookup::KdTree(ImmutableKdTree::<f64, 2>::new_from_slice(points))
+                }
+                IndexPoints::F32(points) => {
+                    NeighbourLookup::KdTreeF32(ImmutableKdTree::<f32, 2>::new_from_slice(points))
+                }
+            },
+            NeighbourSearch::Exact(SpatialIndex::Grid) => {
+                let pixel_size = (extent.0 / dim_x as f64).max(extent.1 / dim_y as f64);
+
+                NeighbourLookup::Buckets(BucketGrid::new(point_data_xy, extent, pixel_size, true))
+            }
+            NeighbourSearch::Approximate => {
+                // Buckets sized to hold about sample_size points each
+                let area = (extent.0 * extent.1).max(1.0);
+                let bucket_size =
+                    (area * neighbours_n as f64 / point_data_xy.len().max(1) as f64).sqrt();
+
+                NeighbourLookup::Buckets(BucketGrid::new(point_data_xy, extent, bucket_size, false))
+            }
+        };
+
+        TileIndex {
+            lookup,
+            extent,
+            dim_x,
+            dim_y,
+        }
+    }
+
+    // Grids a per-point value by averaging it over the nearest neighbours of every pixel
+    pub fn interpolate(&self, neighbours_n: usize, values: &[f32]) -> Vec<f32> {
+        self.interpolate_within(neighbours_n, values, None)
+    }
+
+    // Like interpolate, but leaves out neighbours farther than max_distance meters from the pixel.
+    // Pixels without any neighbour left are NaN voids, which void dilation and the blur keep
+    // apart from the valid heights.
+    pub fn interpolate_within(
+        &self,
+        neighbours_n: usize,
+        values: &[f32],
+        max_distance: Option<f64>,
+    ) -> Vec<f32> {
+        let (delta_x, delta_y) = self.extent;
+        let (dim_x, dim_y) = (self.dim_x, self.dim_y);
+        let nearest_neighbours_n = NonZero::new(neighbours_n).unwrap();
+        let max_squared_distance =
+            max_distance.map_or(f64::INFINITY, |distance| distance * distance);
+
+        let geo_xs = (0..dim_x)
+            .map(|ind_x| (ind_x as f64 / dim_x as f64) * delta_x as f64)
+            .collect::<Vec<f64>>();
+
+        let mut buffer_f32: Vec<f32> = vec![0f32; dim_x * dim_y];
+        let mut candidates = vec![];
+        // Neighbour values of one pixel row, stored neighbour-major so that each lane is
+        // contiguous and the accumulation below vectorizes
+        let mut gathered = vec![0f32; neighbours_n * dim_x];
+        // Sparse areas, tile edges and the distance limit can leave fewer than neighbours_n points
+        let mut found = vec![0usize; dim_x];
+
+        for (row, output_row) in buffer_f32.chunks_exact_mut(dim_x).enumerate() {
+            let ind_y = dim_y - row;
+            let geo_y = (ind_y as f64 / dim_y as f64) * delta_y as f64;
+
+            gathered.fill(0.0);
+
+            for (column, geo_x) in geo_xs.iter().enumerate() {
+                let mut count = 0;
+                let mut gather = |squared_distance: f64, item: usize| {
+                    if squared_distance <= max_squared_distance {
+                        gathered[count * dim_x + column] = values[item];
+                        count += 1;
+                    }
+                };
+
+                match &self.lookup {
+                    NeighbourLookup::KdTree(kdtree) => {
+                        for neighbour in kdtree
+                            .nearest_n::<SquaredEuclidean>(&[*geo_x, geo_y], nearest_neighbours_n)
+                        {
+                            gather(neighbour.distance, neighbour.item as usize);
+                        }
+                    }
+                    NeighbourLookup::KdTreeF32(kdtree) => {
+                        for neighbour in kdtree.nearest_n::<SquaredEuclidean>(
+                            &[*geo_x as f32, geo_y as f32],
+                            nearest_neighbours_n,
+                        ) {
+                            gather(neighbour.distance as f64, neighbour.item as usize);
+                        }
+                    }
+                    NeighbourLookup::Buckets(buckets) => {
+                        for (squared_distance, item) in
+                            buckets.nearest_n(*geo_x, geo_y, neighbours_n, &mut candidates)
+                        {
+                            gather(*squared_distance, *item as usize);
+                        }
+                    }
+                }
+
+                found[column] = count;
+            }
+
+            for lane in gathered.chunks_exact(dim_x) {
+                for (output, value) in output_row.iter_mut().zip(lane) {
+                    *output += value;
+                }
+            }
+
+            for (output, found) in output_row.iter_mut().zip(found.iter()) {
+                *output = if *found > 0 {
+                    *output / *found as f32
+                } else {
+                    f32::NAN
+                };
+            }
+        }
+
+        buffer_f32
+    }
+}
+
+// Points binned into square buckets, searched ring by ring around the pixel.
+//
+// Buckets outside of the searched ring lie at least ring bucket widths away from the pixel, so
+// every point closer than that is among the candidates.
+//
+// For --ann the buckets are sized to hold about sample_size points each and the ring widens until
+// the n-th nearest candidate is at most one bucket width beyond the ring. A chosen neighbour is
+// then at most one bucket width (about 0.5 m at ARSO point densities) farther away than the exact
+// one, and at ARSO densities the first ring almost always suffices.
+//
+// For --index grid the buckets are one output pixel wide and the search is exact: the ring widens
+// until the n-th nearest candidate is closer than any point outside of it can be.
+struct BucketGrid {
+    points: IndexPoints,
+    bucket_size: f64,
+    exact: bool,
+    columns: usize,
+    rows: usize,
+    // Point indices of bucket i are items[starts[i]..starts[i + 1]]
+    starts: Vec<u32>,
+    items: Vec<u32>,
+}
+
+impl BucketGrid {
+    fn new(points: IndexPoints, extent: (f64, f64), bucket_size: f64, exact: bool) -> Self {
+        let columns = ((extent.0 / bucket_size).ceil() as usize).max(1);
+        let rows = ((extent.1 / bucket_size).ceil() as usize).max(1);
+
+        let mut grid = BucketGrid {
+            points,
+            bucket_size,
+            exact,
+            columns,
+            rows,
+            starts: vec![0; columns * rows + 1],
+            items: vec![],
+        };
+
+        let buckets = (0..grid.points.len())
+            .map(|item| {
+                let (x, y) = grid.points.get(item);
+                let (column, row) = grid.get_bucket(x, y);
+                row * columns + column
+            })
+            .collect::<Vec<usize>>();
+
+        for bucket in buckets.iter() {
+            grid.starts[bucket + 1] += 1;
+        }
+        for index in 1..grid.starts.len() {
+            grid.starts[index] += grid.starts[index - 1];
+        }
+
+        let mut next = grid.starts.clone();
+        grid.items = vec![0; grid.points.len()];
+        for (item, bucket) in buckets.into_iter().enumerate() {
+            grid.items[next[bucket] as usize] = item as u32;
+            next[bucket] += 1;
+        }
+
+        grid
+    }
+
+    fn get_bucket(&self, geo_x: f64, geo_y: f64) -> (usize, usize) {
+        let column = (geo_x / self.bucket_size).floor().max(0.0) as usize;
+        let row = (geo_y / self.bucket_size).floor().max(0.0) as usize;
+
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    fn nearest_n<'a>(
+        &self,
+        geo_x: f64,
+        geo_y: f64,
+        neighbours_n: usize,
+        candidates: &'a mut Vec<(f64, u32)>,
+    ) -> impl Iterator<Item = &'a (f64, u32)> {
+        let (column, row) = self.get_bucket(geo_x, geo_y);
+        let mut ring = 1;
+
+        loop {
+            candidates.clear();
+
+            for bucket_row in row.saturating_sub(ring)..=(row + ring).min(self.rows - 1) {
+                for bucket_column in
+                    column.saturating_sub(ring)..=(column + ring).min(self.columns - 1)
+                {
+                    let bucket = bucket_row * self.columns + bucket_column;
+
+                    for item in
+                        &self.items[self.starts[bucket] as usize..self.starts[bucket + 1] as usize]
+                    {
+                        let (x, y) = self.points.get(*item as usize);
+                        let distance = (x - geo_x).powi(2) + (y - geo_y).powi(2);
+                        candidates.push((distance, *item));
+                    }
+                }
+            }
+
+            if ring >= self.columns.max(self.rows) {
+                break;
+            }
+
+            if candidates.len() >= neighbours_n {
+                if neighbours_n == 0 {
+                    break;
+                }
+
+                let reach = if self.exact { ring } else { ring + 1 };
+                candidates.select_nth_unstable_by(neighbours_n - 1, |a, b| a.0.total_cmp(&b.0));
+                if candidates[neighbours_n - 1].0 <= (reach as f64 * self.bucket_size).powi(2) {
+                    break;
+                }
+            }
+
+            ring += 1;
+        }
+
+        if candidates.len() > neighbours_n {
+            candidates.select_nth_unstable_by(neighbours_n - 1, |a, b| a.0.total_cmp(&b.0));
+            candidates.truncate(neighbours_n);
+        }
+
+        let candidates: &'a Vec<(f64, u32)> = candidates;
+        candidates.iter()
+    }
+}
+
+// Fills NaN pixels within distance pixels of valid data with the mean of their valid
+// 4-neighbours, one pixel ring per pass. Farther voids stay NaN.
+pub fn dilate_into_voids(buffer: &mut [f32], dim_x: usize, dim_y: usize, distance: usize) {
+    for _ in 0..distance {
+        let previous = buffer.to_vec();
+        let mut changed = false;
+
+        for index in 0..buffer.len() {
+            if !previous[index].is_nan() {
+                continue;
+            }
+
+            let (column, row) = (index % dim_x, index / dim_x);
+            let neighbours = [
+                (column > 0).then(|| index - 1),
+                (column + 1 < dim_x).then(|| index + 1),
+                (row > 0).then(|| index - dim_x),
+                (row + 1 < dim_y).then(|| index + dim_x),
+            ];
+
+            let (sum, count) = neighbours
+                .into_iter()
+                .flatten()
+                .map(|neighbour| previous[neighbour])
+                .filter(|value| !value.is_nan())
+                .fold((0f32, 0), |(sum, count), value| (sum + value, count + 1));
+
+            if count > 0 {
+                buffer[index] = sum / count as f32;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+// Position of a pixel as laid out by TileIndex::interpolate, rows run from north to south
+pub fn get_pixel_position(
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    (dim_x, dim_y): (usize, usize),
+    column: usize,
+    row: usize,
+) -> (f64, f64) {
+    (
+        bounds_min.0 + column as f64 / dim_x as f64 * (bounds_max.0 - bounds_min.0),
+        bounds_min.1 + (dim_y - row) as f64 / dim_y as f64 * (bounds_max.1 - bounds_min.1),
+    )
+}
+
+pub fn blur_image(
+    kernel_size: u32,
+    dim_x: usize,
+    dim_y: usize,
+    buffer_f32: &mut Vec<f32>,
+) -> Result<(), String> {
+    if kernel_size == 0 {
+        return Ok(());
+    }
+
+    // Normalized convolution: the values and their weights are blurred separately and divided,
+    // so NaN voids and the area past the tile edges drop out of the average instead of being
+    // spread into the terrain or clamped into a border bias. A one pixel ring of zero weight is
+    // enough, since clamping it extends the zero padding as far as the kernel reaches.
+    let (padded_x, padded_y) = (dim_x + 2, dim_y + 2);
+    let mut values = vec![0f32; padded_x * padded_y];
+    let mut weights = vec![0f32; padded_x * padded_y];
+
+    for row in 0..dim_y {
+        for column in 0..dim_x {
+            let value = buffer_f32[row * dim_x + column];
+            if !value.is_nan() {
+                let padded_index = (row + 1) * padded_x + column + 1;
+                values[padded_index] = value;
+                weights[padded_index] = 1.0;
+            }
+        }
+    }
+
+    blur_plane(kernel_size, padded_x, padded_y, &mut values)?;
+    blur_plane(kernel_size, padded_x, padded_y, &mut weights)?;
+
+    for row in 0..dim_y {
+        for column in 0..dim_x {
+            let value = &mut buffer_f32[row * dim_x + column];
+            let padded_index = (row + 1) * padded_x + column + 1;
+            if !value.is_nan() && weights[padded_index] > f32::EPSILON {
+                *value = values[padded_index] / weights[padded_index];
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn blur_plane(
+    kernel_size: u32,
+    dim_x: usize,
+    dim_y: usize,
+    buffer_f32: &mut Vec<f32>,
+) -> Result<(), String> {
+    let mut blured_image = BlurImageMut::borrow(
+        buffer_f32,
+        dim_x as u32,
+        dim_y as u32,
+        libblur::FastBlurChannels::Plane,
+    );
+
+    libblur::fast_gaussian_f32(
+        &mut blured_image,
+        AnisotropicRadius {
+            x_axis: kernel_size,
+            y_axis: kernel_size,
+        },
+        THREADING_POLICY,
+        EdgeMode2D::anisotropy(EdgeMode::Clamp, EdgeMode::Clamp),
+    )
+    .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    const SIDE: usize = 100;
+
+    // Flat points at 1 m spacing over a 100 m tile with a hole of 19x19 m in the middle
+    fn grid_tile_with_hole(max_distance: Option<f64>) -> Vec<f32> {
+        let points = (0..=SIDE)
+            .flat_map(|y| (0..=SIDE).map(move |x| [x as f64, y as f64, 1.0]))
+            .filter(|point| {
+                !((41.0..=59.0).contains(&point[0]) && (41.0..=59.0).contains(&point[1]))
+            })
+            .collect::<Vec<[f64; 3]>>();
+        let values = vec![1.0; points.len()];
+
+        let index = TileIndex::new(
+            points.into_iter(),
+            (0.0, 0.0),
+            (SIDE as f64, SIDE as f64),
+            SIDE,
+            SIDE,
+            4,
+            NeighbourSearch::Exact(SpatialIndex::Kdtree),
+            Precision::F64,
+        );
+
+        index.interpolate_within(4, &values, max_distance)
+    }
+
+    // Points over a 100 m tile, half of them spread evenly and half packed into one corner, so
+    // that the ring search also has to widen over empty buckets
+    fn create_random_points(count: usize, seed: u64) -> Vec<[f64; 2]> {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        (0..count)
+            .map(|index| {
+                let side = if index % 2 == 0 { SIDE as f64 } else { 10.0 };
+                [rng.gen_range(0.0..side), rng.gen_range(0.0..side)]
+            })
+            .collect()
+    }
+
+    // Ascending squared distances of the n nearest points as found by the kd-tree and the buckets
+    fn get_neighbour_distances(
+        points: &[[f64; 2]],
+        buckets: &BucketGrid,
+        query: (f64, f64),
+        neighbours_n: usize,
+    ) -> (Vec<f64>, Vec<f64>) {
+        let kdtree = ImmutableKdTree::<f64, 2>::new_from_slice(points);
+        let mut exact = kdtree
+            .nearest_n::<SquaredEuclidean>(&[query.0, query.1], NonZero::new(neighbours_n).unwrap())
+            .into_iter()
+            .map(|neighbour| neighbour.distance)
+            .collect::<Vec<f64>>();
+        let mut candidates = vec![];
+        let mut found = buckets
+            .nearest_n(query.0, query.1, neighbours_n, &mut candidates)
+            .map(|(distance, _)| *distance)
+            .collect::<Vec<f64>>();
+
+        exact.sort_by(f64::total_cmp);
+        found.sort_by(f64::total_cmp);
+
+        (exact, found)
+    }
+
+    #[test]
+    fn approximate_neighbours_are_at_most_one_bucket_farther() {
+        let points = create_random_points(2_000, 11);
+        let neighbours_n = 8;
+        let bucket_size = ((SIDE * SIDE * neighbours_n) as f64 / points.len() as f64).sqrt();
+        let buckets = BucketGrid::new(
+            IndexPoints::F64(points.clone()),
+            (SIDE as f64, SIDE as f64),
+            bucket_size,
+            false,
+        );
+        let mut rng = StdRng::seed_from_u64(12);
+
+        for _ in 0..500 {
+            let query = (
+                rng.gen_range(0.0..SIDE as f64),
+                rng.gen_range(0.0..SIDE as f64),
+            );
+            let (exact, found) = get_neighbour_distances(&points, &buckets, query, neighbours_n);
+
+            assert_eq!(found.len(), neighbours_n);
+            for (exact, found) in exact.iter().zip(found.iter()) {
+                assert!(found.sqrt() <= exact.sqrt() + bucket_size + 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_index_finds_the_same_neighbours_as_the_kdtree() {
+        for (seed, neighbours_n) in [(1, 1), (2, 4), (3, 12), (4, 40)] {
+            let points = create_random_points(1_000, seed);
+            // One bucket per output pixel of a 128 px tile
+            let buckets = BucketGrid::new(
+                IndexPoints::F64(points.clone()),
+                (SIDE as f64, SIDE as f64),
+                SIDE as f64 / 128.0,
+                true,
+            );
+            let mut rng = StdRng::seed_from_u64(seed + 100);
+
+            for _ in 0..200 {
+                let query = (
+                    rng.gen_range(0.0..SIDE as f64),
+                    rng.gen_range(0.0..SIDE as f64),
+                );
+                let (exact, found) =
+                    get_neighbour_distances(&points, &buckets, query, neighbours_n);
+
+                assert_eq!(found.len(), exact.len());
+                for (exact, found) in exact.iter().zip(found.iter()) {
+                    assert!(
+                        (found - exact).abs() <= 1e-9,
+                        "neighbours of {:?} differ",
+                        query
+                    );
+                }
+            }
+        }
+    }
+
+    // Pixel of the test tile at x, y meters, rows run from y = 100 in the north down to y = 1
+    fn pixel(x: usize, y: usize) -> usize {
+        (SIDE - y) * SIDE + x
+    }
+
+    #[test]
+    fn interpolation_leaves_far_pixels_as_voids() {
+        let heights = grid_tile_with_hole(Some(2.0));
+
+        assert_eq!(heights[pixel(42, 50)], 1.0);
+        assert!(heights[pixel(43, 50)].is_nan());
+        assert!(heights[pixel(50, 50)].is_nan());
+        assert!(
+            grid_tile_with_hole(None)
+                .iter()
+                .all(|height| *height == 1.0)
+        );
+    }
+
+    #[test]
+    fn dilation_fills_the_void_fringe() {
+        let mut heights = grid_tile_with_hole(Some(2.0));
+        dilate_into_voids(&mut heights, SIDE, SIDE, 2);
+
+        assert_eq!(heights[pixel(43, 50)], 1.0);
+        assert_eq!(heights[pixel(44, 50)], 1.0);
+        assert!(heights[pixel(45, 50)].is_nan());
+        assert!(heights[pixel(50, 50)].is_nan());
+    }
+
+    #[test]
+    fn blur_does_not_darken_pixels_next_to_voids() {
+        let mut heights = grid_tile_with_hole(Some(2.0));
+        blur_image(3, SIDE, SIDE, &mut heights).unwrap();
+
+        for (x, y) in [(42, 50), (50, 42), (0, 1), (SIDE - 1, 50)] {
+            assert!(
+                (heights[pixel(x, y)] - 1.0).abs() < 1e-5,
+                "pixel {}, {} was blurred to {}",
+                x,
+                y,
+                heights[pixel(x, y)]
+            );
+        }
+        assert!(heights[pixel(50, 50)].is_nan());
+    }
+}
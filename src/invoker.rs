@@ -0,0 +1,143 @@
+use std::{process::Command, sync::Mutex};
+
+use crate::{
+    RunSummary,
+    core::Config,
+    error::HookError,
+    requester::LazData,
+    storage::{self, OutputSink},
+    writer::OutputError,
+};
+
+// Passes writes through to the inner sink and remembers their paths, so the files of one tile
+// can be handed to --post-tile-hook
+pub struct RecordingSink<'a> {
+    inner: &'a dyn OutputSink,
+    files: Mutex<Vec<String>>,
+}
+
+impl<'a> RecordingSink<'a> {
+    pub fn new(inner: &'a dyn OutputSink) -> Self {
+        RecordingSink {
+            inner,
+            files: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn into_files(self) -> Vec<String> {
+        self.files.into_inner().unwrap()
+    }
+}
+
+impl OutputSink for RecordingSink<'_> {
+    fn put(&self, relative_path: &str, bytes: &[u8]) -> Result<(), OutputError> {
+        self.inner.put(relative_path, bytes)?;
+        self.files.lock().unwrap().push(relative_path.to_string());
+
+        Ok(())
+    }
+}
+
+// Runs the hook once per file of a tile with {file} replaced by the file's quoted path. The tile
+// is described by LTG_* environment variables.
+pub fn run_tile_hook(
+    hook: &str,
+    config: &Config,
+    data: &LazData,
+    height_range: (f64, f64),
+    files: &[String],
+) -> Result<(), HookError> {
+    for file in files {
+        let path = get_output_path(config, file);
+        let variables = [
+            ("LTG_FILE", path.clone()),
+            ("LTG_DESTINATION", config.destination_folder.clone()),
+            ("LTG_TILE_X", data.tile.0.to_string()),
+            ("LTG_TILE_Y", data.tile.1.to_string()),
+            ("LTG_OFFSET_X", data.offset_from_center.0.to_string()),
+            ("LTG_OFFSET_Y", data.offset_from_center.1.to_string()),
+            ("LTG_MIN_HEIGHT", height_range.0.to_string()),
+            ("LTG_MAX_HEIGHT", height_range.1.to_string()),
+            ("LTG_SURVEY", data.survey.clone().unwrap_or_default()),
+        ];
+
+        run_hook(&hook.replace("{file}", &quote(&path)), &variables)?;
+    }
+
+    Ok(())
+}
+
+// Runs the hook once after a completed run with {folder} replaced by the quoted destination
+pub fn run_post_run_hook(
+    hook: &str,
+    config: &Config,
+    summary: &RunSummary,
+) -> Result<(), HookError> {
+    let variables = [
+        ("LTG_DESTINATION", config.destination_folder.clone()),
+        ("LTG_TILES", summary.tiles.to_string()),
+        ("LTG_PENDING_TILES", summary.pending_tiles.to_string()),
+    ];
+
+    run_hook(
+        &hook.replace("{folder}", &quote(&config.destination_folder)),
+        &variables,
+    )
+}
+
+fn get_output_path(config: &Config, relative_path: &str) -> String {
+    if storage::is_object_storage(&config.destination_folder) {
+        return format!(
+            "{}/{}",
+            config.destination_folder.trim_end_matches('/'),
+            relative_path
+        );
+    }
+
+    storage::get_local_path(&config.destination_folder, relative_path)
+        .to_string_lossy()
+        .to_string()
+}
+
+// Paths are substituted as a single shell word, so spaces and shell characters in them are not
+// interpreted. The hook should not quote the placeholders itself.
+#[cfg(not(windows))]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn run_hook(command: &str, variables: &[(&str, String)]) -> Result<(), HookError> {
+    let status = get_shell(command)
+        .envs(variables.iter().map(|(name, value)| (*name, value)))
+        .status()
+        .map_err(|source| HookError::Spawn {
+            command: command.to_string(),
+            source,
+        })?;
+
+    if !status.success() {
+        return Err(HookError::Failed {
+            command: command.to_string(),
+            status,
+        });
+    }
+
+    Ok(())
+}
+
+fn get_shell(command: &str) -> Command {
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+    #[cfg(not(windows))]
+    let (shell, flag) = ("sh", "-c");
+
+    let mut shell = Command::new(shell);
+    shell.arg(flag).arg(command);
+
+    shell
+}
@@ -0,0 +1,248 @@
+// Only the gridding path builds for wasm32, everything that downloads tiles, spawns threads or
+// touches the file system is left out there
+#[cfg(not(target_arch = "wasm32"))]
+use clap::Parser;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod batcher;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bencher;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bundler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cacher;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod computer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod conditioner;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod converter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod core;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod corridor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod deriver;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod differ;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod documenter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod draper;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod excluder;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod exporter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod flattener;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod footprinter;
+pub mod global_constants;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod grader;
+pub mod gridder;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod invoker;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod merger;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod meterer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mosaic;
+pub mod packer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pauser;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipeliner;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod previewer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod progress;
+pub mod projection;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod prompter;
+#[cfg(all(feature = "python", not(target_arch = "wasm32")))]
+pub mod python;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod requester;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod seamer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod spacer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod spiller;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod splatter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod spotter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod storage;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stylizer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tiler;
+#[cfg(all(feature = "tui", not(target_arch = "wasm32")))]
+pub mod tui;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tuner;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod validator;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod verifier;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+pub mod writer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod zarrer;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RunSummary {
+    pub tiles: usize,
+    pub pending_tiles: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate(cli: &core::Cli) -> Result<RunSummary, error::TerrainError> {
+    let config = core::read_config_from_cli(cli)?;
+
+    let resume_state = if config.resume {
+        Some(computer::read_resume_state(&config)?)
+    } else if config.update {
+        // An update reuses the previous normalization so untouched tiles stay consistent
+        let previous = computer::read_compute_config_file(
+            &config.destination_folder,
+            &config.get_run_file_name("config.json"),
+        )?;
+        let known_offsets = previous
+            .tiles
+            .iter()
+            .map(|tile| tile.offset_from_center)
+            .collect::<Vec<(i32, i32)>>();
+
+        Some(computer::ResumeState {
+            pending_points: requester::get_changed_points(&config, &known_offsets)?,
+            min_height: previous.min_height,
+            max_height: previous.max_height,
+        })
+    } else {
+        None
+    };
+
+    run(config, resume_state)
+}
+
+// Regrids the tiles overlapping the box with the settings of generate_args, reading the points
+// from the cache only. The normalization of the earlier run is kept, so the new tiles match
+// their neighbours.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn recompute(
+    bbox: core::BoundingBox,
+    generate_args: &[String],
+) -> Result<RunSummary, error::TerrainError> {
+    let mut args = vec![env!("CARGO_PKG_NAME").to_string()];
+    args.extend(generate_args.iter().cloned());
+
+    let cli = core::Cli::try_parse_from(args)?;
+    let mut config = core::read_config_from_cli(&cli)?;
+
+    if config.cache_dir.is_none() {
+        return Err(error::TerrainError::Settings(
+            "Recomputing reads the point data from the tile cache (--cache-dir)".to_string(),
+        ));
+    }
+
+    // Tiles missing from the cache fail the run instead of being downloaded
+    config.offline = true;
+
+    let previous = computer::read_compute_config_file(
+        &config.destination_folder,
+        &config.get_run_file_name("config.json"),
+    )?;
+    let resume_state = computer::ResumeState {
+        pending_points: bbox.get_all_points_in_area().collect(),
+        min_height: previous.min_height,
+        max_height: previous.max_height,
+    };
+
+    run(config, Some(resume_state))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run(
+    mut config: core::Config,
+    resume_state: Option<computer::ResumeState>,
+) -> Result<RunSummary, error::TerrainError> {
+    if let Some(address) = &config.metrics_address {
+        meterer::serve(address).map_err(|source| error::TerrainError::Metrics {
+            address: address.clone(),
+            source,
+        })?;
+    }
+
+    pauser::listen_for_signal();
+
+    if let Some(resume_state) = &resume_state {
+        if resume_state.pending_points.is_empty() {
+            println!("All tiles are up to date.");
+
+            return Ok(RunSummary {
+                tiles: 0,
+                pending_tiles: 0,
+            });
+        }
+    }
+
+    let cpus = config.worker_count(thread::available_parallelism()?);
+
+    if let Some(max_in_flight_tiles) = config.max_in_flight_tiles {
+        let (tiles, pending_tiles) =
+            computer::compute_textures_streaming(&config, cpus, max_in_flight_tiles, resume_state)?;
+        let summary = RunSummary {
+            tiles,
+            pending_tiles,
+        };
+
+        if let Some(hook) = &config.post_run_hook {
+            invoker::run_post_run_hook(hook, &config, &summary)
+                .map_err(error::TerrainError::Hook)?;
+        }
+
+        return Ok(summary);
+    }
+
+    let laz_binary_data = requester::get_laz_data(
+        cpus,
+        &config,
+        resume_state
+            .as_ref()
+            .map(|state| state.pending_points.as_slice()),
+    )?;
+
+    if config.auto_tune {
+        tuner::auto_tune(&mut config, cpus, &laz_binary_data.data)?;
+    }
+
+    if let Some(export_points) = &config.export_points {
+        exporter::export_points(export_points, &laz_binary_data.data)
+            .map_err(error::TerrainError::Output)?;
+    }
+
+    let summary = RunSummary {
+        tiles: laz_binary_data.data.len(),
+        pending_tiles: laz_binary_data.pending_points.len(),
+    };
+
+    computer::compute_textures_parallel(&config, cpus, laz_binary_data, resume_state)?;
+
+    if let Some(hook) = &config.post_run_hook {
+        invoker::run_post_run_hook(hook, &config, &summary).map_err(error::TerrainError::Hook)?;
+    }
+
+    Ok(summary)
+}
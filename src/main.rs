@@ -1,18 +1,134 @@
 use std::error::Error;
-use std::thread;
 
-mod computer;
-mod core;
-mod global_constants;
-mod requester;
+#[cfg(feature = "tui")]
+use las_terrain_generator::tui;
+use las_terrain_generator::{
+    batcher, bencher, cacher, converter, core, differ, documenter, generate, merger, prompter,
+    recompute, seamer, verifier,
+};
 
 fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let config = core::read_config_from_cli()?;
+    let cli = core::read_cli();
 
-    let cpus = thread::available_parallelism()?;
-    let laz_binary_data = requester::get_laz_data(cpus, &config);
+    match &cli.command {
+        Some(core::Command::Diff {
+            dir_a,
+            dir_b,
+            destination_folder,
+        }) => {
+            core::check_folder_exists(destination_folder)?;
+            Ok(differ::diff_terrains(dir_a, dir_b, destination_folder)?)
+        }
+        Some(core::Command::Merge {
+            folders,
+            destination_folder,
+            mosaic,
+        }) => {
+            if *mosaic == Some(0) {
+                return Err("The mosaic needs at least one pixel per tile".into());
+            }
 
-    computer::compute_textures_parallel(&config, cpus, laz_binary_data)?;
+            core::check_folder_exists(destination_folder)?;
+            merger::merge_runs(folders, destination_folder, *mosaic, None)
+        }
+        Some(core::Command::Renormalize {
+            folder,
+            min_height,
+            max_height,
+            destination_folder,
+        }) => {
+            if min_height.is_nan() || max_height.is_nan() || min_height >= max_height {
+                return Err("The minimum height must be below the maximum height".into());
+            }
 
-    Ok(())
+            let destination_folder = destination_folder.as_ref().unwrap_or(folder);
+            core::check_folder_exists(destination_folder)?;
+            merger::merge_runs(
+                &[folder.clone()],
+                destination_folder,
+                None,
+                Some((*min_height, *max_height)),
+            )
+        }
+        Some(core::Command::Convert {
+            folder,
+            format,
+            destination_folder,
+        }) => {
+            if let Some(destination_folder) = destination_folder {
+                core::check_folder_exists(destination_folder)?;
+            }
+            converter::convert_folder(folder, format, destination_folder.as_deref())
+        }
+        Some(core::Command::Verify { folder }) => verifier::verify_folder(folder),
+        Some(core::Command::Recompute {
+            bbox,
+            generate_args,
+        }) => Ok(recompute(*bbox, generate_args).map(|_| ())?),
+        Some(core::Command::Cache { action }) => match action {
+            core::CacheCommand::Info { cache_dir } => cacher::print_cache_info(cache_dir),
+            core::CacheCommand::Prune {
+                cache_dir,
+                max_size,
+            } => Ok(cacher::prune_cache(cache_dir, *max_size)?),
+        },
+        Some(core::Command::Seams {
+            folder,
+            tolerance_m,
+        }) => seamer::check_seams(folder, *tolerance_m),
+        Some(core::Command::Completions { shell }) => documenter::print_completions(*shell),
+        Some(core::Command::Examples) => documenter::print_examples(),
+        Some(core::Command::Init) => prompter::run_wizard(),
+        Some(core::Command::Config { action }) => match action {
+            core::ConfigCommand::Validate { file } => batcher::validate_config(file),
+        },
+        Some(core::Command::Batch {
+            batch_file,
+            parallel_jobs,
+            cache_dir,
+            report,
+        }) => batcher::run_batch(
+            batch_file,
+            *parallel_jobs,
+            cache_dir.as_deref(),
+            report.as_deref(),
+        ),
+        Some(core::Command::Bench {
+            tiles,
+            tile_size_m,
+            density,
+            resolution,
+            sample_size,
+            blur_kernel_size,
+            threads,
+            format,
+            ann,
+            index,
+            internal_precision,
+            http_url,
+            http_requests,
+        }) => bencher::run_bench(&bencher::BenchConfig {
+            tiles: *tiles,
+            tile_size_m: *tile_size_m,
+            density: *density,
+            resolution: *resolution as usize,
+            sample_size: *sample_size as usize,
+            blur_kernel_size: *blur_kernel_size as u32,
+            threads: threads.clone(),
+            formats: format.clone(),
+            ann: *ann,
+            index: *index,
+            internal_precision: *internal_precision,
+            http_url: http_url.clone(),
+            http_requests: *http_requests,
+            http: core::HttpConfig::try_from(&cli)?,
+        }),
+        #[cfg(feature = "tui")]
+        Some(core::Command::Tui {
+            center,
+            view_radius,
+            generate_args,
+        }) => tui::run_tui(center, *view_radius, generate_args),
+        None => Ok(generate(&cli).map(|_| ())?),
+    }
 }
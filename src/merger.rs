@@ -0,0 +1,516 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    error::Error,
+    fs, mem,
+};
+
+use crate::{
+    computer::{self, ComputeConfig, ComputeParameters, TileMetadata},
+    global_constants::TILE_SIZE_M,
+    projection::{self, Crs},
+    storage::{self, OutputSink},
+    verifier::{self, ChecksumSink},
+    writer::{self, BigTiffMode, ExrWriter, OutputFormat, OutputWriter, PngWriter, TileRaster},
+};
+
+// config.json or the config of a shard in one of the merged folders
+struct Run {
+    folder: String,
+    file_name: String,
+    config: ComputeConfig,
+}
+
+// Combines the tiles of several generated folders, e.g. the shards of one area or an incremental
+// run and its base, into the destination with a single config.json. Runs normalized to different
+// height ranges are rewritten to the range spanning all of them, or to height_range when given.
+pub fn merge_runs(
+    folders: &[String],
+    destination_folder: &str,
+    mosaic_tile_size: Option<usize>,
+    height_range: Option<(f64, f64)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut runs = read_runs(folders)?;
+    check_compatible(&runs)?;
+    check_shards(&runs);
+
+    let data_min_height = runs
+        .iter()
+        .fold(f64::MAX, |min, run| min.min(run.config.min_height));
+    let data_max_height = runs
+        .iter()
+        .fold(f64::MIN, |max, run| max.max(run.config.max_height));
+    let (min_height, max_height) = height_range.unwrap_or((data_min_height, data_max_height));
+
+    if min_height > data_min_height || max_height < data_max_height {
+        println!(
+            "Heights from {:.2} m to {:.2} m do not fit the range {:.2} m to {:.2} m, PNG, TIFF \
+             and raw heightmaps are clipped",
+            data_min_height, data_max_height, min_height, max_height
+        );
+    }
+
+    // Later runs replace the tiles of earlier ones, so an update can be merged over its base
+    let mut tiles = BTreeMap::<String, (usize, TileMetadata)>::new();
+    for (run_index, run) in runs.iter_mut().enumerate() {
+        for tile in mem::take(&mut run.config.tiles) {
+            if let Some((_, previous)) = tiles.get(&tile.file_stem) {
+                if (previous.bounds_min.0 - tile.bounds_min.0).abs() > TILE_SIZE_M / 2.0
+                    || (previous.bounds_min.1 - tile.bounds_min.1).abs() > TILE_SIZE_M / 2.0
+                {
+                    return Err(format!(
+                        "{} covers different areas in the merged runs, they were generated \
+                         around different centers",
+                        tile.file_stem
+                    )
+                    .into());
+                }
+            }
+
+            tiles.insert(tile.file_stem.clone(), (run_index, tile));
+        }
+    }
+
+    let sink = storage::create_output_sink(destination_folder)?;
+    let checksums = ChecksumSink::new(sink.as_ref());
+    let mut renormalized = 0;
+    let mut mosaic_tiles = vec![];
+
+    let destination = storage::resolve_local_folder(destination_folder);
+    let in_place = runs
+        .iter()
+        .map(|run| storage::resolve_local_folder(&run.folder) == destination)
+        .collect::<Vec<bool>>();
+
+    for (file_stem, (run_index, tile)) in tiles.iter() {
+        let run = &runs[*run_index];
+        let renormalize =
+            run.config.min_height != min_height || run.config.max_height != max_height;
+
+        let heightmap_stems = get_heightmap_stems(&run.config.parameters, file_stem);
+        let heightmap_names = heightmap_stems
+            .iter()
+            .flat_map(|stem| {
+                run.config.parameters.formats.iter().map(move |format| {
+                    format!(
+                        "{}.{}",
+                        stem,
+                        format.writer(BigTiffMode::Auto, None).extension()
+                    )
+                })
+            })
+            .collect::<BTreeSet<String>>();
+
+        // Derived maps such as normals or relief visualizations do not depend on the height
+        // range and are copied as they are, unless they already are in the destination
+        for file_name in list_tile_files(&run.folder, file_stem)? {
+            if in_place[*run_index] || (renormalize && heightmap_names.contains(&file_name)) {
+                continue;
+            }
+
+            let bytes = fs::read(storage::get_local_path(&run.folder, &file_name))?;
+            checksums.put(&file_name, &bytes)?;
+        }
+
+        // Read before a renormalization in place replaces the heightmap
+        if let Some(mosaic_tile_size) = mosaic_tile_size {
+            let (dim_x, dim_y, heights) = read_heights(run, file_stem, (min_height, max_height))?;
+            mosaic_tiles.push((
+                tile.offset_from_center,
+                downsample(&heights, (dim_x, dim_y), mosaic_tile_size),
+            ));
+        }
+
+        if renormalize {
+            for stem in heightmap_stems.iter() {
+                let (dim_x, dim_y, heights) = read_heights(run, stem, (min_height, max_height))?;
+                let (bounds_min, bounds_max) = get_raster_bounds(
+                    &run.config,
+                    (tile.bounds_min.0, tile.bounds_min.1),
+                    (tile.bounds_max.0, tile.bounds_max.1),
+                )?;
+                let raster = TileRaster {
+                    bounds_min,
+                    bounds_max,
+                    crs: get_crs(&run.config)?,
+                    dim_x,
+                    dim_y,
+                    heights: &heights,
+                    min_height,
+                    max_height,
+                };
+
+                for format in run.config.parameters.formats.iter() {
+                    let writer =
+                        format.writer(BigTiffMode::Auto, run.config.parameters.integer_heights);
+                    checksums.put(
+                        &format!("{}.{}", stem, writer.extension()),
+                        &writer.encode(&raster)?,
+                    )?;
+                }
+            }
+
+            renormalized += 1;
+        }
+    }
+
+    let tiles = tiles
+        .into_values()
+        .map(|(_, tile)| tile)
+        .collect::<Vec<TileMetadata>>();
+
+    if let Some(mosaic_tile_size) = mosaic_tile_size {
+        write_mosaic(
+            &checksums,
+            &runs[0].config,
+            &tiles,
+            mosaic_tiles,
+            mosaic_tile_size,
+            (min_height, max_height),
+        )?;
+    }
+
+    // The area and settings are those of the first run, the shard is gone after merging
+    let first = &mut runs[0].config;
+    let mut parameters = mem::take(&mut first.parameters);
+    parameters.shard = None;
+
+    let merged = ComputeConfig {
+        texture_resolution: first.texture_resolution,
+        max_height,
+        min_height,
+        real_world_dimensions_m: computer::get_real_world_dimensions(&tiles),
+        crs: first.crs.clone(),
+        source_crs: first.source_crs.clone(),
+        grid: computer::get_grid_layout(&tiles)?,
+        tiles,
+        parameters,
+        auto_tune: first.auto_tune.take(),
+        nodata: first.nodata,
+    };
+
+    checksums.put(
+        "config.json",
+        serde_json::to_string_pretty(&merged)?.as_bytes(),
+    )?;
+    let previous_manifest = in_place
+        .contains(&true)
+        .then(|| verifier::read_manifest(destination_folder).ok())
+        .flatten();
+    checksums.write_merged_manifest(merged.parameters, previous_manifest)?;
+
+    println!(
+        "Merged {} tiles of {} runs into {}",
+        merged.grid.tile_count,
+        runs.len(),
+        destination_folder
+    );
+    if renormalized > 0 {
+        println!(
+            "Rewrote {} tiles to the common height range {:.2} m to {:.2} m",
+            renormalized, min_height, max_height
+        );
+    }
+
+    Ok(())
+}
+
+fn read_runs(folders: &[String]) -> Result<Vec<Run>, Box<dyn Error + Send + Sync>> {
+    let mut runs = vec![];
+
+    for folder in folders {
+        let mut file_names = fs::read_dir(folder)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|file_name| {
+                file_name == "config.json"
+                    || (file_name.starts_with("config.shard-") && file_name.ends_with(".json"))
+            })
+            .collect::<Vec<String>>();
+        file_names.sort();
+
+        if file_names.is_empty() {
+            return Err(format!("{} holds no config.json of a generated terrain", folder).into());
+        }
+
+        for file_name in file_names {
+            runs.push(Run {
+                folder: folder.clone(),
+                config: computer::read_compute_config_file(folder, &file_name)?,
+                file_name,
+            });
+        }
+    }
+
+    Ok(runs)
+}
+
+// Tiles gridded with different settings would not match at their seams
+fn check_compatible(runs: &[Run]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let first = &runs[0];
+
+    for run in runs.iter().skip(1) {
+        let (a, b) = (&first.config, &run.config);
+        let (parameters_a, parameters_b) = (&a.parameters, &b.parameters);
+
+        let formats = |formats: &[OutputFormat]| formats.iter().copied().collect::<HashSet<_>>();
+        let differences = [
+            ("CRS", a.crs != b.crs || a.source_crs != b.source_crs),
+            ("resolution", a.texture_resolution != b.texture_resolution),
+            (
+                "adaptive resolution",
+                parameters_a.adaptive_resolution != parameters_b.adaptive_resolution,
+            ),
+            (
+                "sample size",
+                parameters_a.sample_size != parameters_b.sample_size
+                    || parameters_a.max_neighbour_distance_m
+                        != parameters_b.max_neighbour_distance_m,
+            ),
+            (
+                "blur",
+                parameters_a.blur_kernel_size != parameters_b.blur_kernel_size
+                    || parameters_a.blur_target != parameters_b.blur_target
+                    || parameters_a.void_dilation != parameters_b.void_dilation,
+            ),
+            (
+                "blur levels",
+                parameters_a.blur_levels != parameters_b.blur_levels,
+            ),
+            ("z scale", parameters_a.z_scale != parameters_b.z_scale),
+            ("naming", parameters_a.naming != parameters_b.naming),
+            (
+                "formats",
+                formats(&parameters_a.formats) != formats(&parameters_b.formats),
+            ),
+            (
+                "equalization",
+                parameters_a.equalized != parameters_b.equalized,
+            ),
+            (
+                "integer heights",
+                parameters_a.integer_heights != parameters_b.integer_heights,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(name, _)| name)
+        .collect::<Vec<&str>>();
+
+        if !differences.is_empty() {
+            return Err(format!(
+                "{}/{} and {}/{} differ in {}",
+                first.folder,
+                first.file_name,
+                run.folder,
+                run.file_name,
+                differences.join(", ")
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+fn check_shards(runs: &[Run]) {
+    let shards = runs
+        .iter()
+        .filter_map(|run| run.config.parameters.shard)
+        .collect::<Vec<_>>();
+
+    if let Some(count) = shards.first().map(|shard| shard.count) {
+        for index in 1..=count {
+            if !shards
+                .iter()
+                .any(|shard| shard.count == count && shard.index == index)
+            {
+                println!("Shard {}/{} is not among the merged runs", index, count);
+            }
+        }
+    }
+}
+
+// Stems of the heightmaps written for a tile: the tile itself, its blur levels and its stylized
+// and conditioned copies
+pub fn get_heightmap_stems(parameters: &ComputeParameters, file_stem: &str) -> Vec<String> {
+    std::iter::once(file_stem.to_string())
+        .chain(
+            parameters
+                .blur_levels
+                .iter()
+                .map(|kernel_size| format!("{}_blur{}", file_stem, kernel_size)),
+        )
+        .chain(
+            parameters
+                .stylized
+                .then(|| format!("{}_stylized", file_stem)),
+        )
+        .chain(
+            parameters
+                .hydro_condition
+                .map(|_| format!("{}_conditioned", file_stem)),
+        )
+        .collect()
+}
+
+// Files written for a tile: its heightmaps and the maps named after it, e.g. img_0_1_ao.png
+pub fn list_tile_files(
+    folder: &str,
+    file_stem: &str,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let (dot_prefix, underscore_prefix) = (format!("{}.", file_stem), format!("{}_", file_stem));
+    let mut file_names = vec![];
+
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.file_type()?.is_file()
+            && !file_name.ends_with(".part")
+            && (file_name.starts_with(&dot_prefix) || file_name.starts_with(&underscore_prefix))
+        {
+            file_names.push(file_name);
+        }
+    }
+
+    Ok(file_names)
+}
+
+// Heights of a heightmap of the run, mapped from the run's range into the common one
+fn read_heights(
+    run: &Run,
+    stem: &str,
+    (min_height, max_height): (f64, f64),
+) -> Result<(usize, usize, Vec<f32>), Box<dyn Error + Send + Sync>> {
+    let path = storage::get_local_path(&run.folder, &format!("{}.exr", stem));
+
+    if !path.exists() {
+        return Err(format!(
+            "Merging runs of different height ranges or building a mosaic needs EXR heightmaps, \
+             {} is missing",
+            path.display()
+        )
+        .into());
+    }
+
+    let (dim_x, dim_y, mut heights) = writer::read_texture(path)?;
+    let run_range = run.config.max_height - run.config.min_height;
+    let range = (max_height - min_height).max(f64::EPSILON);
+
+    for height in heights.iter_mut() {
+        let meters = *height as f64 * run_range + run.config.min_height;
+        *height = ((meters - min_height) / range) as f32;
+    }
+
+    Ok((dim_x, dim_y, heights))
+}
+
+pub fn get_crs(config: &ComputeConfig) -> Result<Crs, Box<dyn Error + Send + Sync>> {
+    parse_crs(&config.crs)
+}
+
+// Configs written before the source CRS was recorded were never reprojected
+pub fn get_source_crs(config: &ComputeConfig) -> Result<Crs, Box<dyn Error + Send + Sync>> {
+    if config.source_crs.is_empty() {
+        return get_crs(config);
+    }
+
+    parse_crs(&config.source_crs)
+}
+
+// Bounds of a heightmap in its own CRS from bounds in the CRS of the tile grid
+pub fn get_raster_bounds(
+    config: &ComputeConfig,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+) -> Result<((f64, f64), (f64, f64)), Box<dyn Error + Send + Sync>> {
+    let (source_crs, crs) = (get_source_crs(config)?, get_crs(config)?);
+
+    if source_crs == crs {
+        return Ok((bounds_min, bounds_max));
+    }
+
+    Ok(projection::reproject_bounds(
+        source_crs, crs, bounds_min, bounds_max,
+    ))
+}
+
+fn parse_crs(value: &str) -> Result<Crs, Box<dyn Error + Send + Sync>> {
+    value
+        .strip_prefix("EPSG:")
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(Crs::from_epsg)
+        .ok_or_else(|| format!("Unknown CRS '{}' in config.json", value).into())
+}
+
+// Nearest neighbour, the mosaic is an overview and tiles may differ in resolution
+fn downsample(heights: &[f32], (dim_x, dim_y): (usize, usize), size: usize) -> Vec<f32> {
+    (0..size * size)
+        .map(|index| {
+            let (row, column) = (index / size, index % size);
+            heights[(row * dim_y / size) * dim_x + column * dim_x / size]
+        })
+        .collect()
+}
+
+// One raster of all tiles at mosaic_tile_size pixels per tile, written as mosaic.exr and
+// mosaic.png. Cells without a tile are NaN in the EXR.
+fn write_mosaic(
+    sink: &dyn OutputSink,
+    config: &ComputeConfig,
+    tiles: &[TileMetadata],
+    mosaic_tiles: Vec<((i32, i32), Vec<f32>)>,
+    mosaic_tile_size: usize,
+    (min_height, max_height): (f64, f64),
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let grid = computer::get_grid_layout(tiles)?;
+    let (dim_x, dim_y) = (
+        grid.columns as usize * mosaic_tile_size,
+        grid.rows as usize * mosaic_tile_size,
+    );
+    let mut heights = vec![f32::NAN; dim_x * dim_y];
+
+    // Offsets grow to the north, rows of the raster to the south
+    for ((offset_x, offset_y), tile_heights) in mosaic_tiles {
+        let column = (offset_x - grid.min_offset.0) as usize * mosaic_tile_size;
+        let row = (grid.max_offset.1 - offset_y) as usize * mosaic_tile_size;
+
+        for (tile_row, values) in tile_heights.chunks_exact(mosaic_tile_size).enumerate() {
+            let start = (row + tile_row) * dim_x + column;
+            heights[start..start + mosaic_tile_size].copy_from_slice(values);
+        }
+    }
+
+    // Laid out on the tile grid, so the mosaic is in the CRS of the grid
+    let raster = TileRaster {
+        bounds_min: (
+            tiles
+                .iter()
+                .fold(f64::MAX, |min, tile| min.min(tile.bounds_min.0)),
+            tiles
+                .iter()
+                .fold(f64::MAX, |min, tile| min.min(tile.bounds_min.1)),
+        ),
+        bounds_max: (
+            tiles
+                .iter()
+                .fold(f64::MIN, |max, tile| max.max(tile.bounds_max.0)),
+            tiles
+                .iter()
+                .fold(f64::MIN, |max, tile| max.max(tile.bounds_max.1)),
+        ),
+        crs: get_source_crs(config)?,
+        dim_x,
+        dim_y,
+        heights: &heights,
+        min_height,
+        max_height,
+    };
+
+    sink.put("mosaic.exr", &ExrWriter.encode(&raster)?)?;
+    sink.put("mosaic.png", &PngWriter.encode(&raster)?)?;
+
+    println!("Wrote a {}x{} mosaic", dim_x, dim_y);
+
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use std::{
+    cell::Cell,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::progress::TileStatus;
+
+const STATUSES: [(TileStatus, &str); 6] = [
+    (TileStatus::Downloading, "downloading"),
+    (TileStatus::Downloaded, "downloaded"),
+    (TileStatus::Postponed, "postponed"),
+    (TileStatus::Missing, "missing"),
+    (TileStatus::Failed, "failed"),
+    (TileStatus::Computed, "computed"),
+];
+
+// Index, Gridding, Blur and Encode are parts of Compute
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Download,
+    Decode,
+    Index,
+    Gridding,
+    Blur,
+    Encode,
+    Compute,
+}
+
+const STAGES: [(Stage, &str); 7] = [
+    (Stage::Download, "download"),
+    (Stage::Decode, "decode"),
+    (Stage::Index, "index"),
+    (Stage::Gridding, "gridding"),
+    (Stage::Blur, "blur"),
+    (Stage::Encode, "encode"),
+    (Stage::Compute, "compute"),
+];
+
+// Milliseconds one tile spent in every stage, written to report.json
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct TileTimings {
+    pub download_ms: f64,
+    pub decode_ms: f64,
+    pub index_ms: f64,
+    pub gridding_ms: f64,
+    pub blur_ms: f64,
+    pub encode_ms: f64,
+    pub compute_ms: f64,
+}
+
+impl TileTimings {
+    fn get(mut self, stage: Stage) -> f64 {
+        *self.get_mut(stage)
+    }
+
+    fn get_mut(&mut self, stage: Stage) -> &mut f64 {
+        match stage {
+            Stage::Download => &mut self.download_ms,
+            Stage::Decode => &mut self.decode_ms,
+            Stage::Index => &mut self.index_ms,
+            Stage::Gridding => &mut self.gridding_ms,
+            Stage::Blur => &mut self.blur_ms,
+            Stage::Encode => &mut self.encode_ms,
+            Stage::Compute => &mut self.compute_ms,
+        }
+    }
+
+    pub fn merge(mut self, other: TileTimings) -> TileTimings {
+        for (stage, _) in STAGES {
+            *self.get_mut(stage) += other.get(stage);
+        }
+
+        self
+    }
+
+    // Stacks in the folded format of flamegraph tools, one line per stage with its microseconds.
+    // Compute only counts the time not spent in its parts.
+    pub fn to_folded(self, file_stem: &str) -> String {
+        let parts = [Stage::Index, Stage::Gridding, Stage::Blur, Stage::Encode];
+        let parts_ms = parts.iter().map(|stage| self.get(*stage)).sum::<f64>();
+        let compute_self_ms = (self.compute_ms - parts_ms).max(0.0);
+
+        let mut folded = String::new();
+        for (stage, name) in STAGES {
+            let (stack, ms) = match stage {
+                Stage::Download | Stage::Decode => (name.to_string(), self.get(stage)),
+                Stage::Compute => ("compute".to_string(), compute_self_ms),
+                _ => (format!("compute;{}", name), self.get(stage)),
+            };
+
+            let micros = (ms * 1000.0).round() as u64;
+            if micros > 0 {
+                let _ = writeln!(folded, "{};{} {}", stack, file_stem, micros);
+            }
+        }
+
+        folded
+    }
+}
+
+thread_local! {
+    // Stages recorded on this thread for the tile it currently works on
+    static TILE_TIMINGS: Cell<TileTimings> = Cell::new(TileTimings::default());
+}
+
+// Counters of the whole process, batch jobs add up
+struct Metrics {
+    tiles: [AtomicU64; STATUSES.len()],
+    downloaded_bytes: AtomicU64,
+    stage_micros: [AtomicU64; STAGES.len()],
+    stage_runs: [AtomicU64; STAGES.len()],
+}
+
+static METRICS: Metrics = Metrics {
+    tiles: [const { AtomicU64::new(0) }; STATUSES.len()],
+    downloaded_bytes: AtomicU64::new(0),
+    stage_micros: [const { AtomicU64::new(0) }; STAGES.len()],
+    stage_runs: [const { AtomicU64::new(0) }; STAGES.len()],
+};
+
+static SERVER_ADDRESS: OnceLock<String> = OnceLock::new();
+
+pub fn count_tile(status: TileStatus) {
+    METRICS.tiles[status as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn count_downloaded_bytes(bytes: usize) {
+    METRICS
+        .downloaded_bytes
+        .fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn record_stage(stage: Stage, duration: Duration) {
+    METRICS.stage_micros[stage as usize].fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    METRICS.stage_runs[stage as usize].fetch_add(1, Ordering::Relaxed);
+
+    TILE_TIMINGS.with(|timings| {
+        let mut tile_timings = timings.get();
+        *tile_timings.get_mut(stage) += duration.as_secs_f64() * 1000.0;
+        timings.set(tile_timings);
+    });
+}
+
+// Timings recorded on this thread since the last call
+pub fn take_tile_timings() -> TileTimings {
+    TILE_TIMINGS.take()
+}
+
+// Serves the counters in the Prometheus text format on every request to the address until the
+// process exits. Later calls, e.g. from the next batch job, keep the first server.
+pub fn serve(address: &str) -> io::Result<()> {
+    if let Some(serving) = SERVER_ADDRESS.get() {
+        if serving != address {
+            println!(
+                "Metrics are already served on {}, ignoring {}",
+                serving, address
+            );
+        }
+
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(address)?;
+    let _ = SERVER_ADDRESS.set(address.to_string());
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(error) = answer(stream) {
+                println!("Metrics request failed: {}", error);
+            }
+        }
+    });
+
+    println!("Serving metrics on http://{}/metrics", address);
+
+    Ok(())
+}
+
+fn answer(mut stream: TcpStream) -> io::Result<()> {
+    // Any request gets the metrics, the request itself is only drained
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = [0u8; 1024];
+    let _ = stream.read(&mut request)?;
+
+    let body = render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+fn render() -> String {
+    let mut body = String::new();
+
+    body.push_str("# HELP ltg_tiles_total Tile events by status\n");
+    body.push_str("# TYPE ltg_tiles_total counter\n");
+    for (status, name) in STATUSES {
+        let _ = writeln!(
+            body,
+            "ltg_tiles_total{{status=\"{}\"}} {}",
+            name,
+            METRICS.tiles[status as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    body.push_str("# HELP ltg_downloaded_bytes_total Bytes of LAZ data fetched\n");
+    body.push_str("# TYPE ltg_downloaded_bytes_total counter\n");
+    let _ = writeln!(
+        body,
+        "ltg_downloaded_bytes_total {}",
+        METRICS.downloaded_bytes.load(Ordering::Relaxed)
+    );
+
+    body.push_str(
+        "# HELP ltg_stage_seconds_total Time spent per tile stage, summed over threads\n",
+    );
+    body.push_str("# TYPE ltg_stage_seconds_total counter\n");
+    for (stage, name) in STAGES {
+        let _ = writeln!(
+            body,
+            "ltg_stage_seconds_total{{stage=\"{}\"}} {}",
+            name,
+            METRICS.stage_micros[stage as usize].load(Ordering::Relaxed) as f64 / 1e6
+        );
+    }
+
+    body.push_str("# HELP ltg_stage_runs_total Tiles that went through a stage\n");
+    body.push_str("# TYPE ltg_stage_runs_total counter\n");
+    for (stage, name) in STAGES {
+        let _ = writeln!(
+            body,
+            "ltg_stage_runs_total{{stage=\"{}\"}} {}",
+            name,
+            METRICS.stage_runs[stage as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    body
+}
@@ -0,0 +1,374 @@
+use std::{collections::HashMap, num::NonZero, thread};
+
+use serde::Serialize;
+
+use crate::{
+    core::Config,
+    global_constants::TILE_SIZE_M,
+    requester::LazData,
+    storage::OutputSink,
+    writer::{OutputError, TileRaster},
+};
+
+pub struct MosaicTile<'a> {
+    pub data: &'a LazData,
+    pub heights: &'a [f32],
+}
+
+// Samples the computed tile rasters as one continuous surface in D96TM coordinates. Tiles are
+// looked up through their cell on the survey grid; positions without a tile yield NaN.
+pub struct Mosaic<'a> {
+    tiles: Vec<MosaicTile<'a>>,
+    cells: HashMap<(i64, i64), usize>,
+    dim_x: usize,
+    dim_y: usize,
+    seam_blend_m: f64,
+}
+
+impl<'a> Mosaic<'a> {
+    pub fn new(tiles: Vec<MosaicTile<'a>>, dim_x: usize, dim_y: usize, seam_blend_m: f64) -> Self {
+        let cells = tiles
+            .iter()
+            .enumerate()
+            .map(|(index, tile)| {
+                (
+                    get_cell(tile.data.bounds_min.0, tile.data.bounds_min.1),
+                    index,
+                )
+            })
+            .collect();
+
+        Mosaic {
+            tiles,
+            cells,
+            dim_x,
+            dim_y,
+            seam_blend_m,
+        }
+    }
+
+    // Extent of the covered grid cells as (min_x, min_y, max_x, max_y)
+    pub fn extent(&self) -> (f64, f64, f64, f64) {
+        let (mut min_cell, mut max_cell) = ((i64::MAX, i64::MAX), (i64::MIN, i64::MIN));
+
+        for cell in self.cells.keys() {
+            min_cell = (min_cell.0.min(cell.0), min_cell.1.min(cell.1));
+            max_cell = (max_cell.0.max(cell.0), max_cell.1.max(cell.1));
+        }
+
+        (
+            min_cell.0 as f64 * TILE_SIZE_M,
+            min_cell.1 as f64 * TILE_SIZE_M,
+            (max_cell.0 + 1) as f64 * TILE_SIZE_M,
+            (max_cell.1 + 1) as f64 * TILE_SIZE_M,
+        )
+    }
+
+    pub fn sample(&self, geo_x: f64, geo_y: f64) -> f32 {
+        let cell = get_cell(geo_x, geo_y);
+        let tile = match self.cells.get(&cell) {
+            Some(index) => &self.tiles[*index],
+            None => return f32::NAN,
+        };
+
+        let value = self.sample_tile(tile, geo_x, geo_y);
+
+        if self.seam_blend_m > 0.0 {
+            value + self.get_seam_correction(tile, cell, geo_x, geo_y)
+        } else {
+            value
+        }
+    }
+
+    // Where a neighbouring tile comes from a different survey, both sides are pulled towards the
+    // mean of their edge heights, fading out linearly over the blend width. The two corrections
+    // meet at the seam, so the surface is continuous across it.
+    fn get_seam_correction(
+        &self,
+        tile: &MosaicTile,
+        cell: (i64, i64),
+        geo_x: f64,
+        geo_y: f64,
+    ) -> f32 {
+        let (cell_min_x, cell_min_y) = (cell.0 as f64 * TILE_SIZE_M, cell.1 as f64 * TILE_SIZE_M);
+        let (cell_max_x, cell_max_y) = (cell_min_x + TILE_SIZE_M, cell_min_y + TILE_SIZE_M);
+
+        let edges = [
+            ((-1, 0), geo_x - cell_min_x, (cell_min_x, geo_y)),
+            ((1, 0), cell_max_x - geo_x, (cell_max_x, geo_y)),
+            ((0, -1), geo_y - cell_min_y, (geo_x, cell_min_y)),
+            ((0, 1), cell_max_y - geo_y, (geo_x, cell_max_y)),
+        ];
+
+        let mut correction = 0.0;
+
+        for (step, distance, edge) in edges {
+            if distance >= self.seam_blend_m {
+                continue;
+            }
+
+            let neighbour = match self.cells.get(&(cell.0 + step.0, cell.1 + step.1)) {
+                Some(index) => &self.tiles[*index],
+                None => continue,
+            };
+
+            if neighbour.data.survey == tile.data.survey {
+                continue;
+            }
+
+            let own_edge = self.sample_tile(tile, edge.0, edge.1);
+            let neighbour_edge = self.sample_tile(neighbour, edge.0, edge.1);
+
+            if own_edge.is_nan() || neighbour_edge.is_nan() {
+                continue;
+            }
+
+            let weight = (1.0 - distance / self.seam_blend_m) as f32;
+            correction += (neighbour_edge - own_edge) * 0.5 * weight;
+        }
+
+        correction
+    }
+
+    fn sample_tile(&self, tile: &MosaicTile, geo_x: f64, geo_y: f64) -> f32 {
+        let (min_x, max_y) = (tile.data.bounds_min.0, tile.data.bounds_max.1);
+        let (delta_x, delta_y) = (
+            tile.data.bounds_max.0 - min_x,
+            max_y - tile.data.bounds_min.1,
+        );
+
+        // Row 0 of a tile raster lies on the northern edge of the tile
+        let column =
+            ((geo_x - min_x) / delta_x * self.dim_x as f64).clamp(0.0, (self.dim_x - 1) as f64);
+        let row =
+            ((max_y - geo_y) / delta_y * self.dim_y as f64).clamp(0.0, (self.dim_y - 1) as f64);
+
+        let (column_0, row_0) = (column.floor() as usize, row.floor() as usize);
+        let (column_1, row_1) = (
+            (column_0 + 1).min(self.dim_x - 1),
+            (row_0 + 1).min(self.dim_y - 1),
+        );
+        let (weight_x, weight_y) = (
+            (column - column_0 as f64) as f32,
+            (row - row_0 as f64) as f32,
+        );
+
+        let value = |column: usize, row: usize| tile.heights[row * self.dim_x + column];
+        let top = value(column_0, row_0) * (1.0 - weight_x) + value(column_1, row_0) * weight_x;
+        let bottom = value(column_0, row_1) * (1.0 - weight_x) + value(column_1, row_1) * weight_x;
+
+        top * (1.0 - weight_y) + bottom * weight_y
+    }
+}
+
+// Halves the resolution of a raster by averaging blocks of 2x2 pixels, NaN pixels are left out of
+// the mean and a block without valid pixels stays NaN. An odd last column or row averages the
+// pixels it has. Returns the heights and their dimensions.
+pub fn average_2x2(heights: &[f32], (dim_x, dim_y): (usize, usize)) -> (Vec<f32>, (usize, usize)) {
+    let (half_x, half_y) = (dim_x.div_ceil(2), dim_y.div_ceil(2));
+    let mut averaged = vec![f32::NAN; half_x * half_y];
+
+    for (index, value) in averaged.iter_mut().enumerate() {
+        let (column, row) = (index % half_x * 2, index / half_x * 2);
+        let (mut sum, mut count) = (0f32, 0);
+
+        for (ind_x, ind_y) in [
+            (column, row),
+            (column + 1, row),
+            (column, row + 1),
+            (column + 1, row + 1),
+        ] {
+            if ind_x < dim_x && ind_y < dim_y && !heights[ind_y * dim_x + ind_x].is_nan() {
+                sum += heights[ind_y * dim_x + ind_x];
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            *value = sum / count as f32;
+        }
+    }
+
+    (averaged, (half_x, half_y))
+}
+
+fn get_cell(geo_x: f64, geo_y: f64) -> (i64, i64) {
+    (
+        (geo_x / TILE_SIZE_M).floor() as i64,
+        (geo_y / TILE_SIZE_M).floor() as i64,
+    )
+}
+
+#[derive(Serialize)]
+struct ChunkMetadata {
+    file_stem: String,
+    column: usize,
+    row: usize,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+}
+
+#[derive(Serialize)]
+struct ChunkLayout {
+    chunk_size_px: usize,
+    chunk_size_m: f64,
+    pixel_size_m: f64,
+    columns: usize,
+    rows: usize,
+    chunks: Vec<ChunkMetadata>,
+}
+
+// Resolves --chunk-size and --chunk-size-m into (pixels, meters) per chunk side. A missing
+// value keeps the pixel size of the source tiles.
+pub fn get_chunk_dimensions(config: &Config) -> Option<(usize, f64)> {
+    let tile_pixel_size = TILE_SIZE_M / config.resolution as f64;
+
+    match (config.chunk_size, config.chunk_size_m) {
+        (Some(pixels), Some(meters)) => Some((pixels as usize, meters)),
+        (Some(pixels), None) => Some((pixels as usize, pixels as f64 * tile_pixel_size)),
+        (None, Some(meters)) => Some(((meters / tile_pixel_size).round() as usize, meters)),
+        (None, None) => None,
+    }
+}
+
+// Chunks are distributed over the threads, each of which reuses a single chunk buffer, so memory
+// stays at one chunk per thread regardless of the size of the area
+pub fn write_chunks(
+    config: &Config,
+    cpus: NonZero<usize>,
+    sink: &dyn OutputSink,
+    mosaic: &Mosaic,
+    min_height: f64,
+    max_height: f64,
+) -> Result<(), OutputError> {
+    let (chunk_size_px, chunk_size_m) = match get_chunk_dimensions(config) {
+        Some(dimensions) => dimensions,
+        None => return Ok(()),
+    };
+
+    let (min_x, min_y, max_x, max_y) = mosaic.extent();
+    let pixel_size = chunk_size_m / chunk_size_px as f64;
+    let columns = ((max_x - min_x) / chunk_size_m).ceil() as usize;
+    let rows = ((max_y - min_y) / chunk_size_m).ceil() as usize;
+
+    println!(
+        "Writing up to {} chunks of {} px covering {} m",
+        columns * rows,
+        chunk_size_px,
+        chunk_size_m
+    );
+
+    let chunks = thread::scope(|scope| -> Result<Vec<ChunkMetadata>, OutputError> {
+        let mut results = vec![];
+        for id in 0..cpus.get() {
+            let result = scope.spawn(move || -> Result<Vec<ChunkMetadata>, OutputError> {
+                let mut chunks = vec![];
+                let mut heights = vec![0f32; chunk_size_px * chunk_size_px];
+
+                for chunk_index in (id..columns * rows).step_by(cpus.get()) {
+                    let (column, row) = (chunk_index % columns, chunk_index / columns);
+                    let chunk_min_x = min_x + column as f64 * chunk_size_m;
+                    let chunk_max_y = max_y - row as f64 * chunk_size_m;
+
+                    for (linear_index, height) in heights.iter_mut().enumerate() {
+                        let (ind_x, ind_y) =
+                            (linear_index % chunk_size_px, linear_index / chunk_size_px);
+
+                        *height = mosaic.sample(
+                            chunk_min_x + ind_x as f64 * pixel_size,
+                            chunk_max_y - ind_y as f64 * pixel_size,
+                        );
+                    }
+
+                    if heights.iter().all(|height| height.is_nan()) {
+                        continue;
+                    }
+
+                    let chunk = ChunkMetadata {
+                        file_stem: format!("chunk_{:03}_{:03}", column, row),
+                        column,
+                        row,
+                        bounds_min: (chunk_min_x, chunk_max_y - chunk_size_m),
+                        bounds_max: (chunk_min_x + chunk_size_m, chunk_max_y),
+                    };
+
+                    let raster = TileRaster {
+                        bounds_min: chunk.bounds_min,
+                        bounds_max: chunk.bounds_max,
+                        crs: config.source_crs,
+                        dim_x: chunk_size_px,
+                        dim_y: chunk_size_px,
+                        heights: &heights,
+                        min_height,
+                        max_height,
+                    };
+
+                    for format in config.formats.iter() {
+                        let writer = format.writer(config.big_tiff, config.integer_heights);
+                        let file_name = format!("{}.{}", chunk.file_stem, writer.extension());
+
+                        sink.put(&file_name, &writer.encode(&raster)?)?;
+                    }
+
+                    chunks.push(chunk);
+                }
+
+                Ok(chunks)
+            });
+
+            results.push(result);
+        }
+
+        let mut chunks = vec![];
+        for result in results {
+            chunks.extend(result.join().unwrap()?);
+        }
+
+        chunks.sort_by_key(|chunk| (chunk.row, chunk.column));
+
+        Ok(chunks)
+    })?;
+
+    let layout = ChunkLayout {
+        chunk_size_px,
+        chunk_size_m,
+        pixel_size_m: pixel_size,
+        columns,
+        rows,
+        chunks,
+    };
+
+    sink.put(
+        "chunks.json",
+        serde_json::to_string_pretty(&layout)?.as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn average_2x2_skips_nan_pixels() {
+        let heights = [
+            1.0,
+            3.0,
+            5.0,
+            f32::NAN,
+            f32::NAN,
+            7.0,
+            f32::NAN,
+            f32::NAN,
+            9.0,
+        ];
+        let (averaged, dimensions) = average_2x2(&heights, (3, 3));
+
+        assert_eq!(dimensions, (2, 2));
+        assert_eq!(averaged[0], 2.0);
+        assert_eq!(averaged[1], 6.0);
+        assert!(averaged[2].is_nan());
+        assert_eq!(averaged[3], 9.0);
+    }
+}
@@ -0,0 +1,265 @@
+use std::io::{self, Cursor, Read};
+
+use las::{Bounds, Reader, Vector, point::Classification};
+use thiserror::Error;
+use zip::ZipArchive;
+
+// Packed tiles store the points as flat little-endian arrays so loading them is a plain copy
+// instead of a LAZ decompression. Coordinates are kept as f32 offsets from the tile minimum,
+// which keeps sub-millimeter precision over a 1 km tile.
+const PACKED_MAGIC: &[u8; 4] = b"LTGP";
+// Version 2 appends the intensities; version 1 files are still read, with zero intensity
+const PACKED_VERSION: u32 = 2;
+const PACKED_HEADER_SIZE: usize = 4 + 4 + 8 + 6 * 8;
+// Public header size of LAS 1.0 to 1.2, later versions only extend it
+const LAS_MIN_HEADER_SIZE: usize = 227;
+const ZIP_MAGIC: &[u8; 4] = b"PK\x03\x04";
+
+// Failures of turning downloaded or cached tile bytes into points
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error(transparent)]
+    Las(#[from] las::Error),
+
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Tiff(#[from] tiff::TiffError),
+
+    #[error(transparent)]
+    Png(#[from] png::DecodingError),
+
+    // Bytes that are not a tile of the expected kind, or only part of one
+    #[error("{0}")]
+    Malformed(String),
+}
+
+pub fn is_packed(bytes: &[u8]) -> bool {
+    bytes.starts_with(PACKED_MAGIC)
+}
+
+pub fn pack(laz_bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let (bounds, points) = decode_laz(laz_bytes)?;
+
+    Ok(pack_points(&bounds, &points))
+}
+
+pub fn pack_points(bounds: &Bounds, points: &[las::Point]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(PACKED_HEADER_SIZE + points.len() * 15);
+    bytes.extend_from_slice(PACKED_MAGIC);
+    bytes.extend_from_slice(&PACKED_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(points.len() as u64).to_le_bytes());
+
+    for value in [
+        bounds.min.x,
+        bounds.min.y,
+        bounds.min.z,
+        bounds.max.x,
+        bounds.max.y,
+        bounds.max.z,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for (values, origin) in [
+        (
+            points.iter().map(|point| point.x).collect::<Vec<f64>>(),
+            bounds.min.x,
+        ),
+        (points.iter().map(|point| point.y).collect(), bounds.min.y),
+        (points.iter().map(|point| point.z).collect(), bounds.min.z),
+    ] {
+        for value in values {
+            bytes.extend_from_slice(&((value - origin) as f32).to_le_bytes());
+        }
+    }
+
+    bytes.extend(points.iter().map(|point| u8::from(point.classification)));
+
+    for point in points {
+        bytes.extend_from_slice(&point.intensity.to_le_bytes());
+    }
+
+    bytes
+}
+
+pub fn unpack(bytes: &[u8]) -> Result<(Bounds, Vec<las::Point>), DecodeError> {
+    if bytes.len() < PACKED_HEADER_SIZE || !is_packed(bytes) {
+        return Err(DecodeError::Malformed(
+            "Packed tile header is missing".to_string(),
+        ));
+    }
+
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+
+    if version != 1 && version != PACKED_VERSION {
+        return Err(DecodeError::Malformed(format!(
+            "Unsupported packed tile version {}",
+            version
+        )));
+    }
+
+    let count = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+    let header = (0..6)
+        .map(|index| {
+            let start = 16 + index * 8;
+            f64::from_le_bytes(bytes[start..start + 8].try_into().unwrap())
+        })
+        .collect::<Vec<f64>>();
+
+    let point_size = if version == 1 { 13 } else { 15 };
+
+    if bytes.len() != PACKED_HEADER_SIZE + count * point_size {
+        return Err(DecodeError::Malformed(
+            "Packed tile is truncated".to_string(),
+        ));
+    }
+
+    let bounds = Bounds {
+        min: Vector {
+            x: header[0],
+            y: header[1],
+            z: header[2],
+        },
+        max: Vector {
+            x: header[3],
+            y: header[4],
+            z: header[5],
+        },
+    };
+
+    let coordinate = |axis: usize, index: usize| {
+        let start = PACKED_HEADER_SIZE + (axis * count + index) * 4;
+        f32::from_le_bytes([
+            bytes[start],
+            bytes[start + 1],
+            bytes[start + 2],
+            bytes[start + 3],
+        ]) as f64
+    };
+    let classes = &bytes[PACKED_HEADER_SIZE + count * 12..PACKED_HEADER_SIZE + count * 13];
+    let intensity = |index: usize| {
+        if version == 1 {
+            return 0;
+        }
+
+        let start = PACKED_HEADER_SIZE + count * 13 + index * 2;
+        u16::from_le_bytes([bytes[start], bytes[start + 1]])
+    };
+
+    let points = (0..count)
+        .map(|index| las::Point {
+            x: coordinate(0, index) + bounds.min.x,
+            y: coordinate(1, index) + bounds.min.y,
+            z: coordinate(2, index) + bounds.min.z,
+            classification: Classification::new(classes[index])
+                .unwrap_or(Classification::CreatedNeverClassified),
+            intensity: intensity(index),
+            ..Default::default()
+        })
+        .collect();
+
+    Ok((bounds, points))
+}
+
+// Reads the points of a tile in either packed or LAZ form
+pub fn decode(bytes: Vec<u8>) -> Result<(Bounds, Vec<las::Point>), DecodeError> {
+    if is_packed(&bytes) {
+        unpack(&bytes)
+    } else {
+        decode_laz(&bytes)
+    }
+}
+
+// Some providers ship tiles as zip archives holding a single LAS or LAZ file. Anything that is
+// not a zip archive is returned as is.
+pub fn unwrap_archive(bytes: Vec<u8>) -> Result<Vec<u8>, DecodeError> {
+    if !bytes.starts_with(ZIP_MAGIC) {
+        return Ok(bytes);
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let name = file.name().to_lowercase();
+
+        if file.is_file() && (name.ends_with(".las") || name.ends_with(".laz")) {
+            let mut contents = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut contents)?;
+
+            return Ok(contents);
+        }
+    }
+
+    Err(DecodeError::Malformed(
+        "Zip archive contains no LAS or LAZ file".to_string(),
+    ))
+}
+
+// Cheap sanity check of the LAS public header, so that HTML error pages and truncated downloads
+// are rejected before they reach the reader
+pub fn check_las_header(bytes: &[u8]) -> Result<(), DecodeError> {
+    if bytes.len() < LAS_MIN_HEADER_SIZE || !bytes.starts_with(b"LASF") {
+        return Err(DecodeError::Malformed(
+            "Response is not a LAS/LAZ file".to_string(),
+        ));
+    }
+
+    let (major, minor) = (bytes[24], bytes[25]);
+    if major != 1 || minor > 4 {
+        return Err(DecodeError::Malformed(format!(
+            "Unsupported LAS version {}.{}",
+            major, minor
+        )));
+    }
+
+    let header_size = u16::from_le_bytes([bytes[94], bytes[95]]) as usize;
+    let point_data_offset = u32::from_le_bytes(bytes[96..100].try_into().unwrap()) as usize;
+
+    if header_size < LAS_MIN_HEADER_SIZE || point_data_offset < header_size {
+        return Err(DecodeError::Malformed("LAS header is corrupt".to_string()));
+    }
+
+    // LAZ files start their point data with the offset of the chunk table, which lies behind the
+    // compressed points (-1 when it was appended by a streaming writer). Uncompressed point
+    // records have a fixed length.
+    let compressed = bytes[104] & 0x80 != 0;
+    let expected_size = if compressed {
+        bytes
+            .get(point_data_offset..point_data_offset + 8)
+            .map(|offset| i64::from_le_bytes(offset.try_into().unwrap()))
+            .filter(|chunk_table_offset| *chunk_table_offset >= 0)
+            .map(|chunk_table_offset| chunk_table_offset as usize + 8)
+            .unwrap_or(point_data_offset + 8)
+    } else {
+        let record_length = u16::from_le_bytes([bytes[105], bytes[106]]) as usize;
+        let point_count = u32::from_le_bytes(bytes[107..111].try_into().unwrap()) as usize;
+
+        point_data_offset + point_count * record_length
+    };
+
+    if bytes.len() < expected_size {
+        return Err(DecodeError::Malformed(format!(
+            "LAS file is truncated, {} of at least {} bytes",
+            bytes.len(),
+            expected_size
+        )));
+    }
+
+    Ok(())
+}
+
+fn decode_laz(bytes: &[u8]) -> Result<(Bounds, Vec<las::Point>), DecodeError> {
+    check_las_header(bytes)?;
+
+    let mut reader = Reader::new(Cursor::new(bytes))?;
+    let bounds = reader.header().bounds();
+    let points = reader.points().collect::<Result<Vec<_>, _>>()?;
+
+    Ok((bounds, points))
+}
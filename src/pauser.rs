@@ -0,0 +1,70 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::{core::Config, storage};
+
+const PAUSE_FILE: &str = ".pause";
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+static SIGNAL_PAUSED: AtomicBool = AtomicBool::new(false);
+static LISTENING: AtomicBool = AtomicBool::new(false);
+// Set while some thread waits, so pausing and resuming is announced once and not per thread
+static ANNOUNCED: AtomicBool = AtomicBool::new(false);
+
+// Makes SIGUSR1 toggle between paused and running. Without a listener the signal would end the
+// process.
+pub fn listen_for_signal() {
+    if LISTENING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    #[cfg(unix)]
+    match signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1]) {
+        Ok(mut signals) => {
+            thread::spawn(move || {
+                for _ in signals.forever() {
+                    let paused = !SIGNAL_PAUSED.fetch_xor(true, Ordering::SeqCst);
+                    println!(
+                        "Received SIGUSR1, {}",
+                        if paused { "pausing" } else { "resuming" }
+                    );
+                }
+            });
+        }
+        Err(error) => println!("Could not listen for SIGUSR1: {}", error),
+    }
+}
+
+// Blocks before a tile is downloaded or computed while the run is paused by SIGUSR1 or a .pause
+// file in the destination folder. Tiles already in progress finish first.
+pub fn wait_while_paused(config: &Config) {
+    let pause_file = get_pause_file(config);
+
+    while SIGNAL_PAUSED.load(Ordering::SeqCst) || pause_file.exists() {
+        if !ANNOUNCED.swap(true, Ordering::SeqCst) {
+            println!(
+                "Paused, remove {} or send SIGUSR1 to continue",
+                pause_file.display()
+            );
+        }
+
+        thread::sleep(PAUSE_POLL_INTERVAL);
+    }
+
+    if ANNOUNCED.swap(false, Ordering::SeqCst) {
+        println!("Resumed");
+    }
+}
+
+// Buckets can not be polled cheaply, their runs look for the file in the working directory
+fn get_pause_file(config: &Config) -> PathBuf {
+    if storage::is_object_storage(&config.destination_folder) {
+        PathBuf::from(PAUSE_FILE)
+    } else {
+        PathBuf::from(&config.destination_folder).join(PAUSE_FILE)
+    }
+}
@@ -0,0 +1,166 @@
+use std::f64::consts::PI;
+
+use serde_json::{Value, json};
+
+use crate::{core::Config, requester::LazData, storage::OutputSink, writer::OutputError};
+
+// Writes pipeline.json, a PDAL pipeline reproducing the gridding of every tile. PDAL grids by
+// radius rather than by neighbour count, so the radius is chosen to hold sample_size points on
+// average at the tile's density. Excluded polygons are cropped away and heights are clamped and
+// scaled before gridding as in the run. Steps without a PDAL counterpart, such as the Gaussian
+// blur and the height normalization, are listed under not_reproduced and left to the raster
+// tooling.
+pub fn write_pipeline(
+    config: &Config,
+    sink: &dyn OutputSink,
+    data: &[LazData],
+) -> Result<(), OutputError> {
+    // Fallback tiles were never read from a survey file, so PDAL has nothing to reproduce there
+    let stages = data
+        .iter()
+        .filter(|data| !data.fallback)
+        .flat_map(|data| {
+            let tag = format!("tile_{}_{}", data.tile.0, data.tile.1);
+            let (width_m, height_m) = (
+                data.bounds_max.0 - data.bounds_min.0,
+                data.bounds_max.1 - data.bounds_min.1,
+            );
+            let density = data.points.len() as f64 / (width_m * height_m).max(1.0);
+            let radius = (config.sample_size as f64 / (PI * density.max(f64::EPSILON))).sqrt();
+            let dim = config.tile_resolution(data.density()) as u64;
+
+            let mut stages = vec![json!({
+                "type": "readers.las",
+                "filename": format!("TMR_{}_{}.laz", data.tile.0, data.tile.1),
+                "spatialreference": config.source_crs.to_string(),
+                "tag": tag,
+            })];
+            let mut filters = get_filters(config, data);
+            let mut input = tag.clone();
+
+            for (index, filter) in filters.iter_mut().enumerate() {
+                let filter_tag = format!("{}_{}", tag, index + 1);
+                filter["inputs"] = json!([input]);
+                filter["tag"] = json!(filter_tag);
+                input = filter_tag;
+            }
+            stages.extend(filters);
+
+            stages.push(json!({
+                "type": "writers.gdal",
+                "inputs": [input],
+                "filename": format!(
+                    "{}.tif",
+                    data.file_stem
+                ),
+                "gdaldriver": "GTiff",
+                "output_type": "mean",
+                "data_type": "float32",
+                "resolution": width_m / dim as f64,
+                "radius": radius,
+                "origin_x": data.bounds_min.0,
+                "origin_y": data.bounds_min.1,
+                "width": dim,
+                "height": dim,
+            }));
+
+            stages
+        })
+        .collect::<Vec<Value>>();
+
+    sink.put(
+        "pipeline.json",
+        serde_json::to_string_pretty(&json!({
+            "pipeline": stages,
+            "not_reproduced": get_unreproduced_steps(config, data),
+        }))?
+        .as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+// Point filters the run applies to a tile before gridding it
+fn get_filters(config: &Config, data: &LazData) -> Vec<Value> {
+    let mut filters = config
+        .exclusion_areas
+        .iter()
+        .filter(|area| {
+            area.overlaps(
+                (data.bounds_min.0, data.bounds_min.1),
+                (data.bounds_max.0, data.bounds_max.1),
+            )
+        })
+        .map(|area| {
+            json!({
+                "type": "filters.crop",
+                "polygon": area.to_wkt(),
+                "outside": true,
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    // Heights are clamped and then scaled, as Config::adjust_height does
+    let mut assignments = vec![];
+    if let Some(clamp_min) = config.clamp_min {
+        assignments.push(format!("Z = {} WHERE Z < {}", clamp_min, clamp_min));
+    }
+    if let Some(clamp_max) = config.clamp_max {
+        assignments.push(format!("Z = {} WHERE Z > {}", clamp_max, clamp_max));
+    }
+    if config.z_scale != 1.0 {
+        assignments.push(format!("Z = Z * {}", config.z_scale));
+    }
+
+    if !assignments.is_empty() {
+        filters.push(json!({
+            "type": "filters.assign",
+            "value": assignments,
+        }));
+    }
+
+    filters
+}
+
+// Steps of the run that shape the heightmaps but are missing from the pipeline
+fn get_unreproduced_steps(config: &Config, data: &[LazData]) -> Vec<&'static str> {
+    let steps = [
+        (true, "height normalization to the 0..1 range of the run"),
+        (
+            config.blur_kernel_size > 0,
+            "Gaussian blur of the gridded heights (-b)",
+        ),
+        (
+            config.max_neighbour_distance_m.is_some(),
+            "voids beyond the neighbour distance (--max-neighbour-distance-m)",
+        ),
+        (config.void_dilation > 0, "void dilation (--void-dilation)"),
+        (
+            config.spot_elevations.is_some(),
+            "spot elevations set on the gridded heights (--spot-elevations)",
+        ),
+        (
+            !config.flatten_footprints.is_empty(),
+            "flattened building footprints (--flatten-footprints)",
+        ),
+        (
+            config.sea_level.is_some(),
+            "flattening to the sea level (--sea-level, --sea-polygons)",
+        ),
+        (!config.roads.is_empty(), "road grading (--roads)"),
+        (
+            config.target_crs != config.source_crs,
+            "resampling into the target CRS (--target-crs)",
+        ),
+        (
+            data.iter().any(|data| data.fallback),
+            "tiles filled from the fallback DEM (--fallback-dem)",
+        ),
+    ];
+
+    steps
+        .into_iter()
+        .filter(|(applies, _)| *applies)
+        .map(|(_, step)| step)
+        .collect()
+}
@@ -0,0 +1,132 @@
+use crate::{projection::Crs, storage::OutputSink, writer::OutputError};
+
+const THUMBNAIL_SIZE: usize = 256;
+const LEAFLET_URL: &str = "https://unpkg.com/leaflet@1.9.4/dist";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub struct PreviewTile {
+    pub file_stem: String,
+    pub bounds_min: (f64, f64),
+    pub bounds_max: (f64, f64),
+    pub thumbnail: Vec<u8>,
+}
+
+// Nearest-neighbour downsampled 8-bit PNG of a normalized raster, small enough to embed
+pub fn create_thumbnail(
+    heights: &[f32],
+    dim_x: usize,
+    dim_y: usize,
+) -> Result<Vec<u8>, OutputError> {
+    let (size_x, size_y) = (dim_x.min(THUMBNAIL_SIZE), dim_y.min(THUMBNAIL_SIZE));
+
+    let pixels = (0..size_x * size_y)
+        .map(|index| {
+            let (x, y) = (
+                index % size_x * dim_x / size_x,
+                index / size_x * dim_y / size_y,
+            );
+            (heights[y * dim_x + x].clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect::<Vec<u8>>();
+
+    let mut bytes = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, size_x as u32, size_y as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+    }
+
+    Ok(bytes)
+}
+
+// Writes preview.html, a Leaflet map with every tile overlaid on OpenStreetMap. The thumbnails
+// are embedded as data URIs so the file can be shared on its own.
+pub fn write_preview(
+    sink: &dyn OutputSink,
+    tiles: &[PreviewTile],
+    crs: Crs,
+) -> Result<(), OutputError> {
+    let overlays = tiles
+        .iter()
+        .map(|tile| {
+            let corners = [
+                crs.to_wgs84(tile.bounds_min.0, tile.bounds_min.1),
+                crs.to_wgs84(tile.bounds_max.0, tile.bounds_min.1),
+                crs.to_wgs84(tile.bounds_min.0, tile.bounds_max.1),
+                crs.to_wgs84(tile.bounds_max.0, tile.bounds_max.1),
+            ];
+            let south = corners.iter().map(|corner| corner.0).fold(f64::MAX, f64::min);
+            let north = corners.iter().map(|corner| corner.0).fold(f64::MIN, f64::max);
+            let west = corners.iter().map(|corner| corner.1).fold(f64::MAX, f64::min);
+            let east = corners.iter().map(|corner| corner.1).fold(f64::MIN, f64::max);
+
+            format!(
+                "      L.imageOverlay(\"data:image/png;base64,{}\", [[{:.7}, {:.7}], [{:.7}, {:.7}]], {{ opacity: 0.75, interactive: true }}).bindTooltip(\"{}\").addTo(tiles);",
+                encode_base64(&tile.thumbnail),
+                south,
+                west,
+                north,
+                east,
+                tile.file_stem
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>Terrain preview</title>
+    <link rel="stylesheet" href="{leaflet}/leaflet.css">
+    <script src="{leaflet}/leaflet.js"></script>
+    <style>html, body, #map {{ height: 100%; margin: 0; }}</style>
+  </head>
+  <body>
+    <div id="map"></div>
+    <script>
+      const map = L.map("map");
+      L.tileLayer("https://tile.openstreetmap.org/{{z}}/{{x}}/{{y}}.png", {{
+        maxZoom: 19,
+        attribution: "&copy; OpenStreetMap contributors"
+      }}).addTo(map);
+      const tiles = L.featureGroup().addTo(map);
+{overlays}
+      map.fitBounds(tiles.getBounds());
+    </script>
+  </body>
+</html>
+"#,
+        leaflet = LEAFLET_URL,
+        overlays = overlays
+    );
+
+    sink.put("preview.html", html.as_bytes())?;
+
+    Ok(())
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for group in bytes.chunks(3) {
+        let value = (group[0] as u32) << 16
+            | (*group.get(1).unwrap_or(&0) as u32) << 8
+            | *group.get(2).unwrap_or(&0) as u32;
+
+        for index in 0..4 {
+            if index <= group.len() {
+                encoded.push(BASE64_ALPHABET[(value >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
@@ -0,0 +1,41 @@
+use std::sync::{
+    Mutex, OnceLock,
+    mpsc::{self, Receiver, Sender},
+};
+
+use crate::{core::Point, meterer};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TileStatus {
+    Downloading,
+    Downloaded,
+    Postponed,
+    Missing,
+    Failed,
+    Computed,
+}
+
+pub struct TileEvent {
+    pub tile: Point,
+    pub status: TileStatus,
+}
+
+static OBSERVER: OnceLock<Mutex<Option<Sender<TileEvent>>>> = OnceLock::new();
+
+// Starts forwarding tile events to the returned receiver, replacing any earlier observer
+pub fn observe() -> Receiver<TileEvent> {
+    let (sender, receiver) = mpsc::channel();
+    *OBSERVER.get_or_init(|| Mutex::new(None)).lock().unwrap() = Some(sender);
+
+    receiver
+}
+
+pub fn report(tile: Point, status: TileStatus) {
+    meterer::count_tile(status);
+
+    if let Some(observer) = OBSERVER.get() {
+        if let Some(sender) = observer.lock().unwrap().as_ref() {
+            let _ = sender.send(TileEvent { tile, status });
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, Write},
+    num::NonZero,
+};
+
+use crate::{
+    batcher, global_constants::TILE_SIZE_M, projection::Crs, spacer::ESTIMATED_LAZ_TILE_BYTES,
+    storage,
+};
+
+// Engines the wizard knows the output settings of
+const ENGINES: [&str; 4] = ["none", "unreal", "godot", "bevy"];
+
+// Asks for the area, the target engine and the output folder of a first run, shows the expected
+// download and writes the answers as a batch file that can be started right away
+pub fn run_wizard() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut ask = |question: &str, default: &str| -> Result<String, Box<dyn Error + Send + Sync>> {
+        print!("{} [{}]: ", question, default);
+        io::stdout().flush()?;
+
+        let answer = lines.next().transpose()?.unwrap_or_default();
+        let answer = answer.trim();
+
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer.to_string()
+        })
+    };
+
+    let center = loop {
+        let answer = ask(
+            "Center as latitude,longitude (e.g. 46.3683,14.1146) or tile index x,y",
+            "46.3683,14.1146",
+        )?;
+
+        match parse_center(&answer) {
+            Some(center) => break center,
+            None => println!("'{}' is neither latitude,longitude nor x,y", answer),
+        }
+    };
+    let radius = loop {
+        let answer = ask("Tiles around the center, 0 for a single 1 km tile", "1")?;
+
+        match answer.parse::<u16>() {
+            Ok(radius) => break radius,
+            Err(_) => println!("'{}' is not a whole number", answer),
+        }
+    };
+    let engine = loop {
+        let answer = ask(
+            &format!("Target engine, one of {}", ENGINES.join(", ")),
+            "none",
+        )?
+        .to_lowercase();
+
+        if ENGINES.contains(&answer.as_str()) {
+            break answer;
+        }
+        println!("'{}' is not one of {}", answer, ENGINES.join(", "));
+    };
+    let destination_folder = ask("Output folder", "terrain")?;
+    let possible_blocks = ask("Survey blocks to look the tiles up in", "21 35")?;
+
+    let mut args = vec![
+        "-p".to_string(),
+        format!("{},{}", center.0, center.1),
+        "-r".to_string(),
+        radius.to_string(),
+        "-d".to_string(),
+        destination_folder.clone(),
+        "--possible-blocks".to_string(),
+    ];
+    args.extend(possible_blocks.split_whitespace().map(str::to_string));
+
+    // Unreal takes 16 bit PNG and RAW landscapes at sizes such as 1009
+    let resolution = match engine.as_str() {
+        "unreal" => {
+            args.extend(["--format", "png", "raw", "--resolution", "1009"].map(str::to_string));
+            1009u64
+        }
+        "godot" | "bevy" => {
+            args.extend(["--engine-bundle".to_string(), engine.clone()]);
+            1024
+        }
+        _ => 1024,
+    };
+
+    let tile_count = (radius as u64 * 2 + 1).pow(2);
+    println!(
+        "{} tiles, about {:.1} GB to download and {:.1} GB of output",
+        tile_count,
+        (tile_count * ESTIMATED_LAZ_TILE_BYTES) as f64 / 1e9,
+        // Four bytes per pixel for an EXR or for a PNG and RAW pair
+        (tile_count * resolution * resolution * 4) as f64 / 1e9
+    );
+
+    let batch_file = ask(
+        "Batch file to write",
+        &format!("{}.json", destination_folder),
+    )?;
+    batcher::write_single_job(&batch_file, &destination_folder, args)?;
+    // Jobs are validated like a run, which expects the output folder to exist
+    if !storage::is_object_storage(&destination_folder) {
+        fs::create_dir_all(&destination_folder)?;
+    }
+    batcher::validate_batch(&batch_file)?;
+    println!(
+        "Run it later with: las-terrain-generator batch {}",
+        batch_file
+    );
+
+    if ask("Start the run now? (y/n)", "n")?.eq_ignore_ascii_case("y") {
+        batcher::run_batch(&batch_file, NonZero::<usize>::MIN, None, None)?;
+    }
+
+    Ok(())
+}
+
+// Latitude and longitude carry decimals and are projected onto the ARSO tile grid, whole numbers
+// are taken as a tile index
+fn parse_center(value: &str) -> Option<(i32, i32)> {
+    let (first, second) = value.split_once(',')?;
+    let (first, second) = (first.trim(), second.trim());
+
+    if let (Ok(x), Ok(y)) = (first.parse::<i32>(), second.parse::<i32>()) {
+        return Some((x, y));
+    }
+
+    let (lat, lon) = (first.parse::<f64>().ok()?, second.parse::<f64>().ok()?);
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return None;
+    }
+
+    let (x, y) = Crs::D96Tm.from_wgs84(lat, lon);
+
+    Some((
+        (x / TILE_SIZE_M).floor() as i32,
+        (y / TILE_SIZE_M).floor() as i32,
+    ))
+}
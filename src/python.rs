@@ -0,0 +1,244 @@
+use std::{error::Error, thread};
+
+use clap::{Parser, ValueEnum};
+use numpy::{IntoPyArray, PyArray2, ndarray::Array2};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    computer,
+    core::{self, Cli},
+    gridder::{NeighbourSearch, Precision, SpatialIndex},
+    projection::Crs,
+    requester::{self, LazData},
+    storage,
+    writer::{BigTiffMode, OutputFormat, TileRaster},
+};
+
+// A downloaded tile with its point cloud, as returned by fetch
+#[pyclass(name = "Tile", frozen)]
+pub struct PyTile {
+    data: LazData,
+    crs: Crs,
+}
+
+#[pymethods]
+impl PyTile {
+    #[getter]
+    fn tile(&self) -> (i32, i32) {
+        (self.data.tile.0, self.data.tile.1)
+    }
+
+    #[getter]
+    fn bounds_min(&self) -> (f64, f64, f64) {
+        self.data.bounds_min
+    }
+
+    #[getter]
+    fn bounds_max(&self) -> (f64, f64, f64) {
+        self.data.bounds_max
+    }
+
+    #[getter]
+    fn survey(&self) -> Option<String> {
+        self.data.survey.clone()
+    }
+
+    #[getter]
+    fn density(&self) -> f64 {
+        self.data.density()
+    }
+
+    // Points as an (n, 3) array of x, y and z
+    fn points<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f64>> {
+        let points = &self.data.points;
+
+        Array2::from_shape_fn((points.len(), 3), |(row, column)| points.get(row)[column])
+            .into_pyarray_bound(py)
+    }
+}
+
+// An interpolated height raster of one tile, as returned by grid. Heights are kept normalized to
+// the tile's height range like the rest of the pipeline and converted to meters on access.
+#[pyclass(name = "Grid", frozen)]
+pub struct PyGrid {
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    crs: Crs,
+    dim: usize,
+    heights: Vec<f32>,
+    min_height: f64,
+    max_height: f64,
+}
+
+#[pymethods]
+impl PyGrid {
+    #[getter]
+    fn bounds_min(&self) -> (f64, f64) {
+        self.bounds_min
+    }
+
+    #[getter]
+    fn bounds_max(&self) -> (f64, f64) {
+        self.bounds_max
+    }
+
+    #[getter]
+    fn resolution(&self) -> usize {
+        self.dim
+    }
+
+    // Heights in meters as a (resolution, resolution) array, row 0 lies on the northern edge
+    #[getter]
+    fn heights<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f64>> {
+        let range = self.max_height - self.min_height;
+
+        Array2::from_shape_fn((self.dim, self.dim), |(row, column)| {
+            self.min_height + self.heights[row * self.dim + column] as f64 * range
+        })
+        .into_pyarray_bound(py)
+    }
+}
+
+// Downloads the tiles of an area given by the same arguments as a generate run, e.g.
+// fetch(["-p", "401,101", "-r", "1", "--possible-blocks", "35"])
+#[pyfunction]
+fn fetch(py: Python<'_>, args: Vec<String>) -> PyResult<Vec<PyTile>> {
+    let mut cli_args = vec![env!("CARGO_PKG_NAME").to_string()];
+    cli_args.extend(args);
+
+    let cli =
+        Cli::try_parse_from(cli_args).map_err(|value| PyValueError::new_err(value.to_string()))?;
+    let config = core::read_config_from_cli(&cli)
+        .map_err(|value| PyValueError::new_err(value.to_string()))?;
+
+    let result = py
+        .allow_threads(|| -> Result<_, Box<dyn Error + Send + Sync>> {
+            let cpus = thread::available_parallelism()?;
+
+            Ok(requester::get_laz_data(cpus, &config, None)?)
+        })
+        .map_err(to_runtime_error)?;
+
+    Ok(result
+        .data
+        .into_iter()
+        .map(|data| PyTile {
+            data,
+            crs: config.source_crs,
+        })
+        .collect())
+}
+
+// Grids a tile's points by averaging the nearest neighbours of every pixel, then blurs the result
+#[pyfunction]
+#[pyo3(signature = (tile, resolution = 1024, sample_size = 3, blur = 0, approximate = false))]
+fn grid(
+    py: Python<'_>,
+    tile: &PyTile,
+    resolution: usize,
+    sample_size: usize,
+    blur: u32,
+    approximate: bool,
+) -> PyResult<PyGrid> {
+    if resolution == 0 || sample_size == 0 {
+        return Err(PyValueError::new_err(
+            "Resolution and sample size must be greater than 0",
+        ));
+    }
+
+    let data = &tile.data;
+    let (min_height, max_height) = (data.bounds_min.2, data.bounds_max.2);
+    let range = (max_height - min_height).max(f64::EPSILON);
+
+    let heights = py
+        .allow_threads(|| -> Result<Vec<f32>, Box<dyn Error + Send + Sync>> {
+            let index = computer::create_tile_index(
+                data,
+                resolution,
+                resolution,
+                sample_size,
+                NeighbourSearch::new(approximate, SpatialIndex::Kdtree),
+                Precision::F64,
+            );
+            let values = data
+                .points
+                .iter()
+                .map(|point| ((point[2] - min_height) / range) as f32)
+                .collect::<Vec<f32>>();
+
+            let mut heights = index.interpolate(sample_size, &values);
+            computer::blur_image(blur, resolution, resolution, &mut heights)?;
+
+            Ok(heights)
+        })
+        .map_err(to_runtime_error)?;
+
+    Ok(PyGrid {
+        bounds_min: (data.bounds_min.0, data.bounds_min.1),
+        bounds_max: (data.bounds_max.0, data.bounds_max.1),
+        crs: tile.crs,
+        dim: resolution,
+        heights,
+        min_height,
+        max_height: min_height + range,
+    })
+}
+
+// Writes a grid as {name}.{extension} for every format into a local folder or object storage
+#[pyfunction]
+#[pyo3(signature = (grid, destination, name, formats = vec!["tiff".to_string()]))]
+fn export(
+    py: Python<'_>,
+    grid: &PyGrid,
+    destination: &str,
+    name: &str,
+    formats: Vec<String>,
+) -> PyResult<()> {
+    let formats = formats
+        .iter()
+        .map(|format| OutputFormat::from_str(format, true).map_err(PyValueError::new_err))
+        .collect::<PyResult<Vec<OutputFormat>>>()?;
+
+    py.allow_threads(|| -> Result<(), Box<dyn Error + Send + Sync>> {
+        let sink = storage::create_output_sink(destination)?;
+        let raster = TileRaster {
+            bounds_min: grid.bounds_min,
+            bounds_max: grid.bounds_max,
+            crs: grid.crs,
+            dim_x: grid.dim,
+            dim_y: grid.dim,
+            heights: &grid.heights,
+            min_height: grid.min_height,
+            max_height: grid.max_height,
+        };
+
+        for format in formats {
+            let writer = format.writer(BigTiffMode::Auto, None);
+            sink.put(
+                &format!("{}.{}", name, writer.extension()),
+                &writer.encode(&raster)?,
+            )?;
+        }
+
+        Ok(())
+    })
+    .map_err(to_runtime_error)
+}
+
+fn to_runtime_error(error: Box<dyn Error + Send + Sync>) -> PyErr {
+    PyRuntimeError::new_err(error.to_string())
+}
+
+#[pymodule]
+fn las_terrain_generator(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyTile>()?;
+    module.add_class::<PyGrid>()?;
+    module.add_function(wrap_pyfunction!(fetch, module)?)?;
+    module.add_function(wrap_pyfunction!(grid, module)?)?;
+    module.add_function(wrap_pyfunction!(export, module)?)?;
+
+    Ok(())
+}
@@ -1,141 +1,2094 @@
 use itertools::Itertools;
-use las::Reader;
+use las::{Bounds, Vector};
 use rand::Rng;
-use reqwest::blocking::Client;
+use reqwest::Proxy;
+use reqwest::Version;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{ETAG, HeaderMap, HeaderName, HeaderValue, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::io::{Cursor, ErrorKind, Read};
 use std::num::NonZero;
-use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
-use std::{io::Cursor, sync::mpsc};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tiff::decoder::{Decoder, DecodingResult};
 
+use crate::cacher;
 use crate::core::Config;
+use crate::core::HttpConfig;
 use crate::core::Point;
-use crate::global_constants::{MAX_POINT_DIM, MIN_POINT_DIM};
+use crate::core::TileNaming;
+use crate::corridor;
+use crate::error::TerrainError;
+use crate::excluder::{self, Area};
+use crate::global_constants::{
+    ARSO_BASE_URL, ARSO_MAX_TILE_INDEX, ARSO_MIN_TILE_INDEX, TILE_SIZE_M,
+};
+use crate::meterer::{self, Stage, TileTimings};
+use crate::packer::{self, DecodeError};
+use crate::pauser;
+use crate::progress::{self, TileStatus};
+use crate::spacer;
+use crate::spiller::{PointStore, Spiller};
+use crate::spotter::SpotElevations;
+use crate::storage;
+#[cfg(feature = "object-storage")]
+use crate::storage::ObjectStorage;
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+// Tries per tile URL when the transfer fails, the server errors or the body is cut off or invalid
+const DOWNLOAD_ATTEMPTS: u64 = 3;
+// Tile file extensions looked up in folders and buckets, in order of preference. The content is
+// sniffed, so the extension only has to match the file name.
+const TILE_EXTENSIONS: [&str; 3] = ["laz", "las", "zip"];
+// Compression level of --compress-cache, a fast level as cached tiles are read often
+const CACHE_ZSTD_LEVEL: i32 = 3;
+// Elevations at or below this are treated as DEM no-data (e.g. -32767 in Copernicus tiles)
+const DEM_NODATA_LIMIT: f64 = -1000.0;
 
 pub struct LazData {
-    pub offset_from_center: (i16, i16),
+    pub tile: Point,
+    pub offset_from_center: (i32, i32),
+    pub file_stem: String,
     pub bounds_max: (f64, f64, f64),
     pub bounds_min: (f64, f64, f64),
-    pub points: Vec<las::Point>,
+    pub points: PointStore,
+    pub attributes: PointAttributes,
+    pub fallback: bool,
+    pub survey: Option<String>,
+    // Image bytes from --orthophoto, decoded when the tile is computed
+    pub orthophoto: Option<Vec<u8>>,
+    // Download and decode time, the compute stages are added when the tile is computed
+    pub timings: TileTimings,
+    // Slot of --max-in-flight-tiles, freed when the decoded tile is dropped
+    pub permit: TilePermit,
 }
 
-pub fn get_laz_data(cpus: NonZero<usize>, config: &Config) -> Vec<LazData> {
-    let points = filter_points(&config);
-    let mut laz_readers: Vec<LazData> = Vec::new();
+impl LazData {
+    // Points per square meter over the whole tile
+    pub fn density(&self) -> f64 {
+        self.points.len() as f64 / (TILE_SIZE_M * TILE_SIZE_M)
+    }
+}
 
-    let coordinate_origin = points.first().expect("There is no points");
-    let coordinate_origin = (coordinate_origin.0, coordinate_origin.1);
+// Counting semaphore bounding how many decoded tiles exist at once, from before their download
+// until they are dropped after computing
+pub struct TilePermits {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl TilePermits {
+    pub fn new(count: usize) -> Arc<Self> {
+        Arc::new(TilePermits {
+            available: Mutex::new(count),
+            released: Condvar::new(),
+        })
+    }
+
+    // Blocks until a permit is free
+    pub fn acquire(self: &Arc<Self>) -> TilePermit {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+
+        TilePermit {
+            permits: Some(Arc::clone(self)),
+        }
+    }
+}
+
+// A permit of TilePermits, given back on drop. The default holds none, for unbounded runs.
+#[derive(Default)]
+pub struct TilePermit {
+    permits: Option<Arc<TilePermits>>,
+}
+
+impl Drop for TilePermit {
+    fn drop(&mut self) {
+        if let Some(permits) = &self.permits {
+            *permits.available.lock().unwrap() += 1;
+            permits.released.notify_one();
+        }
+    }
+}
+
+// Per-point attributes in the order of the coordinates. They are only collected when an output
+// needs them and are empty otherwise.
+#[derive(Default)]
+pub struct PointAttributes {
+    pub intensities: Vec<u16>,
+    pub classes: Vec<u8>,
+}
+
+pub struct DownloadResult {
+    pub data: Vec<LazData>,
+    pub pending_points: Vec<Point>,
+    pub missing_points: Vec<Point>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BudgetExhausted;
+
+impl Display for BudgetExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad("Download budget exhausted")
+    }
+}
+
+impl Error for BudgetExhausted {}
+
+pub struct DownloadLimiter {
+    max_bandwidth: Option<u64>,
+    max_total_download: Option<u64>,
+    started: Instant,
+    downloaded: AtomicU64,
+}
+
+impl DownloadLimiter {
+    pub fn new(max_bandwidth: Option<u64>, max_total_download: Option<u64>) -> Self {
+        DownloadLimiter {
+            max_bandwidth,
+            max_total_download,
+            started: Instant::now(),
+            downloaded: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        match self.max_total_download {
+            Some(max_total_download) => {
+                self.downloaded.load(Ordering::SeqCst) >= max_total_download
+            }
+            None => false,
+        }
+    }
+
+    // Accounts for downloaded bytes and sleeps long enough to keep the average rate of all
+    // threads combined below the bandwidth limit
+    pub fn consume(&self, bytes: u64) -> Result<(), BudgetExhausted> {
+        let downloaded = self.downloaded.fetch_add(bytes, Ordering::SeqCst) + bytes;
+
+        if let Some(max_total_download) = self.max_total_download {
+            if downloaded > max_total_download {
+                return Err(BudgetExhausted);
+            }
+        }
+
+        if let Some(max_bandwidth) = self.max_bandwidth {
+            let expected = Duration::from_secs_f64(downloaded as f64 / max_bandwidth as f64);
+            let elapsed = self.started.elapsed();
+
+            if expected > elapsed {
+                thread::sleep(expected - elapsed);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_limited(
+    reader: &mut impl Read,
+    limiter: &DownloadLimiter,
+) -> Result<Vec<u8>, TerrainError> {
+    let mut data_bytes = vec![];
+    let mut chunk = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+
+    loop {
+        let read = reader.read(&mut chunk)?;
+
+        if read == 0 {
+            break;
+        }
+
+        limiter.consume(read as u64)?;
+        data_bytes.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(data_bytes)
+}
+
+// Identifies a published version of a tile, used to detect tiles that changed since they were cached
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TileValidator {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+pub trait TileSource: Send + Sync {
+    // Returns the raw LAZ bytes of the tile, or None when the source has no data for the point
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError>;
+
+    // Returns the current version of the tile without downloading it. Sources that can not tell
+    // return None, in which case cached tiles are always considered up to date.
+    fn validator(&self, _point: &Point) -> Result<Option<TileValidator>, TerrainError> {
+        Ok(None)
+    }
+
+    // Returns a short description of the connection usage, printed once downloading finished
+    fn usage(&self) -> Option<String> {
+        None
+    }
+
+    // Whether the point lies on the part of the tile grid the source covers
+    fn covers(&self, _point: &Point) -> bool {
+        true
+    }
+
+    // Whether the tile can be served without network access
+    fn is_available_offline(&self, _point: &Point) -> Result<bool, TerrainError> {
+        Ok(false)
+    }
 
-    let shared_points = Arc::new(points);
-    let shared_blocks = Arc::new(
-        config
-            .possible_blocks
+    // Whether an already fetched tile came from a lower fidelity fallback instead of the survey
+    fn is_fallback(&self, _point: &Point) -> bool {
+        false
+    }
+
+    // Which survey an already fetched tile belongs to, e.g. its ARSO block, when the source knows
+    fn survey(&self, _point: &Point) -> Option<String> {
+        None
+    }
+}
+
+// Which copy of a tile is used when several ARSO blocks hold it. First takes the first block in
+// the configured order that responds, Newest and Oldest compare the Last-Modified dates of all
+// copies and Block restricts every source to a single block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SurveySelection {
+    #[default]
+    First,
+    Newest,
+    Oldest,
+    Block(u8),
+}
+
+// Sortable (year, month, day, time) of an RFC 1123 date such as "Wed, 21 Oct 2015 07:28:00 GMT"
+fn parse_http_date(value: &str) -> Option<(u16, u8, u8, String)> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let parts = value.split_whitespace().collect::<Vec<&str>>();
+    if parts.len() < 5 {
+        return None;
+    }
+
+    let month = MONTHS.iter().position(|month| *month == parts[2])? as u8 + 1;
+
+    Some((
+        parts[3].parse().ok()?,
+        month,
+        parts[1].parse().ok()?,
+        parts[4].to_string(),
+    ))
+}
+
+// Tiles are requested from the first base URL and fail over to the mirrors in order. A tile moves
+// to the next mirror only when a server could not be reached or answered with a server error,
+// a plain "not found" on every block is final.
+pub struct ArsoSource {
+    base_urls: Vec<String>,
+    blocks: Vec<u8>,
+    selection: SurveySelection,
+    client: Client,
+    basic_auth: Option<(String, String)>,
+    request_timeout: Duration,
+    limiter: Arc<DownloadLimiter>,
+    stats: RequestStats,
+    surveys: Mutex<Vec<(Point, String)>>,
+}
+
+// Counters over all requests sent through the shared client. The time until response headers
+// arrive includes connection setup, so it drops when pooled connections are reused.
+#[derive(Default)]
+struct RequestStats {
+    requests: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+    http2: AtomicU64,
+    header_wait_us: AtomicU64,
+}
+
+impl RequestStats {
+    fn record(&self, response: &reqwest::Result<Response>, elapsed: Duration) {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        self.header_wait_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::SeqCst);
+
+        match response {
+            Ok(response) if response.version() == Version::HTTP_2 => {
+                self.http2.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(_) => {}
+            Err(error) => {
+                self.failed.fetch_add(1, Ordering::SeqCst);
+
+                if error.is_timeout() {
+                    self.timed_out.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    // Body reads that hit the request deadline surface as I/O errors of the response reader
+    fn record_read_error(&self, error: &TerrainError) {
+        if let TerrainError::Io(error) = error {
+            if error.kind() == ErrorKind::TimedOut {
+                self.timed_out.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl ArsoSource {
+    pub fn new(
+        base_urls: &[&str],
+        blocks: Vec<u8>,
+        selection: SurveySelection,
+        http: &HttpConfig,
+        limiter: Arc<DownloadLimiter>,
+    ) -> Result<Self, TerrainError> {
+        Ok(ArsoSource {
+            base_urls: base_urls
+                .iter()
+                .map(|base_url| base_url.trim_end_matches('/').to_string())
+                .collect(),
+            blocks,
+            selection,
+            client: build_client(http)?,
+            basic_auth: http.basic_auth.clone(),
+            request_timeout: http.request_timeout,
+            limiter,
+            stats: RequestStats::default(),
+            surveys: Mutex::new(vec![]),
+        })
+    }
+}
+
+fn build_client(http: &HttpConfig) -> Result<Client, TerrainError> {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in http.headers.iter() {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|value| TerrainError::Settings(format!("Header {}: {}", name, value)))?,
+            HeaderValue::from_str(value)
+                .map_err(|value| TerrainError::Settings(format!("Header {}: {}", name, value)))?,
+        );
+    }
+
+    // One client is shared by all download threads, so its pool keeps connections alive between
+    // tiles and multiplexes requests over HTTP/2 where the server offers it
+    let mut builder = Client::builder()
+        .default_headers(headers)
+        .connect_timeout(http.connect_timeout)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(60))
+        .http2_adaptive_window(true);
+
+    if let Some(proxy) = &http.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+
+    if let Some(user_agent) = &http.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+
+    Ok(builder.build()?)
+}
+
+// Sends the same requests once with a fresh client per request, which has to open a new
+// connection every time, and once through one shared client that reuses its pooled connection
+pub fn compare_clients(
+    url: &str,
+    requests: usize,
+    http: &HttpConfig,
+) -> Result<(Duration, Duration), TerrainError> {
+    let mut fresh = Duration::ZERO;
+
+    for _ in 0..requests {
+        let client = build_client(http)?;
+        fresh += time_request(&client, url, http)?;
+    }
+
+    let client = build_client(http)?;
+    let mut shared = Duration::ZERO;
+
+    for _ in 0..requests {
+        shared += time_request(&client, url, http)?;
+    }
+
+    Ok((fresh, shared))
+}
+
+// The body is read to the end so the connection goes back to the pool
+fn time_request(client: &Client, url: &str, http: &HttpConfig) -> Result<Duration, TerrainError> {
+    let request = client.get(url).timeout(http.request_timeout);
+    let request = match &http.basic_auth {
+        Some((user, password)) => request.basic_auth(user, Some(password)),
+        None => request,
+    };
+
+    let started = Instant::now();
+    let response = request.send()?.error_for_status()?;
+    response.bytes()?;
+
+    Ok(started.elapsed())
+}
+
+// Outcome of one try of retry_download
+enum Attempt<T> {
+    Done(T),
+    // The transfer failed in a way another try may fix
+    Retry,
+}
+
+// Tries a download up to DOWNLOAD_ATTEMPTS times, pausing a second longer before each retry.
+// Returns None when every try asked for a retry, errors end the download at once.
+fn retry_download<T>(
+    url: &str,
+    mut attempt: impl FnMut() -> Result<Attempt<T>, TerrainError>,
+) -> Result<Option<T>, TerrainError> {
+    for attempt_number in 1..=DOWNLOAD_ATTEMPTS {
+        if attempt_number > 1 {
+            println!(
+                "Retrying url {} (attempt {} of {})",
+                url, attempt_number, DOWNLOAD_ATTEMPTS
+            );
+            thread::sleep(Duration::from_secs(attempt_number));
+        }
+
+        if let Attempt::Done(value) = attempt()? {
+            return Ok(Some(value));
+        }
+    }
+
+    Ok(None)
+}
+
+enum Download {
+    Tile(Vec<u8>),
+    // The block has no tile at this point
+    Missing,
+    // Every attempt failed, the mirror may be down
+    Failed,
+}
+
+impl TileSource for ArsoSource {
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError> {
+        for (mirror_index, base_url) in self.base_urls.iter().enumerate() {
+            if mirror_index > 0 {
+                println!("Point {}:{}|mirror {}", point.0, point.1, base_url);
+            }
+
+            let mut reachable = true;
+            let blocks = self.get_selected_blocks(base_url, point);
+
+            for block_number in blocks.iter() {
+                println!("Point {}:{}|block {}", point.0, point.1, block_number);
+
+                let url = format!(
+                    "{}/b_{}/D96TM/TMR_{}_{}.laz",
+                    base_url, block_number, point.0, point.1
+                );
+
+                let data_bytes = match self.download(&url)? {
+                    Download::Tile(data_bytes) => data_bytes,
+                    Download::Missing => continue,
+                    Download::Failed => {
+                        reachable = false;
+                        continue;
+                    }
+                };
+
+                self.surveys
+                    .lock()
+                    .unwrap()
+                    .push((*point, format!("b_{}", block_number)));
+
+                thread::sleep(Duration::from_secs(1 * rand::thread_rng().gen_range(0..5)));
+                // If you find the right block, x, y combination, you got the point. Thus you can move to the next one (break the loop)
+                return Ok(Some(data_bytes));
+            }
+
+            if reachable {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn validator(&self, point: &Point) -> Result<Option<TileValidator>, TerrainError> {
+        let mut last_error = None;
+
+        for base_url in self.base_urls.iter() {
+            match self.validator_at(base_url, point) {
+                Ok(validator) => return Ok(validator),
+                Err(value) => last_error = Some(value),
+            }
+        }
+
+        match last_error {
+            Some(value) => Err(value),
+            None => Ok(None),
+        }
+    }
+
+    fn survey(&self, point: &Point) -> Option<String> {
+        self.surveys
+            .lock()
+            .unwrap()
             .iter()
-            .map(|e| *e)
-            .unique()
-            .collect::<Vec<u8>>(),
-    );
+            .find(|(surveyed, _)| surveyed == point)
+            .map(|(_, survey)| survey.clone())
+    }
+
+    fn covers(&self, point: &Point) -> bool {
+        point.0 >= ARSO_MIN_TILE_INDEX
+            && point.1 >= ARSO_MIN_TILE_INDEX
+            && point.0 < ARSO_MAX_TILE_INDEX
+            && point.1 < ARSO_MAX_TILE_INDEX
+    }
+
+    fn usage(&self) -> Option<String> {
+        let requests = self.stats.requests.load(Ordering::SeqCst);
+
+        if requests == 0 {
+            return None;
+        }
+
+        Some(format!(
+            "{} HTTP requests ({} over HTTP/2, {} failed, {} timed out), average wait for response headers {:.0} ms",
+            requests,
+            self.stats.http2.load(Ordering::SeqCst),
+            self.stats.failed.load(Ordering::SeqCst),
+            self.stats.timed_out.load(Ordering::SeqCst),
+            self.stats.header_wait_us.load(Ordering::SeqCst) as f64 / requests as f64 / 1000.0
+        ))
+    }
+}
+
+impl ArsoSource {
+    // Blocks in the order they are tried for the tile. A single block is already the only
+    // configured one, Newest and Oldest order them by date.
+    fn get_selected_blocks(&self, base_url: &str, point: &Point) -> Vec<u8> {
+        match self.selection {
+            SurveySelection::Newest | SurveySelection::Oldest => {
+                self.get_blocks_by_date(base_url, point)
+            }
+            _ => self.blocks.clone(),
+        }
+    }
+
+    // Blocks holding the tile, ordered by the Last-Modified date of their copy as requested by
+    // the survey selection. Blocks without a date come last, unreachable servers keep the
+    // configured order so the download itself reports the failure.
+    fn get_blocks_by_date(&self, base_url: &str, point: &Point) -> Vec<u8> {
+        let mut dated = vec![];
+        let mut undated = vec![];
 
-    let (tx, rx) = mpsc::channel();
+        for block_number in self.blocks.iter() {
+            let url = format!(
+                "{}/b_{}/D96TM/TMR_{}_{}.laz",
+                base_url, block_number, point.0, point.1
+            );
 
-    for id in 0..cpus.get() {
-        let shared_points = Arc::clone(&shared_points);
-        let shared_blocks = Arc::clone(&shared_blocks);
-        let tx = tx.clone();
+            let response = match self.send(self.client.head(&url)) {
+                Ok(response) => response,
+                Err(_) => return self.blocks.clone(),
+            };
+
+            if !response.status().is_success() {
+                continue;
+            }
+
+            match response
+                .headers()
+                .get(LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_http_date)
+            {
+                Some(date) => dated.push((date, *block_number)),
+                None => undated.push(*block_number),
+            }
+        }
+
+        dated.sort();
+        if self.selection == SurveySelection::Newest {
+            dated.reverse();
+        }
+
+        dated
+            .into_iter()
+            .map(|(_, block_number)| block_number)
+            .chain(undated)
+            .collect()
+    }
+
+    // Downloads and checks one tile, retrying transfer errors, server errors and bodies that are
+    // shorter than announced or not a LAS/LAZ file. Only an exhausted budget is an error.
+    fn download(&self, url: &str) -> Result<Download, TerrainError> {
+        let download = retry_download(url, || {
+            let mut response = match self.send(self.client.get(url)) {
+                Ok(response) => response,
+                Err(_) => {
+                    println!("HTTP get not successful, error. Skipping point url {}", url);
+                    return Ok(Attempt::Retry);
+                }
+            };
 
-        thread::spawn(move || {
-            let mut access_index = id;
-            let client = Client::new();
+            if !response.status().is_success() {
+                println!(
+                    "HTTP status not successful (not 200 OK). Skipping point url {}",
+                    url
+                );
 
-            loop {
-                if access_index >= shared_points.len() {
-                    break;
+                if response.status().is_server_error() {
+                    return Ok(Attempt::Retry);
                 }
 
-                let point = &shared_points[access_index];
+                return Ok(Attempt::Done(Download::Missing));
+            }
+
+            let content_length = response.content_length();
+            let data_bytes = match read_limited(&mut response, &self.limiter) {
+                Ok(data_bytes) => data_bytes,
+                Err(value @ TerrainError::BudgetExhausted(_)) => return Err(value),
+                Err(value) => {
+                    self.stats.record_read_error(&value);
 
-                for block_number in shared_blocks.iter() {
-                    println!("Point {}:{}|block {}", point.0, point.1, block_number);
+                    println!("Err: {}", value);
+                    println!(
+                        "Reading bytes was not successful. Skipping point url {}",
+                        url
+                    );
+                    return Ok(Attempt::Retry);
+                }
+            };
 
-                    let url = format!(
-                        "https://gis.arso.gov.si/lidar/otr/laz/b_{}/D96TM/TMR_{}_{}.laz",
-                        block_number, point.0, point.1
+            if let Some(content_length) = content_length {
+                if (data_bytes.len() as u64) < content_length {
+                    println!(
+                        "Received {} of {} bytes. Skipping point url {}",
+                        data_bytes.len(),
+                        content_length,
+                        url
                     );
+                    return Ok(Attempt::Retry);
+                }
+            }
 
-                    let response = client.get(&url).timeout(Duration::from_secs(300)).send();
+            let data_bytes = match packer::unwrap_archive(data_bytes) {
+                Ok(data_bytes) => data_bytes,
+                Err(value) => {
+                    println!("Err: {}", value);
+                    println!("Unpacking was not successful. Skipping point url {}", url);
+                    return Ok(Attempt::Retry);
+                }
+            };
 
-                    if response.is_err() {
-                        println!("HTTP get not successful, error. Skipping point url {}", url);
-                        continue;
+            // Error pages served with a success status and cut off bodies are worth another try
+            if let Err(value) = packer::check_las_header(&data_bytes) {
+                println!("Err: {}", value);
+                println!("Downloaded tile is not valid. Skipping point url {}", url);
+                return Ok(Attempt::Retry);
+            }
+
+            Ok(Attempt::Done(Download::Tile(data_bytes)))
+        })?;
+
+        Ok(download.unwrap_or(Download::Failed))
+    }
+
+    fn validator_at(
+        &self,
+        base_url: &str,
+        point: &Point,
+    ) -> Result<Option<TileValidator>, TerrainError> {
+        // The version of the copy fetch would download, not of the first block holding the tile
+        for block_number in self.get_selected_blocks(base_url, point).iter() {
+            let url = format!(
+                "{}/b_{}/D96TM/TMR_{}_{}.laz",
+                base_url, block_number, point.0, point.1
+            );
+
+            let response = self.send(self.client.head(&url))?;
+
+            if response.status().is_server_error() {
+                return Err(TerrainError::HttpStatus {
+                    url,
+                    status: response.status().as_u16(),
+                });
+            }
+
+            if !response.status().is_success() {
+                continue;
+            }
+
+            let header = |name: HeaderName| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string())
+            };
+
+            return Ok(Some(TileValidator {
+                etag: header(ETAG),
+                last_modified: header(LAST_MODIFIED),
+            }));
+        }
+
+        Ok(None)
+    }
+
+    fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let request = request.timeout(self.request_timeout);
+        let request = match &self.basic_auth {
+            Some((user, password)) => request.basic_auth(user, Some(password)),
+            None => request,
+        };
+
+        let started = Instant::now();
+        let response = request.send();
+        self.stats.record(&response, started.elapsed());
+
+        response
+    }
+}
+
+pub struct LocalSource {
+    folder: PathBuf,
+    blocks: Vec<u8>,
+}
+
+impl LocalSource {
+    pub fn new(folder: &str, blocks: Vec<u8>) -> Self {
+        LocalSource {
+            folder: PathBuf::from(folder),
+            blocks,
+        }
+    }
+}
+
+impl LocalSource {
+    fn find(&self, point: &Point) -> Result<Option<PathBuf>, TerrainError> {
+        let mut candidates = vec![];
+
+        for extension in TILE_EXTENSIONS {
+            let file_name = format!("TMR_{}_{}.{}", point.0, point.1, extension);
+
+            // Accept both a flat folder of tiles and a mirror of the ARSO block layout
+            candidates.push(self.folder.join(&file_name));
+            candidates.extend(self.blocks.iter().map(|block_number| {
+                self.folder
+                    .join(format!("b_{}", block_number))
+                    .join("D96TM")
+                    .join(&file_name)
+            }));
+        }
+
+        for candidate in candidates {
+            if fs::exists(&candidate)? {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl TileSource for LocalSource {
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError> {
+        match self.find(point)? {
+            Some(path) => {
+                println!("Point {}:{}|file {}", point.0, point.1, path.display());
+                Ok(Some(
+                    packer::unwrap_archive(fs::read(&path)?).map_err(TerrainError::LazDecode)?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn is_available_offline(&self, point: &Point) -> Result<bool, TerrainError> {
+        Ok(self.find(point)?.is_some())
+    }
+
+    fn validator(&self, point: &Point) -> Result<Option<TileValidator>, TerrainError> {
+        let path = match self.find(point)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let modified = fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(std::io::Error::other)?
+            .as_secs();
+
+        Ok(Some(TileValidator {
+            etag: None,
+            last_modified: Some(modified.to_string()),
+        }))
+    }
+}
+
+#[cfg(feature = "object-storage")]
+pub struct ObjectStorageSource {
+    storage: ObjectStorage,
+    blocks: Vec<u8>,
+}
+
+#[cfg(feature = "object-storage")]
+impl ObjectStorageSource {
+    pub fn new(uri: &str, blocks: Vec<u8>) -> Result<Self, TerrainError> {
+        Ok(ObjectStorageSource {
+            storage: ObjectStorage::new(uri).map_err(TerrainError::Source)?,
+            blocks,
+        })
+    }
+}
+
+#[cfg(feature = "object-storage")]
+impl TileSource for ObjectStorageSource {
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError> {
+        let mut candidates = vec![];
+
+        for extension in TILE_EXTENSIONS {
+            let file_name = format!("TMR_{}_{}.{}", point.0, point.1, extension);
+
+            candidates.push(file_name.clone());
+            candidates.extend(
+                self.blocks
+                    .iter()
+                    .map(|block_number| format!("b_{}/D96TM/{}", block_number, file_name)),
+            );
+        }
+
+        for candidate in candidates {
+            if let Some(data_bytes) = self.storage.get(&candidate).map_err(TerrainError::Source)? {
+                println!("Point {}:{}|object {}", point.0, point.1, candidate);
+                return Ok(Some(
+                    packer::unwrap_archive(data_bytes).map_err(TerrainError::LazDecode)?,
+                ));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Keeps fetched tiles in a folder. With packing enabled tiles are stored converted to the packed
+// point format, so later runs over the same area skip LAZ decompression. With compression they
+// are additionally stored zstd compressed as .zst files.
+pub struct CachedSource {
+    inner: Arc<dyn TileSource>,
+    folder: PathBuf,
+    pack: bool,
+    compress: bool,
+    survey: SurveySelection,
+}
+
+impl CachedSource {
+    pub fn new(
+        inner: Arc<dyn TileSource>,
+        folder: &str,
+        pack: bool,
+        compress: bool,
+        survey: SurveySelection,
+    ) -> Result<Self, TerrainError> {
+        fs::create_dir_all(folder)?;
+
+        Ok(CachedSource {
+            inner,
+            folder: PathBuf::from(folder),
+            pack,
+            compress,
+            survey,
+        })
+    }
+
+    // A tile cached from another block than the one --survey asks for is not a hit, fetching it
+    // again replaces the cached copy
+    fn find_cached(&self, point: &Point) -> Result<Option<PathBuf>, TerrainError> {
+        if let SurveySelection::Block(block_number) = self.survey {
+            let recorded = fs::read_to_string(self.survey_path(point)).ok();
+
+            if recorded.as_deref().map(str::trim) != Some(format!("b_{}", block_number).as_str()) {
+                return Ok(None);
+            }
+        }
+
+        for extension in ["ltp.zst", "laz.zst", "ltp", "laz"] {
+            let cached_path = self
+                .folder
+                .join(format!("TMR_{}_{}.{}", point.0, point.1, extension));
+
+            if fs::exists(&cached_path)? {
+                return Ok(Some(cached_path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Drops the cached copy of a tile when the source published a different version since it was
+    // cached. Returns whether the tile has to be fetched again.
+    pub fn refresh(&self, point: &Point) -> Result<bool, TerrainError> {
+        let cached_path = match self.find_cached(point)? {
+            Some(cached_path) => cached_path,
+            None => return Ok(true),
+        };
+
+        let current = match self.inner.validator(point)? {
+            Some(current) => current,
+            None => return Ok(false),
+        };
+        let recorded = fs::read_to_string(self.validator_path(point))
+            .ok()
+            .and_then(|json| serde_json::from_str::<TileValidator>(&json).ok());
+
+        if recorded.as_ref() == Some(&current) {
+            return Ok(false);
+        }
+
+        println!("Point {}:{}|changed at source", point.0, point.1);
+        fs::remove_file(&cached_path)?;
+
+        Ok(true)
+    }
+
+    fn validator_path(&self, point: &Point) -> PathBuf {
+        self.folder
+            .join(format!("TMR_{}_{}.json", point.0, point.1))
+    }
+
+    fn survey_path(&self, point: &Point) -> PathBuf {
+        self.folder
+            .join(format!("TMR_{}_{}.survey", point.0, point.1))
+    }
+}
+
+impl TileSource for CachedSource {
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError> {
+        if let Some(cached_path) = self.find_cached(point)? {
+            println!("Point {}:{}|cache", point.0, point.1);
+            cacher::touch(&cached_path);
+
+            let data_bytes = fs::read(&cached_path)?;
+            if cached_path
+                .extension()
+                .is_some_and(|extension| extension == "zst")
+            {
+                return Ok(Some(zstd::decode_all(data_bytes.as_slice())?));
+            }
+
+            return Ok(Some(data_bytes));
+        }
+
+        let data_bytes = match self.inner.fetch(point)? {
+            Some(data_bytes) if self.pack => {
+                Some(packer::pack(&data_bytes).map_err(TerrainError::LazDecode)?)
+            }
+            data_bytes => data_bytes,
+        };
+
+        if let Some(data_bytes) = &data_bytes {
+            let file_name = format!(
+                "TMR_{}_{}.{}{}",
+                point.0,
+                point.1,
+                if self.pack { "ltp" } else { "laz" },
+                if self.compress { ".zst" } else { "" }
+            );
+            let cached_path = self.folder.join(&file_name);
+
+            // Several jobs may share the cache, so only complete files ever appear under the final name
+            if self.compress {
+                let compressed = zstd::encode_all(data_bytes.as_slice(), CACHE_ZSTD_LEVEL)?;
+                storage::write_atomically(&cached_path, &compressed)?;
+            } else {
+                storage::write_atomically(&cached_path, data_bytes)?;
+            }
+
+            if let Ok(Some(validator)) = self.inner.validator(point) {
+                storage::write_atomically(
+                    &self.validator_path(point),
+                    serde_json::to_string_pretty(&validator)?.as_bytes(),
+                )?;
+            }
+
+            if let Some(survey) = self.inner.survey(point) {
+                storage::write_atomically(&self.survey_path(point), survey.as_bytes())?;
+            }
+        }
+
+        Ok(data_bytes)
+    }
+
+    // Cached tiles remember their survey next to the data, since the source never sees them
+    fn survey(&self, point: &Point) -> Option<String> {
+        self.inner
+            .survey(point)
+            .or_else(|| fs::read_to_string(self.survey_path(point)).ok())
+    }
+
+    fn validator(&self, point: &Point) -> Result<Option<TileValidator>, TerrainError> {
+        self.inner.validator(point)
+    }
+
+    fn usage(&self) -> Option<String> {
+        self.inner.usage()
+    }
+
+    fn covers(&self, point: &Point) -> bool {
+        self.inner.covers(point)
+    }
+
+    fn is_available_offline(&self, point: &Point) -> Result<bool, TerrainError> {
+        Ok(self.find_cached(point)?.is_some() || self.inner.is_available_offline(point)?)
+    }
+}
+
+// Coarse elevation model served per tile as a GeoTIFF, e.g. a WCS GetCoverage request or a
+// Copernicus DEM proxy. The URL template receives the tile bounds in D96TM through {minx},
+// {miny}, {maxx}, {maxy} and the requested raster size through {width} and {height}.
+pub struct DemSource {
+    url_template: String,
+    resolution_m: f64,
+    client: Client,
+    request_timeout: Duration,
+    limiter: Arc<DownloadLimiter>,
+}
+
+impl DemSource {
+    pub fn new(
+        url_template: &str,
+        resolution_m: f64,
+        http: &HttpConfig,
+        limiter: Arc<DownloadLimiter>,
+    ) -> Result<Self, TerrainError> {
+        Ok(DemSource {
+            url_template: url_template.to_string(),
+            resolution_m,
+            client: build_client(http)?,
+            request_timeout: http.request_timeout,
+            limiter,
+        })
+    }
+
+    // Every valid DEM cell becomes a point at the cell center, so the gridding treats the
+    // model like a very sparse survey
+    fn to_points(
+        tiff_bytes: &[u8],
+        bounds_min: (f64, f64),
+        bounds_max: (f64, f64),
+    ) -> Result<(Bounds, Vec<las::Point>), TerrainError> {
+        let mut decoder = Decoder::new(Cursor::new(tiff_bytes))
+            .map_err(|value| TerrainError::LazDecode(value.into()))?;
+        let (width, height) = decoder
+            .dimensions()
+            .map_err(|value| TerrainError::LazDecode(value.into()))?;
+        let heights = match decoder
+            .read_image()
+            .map_err(|value| TerrainError::LazDecode(value.into()))?
+        {
+            DecodingResult::F32(values) => values.into_iter().map(|value| value as f64).collect(),
+            DecodingResult::F64(values) => values,
+            DecodingResult::I16(values) => values.into_iter().map(|value| value as f64).collect(),
+            DecodingResult::U16(values) => values.into_iter().map(|value| value as f64).collect(),
+            DecodingResult::I32(values) => values.into_iter().map(|value| value as f64).collect(),
+            _ => {
+                return Err(TerrainError::LazDecode(DecodeError::Malformed(
+                    "Unsupported DEM sample format".to_string(),
+                )));
+            }
+        };
+
+        let pixel_size = (
+            (bounds_max.0 - bounds_min.0) / width as f64,
+            (bounds_max.1 - bounds_min.1) / height as f64,
+        );
+
+        let points = heights
+            .iter()
+            .enumerate()
+            .filter(|(_, height)| height.is_finite() && **height > DEM_NODATA_LIMIT)
+            .map(|(index, height)| las::Point {
+                x: bounds_min.0 + ((index % width as usize) as f64 + 0.5) * pixel_size.0,
+                y: bounds_max.1 - ((index / width as usize) as f64 + 0.5) * pixel_size.1,
+                z: *height,
+                ..Default::default()
+            })
+            .collect::<Vec<las::Point>>();
+
+        let (min_z, max_z) = points.iter().fold((f64::MAX, f64::MIN), |bounds, point| {
+            (bounds.0.min(point.z), bounds.1.max(point.z))
+        });
+        let bounds = Bounds {
+            min: Vector {
+                x: bounds_min.0,
+                y: bounds_min.1,
+                z: min_z,
+            },
+            max: Vector {
+                x: bounds_max.0,
+                y: bounds_max.1,
+                z: max_z,
+            },
+        };
+
+        Ok((bounds, points))
+    }
+}
+
+impl TileSource for DemSource {
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError> {
+        let bounds_min = (point.0 as f64 * TILE_SIZE_M, point.1 as f64 * TILE_SIZE_M);
+        let bounds_max = (bounds_min.0 + TILE_SIZE_M, bounds_min.1 + TILE_SIZE_M);
+        let size = ((TILE_SIZE_M / self.resolution_m).round() as u32).max(1);
+
+        let url = self
+            .url_template
+            .replace("{minx}", &bounds_min.0.to_string())
+            .replace("{miny}", &bounds_min.1.to_string())
+            .replace("{maxx}", &bounds_max.0.to_string())
+            .replace("{maxy}", &bounds_max.1.to_string())
+            .replace("{width}", &size.to_string())
+            .replace("{height}", &size.to_string());
+
+        // Transfer and server errors are retried like survey tiles, other statuses skip the tile
+        let tiff_bytes = retry_download(&url, || {
+            let mut response = match self.client.get(&url).timeout(self.request_timeout).send() {
+                Ok(response) => response,
+                Err(value) => {
+                    println!("Err: {}", value);
+                    println!("DEM request not successful. Skipping point url {}", url);
+                    return Ok(Attempt::Retry);
+                }
+            };
+
+            if !response.status().is_success() {
+                println!("DEM request not successful. Skipping point url {}", url);
+
+                if response.status().is_server_error() {
+                    return Ok(Attempt::Retry);
+                }
+
+                return Ok(Attempt::Done(None));
+            }
+
+            match read_limited(&mut response, &self.limiter) {
+                Ok(tiff_bytes) => Ok(Attempt::Done(Some(tiff_bytes))),
+                Err(value @ TerrainError::BudgetExhausted(_)) => Err(value),
+                Err(value) => {
+                    println!("Err: {}", value);
+                    println!("Reading DEM was not successful. Skipping point url {}", url);
+                    Ok(Attempt::Retry)
+                }
+            }
+        })?;
+
+        let tiff_bytes = match tiff_bytes {
+            Some(Some(tiff_bytes)) => tiff_bytes,
+            Some(None) => return Ok(None),
+            None => {
+                return Err(TerrainError::Unavailable(format!(
+                    "DEM request failed {} times: {}",
+                    DOWNLOAD_ATTEMPTS, url
+                )));
+            }
+        };
+        let (bounds, points) = DemSource::to_points(&tiff_bytes, bounds_min, bounds_max)?;
+
+        if points.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(packer::pack_points(&bounds, &points)))
+    }
+}
+
+// Orthophoto imagery of a tile from the --orthophoto URL template, which receives the bounds of
+// the tile's points through {minx}, {miny}, {maxx}, {maxy} and the image size through {width}
+// and {height}. With a tile cache the images are kept in its orthophoto folder, so recompute and
+// --offline runs reuse them.
+pub struct OrthophotoSource {
+    url_template: String,
+    size: u16,
+    client: Client,
+    request_timeout: Duration,
+    limiter: Arc<DownloadLimiter>,
+    cache_folder: Option<PathBuf>,
+    offline: bool,
+}
+
+impl OrthophotoSource {
+    pub fn new(
+        url_template: &str,
+        config: &Config,
+        limiter: Arc<DownloadLimiter>,
+    ) -> Result<Self, TerrainError> {
+        let cache_folder = config
+            .cache_dir
+            .as_ref()
+            .map(|cache_dir| PathBuf::from(cache_dir).join("orthophoto"));
+        if let Some(cache_folder) = &cache_folder {
+            fs::create_dir_all(cache_folder)?;
+        }
+
+        Ok(OrthophotoSource {
+            url_template: url_template.to_string(),
+            size: config.resolution,
+            client: build_client(&config.http)?,
+            request_timeout: config.http.request_timeout,
+            limiter,
+            cache_folder,
+            offline: config.offline,
+        })
+    }
+
+    // Image bytes as served, or None when the service has no image for the tile. Failed requests
+    // are reported and skipped like a bad status, the tile is computed without an orthophoto.
+    pub fn fetch(
+        &self,
+        point: &Point,
+        bounds_min: (f64, f64),
+        bounds_max: (f64, f64),
+    ) -> Result<Option<Vec<u8>>, TerrainError> {
+        let cached_path = self.cache_folder.as_ref().map(|cache_folder| {
+            cache_folder.join(format!("TMR_{}_{}_{}.img", point.0, point.1, self.size))
+        });
+
+        if let Some(cached_path) = &cached_path {
+            if fs::exists(cached_path)? {
+                cacher::touch(cached_path);
+                return Ok(Some(fs::read(cached_path)?));
+            }
+        }
+
+        if self.offline {
+            println!(
+                "Offline mode, no cached orthophoto. Skipping point {}:{}",
+                point.0, point.1
+            );
+            return Ok(None);
+        }
+
+        let url = self
+            .url_template
+            .replace("{minx}", &bounds_min.0.to_string())
+            .replace("{miny}", &bounds_min.1.to_string())
+            .replace("{maxx}", &bounds_max.0.to_string())
+            .replace("{maxy}", &bounds_max.1.to_string())
+            .replace("{width}", &self.size.to_string())
+            .replace("{height}", &self.size.to_string());
+
+        let mut response = match self.client.get(&url).timeout(self.request_timeout).send() {
+            Ok(response) if response.status().is_success() => response,
+            Ok(_) => {
+                println!("Orthophoto request not successful. Skipping url {}", url);
+                return Ok(None);
+            }
+            Err(value) => {
+                println!("Err: {}", value);
+                println!("Orthophoto request not successful. Skipping url {}", url);
+                return Ok(None);
+            }
+        };
+
+        let image_bytes = read_limited(&mut response, &self.limiter)?;
+
+        if let Some(cached_path) = &cached_path {
+            storage::write_atomically(cached_path, &image_bytes)?;
+        }
+
+        Ok(Some(image_bytes))
+    }
+}
+
+// Serves tiles from the LiDAR source and falls back to the DEM where the survey has no tile.
+// Points served by the fallback are remembered so their outputs can be marked.
+pub struct FallbackSource {
+    primary: Arc<dyn TileSource>,
+    fallback: DemSource,
+    fallback_points: Mutex<Vec<Point>>,
+}
+
+impl FallbackSource {
+    pub fn new(primary: Arc<dyn TileSource>, fallback: DemSource) -> Self {
+        FallbackSource {
+            primary,
+            fallback,
+            fallback_points: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl TileSource for FallbackSource {
+    fn fetch(&self, point: &Point) -> Result<Option<Vec<u8>>, TerrainError> {
+        if self.primary.covers(point) {
+            if let Some(data_bytes) = self.primary.fetch(point)? {
+                return Ok(Some(data_bytes));
+            }
+        }
+
+        println!(
+            "No LiDAR tile for point {}:{}, using the fallback DEM",
+            point.0, point.1
+        );
+
+        let data_bytes = self.fallback.fetch(point)?;
+        if data_bytes.is_some() {
+            self.fallback_points.lock().unwrap().push(*point);
+        }
+
+        Ok(data_bytes)
+    }
+
+    fn validator(&self, point: &Point) -> Result<Option<TileValidator>, TerrainError> {
+        self.primary.validator(point)
+    }
+
+    fn usage(&self) -> Option<String> {
+        self.primary.usage()
+    }
+
+    fn is_available_offline(&self, point: &Point) -> Result<bool, TerrainError> {
+        self.primary.is_available_offline(point)
+    }
+
+    fn is_fallback(&self, point: &Point) -> bool {
+        self.fallback_points.lock().unwrap().contains(point)
+    }
+
+    fn survey(&self, point: &Point) -> Option<String> {
+        if self.is_fallback(point) {
+            Some("fallback".to_string())
+        } else {
+            self.primary.survey(point)
+        }
+    }
+}
+
+pub fn create_tile_source(
+    uri: &str,
+    mirrors: &[String],
+    blocks: Vec<u8>,
+    selection: SurveySelection,
+    http: &HttpConfig,
+    limiter: Arc<DownloadLimiter>,
+) -> Result<Arc<dyn TileSource>, TerrainError> {
+    let (scheme, location) = uri.split_once(':').unwrap_or((uri, ""));
+
+    match scheme {
+        "arso" => {
+            let base_url = if location.is_empty() {
+                ARSO_BASE_URL
+            } else {
+                location
+            };
+            let base_urls = std::iter::once(base_url)
+                .chain(mirrors.iter().map(String::as_str))
+                .collect::<Vec<&str>>();
+
+            Ok(Arc::new(ArsoSource::new(
+                &base_urls, blocks, selection, http, limiter,
+            )?))
+        }
+        "file" => Ok(Arc::new(LocalSource::new(
+            location.trim_start_matches("//"),
+            blocks,
+        ))),
+        #[cfg(feature = "object-storage")]
+        "s3" | "gs" => Ok(Arc::new(ObjectStorageSource::new(uri, blocks)?)),
+        #[cfg(not(feature = "object-storage"))]
+        "s3" | "gs" => Err(TerrainError::Settings(
+            "Object storage sources require the 'object-storage' feature".to_string(),
+        )),
+        "ept" => Err(TerrainError::Settings(format!(
+            "Tile source scheme '{}' is not supported yet",
+            scheme
+        ))),
+        _ => Err(TerrainError::Settings(format!(
+            "Unknown tile source '{}'",
+            uri
+        ))),
+    }
+}
+
+pub fn get_laz_data(
+    cpus: NonZero<usize>,
+    config: &Config,
+    resume_points: Option<&[Point]>,
+) -> Result<DownloadResult, TerrainError> {
+    let mut data = vec![];
+    let mut download = stream_laz_data(cpus, config, resume_points, |tile| {
+        data.push(tile);
+        Ok(())
+    })?;
+
+    check_density(config, &data)?;
+    download.data = data;
+
+    Ok(download)
+}
+
+// Downloads and decodes the tiles, handing each one to on_tile as soon as it is ready. The
+// returned result holds the pending and missing points only. With --max-in-flight-tiles the
+// workers take a permit before each download that the tile holds until it is dropped, so at most
+// that many decoded tiles exist at once, wherever on_tile keeps them.
+pub fn stream_laz_data(
+    cpus: NonZero<usize>,
+    config: &Config,
+    resume_points: Option<&[Point]>,
+    mut on_tile: impl FnMut(LazData) -> Result<(), TerrainError>,
+) -> Result<DownloadResult, TerrainError> {
+    let limiter = Arc::new(DownloadLimiter::new(
+        config.max_bandwidth,
+        config.max_total_download,
+    ));
+    let shared_source = create_tile_source(
+        &config.source,
+        &config.mirrors,
+        get_blocks(config),
+        config.survey,
+        &config.http,
+        Arc::clone(&limiter),
+    )?;
+    let shared_source: Arc<dyn TileSource> = match &config.cache_dir {
+        Some(cache_dir) => Arc::new(CachedSource::new(
+            shared_source,
+            cache_dir,
+            config.pack_cache,
+            config.compress_cache,
+            config.survey,
+        )?),
+        None => shared_source,
+    };
+    // The fallback sits outside the cache, so tiles that later appear in the survey replace it
+    let shared_source: Arc<dyn TileSource> = match &config.fallback_dem {
+        Some(url_template) => Arc::new(FallbackSource::new(
+            shared_source,
+            DemSource::new(
+                url_template,
+                config.fallback_dem_resolution,
+                &config.http,
+                Arc::clone(&limiter),
+            )?,
+        )),
+        None => shared_source,
+    };
+
+    let points = filter_points(&config, shared_source.as_ref())?;
+
+    let coordinate_origin = points.first().ok_or_else(get_empty_selection_error)?;
+    let coordinate_origin = (coordinate_origin.0, coordinate_origin.1);
+    let naming = TileNaming {
+        scheme: config.naming,
+        origin: Point(coordinate_origin.0, coordinate_origin.1),
+        area_min: Point(
+            points.iter().map(|point| point.0).min().unwrap_or(0),
+            points.iter().map(|point| point.1).min().unwrap_or(0),
+        ),
+    };
+
+    // The origin is always taken from the full area so resumed runs and shards keep the same file
+    // names
+    let points = match config.shard {
+        Some(shard) => points
+            .into_iter()
+            .filter(|point| shard.contains(point))
+            .collect(),
+        None => points,
+    };
+    let points = match resume_points {
+        Some(resume_points) => points
+            .into_iter()
+            .filter(|point| resume_points.contains(point))
+            .collect(),
+        None => points,
+    };
+    let points = if config.nearest_first {
+        order_nearest_first(config, points)
+    } else {
+        points
+    };
+
+    if config.offline {
+        let mut missing_points = vec![];
+
+        for point in points.iter() {
+            if !shared_source.is_available_offline(point)? {
+                missing_points.push(format!("{}:{}", point.0, point.1));
+            }
+        }
+
+        if !missing_points.is_empty() {
+            return Err(TerrainError::Unavailable(format!(
+                "Offline mode, {} tiles are not available locally: {}",
+                missing_points.len(),
+                missing_points.join(", ")
+            )));
+        }
+    }
+
+    let mut uncached_tile_count = 0;
+    if config.cache_dir.is_some() {
+        for point in points.iter() {
+            if !shared_source.is_available_offline(point)? {
+                uncached_tile_count += 1;
+            }
+        }
+    }
+    spacer::check_free_space(config, points.len(), uncached_tile_count)?;
+
+    let orthophoto = match &config.orthophoto {
+        Some(url_template) => Some(OrthophotoSource::new(
+            url_template,
+            config,
+            Arc::clone(&limiter),
+        )?),
+        None => None,
+    };
+
+    let fetcher = TileFetcher {
+        source: shared_source.as_ref(),
+        limiter: &limiter,
+        spiller: Spiller::new(config.max_memory, config.internal_precision),
+        keep_attributes: config.needs_point_attributes(),
+        coordinate_origin,
+        naming,
+        spot_elevations: config.spot_elevations.as_ref(),
+        exclusion_areas: &config.exclusion_areas,
+        orthophoto: orthophoto.as_ref(),
+    };
+
+    let mut pending_points = vec![];
+    let mut missing_points = vec![];
+    let mut consumer_error = None;
+
+    // Workers pull the next point from a shared counter and hand decoded tiles over a channel
+    // bounded to one tile per worker, so decoding can not run ahead of the collection below
+    let next_index = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::sync_channel(cpus.get());
+    let permits = config.max_in_flight_tiles.map(TilePermits::new);
+
+    thread::scope(|scope| {
+        for _ in 0..cpus.get() {
+            let tx = tx.clone();
+            let (points, next_index, fetcher, permits) = (&points, &next_index, &fetcher, &permits);
+
+            scope.spawn(move || {
+                while let Some(point) = points.get(next_index.fetch_add(1, Ordering::Relaxed)) {
+                    pauser::wait_while_paused(config);
+                    let permit = permits.as_ref().map(|permits| permits.acquire());
+                    let started = Instant::now();
+
+                    // A panic while fetching or decoding fails only this tile instead of silently
+                    // taking the rest of the worker's tiles with it
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(|| fetcher.fetch(point)))
+                        .unwrap_or_else(|_| {
+                            println!(
+                                "Processing panicked. Skipping point {}:{}",
+                                point.0, point.1
+                            );
+                            progress::report(*point, TileStatus::Failed);
+                            TileOutcome::Missing
+                        });
+
+                    if config.nice {
+                        thread::sleep(started.elapsed());
                     }
 
-                    let response = response.unwrap();
+                    // Tiles that were not decoded give their permit back right away
+                    let outcome = match (outcome, permit) {
+                        (TileOutcome::Downloaded(mut data), Some(permit)) => {
+                            data.permit = permit;
+                            TileOutcome::Downloaded(data)
+                        }
+                        (outcome, _) => outcome,
+                    };
 
-                    if !response.status().is_success() {
-                        println!(
-                            "HTTP status not successful (not 200 OK). Skipping point url {}",
-                            url
-                        );
-                        continue;
+                    if tx.send((*point, outcome)).is_err() {
+                        break;
                     }
+                }
+            });
+        }
 
-                    let data_bytes = response.bytes();
+        // Last TX must be dropped to ensure rx does not continue listening
+        drop(tx);
 
-                    if let Err(value) = data_bytes {
-                        println!("Err: {}", value);
-                        println!(
-                            "Reading bytes was not successful. Skipping point url {}",
-                            url
-                        );
-                        continue;
+        // Dropping rx on a consumer error makes the workers stop at their next send
+        for (point, outcome) in rx {
+            match outcome {
+                TileOutcome::Downloaded(data) => {
+                    if let Err(value) = on_tile(data) {
+                        consumer_error = Some(value);
+                        break;
                     }
+                }
+                TileOutcome::Postponed => pending_points.push(point),
+                TileOutcome::Missing => missing_points.push(point),
+            }
+        }
+    });
+
+    if let Some(value) = consumer_error {
+        return Err(value);
+    }
 
-                    let offset_from_center =
-                        (point.0 - coordinate_origin.0, point.1 - coordinate_origin.1);
+    if let (Some(cache_dir), Some(max_cache_size)) = (&config.cache_dir, config.max_cache_size) {
+        cacher::prune_cache(cache_dir, max_cache_size)?;
+    }
 
-                    let mut laz_reader = Reader::new(Cursor::new(data_bytes.unwrap())).unwrap();
-                    let bounds = laz_reader.header().bounds();
-                    let points = laz_reader.points().collect::<Result<Vec<_>, _>>().unwrap();
+    if let Some(usage) = shared_source.usage() {
+        println!("Connection usage: {}", usage);
+    }
 
-                    tx.send((offset_from_center, bounds, points))
-                        .expect(&format!("Issue in thread: '{}', in tx send", id));
+    Ok(DownloadResult {
+        data: vec![],
+        pending_points,
+        missing_points,
+    })
+}
 
-                    thread::sleep(Duration::from_secs(1 * rand::thread_rng().gen_range(0..5)));
-                    // If you find the right block, x, y combination, you got the point. Thus you can move to the next one (break the loop)
-                    break;
-                }
+enum TileOutcome {
+    Downloaded(LazData),
+    Postponed,
+    Missing,
+}
+
+// Everything a download worker needs to turn a point into a decoded tile
+struct TileFetcher<'a> {
+    source: &'a dyn TileSource,
+    limiter: &'a DownloadLimiter,
+    spiller: Spiller,
+    keep_attributes: bool,
+    coordinate_origin: (i32, i32),
+    naming: TileNaming,
+    spot_elevations: Option<&'a SpotElevations>,
+    exclusion_areas: &'a [Area],
+    orthophoto: Option<&'a OrthophotoSource>,
+}
+
+impl TileFetcher<'_> {
+    fn fetch(&self, point: &Point) -> TileOutcome {
+        if self.limiter.is_exhausted() {
+            return TileOutcome::Postponed;
+        }
+
+        progress::report(*point, TileStatus::Downloading);
+        meterer::take_tile_timings();
+
+        let download_start = Instant::now();
+        let data_bytes = match self.source.fetch(point) {
+            Ok(Some(data_bytes)) => {
+                meterer::record_stage(Stage::Download, download_start.elapsed());
+                meterer::count_downloaded_bytes(data_bytes.len());
+                data_bytes
+            }
+            Err(value @ TerrainError::BudgetExhausted(_)) => {
+                println!("{}. Postponing point {}:{}", value, point.0, point.1);
+                progress::report(*point, TileStatus::Postponed);
+                return TileOutcome::Postponed;
+            }
+            Ok(None) => {
+                println!("No tile found for point {}:{}", point.0, point.1);
+                progress::report(*point, TileStatus::Missing);
+                return TileOutcome::Missing;
+            }
+            Err(value) => {
+                progress::report(*point, TileStatus::Failed);
+                println!("Err: {}", value);
+                println!(
+                    "Fetching was not successful. Skipping point {}:{}",
+                    point.0, point.1
+                );
+                return TileOutcome::Missing;
+            }
+        };
+
+        let offset_from_center = (
+            point.0 - self.coordinate_origin.0,
+            point.1 - self.coordinate_origin.1,
+        );
+
+        let decode_start = Instant::now();
+        let (bounds, mut points) = match packer::decode(data_bytes) {
+            Ok(decoded) => {
+                meterer::record_stage(Stage::Decode, decode_start.elapsed());
+                decoded
+            }
+            Err(value) => {
+                progress::report(*point, TileStatus::Failed);
+                println!("Err: {}", TerrainError::LazDecode(value));
+                println!(
+                    "Decoding was not successful. Skipping point {}:{}",
+                    point.0, point.1
+                );
+                return TileOutcome::Missing;
+            }
+        };
+        let tile_areas = self
+            .exclusion_areas
+            .iter()
+            .filter(|area| {
+                area.overlaps((bounds.min.x, bounds.min.y), (bounds.max.x, bounds.max.y))
+            })
+            .collect::<Vec<&Area>>();
+        if !tile_areas.is_empty() {
+            points.retain(|point| !excluder::is_excluded(&tile_areas, point.x, point.y));
 
-                access_index += cpus.get();
+            if points.is_empty() {
+                progress::report(*point, TileStatus::Missing);
+                println!(
+                    "All points are excluded. Skipping point {}:{}",
+                    point.0, point.1
+                );
+                return TileOutcome::Missing;
             }
+        }
+
+        let mut bounds_min = (bounds.min.x, bounds.min.y, bounds.min.z);
+        let mut bounds_max = (bounds.max.x, bounds.max.y, bounds.max.z);
+        let xyz_points = points
+            .iter()
+            .map(|point| [point.x, point.y, point.z])
+            .collect::<Vec<[f64; 3]>>();
+
+        // Spot elevations are set on the gridded raster, the height range has to hold them
+        if let Some(spot_elevations) = self.spot_elevations {
+            for spot in spot_elevations
+                .get_spots_within((bounds_min.0, bounds_min.1), (bounds_max.0, bounds_max.1))
+            {
+                bounds_min.2 = bounds_min.2.min(spot[2]);
+                bounds_max.2 = bounds_max.2.max(spot[2]);
+            }
+        }
+
+        let attributes = if self.keep_attributes {
+            PointAttributes {
+                intensities: points.iter().map(|point| point.intensity).collect(),
+                classes: points
+                    .iter()
+                    .map(|point| u8::from(point.classification))
+                    .collect(),
+            }
+        } else {
+            PointAttributes::default()
+        };
+        let stored_points = match self.spiller.store(xyz_points) {
+            Ok(stored_points) => stored_points,
+            Err(value) => {
+                progress::report(*point, TileStatus::Failed);
+                println!("Err: {}", value);
+                println!(
+                    "Storing points was not successful. Skipping point {}:{}",
+                    point.0, point.1
+                );
+                return TileOutcome::Missing;
+            }
+        };
+
+        // A tile without its orthophoto is still computed
+        let orthophoto = self.orthophoto.and_then(|orthophoto| {
+            orthophoto
+                .fetch(
+                    point,
+                    (bounds_min.0, bounds_min.1),
+                    (bounds_max.0, bounds_max.1),
+                )
+                .unwrap_or_else(|value| {
+                    println!("Err: {}", value);
+                    println!(
+                        "Fetching the orthophoto was not successful. Skipping point {}:{}",
+                        point.0, point.1
+                    );
+                    None
+                })
         });
+
+        progress::report(*point, TileStatus::Downloaded);
+
+        TileOutcome::Downloaded(LazData {
+            tile: *point,
+            offset_from_center,
+            file_stem: self.naming.get_file_stem(*point),
+            bounds_max,
+            bounds_min,
+            points: stored_points,
+            attributes,
+            fallback: self.source.is_fallback(point),
+            survey: self.source.survey(point),
+            orthophoto,
+            timings: meterer::take_tile_timings(),
+            permit: TilePermit::default(),
+        })
     }
+}
 
-    // Last TX must be dropped to ensure rx does not continue listening
-    drop(tx);
+// Reports the survey density and warns about tiles with fewer points than output pixels, which
+// the interpolation can only smear out. Fallback tiles are sparse by design and left out.
+fn check_density(config: &Config, data: &[LazData]) -> Result<(), TerrainError> {
+    let surveyed = data
+        .iter()
+        .filter(|data| !data.fallback)
+        .collect::<Vec<&LazData>>();
 
-    for received in rx {
-        laz_readers.push(LazData {
-            offset_from_center: received.0,
-            bounds_max: (received.1.max.x, received.1.max.y, received.1.max.z),
-            bounds_min: (received.1.min.x, received.1.min.y, received.1.min.z),
-            points: received.2,
-        });
+    if surveyed.is_empty() {
+        return Ok(());
+    }
+
+    let densities = surveyed
+        .iter()
+        .map(|data| data.density())
+        .collect::<Vec<f64>>();
+    println!(
+        "Point density: min {:.2}, mean {:.2}, max {:.2} pts/m²",
+        densities
+            .iter()
+            .fold(f64::MAX, |min, density| min.min(*density)),
+        densities.iter().sum::<f64>() / densities.len() as f64,
+        densities
+            .iter()
+            .fold(0f64, |max, density| max.max(*density))
+    );
+
+    let mut too_sparse = vec![];
+
+    for (data, density) in surveyed.iter().zip(densities) {
+        let pixel_density = (config.tile_resolution(density) as f64 / TILE_SIZE_M).powi(2);
+
+        if density < pixel_density {
+            println!(
+                "Warning: tile {}:{} has {:.2} pts/m², fewer than the {:.2} pixels/m² of the output",
+                data.tile.0, data.tile.1, density, pixel_density
+            );
+        }
+
+        if config
+            .min_density
+            .is_some_and(|min_density| density < min_density)
+        {
+            too_sparse.push(format!("{}:{}", data.tile.0, data.tile.1));
+        }
+    }
+
+    if !too_sparse.is_empty() {
+        return Err(TerrainError::TooSparse(format!(
+            "{} tiles are below the minimum density: {}",
+            too_sparse.len(),
+            too_sparse.join(", ")
+        )));
     }
 
-    laz_readers
+    Ok(())
 }
 
-fn filter_points(config: &Config) -> Vec<Point> {
-    config
+// Points whose tiles have to be regenerated by an update run: tiles missing from the previous run
+// and tiles whose source changed since they were cached
+pub fn get_changed_points(
+    config: &Config,
+    known_offsets: &[(i32, i32)],
+) -> Result<Vec<Point>, TerrainError> {
+    let cache_dir = config.cache_dir.as_ref().ok_or_else(|| {
+        TerrainError::Settings("Updating requires a tile cache (--cache-dir)".to_string())
+    })?;
+    let source = CachedSource::new(
+        create_tile_source(
+            &config.source,
+            &config.mirrors,
+            get_blocks(config),
+            config.survey,
+            &config.http,
+            Arc::new(DownloadLimiter::new(None, None)),
+        )?,
+        cache_dir,
+        config.pack_cache,
+        config.compress_cache,
+        config.survey,
+    )?;
+
+    let points = filter_points(&config, &source)?;
+    let coordinate_origin = *points.first().ok_or_else(get_empty_selection_error)?;
+
+    let mut changed_points = vec![];
+
+    for point in points {
+        let offset_from_center = (point.0 - coordinate_origin.0, point.1 - coordinate_origin.1);
+
+        if !known_offsets.contains(&offset_from_center) || source.refresh(&point)? {
+            changed_points.push(point);
+        }
+    }
+
+    Ok(changed_points)
+}
+
+fn get_blocks(config: &Config) -> Vec<u8> {
+    if let SurveySelection::Block(block_number) = config.survey {
+        return vec![block_number];
+    }
+
+    config.possible_blocks.iter().map(|e| *e).unique().collect()
+}
+
+// Stable, so tiles at the same distance keep the order of the area definition
+fn order_nearest_first(config: &Config, mut points: Vec<Point>) -> Vec<Point> {
+    let center = match config.core_points.first() {
+        Some(core_point) => {
+            let center = core_point.center();
+            (center.0 as f64, center.1 as f64)
+        }
+        None => {
+            let (min_x, max_x) = points
+                .iter()
+                .map(|point| point.0)
+                .minmax()
+                .into_option()
+                .unwrap_or_default();
+            let (min_y, max_y) = points
+                .iter()
+                .map(|point| point.1)
+                .minmax()
+                .into_option()
+                .unwrap_or_default();
+            ((min_x + max_x) as f64 / 2.0, (min_y + max_y) as f64 / 2.0)
+        }
+    };
+
+    points.sort_by(|a, b| {
+        let distance = |point: &Point| {
+            (point.0 as f64 - center.0).powi(2) + (point.1 as f64 - center.1).powi(2)
+        };
+        distance(a).total_cmp(&distance(b))
+    });
+
+    points
+}
+
+// Areas can hold no tile, e.g. a polygon smaller than a tile or a region the source does not cover
+fn get_empty_selection_error() -> TerrainError {
+    TerrainError::Settings("The selected area contains no tiles".to_string())
+}
+
+fn filter_points(config: &Config, source: &dyn TileSource) -> Result<Vec<Point>, TerrainError> {
+    let route_points = match &config.route {
+        Some(route) => {
+            corridor::get_route_points(route, config.corridor_width_m, config.source_crs)
+                .map_err(TerrainError::Input)?
+        }
+        None => vec![],
+    };
+
+    Ok(config
         .core_points
         .iter()
         .flat_map(|core_point| core_point.get_all_points_in_area())
-        .filter(|point| {
-            point.0 >= MIN_POINT_DIM
-                && point.1 >= MIN_POINT_DIM
-                && point.0 < MAX_POINT_DIM
-                && point.1 < MAX_POINT_DIM
-        })
+        .chain(
+            config
+                .rectangles
+                .iter()
+                .flat_map(|rectangle| rectangle.get_all_points_in_area()),
+        )
+        .chain(
+            config
+                .polygons
+                .iter()
+                .flat_map(|polygon| polygon.get_all_points_in_area()),
+        )
+        .chain(route_points)
+        .filter(|point| source.covers(point))
         .unique()
-        .collect()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_permits_are_given_back_on_drop() {
+        let permits = TilePermits::new(2);
+        let first = permits.acquire();
+        let second = permits.acquire();
+
+        assert_eq!(*permits.available.lock().unwrap(), 0);
+
+        // A third tile waits until one of the others is dropped
+        let third = thread::scope(|scope| {
+            let waiting = scope.spawn(|| permits.acquire());
+            thread::sleep(Duration::from_millis(50));
+            assert!(!waiting.is_finished());
+
+            drop(first);
+            waiting.join().unwrap()
+        });
+
+        drop((second, third));
+        assert_eq!(*permits.available.lock().unwrap(), 2);
+        assert!(TilePermit::default().permits.is_none());
+    }
+
+    #[test]
+    fn downloads_are_retried_until_done() {
+        let mut attempts = 0;
+        let result = retry_download("http://localhost/tile", || {
+            attempts += 1;
+            Ok(if attempts < 2 {
+                Attempt::Retry
+            } else {
+                Attempt::Done(attempts)
+            })
+        });
+
+        assert_eq!(result.unwrap(), Some(2));
+    }
+
+    #[test]
+    fn http_dates_sort_chronologically() {
+        let dates = [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Thu, 31 Dec 2015 23:59:59 GMT",
+            "Fri, 01 Jan 2016 00:00:00 GMT",
+            "Mon, 04 Jan 2016 00:00:00 GMT",
+            "Mon, 01 Feb 2016 00:00:00 GMT",
+            "Mon, 01 Feb 2016 09:15:00 GMT",
+            "Mon, 01 Feb 2016 10:05:00 GMT",
+        ];
+
+        let parsed = dates
+            .iter()
+            .map(|date| parse_http_date(date).unwrap())
+            .collect::<Vec<(u16, u8, u8, String)>>();
+
+        assert!(
+            parsed.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            parsed
+        );
+        assert_eq!(parsed[2], (2016, 1, 1, "00:00:00".to_string()));
+    }
+
+    #[test]
+    fn malformed_http_dates_are_rejected() {
+        assert_eq!(parse_http_date("Fri, 01 Foo 2016 00:00:00 GMT"), None);
+        assert_eq!(parse_http_date("Fri, 01 Jan 2016"), None);
+        assert_eq!(parse_http_date("Fri, 1st Jan 2016 00:00:00 GMT"), None);
+    }
 }
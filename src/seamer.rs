@@ -0,0 +1,206 @@
+use std::{collections::HashMap, error::Error};
+
+use serde::Serialize;
+
+use crate::{
+    computer::{self, ComputeConfig, TileMetadata},
+    storage, writer,
+};
+
+#[derive(Serialize)]
+struct Seam {
+    // West or south tile of the pair
+    tile_a: String,
+    // East or north tile of the pair
+    tile_b: String,
+    direction: SeamDirection,
+    samples: usize,
+    max_discontinuity_m: f64,
+    mean_discontinuity_m: f64,
+    flagged: bool,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SeamDirection {
+    // Tiles side by side, the east column of tile_a meets the west column of tile_b
+    Vertical,
+    // Tiles above each other, the north row of tile_a meets the south row of tile_b
+    Horizontal,
+}
+
+#[derive(Serialize)]
+struct SeamSummary {
+    tolerance_m: f64,
+    max_discontinuity_m: f64,
+    flagged: usize,
+    seams: Vec<Seam>,
+}
+
+// Compares the border rows and columns of every pair of adjacent tiles in a generated folder,
+// writes seams.json and fails when a seam jumps by more than the tolerance
+pub fn check_seams(folder: &str, tolerance_m: f64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let config = computer::read_compute_config(folder)?;
+
+    let tiles = config
+        .tiles
+        .iter()
+        .map(|tile| (tile.offset_from_center, tile))
+        .collect::<HashMap<(i32, i32), &TileMetadata>>();
+
+    let mut rasters = HashMap::new();
+    let mut seams = vec![];
+
+    for tile in config.tiles.iter() {
+        let (offset_x, offset_y) = tile.offset_from_center;
+
+        for neighbour_offset in [(offset_x + 1, offset_y), (offset_x, offset_y + 1)] {
+            let neighbour = match tiles.get(&neighbour_offset) {
+                Some(neighbour) => *neighbour,
+                None => continue,
+            };
+
+            // Order the pair west to east or south to north by their bounds, which does not
+            // depend on the direction the offsets grow in
+            let (direction, tile_a, tile_b) = if neighbour_offset.1 == offset_y {
+                if tile.bounds_min.0 < neighbour.bounds_min.0 {
+                    (SeamDirection::Vertical, tile, neighbour)
+                } else {
+                    (SeamDirection::Vertical, neighbour, tile)
+                }
+            } else if tile.bounds_min.1 < neighbour.bounds_min.1 {
+                (SeamDirection::Horizontal, tile, neighbour)
+            } else {
+                (SeamDirection::Horizontal, neighbour, tile)
+            };
+
+            for metadata in [tile_a, tile_b] {
+                if !rasters.contains_key(&metadata.file_stem) {
+                    rasters.insert(metadata.file_stem.clone(), read_tile(folder, metadata)?);
+                }
+            }
+
+            let edge_a = get_edge(&rasters[&tile_a.file_stem], direction, true);
+            let edge_b = get_edge(&rasters[&tile_b.file_stem], direction, false);
+            seams.push(measure_seam(
+                &config,
+                tile_a,
+                tile_b,
+                direction,
+                &edge_a,
+                &edge_b,
+                tolerance_m,
+            ));
+        }
+    }
+
+    let flagged = seams.iter().filter(|seam| seam.flagged).count();
+    for seam in seams.iter().filter(|seam| seam.flagged) {
+        println!(
+            "Seam {} | {}: max discontinuity {:.3} m, mean {:.3} m",
+            seam.tile_a, seam.tile_b, seam.max_discontinuity_m, seam.mean_discontinuity_m
+        );
+    }
+
+    let summary = SeamSummary {
+        tolerance_m,
+        max_discontinuity_m: seams
+            .iter()
+            .fold(0f64, |max, seam| max.max(seam.max_discontinuity_m)),
+        flagged,
+        seams,
+    };
+
+    println!(
+        "Checked {} seams: max discontinuity {:.3} m, {} above {:.3} m",
+        summary.seams.len(),
+        summary.max_discontinuity_m,
+        summary.flagged,
+        tolerance_m
+    );
+
+    let json = serde_json::to_string_pretty(&summary)?;
+    storage::write_atomically(
+        &storage::get_local_path(folder, "seams.json"),
+        json.as_bytes(),
+    )?;
+
+    if flagged > 0 {
+        return Err(format!(
+            "{} of {} seams exceed the tolerance of {} m",
+            flagged,
+            summary.seams.len(),
+            tolerance_m
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn read_tile(
+    folder: &str,
+    tile: &TileMetadata,
+) -> Result<(usize, usize, Vec<f32>), Box<dyn Error + Send + Sync>> {
+    let path = storage::get_local_path(folder, &format!("{}.exr", tile.file_stem));
+
+    if !path.exists() {
+        return Err(format!("Seam check needs EXR tiles, {} is missing", path.display()).into());
+    }
+
+    writer::read_texture(path)
+}
+
+// Border pixels of a tile from west to east or north to south. The far edge is the east column
+// or north row, the near edge the west column or south row.
+fn get_edge(
+    (dim_x, dim_y, buffer): &(usize, usize, Vec<f32>),
+    direction: SeamDirection,
+    far: bool,
+) -> Vec<f32> {
+    match (direction, far) {
+        (SeamDirection::Vertical, true) => (0..*dim_y)
+            .map(|row| buffer[row * dim_x + dim_x - 1])
+            .collect(),
+        (SeamDirection::Vertical, false) => (0..*dim_y).map(|row| buffer[row * dim_x]).collect(),
+        (SeamDirection::Horizontal, true) => buffer[..*dim_x].to_vec(),
+        (SeamDirection::Horizontal, false) => buffer[(dim_y - 1) * dim_x..].to_vec(),
+    }
+}
+
+// Tiles of different resolutions are compared at the positions of the coarser edge
+fn measure_seam(
+    config: &ComputeConfig,
+    tile_a: &TileMetadata,
+    tile_b: &TileMetadata,
+    direction: SeamDirection,
+    edge_a: &[f32],
+    edge_b: &[f32],
+    tolerance_m: f64,
+) -> Seam {
+    let samples = edge_a.len().min(edge_b.len());
+    let height_range = config.max_height - config.min_height;
+
+    let discontinuities = (0..samples)
+        .map(|sample| {
+            let value_a = edge_a[sample * edge_a.len() / samples];
+            let value_b = edge_b[sample * edge_b.len() / samples];
+
+            (value_b - value_a).abs() as f64 * height_range
+        })
+        .collect::<Vec<f64>>();
+
+    let max_discontinuity_m = discontinuities
+        .iter()
+        .fold(0f64, |max, discontinuity| max.max(*discontinuity));
+
+    Seam {
+        tile_a: tile_a.file_stem.clone(),
+        tile_b: tile_b.file_stem.clone(),
+        direction,
+        samples,
+        max_discontinuity_m,
+        mean_discontinuity_m: discontinuities.iter().sum::<f64>() / samples.max(1) as f64,
+        flagged: max_discontinuity_m > tolerance_m,
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use crate::{core::Config, error::TerrainError, storage, writer::OutputFormat};
+
+// Rough size of one downloaded LAZ tile, used for the cache estimate
+pub const ESTIMATED_LAZ_TILE_BYTES: u64 = 64 << 20;
+// Headroom for previews, derived maps and metadata written next to the heightmaps
+const OUTPUT_MARGIN: f64 = 1.25;
+
+// Estimates what the run will write to the destination and the download cache and fails before
+// downloading when a volume has less free space than that
+pub fn check_free_space(
+    config: &Config,
+    tile_count: usize,
+    uncached_tile_count: usize,
+) -> Result<(), TerrainError> {
+    if config.skip_space_check {
+        return Ok(());
+    }
+
+    let pixels = config.resolution as u64 * config.resolution as u64;
+    let bytes_per_pixel = config
+        .formats
+        .iter()
+        .map(|format| match format {
+            OutputFormat::Exr | OutputFormat::Tiff => 4,
+            OutputFormat::Png | OutputFormat::Raw => 2,
+        })
+        .sum::<u64>();
+    let output_bytes =
+        ((tile_count as u64 * pixels * bytes_per_pixel) as f64 * OUTPUT_MARGIN) as u64;
+    let cache_bytes = uncached_tile_count as u64 * ESTIMATED_LAZ_TILE_BYTES;
+    let output_folder =
+        Some(&config.destination_folder).filter(|folder| !storage::is_object_storage(folder));
+
+    // Folders on one volume share its free space, so the output and the cache have to fit
+    // together
+    if let (Some(output_folder), Some(cache_dir)) = (output_folder, &config.cache_dir) {
+        let volume = get_volume_id(output_folder);
+
+        if volume.is_some() && volume == get_volume_id(cache_dir) {
+            return check_volume(
+                output_folder,
+                output_bytes + cache_bytes,
+                "output and download cache",
+            );
+        }
+    }
+
+    if let Some(output_folder) = output_folder {
+        check_volume(output_folder, output_bytes, "output")?;
+    }
+
+    if let Some(cache_dir) = &config.cache_dir {
+        check_volume(cache_dir, cache_bytes, "download cache")?;
+    }
+
+    Ok(())
+}
+
+// Device of the folder, None when it can not be read, e.g. a cache folder not created yet
+#[cfg(unix)]
+fn get_volume_id(folder: &str) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(folder)
+        .ok()
+        .map(|metadata| metadata.dev())
+}
+
+// Drive or share of the folder
+#[cfg(not(unix))]
+fn get_volume_id(folder: &str) -> Option<String> {
+    let path = std::fs::canonicalize(folder).ok()?;
+
+    path.components()
+        .next()
+        .map(|prefix| prefix.as_os_str().to_string_lossy().to_lowercase())
+}
+
+fn check_volume(folder: &str, needed: u64, purpose: &str) -> Result<(), TerrainError> {
+    let available = match fs2::available_space(Path::new(folder)) {
+        Ok(available) => available,
+        Err(value) => {
+            println!("Could not read the free space of {}: {}", folder, value);
+            return Ok(());
+        }
+    };
+
+    println!(
+        "Estimated {} size {} MB, {} MB free in {}",
+        purpose,
+        needed >> 20,
+        available >> 20,
+        folder
+    );
+
+    if available < needed {
+        return Err(TerrainError::DiskSpace(format!(
+            "Not enough disk space for the {} in {}: about {} MB needed, {} MB free. \
+             Free space or pass --skip-space-check to run anyway.",
+            purpose,
+            folder,
+            needed >> 20,
+            available >> 20
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use memmap2::Mmap;
+
+use crate::gridder::Precision;
+
+// Coordinates of a tile's points, held either in memory or in a memory-mapped spill file. With
+// --internal-precision f32 they are kept as f32 offsets from the lowest corner of the tile's
+// points, at half the size. In-memory points hold their share of the --max-memory budget until
+// they are dropped.
+pub enum PointStore {
+    Memory(Vec<[f64; 3]>, Reservation),
+    Mapped {
+        map: Mmap,
+        len: usize,
+    },
+    Compact {
+        origin: [f64; 3],
+        points: Vec<[f32; 3]>,
+        reservation: Reservation,
+    },
+    MappedCompact {
+        origin: [f64; 3],
+        map: Mmap,
+        len: usize,
+    },
+}
+
+impl PointStore {
+    pub fn len(&self) -> usize {
+        match self {
+            PointStore::Memory(points, _) => points.len(),
+            PointStore::Compact { points, .. } => points.len(),
+            PointStore::Mapped { len, .. } | PointStore::MappedCompact { len, .. } => *len,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> [f64; 3] {
+        let (wide, origin, compact) = self.as_slices();
+
+        match wide.get(index) {
+            Some(point) => *point,
+            None => expand(origin, &compact[index]),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = [f64; 3]> + '_ {
+        let (wide, origin, compact) = self.as_slices();
+
+        wide.iter()
+            .copied()
+            .chain(compact.iter().map(move |point| expand(origin, point)))
+    }
+
+    // The full precision points or the origin and offsets, the other slice is empty
+    fn as_slices(&self) -> (&[[f64; 3]], [f64; 3], &[[f32; 3]]) {
+        match self {
+            PointStore::Memory(points, _) => (points, [0.0; 3], &[]),
+            PointStore::Compact { origin, points, .. } => (&[], *origin, points),
+            // The spill file holds exactly len native-endian coordinate triples and mappings
+            // are page aligned, which satisfies the alignment of f64 and f32
+            PointStore::Mapped { map, len } => (
+                unsafe { std::slice::from_raw_parts(map.as_ptr() as *const [f64; 3], *len) },
+                [0.0; 3],
+                &[],
+            ),
+            PointStore::MappedCompact { origin, map, len } => (&[], *origin, unsafe {
+                std::slice::from_raw_parts(map.as_ptr() as *const [f32; 3], *len)
+            }),
+        }
+    }
+}
+
+fn expand(origin: [f64; 3], point: &[f32; 3]) -> [f64; 3] {
+    [
+        origin[0] + point[0] as f64,
+        origin[1] + point[1] as f64,
+        origin[2] + point[2] as f64,
+    ]
+}
+
+// A computed tile raster kept around for chunk assembly, in memory or in a spill file
+pub enum RasterStore {
+    Memory(Vec<f32>, Reservation),
+    Mapped { map: Mmap, len: usize },
+}
+
+impl RasterStore {
+    pub fn as_slice(&self) -> &[f32] {
+        match self {
+            RasterStore::Memory(heights, _) => heights,
+            RasterStore::Mapped { map, len } => unsafe {
+                std::slice::from_raw_parts(map.as_ptr() as *const f32, *len)
+            },
+        }
+    }
+}
+
+// Bytes of the memory budget claimed by an in-memory store, given back when the store is dropped.
+// The default holds none, for stores outside of any budget.
+#[derive(Default)]
+pub struct Reservation {
+    budget: Option<(Arc<AtomicU64>, u64)>,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some((used_memory, size)) = &self.budget {
+            used_memory.fetch_sub(*size, Ordering::SeqCst);
+        }
+    }
+}
+
+// Keeps point arrays in memory until --max-memory is reached and spills the rest to files in
+// the temp directory, so areas larger than RAM are paged in by the OS on demand
+pub struct Spiller {
+    max_memory: Option<u64>,
+    precision: Precision,
+    used_memory: Arc<AtomicU64>,
+    spilled: AtomicUsize,
+    folder: PathBuf,
+}
+
+impl Spiller {
+    pub fn new(max_memory: Option<u64>, precision: Precision) -> Self {
+        Spiller {
+            max_memory,
+            precision,
+            used_memory: Arc::new(AtomicU64::new(0)),
+            spilled: AtomicUsize::new(0),
+            folder: std::env::temp_dir().join(format!("las-terrain-generator-{}", process::id())),
+        }
+    }
+
+    pub fn store(&self, points: Vec<[f64; 3]>) -> io::Result<PointStore> {
+        if self.precision == Precision::F32 {
+            return self.store_compact(points);
+        }
+
+        if let Some(reservation) = self.reserve((points.len() * size_of::<[f64; 3]>()) as u64) {
+            return Ok(PointStore::Memory(points, reservation));
+        }
+
+        let map = self.spill("points", |writer| {
+            for point in points.iter() {
+                for value in point {
+                    writer.write_all(&value.to_ne_bytes())?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(PointStore::Mapped {
+            map,
+            len: points.len(),
+        })
+    }
+
+    fn store_compact(&self, points: Vec<[f64; 3]>) -> io::Result<PointStore> {
+        let origin = points.iter().fold([f64::MAX; 3], |origin, point| {
+            [
+                origin[0].min(point[0]),
+                origin[1].min(point[1]),
+                origin[2].min(point[2]),
+            ]
+        });
+        let len = points.len();
+        let points = points
+            .into_iter()
+            .map(|point| {
+                [
+                    (point[0] - origin[0]) as f32,
+                    (point[1] - origin[1]) as f32,
+                    (point[2] - origin[2]) as f32,
+                ]
+            })
+            .collect::<Vec<[f32; 3]>>();
+
+        if let Some(reservation) = self.reserve((len * size_of::<[f32; 3]>()) as u64) {
+            return Ok(PointStore::Compact {
+                origin,
+                points,
+                reservation,
+            });
+        }
+
+        let map = self.spill("points", |writer| {
+            for point in points.iter() {
+                for value in point {
+                    writer.write_all(&value.to_ne_bytes())?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(PointStore::MappedCompact { origin, map, len })
+    }
+
+    pub fn store_raster(&self, heights: Vec<f32>) -> io::Result<RasterStore> {
+        if let Some(reservation) = self.reserve((heights.len() * size_of::<f32>()) as u64) {
+            return Ok(RasterStore::Memory(heights, reservation));
+        }
+
+        let map = self.spill("raster", |writer| {
+            for height in heights.iter() {
+                writer.write_all(&height.to_ne_bytes())?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(RasterStore::Mapped {
+            map,
+            len: heights.len(),
+        })
+    }
+
+    // Claims size bytes of the memory budget, None when they have to be spilled instead
+    fn reserve(&self, size: u64) -> Option<Reservation> {
+        let max_memory = match self.max_memory {
+            Some(max_memory) => max_memory,
+            None => return Some(Reservation::default()),
+        };
+
+        if self.used_memory.fetch_add(size, Ordering::SeqCst) + size <= max_memory {
+            return Some(Reservation {
+                budget: Some((self.used_memory.clone(), size)),
+            });
+        }
+
+        self.used_memory.fetch_sub(size, Ordering::SeqCst);
+
+        None
+    }
+
+    fn spill(
+        &self,
+        prefix: &str,
+        write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+    ) -> io::Result<Mmap> {
+        fs::create_dir_all(&self.folder)?;
+        let path = self.folder.join(format!(
+            "{}_{}.bin",
+            prefix,
+            self.spilled.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            write(&mut writer)?;
+            writer.flush()?;
+        }
+
+        let file = File::open(&path)?;
+        let map = unsafe { Mmap::map(&file)? };
+
+        // The mapping stays valid after unlinking, so the file is gone once the run ends.
+        // Platforms that refuse to remove mapped files keep it until the temp dir is cleaned.
+        let _ = fs::remove_file(&path);
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_stores_release_their_memory() {
+        // Room for one raster of 20 heights, 80 bytes
+        let spiller = Spiller::new(Some(100), Precision::F64);
+
+        let first = spiller.store_raster(vec![1.0; 20]).unwrap();
+        let second = spiller.store_raster(vec![2.0; 20]).unwrap();
+
+        assert!(matches!(first, RasterStore::Memory(..)));
+        assert!(matches!(second, RasterStore::Mapped { .. }));
+        assert_eq!(second.as_slice(), &[2.0; 20]);
+
+        drop(first);
+        let third = spiller.store_raster(vec![3.0; 20]).unwrap();
+
+        assert!(matches!(third, RasterStore::Memory(..)));
+        assert_eq!(spiller.used_memory.load(Ordering::SeqCst), 80);
+
+        drop(third);
+        assert_eq!(spiller.used_memory.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn dropped_point_stores_release_their_memory() {
+        let spiller = Spiller::new(Some(100), Precision::F32);
+        let points = vec![[1.0, 2.0, 3.0]; 8];
+
+        // 8 compact points are 96 bytes
+        let first = spiller.store(points.clone()).unwrap();
+        assert!(matches!(first, PointStore::Compact { .. }));
+        assert!(matches!(
+            spiller.store(points.clone()).unwrap(),
+            PointStore::MappedCompact { .. }
+        ));
+
+        drop(first);
+        let second = spiller.store(points).unwrap();
+
+        assert!(matches!(second, PointStore::Compact { .. }));
+        assert_eq!(second.get(7), [1.0, 2.0, 3.0]);
+    }
+}
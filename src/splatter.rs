@@ -0,0 +1,163 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::{
+    deriver::{DerivedMap, TileMaps},
+    error::TerrainError,
+    gridder::TileIndex,
+    requester::LazData,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SplatChannel {
+    R,
+    G,
+    B,
+    A,
+}
+
+// One [[rule]] of a rules file. Every given condition has to hold for a pixel to be painted,
+// classes count by the share of neighbouring points in one of the classes.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SplatRule {
+    name: Option<String>,
+    channel: SplatChannel,
+    slope_min: Option<f64>,
+    slope_max: Option<f64>,
+    height_min: Option<f64>,
+    height_max: Option<f64>,
+    #[serde(default)]
+    classes: Vec<u8>,
+}
+
+// Rules of --splat-rules, e.g.
+//
+// [[rule]]
+// name = "rock"
+// channel = "r"
+// slope_min = 35
+//
+// [[rule]]
+// channel = "g"
+// height_max = 800
+//
+// [[rule]]
+// channel = "b"
+// classes = [5]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplatRules {
+    #[serde(rename = "rule")]
+    rules: Vec<SplatRule>,
+}
+
+impl SplatRules {
+    pub fn needs_point_attributes(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.classes.is_empty())
+    }
+
+    // Names of the rules painting each channel, for descriptors of the written maps
+    pub fn channel_names(&self) -> [String; 4] {
+        [
+            SplatChannel::R,
+            SplatChannel::G,
+            SplatChannel::B,
+            SplatChannel::A,
+        ]
+        .map(|channel| {
+            self.rules
+                .iter()
+                .filter(|rule| rule.channel == channel)
+                .map(|rule| rule.name.as_deref().unwrap_or("unnamed"))
+                .collect::<Vec<&str>>()
+                .join("+")
+        })
+    }
+
+    // RGBA weights of a tile on the grid of its heightmap. Several rules on one channel take the
+    // strongest weight and the channels of a pixel are normalized to sum to one.
+    pub fn compute(
+        &self,
+        maps: &mut TileMaps,
+        index: &TileIndex,
+        data: &LazData,
+        sample_size: usize,
+        height_range: (f64, f64),
+    ) -> Result<[Vec<f32>; 4], TerrainError> {
+        let heights = maps
+            .get(DerivedMap::Height)?
+            .iter()
+            .map(|height| height_range.0 + *height as f64 * (height_range.1 - height_range.0))
+            .collect::<Vec<f64>>();
+        let slopes = maps.get(DerivedMap::Slope)?.to_vec();
+
+        let mut channels: [Vec<f32>; 4] = Default::default();
+        for channel in channels.iter_mut() {
+            *channel = vec![0.0; heights.len()];
+        }
+
+        for rule in self.rules.iter() {
+            let class_shares = (!rule.classes.is_empty()).then(|| {
+                let indicators = data
+                    .attributes
+                    .classes
+                    .iter()
+                    .map(|class| {
+                        if rule.classes.contains(class) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect::<Vec<f32>>();
+
+                index.interpolate(sample_size, &indicators)
+            });
+
+            let channel = &mut channels[rule.channel as usize];
+
+            for (pixel, weight) in channel.iter_mut().enumerate() {
+                let (height, slope) = (heights[pixel], slopes[pixel] as f64);
+                let inside = rule.slope_min.is_none_or(|min| slope >= min)
+                    && rule.slope_max.is_none_or(|max| slope <= max)
+                    && rule.height_min.is_none_or(|min| height >= min)
+                    && rule.height_max.is_none_or(|max| height <= max);
+
+                if !inside {
+                    continue;
+                }
+
+                let share = class_shares.as_ref().map_or(1.0, |shares| shares[pixel]);
+                *weight = weight.max(share);
+            }
+        }
+
+        for pixel in 0..heights.len() {
+            let total = channels.iter().map(|channel| channel[pixel]).sum::<f32>();
+
+            if total > 0.0 {
+                for channel in channels.iter_mut() {
+                    channel[pixel] /= total;
+                }
+            }
+        }
+
+        Ok(channels)
+    }
+}
+
+pub fn parse_splat_rules(path: &str) -> Result<SplatRules, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read splat rules '{}': {}", path, error))?;
+    let rules: SplatRules = toml::from_str(&contents)
+        .map_err(|error| format!("Invalid splat rules '{}': {}", path, error))?;
+
+    if rules.rules.is_empty() {
+        return Err(format!("Splat rules '{}' contain no [[rule]]", path));
+    }
+
+    Ok(rules)
+}
@@ -0,0 +1,165 @@
+use std::fs;
+
+// Pixels within this many pixels of a spot are blended towards its height, the weight falling
+// linearly with the distance
+const SPOT_RADIUS_PX: f64 = 2.0;
+
+// Surveyed heights such as benchmarks or bridge decks, x,y,z in the source CRS
+#[derive(Clone, Debug, Default)]
+pub struct SpotElevations {
+    spots: Vec<[f64; 3]>,
+}
+
+impl SpotElevations {
+    pub fn get_spots_within(
+        &self,
+        bounds_min: (f64, f64),
+        bounds_max: (f64, f64),
+    ) -> impl Iterator<Item = &[f64; 3]> {
+        self.spots.iter().filter(move |spot| {
+            (bounds_min.0..=bounds_max.0).contains(&spot[0])
+                && (bounds_min.1..=bounds_max.1).contains(&spot[1])
+        })
+    }
+
+    // Sets the pixel nearest to each spot of the tile to the spot's normalized height and blends
+    // the pixels around it, so the surveyed heights are kept exactly without a spike. Pixels are
+    // laid out as by gridder::get_pixel_position. Voids are only filled at the nearest pixel.
+    pub fn apply(
+        &self,
+        bounds_min: (f64, f64),
+        bounds_max: (f64, f64),
+        (dim_x, dim_y): (usize, usize),
+        normalize_height: impl Fn(f64) -> f32,
+        heights: &mut [f32],
+    ) {
+        for spot in self.get_spots_within(bounds_min, bounds_max) {
+            let target = normalize_height(spot[2]);
+            let (spot_x, spot_y) = (
+                (spot[0] - bounds_min.0) / (bounds_max.0 - bounds_min.0) * dim_x as f64,
+                (bounds_max.1 - spot[1]) / (bounds_max.1 - bounds_min.1) * dim_y as f64,
+            );
+            let nearest = (
+                (spot_x.round() as usize).min(dim_x - 1),
+                (spot_y.round() as usize).min(dim_y - 1),
+            );
+
+            let columns = (spot_x - SPOT_RADIUS_PX).ceil().max(0.0) as usize
+                ..=((spot_x + SPOT_RADIUS_PX).floor() as usize).min(dim_x - 1);
+            let rows = (spot_y - SPOT_RADIUS_PX).ceil().max(0.0) as usize
+                ..=((spot_y + SPOT_RADIUS_PX).floor() as usize).min(dim_y - 1);
+
+            for row in rows {
+                for column in columns.clone() {
+                    let height = &mut heights[row * dim_x + column];
+
+                    if (column, row) == nearest {
+                        *height = target;
+                        continue;
+                    }
+
+                    let distance = (column as f64 - spot_x).hypot(row as f64 - spot_y);
+                    let weight = (1.0 - distance / SPOT_RADIUS_PX).max(0.0) as f32;
+
+                    if !height.is_nan() {
+                        *height += (target - *height) * weight;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// CSV of x,y,z rows separated by commas, semicolons or whitespace. A header line and lines
+// starting with # are skipped.
+pub fn parse_spot_elevations(path: &str) -> Result<SpotElevations, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read spot elevations '{}': {}", path, error))?;
+    let mut spots = vec![];
+
+    for (line_index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line
+            .split(|character: char| {
+                character == ',' || character == ';' || character.is_whitespace()
+            })
+            .filter(|field| !field.is_empty())
+            .collect::<Vec<&str>>();
+        let values = fields
+            .iter()
+            .take(3)
+            .map(|field| field.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>();
+
+        match values {
+            Ok(values) if values.len() == 3 && values.iter().all(|value| value.is_finite()) => {
+                spots.push([values[0], values[1], values[2]])
+            }
+            Err(_) if spots.is_empty() && line_index == 0 => continue,
+            _ => {
+                return Err(format!(
+                    "Invalid spot elevation on line {} of '{}', expected x,y,z: {}",
+                    line_index + 1,
+                    path,
+                    line
+                ));
+            }
+        }
+    }
+
+    if spots.is_empty() {
+        return Err(format!("Spot elevations '{}' contain no x,y,z rows", path));
+    }
+
+    Ok(SpotElevations { spots })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spots_are_kept_exactly_and_blended_around() {
+        let spots = SpotElevations {
+            spots: vec![[5.0, 5.0, 0.8]],
+        };
+        let mut heights = vec![0.2; 10 * 10];
+        heights[0] = f32::NAN;
+
+        spots.apply(
+            (0.0, 0.0),
+            (10.0, 10.0),
+            (10, 10),
+            |height| height as f32,
+            &mut heights,
+        );
+
+        // The spot lies on the sample point of column 5, row 5
+        assert_eq!(heights[5 * 10 + 5], 0.8);
+        assert!((heights[5 * 10 + 6] - 0.5).abs() < 1e-6);
+        assert_eq!(heights[5 * 10 + 8], 0.2);
+        assert!(heights[0].is_nan());
+    }
+
+    #[test]
+    fn spots_outside_the_tile_are_ignored() {
+        let spots = SpotElevations {
+            spots: vec![[15.0, 5.0, 0.8]],
+        };
+        let mut heights = vec![0.2; 10 * 10];
+
+        spots.apply(
+            (0.0, 0.0),
+            (10.0, 10.0),
+            (10, 10),
+            |height| height as f32,
+            &mut heights,
+        );
+
+        assert!(heights.iter().all(|height| *height == 0.2));
+    }
+}
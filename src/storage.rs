@@ -0,0 +1,181 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    thread,
+};
+
+#[cfg(feature = "object-storage")]
+use s3::{Bucket, Region, creds::Credentials};
+
+use crate::{error::StorageError, writer::OutputError};
+
+#[cfg(feature = "object-storage")]
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+
+pub trait OutputSink: Send + Sync {
+    fn put(&self, relative_path: &str, bytes: &[u8]) -> Result<(), OutputError>;
+}
+
+pub struct LocalSink {
+    folder: PathBuf,
+}
+
+impl LocalSink {
+    pub fn new(folder: &str) -> Self {
+        LocalSink {
+            folder: resolve_local_folder(folder),
+        }
+    }
+}
+
+impl OutputSink for LocalSink {
+    fn put(&self, relative_path: &str, bytes: &[u8]) -> Result<(), OutputError> {
+        let path = get_local_path(&self.folder, relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        write_atomically(&path, bytes)?;
+
+        Ok(())
+    }
+}
+
+// Bucket access for `s3://bucket/prefix` and `gs://bucket/prefix` locations. Credentials come
+// from the standard AWS chain (environment, profile, instance metadata); GCS is reached through
+// its S3-compatible endpoint using HMAC keys supplied the same way.
+#[cfg(feature = "object-storage")]
+pub struct ObjectStorage {
+    bucket: Box<Bucket>,
+    prefix: String,
+}
+
+#[cfg(feature = "object-storage")]
+impl ObjectStorage {
+    pub fn new(uri: &str) -> Result<Self, StorageError> {
+        let (scheme, location) = uri.split_once("://").unwrap_or(("s3", uri));
+        let (bucket_name, prefix) = location.split_once('/').unwrap_or((location, ""));
+
+        let region = match scheme {
+            "gs" => Region::Custom {
+                region: "auto".to_string(),
+                endpoint: GCS_ENDPOINT.to_string(),
+            },
+            _ => {
+                let region =
+                    std::env::var("AWS_REGION").unwrap_or_else(|_| "eu-central-1".to_string());
+                let endpoint = std::env::var("AWS_ENDPOINT_URL")
+                    .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+
+                Region::Custom { region, endpoint }
+            }
+        };
+
+        let bucket = Bucket::new(bucket_name, region, Credentials::default()?)?.with_path_style();
+
+        Ok(ObjectStorage {
+            bucket,
+            prefix: prefix.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub fn get(&self, relative_path: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        let key = self.key(relative_path);
+        let response = self.bucket.get_object(&key)?;
+
+        match response.status_code() {
+            200..=299 => Ok(Some(response.bytes().to_vec())),
+            404 => Ok(None),
+            status => Err(StorageError::Status { key, status }),
+        }
+    }
+
+    fn key(&self, relative_path: &str) -> String {
+        if self.prefix.is_empty() {
+            relative_path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, relative_path)
+        }
+    }
+}
+
+#[cfg(feature = "object-storage")]
+impl OutputSink for ObjectStorage {
+    fn put(&self, relative_path: &str, bytes: &[u8]) -> Result<(), OutputError> {
+        let key = self.key(relative_path);
+        let response = self
+            .bucket
+            .put_object(&key, bytes)
+            .map_err(StorageError::from)?;
+
+        if !(200..300).contains(&response.status_code()) {
+            return Err(StorageError::Status {
+                key,
+                status: response.status_code(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn is_object_storage(location: &str) -> bool {
+    location.starts_with("s3://") || location.starts_with("gs://")
+}
+
+// Writes next to the target first and renames on success, so an interrupted run never leaves a
+// truncated file under the final name and existing outputs can be trusted. The data is synced
+// before the rename, otherwise a power loss can leave the renamed file empty.
+pub fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut partial_name = path.file_name().unwrap_or_default().to_os_string();
+    partial_name.push(format!(
+        ".{}.{:?}.part",
+        process::id(),
+        thread::current().id()
+    ));
+    let partial_path = path.with_file_name(partial_name);
+
+    let result = write_synced(&partial_path, bytes).and_then(|_| fs::rename(&partial_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+
+    result
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+// Absolute form of an existing local folder. On Windows this is the \\?\ form, which lifts the
+// 260 character path limit and keeps UNC shares working as \\?\UNC\server\share.
+pub fn resolve_local_folder(folder: &str) -> PathBuf {
+    fs::canonicalize(folder).unwrap_or_else(|_| PathBuf::from(folder))
+}
+
+// Joins a sink path, which is always separated by '/', onto a local folder with the separators
+// of the platform. Verbatim Windows paths do not accept '/' at all.
+pub fn get_local_path(folder: impl AsRef<Path>, relative_path: &str) -> PathBuf {
+    relative_path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .fold(folder.as_ref().to_path_buf(), |path, part| path.join(part))
+}
+
+pub fn create_output_sink(destination: &str) -> Result<Arc<dyn OutputSink>, OutputError> {
+    if !is_object_storage(destination) {
+        return Ok(Arc::new(LocalSink::new(destination)));
+    }
+
+    #[cfg(feature = "object-storage")]
+    return Ok(Arc::new(ObjectStorage::new(destination)?));
+
+    #[cfg(not(feature = "object-storage"))]
+    Err(StorageError::Unsupported.into())
+}
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, fs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gridder;
+
+// One filter of a style, applied in the order of the style
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum StyleFilter {
+    // Quantizes the elevation into steps, sharpness is the flat share of every step
+    Terrace {
+        step_m: f64,
+        #[serde(default = "default_sharpness")]
+        sharpness: f64,
+    },
+    // Raises the terrain above its local mean by strength times its local relief
+    Ridges {
+        strength: f64,
+        #[serde(default = "default_radius_m")]
+        radius_m: f64,
+    },
+    // Thermal erosion moving material down slopes steeper than the talus angle
+    Erosion {
+        iterations: usize,
+        #[serde(default = "default_talus_deg")]
+        talus_deg: f64,
+    },
+}
+
+fn default_sharpness() -> f64 {
+    0.7
+}
+
+fn default_radius_m() -> f64 {
+    20.0
+}
+
+fn default_talus_deg() -> f64 {
+    35.0
+}
+
+// Filters of a style in the batch file or --style-file, written to a stylized copy of the
+// heightmap of the runs naming it with --stylize, e.g.
+//
+// "styles": {
+//   "eroded-terraces": {
+//     "filters": [
+//       { "type": "erosion", "iterations": 20 },
+//       { "type": "terrace", "step_m": 10, "sharpness": 0.8 }
+//     ]
+//   }
+// }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Style {
+    filters: Vec<StyleFilter>,
+}
+
+// Reads --style-file, a JSON object of named styles like the "styles" of batch files
+pub fn parse_style_file(path: &str) -> Result<BTreeMap<String, Style>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read styles '{}': {}", path, error))?;
+
+    serde_json::from_str(&contents).map_err(|error| format!("Invalid styles '{}': {}", path, error))
+}
+
+impl Style {
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // Stylized copy of normalized heights. meter_height is the normalized height of one meter.
+    pub fn apply(
+        &self,
+        heights: &[f32],
+        dim_x: usize,
+        dim_y: usize,
+        pixel_size_m: f64,
+        meter_height: f64,
+    ) -> Result<Vec<f32>, String> {
+        let mut stylized = heights.to_vec();
+
+        for filter in self.filters.iter() {
+            match filter {
+                StyleFilter::Terrace { step_m, sharpness } => {
+                    terrace(&mut stylized, *step_m * meter_height, *sharpness)
+                }
+                StyleFilter::Ridges { strength, radius_m } => {
+                    let radius = (radius_m / pixel_size_m).round().max(1.0) as u32;
+                    let mut local_mean = stylized.clone();
+                    gridder::blur_image(radius, dim_x, dim_y, &mut local_mean)?;
+
+                    for (value, mean) in stylized.iter_mut().zip(local_mean) {
+                        *value += (*strength * (*value - mean).max(0.0) as f64) as f32;
+                    }
+                }
+                StyleFilter::Erosion {
+                    iterations,
+                    talus_deg,
+                } => {
+                    let talus = (talus_deg.to_radians().tan() * pixel_size_m * meter_height) as f32;
+                    erode(&mut stylized, dim_x, dim_y, *iterations, talus);
+                }
+            }
+        }
+
+        for value in stylized.iter_mut() {
+            *value = value.clamp(0.0, 1.0);
+        }
+
+        Ok(stylized)
+    }
+}
+
+fn terrace(heights: &mut [f32], step: f64, sharpness: f64) {
+    let sharpness = sharpness.clamp(0.0, 1.0);
+
+    for value in heights.iter_mut().filter(|value| !value.is_nan()) {
+        let steps = *value as f64 / step;
+        let level = steps.floor();
+        // Flat for the first part of the step, then a ramp up to the next level
+        let rise = if sharpness < 1.0 {
+            ((steps - level - sharpness) / (1.0 - sharpness)).max(0.0)
+        } else {
+            0.0
+        };
+
+        *value = ((level + rise) * step) as f32;
+    }
+}
+
+// Every pass moves half of the height above the talus difference to the lowest 4-neighbour
+fn erode(heights: &mut [f32], dim_x: usize, dim_y: usize, iterations: usize, talus: f32) {
+    for _ in 0..iterations {
+        let previous = heights.to_vec();
+
+        for index in 0..previous.len() {
+            let (column, row) = (index % dim_x, index / dim_x);
+            let lowest = [
+                (column > 0).then(|| index - 1),
+                (column + 1 < dim_x).then(|| index + 1),
+                (row > 0).then(|| index - dim_x),
+                (row + 1 < dim_y).then(|| index + dim_x),
+            ]
+            .into_iter()
+            .flatten()
+            .filter(|neighbour| !previous[*neighbour].is_nan())
+            .min_by(|a, b| previous[*a].total_cmp(&previous[*b]));
+
+            let Some(lowest) = lowest else {
+                continue;
+            };
+            let difference = previous[index] - previous[lowest];
+
+            if difference > talus {
+                let moved = (difference - talus) / 2.0;
+                heights[index] -= moved;
+                heights[lowest] += moved;
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::{collections::HashMap, num::NonZero, thread};
+
+use serde_json::json;
+
+use crate::{
+    core::Config,
+    mosaic::{self, Mosaic},
+    projection::{self, Crs},
+    storage::OutputSink,
+    writer::{OutputError, TileRaster},
+};
+
+// Pixels per side of a web map tile
+const TILE_SIZE_PX: usize = 256;
+// Half the side of the Web Mercator square, x and y of EPSG:3857 run from minus to plus this
+const WEB_MERCATOR_HALF_SIDE: f64 = 20_037_508.342_789_244;
+const MAX_ZOOM: u8 = 24;
+
+// Tile x and y ranges (inclusive) of one zoom level covering a rectangle in EPSG:3857
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TileRange {
+    pub zoom: u8,
+    pub min: (u32, u32),
+    pub max: (u32, u32),
+}
+
+impl TileRange {
+    pub fn new(zoom: u8, bounds_min: (f64, f64), bounds_max: (f64, f64)) -> Self {
+        let tile_size = get_tile_size_m(zoom);
+        let last = (1u32 << zoom) - 1;
+        let to_index = |offset: f64| ((offset / tile_size).floor().max(0.0) as u32).min(last);
+
+        // Tile rows count from the northern edge of the map
+        TileRange {
+            zoom,
+            min: (
+                to_index(bounds_min.0 + WEB_MERCATOR_HALF_SIDE),
+                to_index(WEB_MERCATOR_HALF_SIDE - bounds_max.1),
+            ),
+            max: (
+                to_index(bounds_max.0 + WEB_MERCATOR_HALF_SIDE),
+                to_index(WEB_MERCATOR_HALF_SIDE - bounds_min.1),
+            ),
+        }
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.min.1..=self.max.1).flat_map(move |y| (self.min.0..=self.max.0).map(move |x| (x, y)))
+    }
+
+    pub fn is_single_tile(&self) -> bool {
+        self.min == self.max
+    }
+}
+
+// Side of a tile of the zoom level in EPSG:3857 meters
+fn get_tile_size_m(zoom: u8) -> f64 {
+    2.0 * WEB_MERCATOR_HALF_SIDE / (1u64 << zoom) as f64
+}
+
+// Bounds of tile x/y of the zoom level in EPSG:3857 as (min, max)
+pub fn get_tile_bounds(zoom: u8, x: u32, y: u32) -> ((f64, f64), (f64, f64)) {
+    let tile_size = get_tile_size_m(zoom);
+    let (min_x, max_y) = (
+        -WEB_MERCATOR_HALF_SIDE + x as f64 * tile_size,
+        WEB_MERCATOR_HALF_SIDE - y as f64 * tile_size,
+    );
+
+    ((min_x, max_y - tile_size), (min_x + tile_size, max_y))
+}
+
+// Coarsest zoom level whose pixels on the ground at the latitude are at most pixel_size_m wide,
+// so the finest level keeps all the detail of the survey tiles
+pub fn get_max_zoom(pixel_size_m: f64, latitude_deg: f64) -> u8 {
+    let ground_tile_size = 2.0 * WEB_MERCATOR_HALF_SIDE * latitude_deg.to_radians().cos();
+    let zoom = (ground_tile_size / (TILE_SIZE_PX as f64 * pixel_size_m))
+        .log2()
+        .ceil();
+
+    zoom.clamp(0.0, MAX_ZOOM as f64) as u8
+}
+
+// Writes the mosaic as a slippy map pyramid of 256 px Web Mercator tiles, {z}/{x}/{y}.{ext} with y
+// counting from the north as static tile servers and web maps expect. The finest zoom level
+// matches the pixel size of the survey tiles, every coarser one averages 2x2 pixels of the level
+// below it, down to the level at which the whole area fits into one tile. A TileJSON file
+// describing the pyramid is written next to it.
+pub fn write_tiles(
+    config: &Config,
+    cpus: NonZero<usize>,
+    sink: &dyn OutputSink,
+    mosaic: &Mosaic,
+    pixel_size_m: f64,
+    min_height: f64,
+    max_height: f64,
+) -> Result<(), OutputError> {
+    let (min_x, min_y, max_x, max_y) = mosaic.extent();
+    let (bounds_min, bounds_max) = projection::reproject_bounds(
+        config.source_crs,
+        Crs::WebMercator,
+        (min_x, min_y),
+        (max_x, max_y),
+    );
+    let (center_latitude, _) = config
+        .source_crs
+        .to_wgs84((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+
+    let max_zoom = get_max_zoom(pixel_size_m, center_latitude);
+    let mut min_zoom = max_zoom;
+    while min_zoom > 0 && !TileRange::new(min_zoom, bounds_min, bounds_max).is_single_tile() {
+        min_zoom -= 1;
+    }
+
+    println!(
+        "Writing web map tiles of zoom levels {} to {}",
+        min_zoom, max_zoom
+    );
+
+    let range = TileRange::new(max_zoom, bounds_min, bounds_max);
+    let mut level = sample_level(config.source_crs, cpus, mosaic, &range);
+
+    for zoom in (min_zoom..=max_zoom).rev() {
+        if zoom < max_zoom {
+            level = average_level(&level);
+        }
+
+        for ((x, y), heights) in level.iter() {
+            let (tile_min, tile_max) = get_tile_bounds(zoom, *x, *y);
+            let raster = TileRaster {
+                bounds_min: tile_min,
+                bounds_max: tile_max,
+                crs: Crs::WebMercator,
+                dim_x: TILE_SIZE_PX,
+                dim_y: TILE_SIZE_PX,
+                heights,
+                min_height,
+                max_height,
+            };
+
+            for format in config.formats.iter() {
+                let writer = format.writer(config.big_tiff, config.integer_heights);
+                let file_name = format!("{}/{}/{}.{}", zoom, x, y, writer.extension());
+
+                sink.put(&file_name, &writer.encode(&raster)?)?;
+            }
+        }
+    }
+
+    let (south_west, north_east) = (
+        Crs::WebMercator.to_wgs84(bounds_min.0, bounds_min.1),
+        Crs::WebMercator.to_wgs84(bounds_max.0, bounds_max.1),
+    );
+    let tile_urls = config
+        .formats
+        .iter()
+        .map(|format| {
+            let writer = format.writer(config.big_tiff, config.integer_heights);
+            format!("{{z}}/{{x}}/{{y}}.{}", writer.extension())
+        })
+        .collect::<Vec<String>>();
+
+    sink.put(
+        "tiles.json",
+        serde_json::to_string_pretty(&json!({
+            "tilejson": "3.0.0",
+            "tiles": tile_urls,
+            "scheme": "xyz",
+            "minzoom": min_zoom,
+            "maxzoom": max_zoom,
+            "bounds": [south_west.1, south_west.0, north_east.1, north_east.0],
+            "min_height": min_height,
+            "max_height": max_height,
+        }))?
+        .as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+// Samples the tiles of the finest zoom level at their pixel centers, tiles outside of the mosaic
+// are left out
+fn sample_level(
+    source_crs: Crs,
+    cpus: NonZero<usize>,
+    mosaic: &Mosaic,
+    range: &TileRange,
+) -> HashMap<(u32, u32), Vec<f32>> {
+    let tiles = range.tiles().collect::<Vec<(u32, u32)>>();
+    let tiles = &tiles;
+    let pixel_size = get_tile_size_m(range.zoom) / TILE_SIZE_PX as f64;
+
+    thread::scope(|scope| {
+        let mut results = vec![];
+        for id in 0..cpus.get() {
+            results.push(scope.spawn(move || {
+                let mut level = vec![];
+
+                for (x, y) in tiles.iter().skip(id).step_by(cpus.get()) {
+                    let ((tile_min_x, _), (_, tile_max_y)) = get_tile_bounds(range.zoom, *x, *y);
+                    let heights = (0..TILE_SIZE_PX * TILE_SIZE_PX)
+                        .map(|index| {
+                            let (column, row) = (index % TILE_SIZE_PX, index / TILE_SIZE_PX);
+                            let (geo_x, geo_y) = Crs::WebMercator.transform_to(
+                                source_crs,
+                                tile_min_x + (column as f64 + 0.5) * pixel_size,
+                                tile_max_y - (row as f64 + 0.5) * pixel_size,
+                            );
+
+                            mosaic.sample(geo_x, geo_y)
+                        })
+                        .collect::<Vec<f32>>();
+
+                    if !heights.iter().all(|height| height.is_nan()) {
+                        level.push(((*x, *y), heights));
+                    }
+                }
+
+                level
+            }));
+        }
+
+        results
+            .into_iter()
+            .flat_map(|result| result.join().unwrap())
+            .collect()
+    })
+}
+
+// Tiles of the next coarser zoom level, each the 2x2 NaN-aware mean of its four children
+fn average_level(level: &HashMap<(u32, u32), Vec<f32>>) -> HashMap<(u32, u32), Vec<f32>> {
+    let mut parents = HashMap::<(u32, u32), Vec<f32>>::new();
+
+    for ((x, y), heights) in level.iter() {
+        let parent = parents
+            .entry((x / 2, y / 2))
+            .or_insert_with(|| vec![f32::NAN; 4 * TILE_SIZE_PX * TILE_SIZE_PX]);
+        let (offset_x, offset_y) = (
+            (x % 2) as usize * TILE_SIZE_PX,
+            (y % 2) as usize * TILE_SIZE_PX,
+        );
+
+        for (row, values) in heights.chunks_exact(TILE_SIZE_PX).enumerate() {
+            let start = (offset_y + row) * 2 * TILE_SIZE_PX + offset_x;
+            parent[start..start + TILE_SIZE_PX].copy_from_slice(values);
+        }
+    }
+
+    parents
+        .into_iter()
+        .map(|(tile, children)| {
+            let (heights, _) = mosaic::average_2x2(&children, (2 * TILE_SIZE_PX, 2 * TILE_SIZE_PX));
+            (tile, heights)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_range_matches_slippy_map_indices() {
+        // Ljubljana lies on tile 10/553/364 of the OpenStreetMap tile scheme
+        let position = Crs::WebMercator.from_wgs84(46.05, 14.5);
+        let range = TileRange::new(10, position, position);
+
+        assert_eq!(range.min, (553, 364));
+        assert!(range.is_single_tile());
+    }
+
+    #[test]
+    fn tile_bounds_contain_their_tiles() {
+        let position = Crs::WebMercator.from_wgs84(46.05, 14.5);
+        let range = TileRange::new(14, position, position);
+        let (tile_min, tile_max) = get_tile_bounds(14, range.min.0, range.min.1);
+
+        assert!(tile_min.0 <= position.0 && position.0 < tile_max.0);
+        assert!(tile_min.1 < position.1 && position.1 <= tile_max.1);
+        assert_eq!(get_tile_bounds(0, 0, 0).1.0, WEB_MERCATOR_HALF_SIDE);
+    }
+
+    #[test]
+    fn max_zoom_keeps_the_pixel_size() {
+        // Zoom 17 pixels are 0.83 m wide at 46 degrees north, zoom 16 ones 1.66 m
+        assert_eq!(get_max_zoom(1.0, 46.0), 17);
+        assert_eq!(get_max_zoom(2.0, 46.0), 16);
+        assert_eq!(get_max_zoom(1e-6, 46.0), MAX_ZOOM);
+    }
+
+    #[test]
+    fn coarser_level_averages_its_children() {
+        let mut level = HashMap::new();
+        level.insert((4, 6), vec![1.0; TILE_SIZE_PX * TILE_SIZE_PX]);
+        level.insert((5, 6), vec![3.0; TILE_SIZE_PX * TILE_SIZE_PX]);
+
+        let parents = average_level(&level);
+        let parent = &parents[&(2, 3)];
+
+        assert_eq!(parents.len(), 1);
+        assert_eq!(parent[0], 1.0);
+        assert_eq!(parent[TILE_SIZE_PX - 1], 3.0);
+        // The southern children are missing
+        assert!(parent[TILE_SIZE_PX * TILE_SIZE_PX - 1].is_nan());
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    str::FromStr,
+    sync::mpsc::Receiver,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use clap::Parser;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+};
+
+use crate::{
+    RunSummary,
+    core::{Cli, Point},
+    error::TerrainError,
+    progress::{self, TileEvent, TileStatus},
+};
+
+type RunResult = Result<RunSummary, TerrainError>;
+
+struct TuiState {
+    center: Point,
+    view_radius: i32,
+    cursor: Point,
+    selected: HashSet<Point>,
+    statuses: HashMap<Point, TileStatus>,
+    generate_args: Vec<String>,
+    run: Option<JoinHandle<RunResult>>,
+    message: String,
+}
+
+pub fn run_tui(
+    center: &str,
+    view_radius: u16,
+    generate_args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let center = Point::from_str(center)?;
+    let mut state = TuiState {
+        center,
+        view_radius: view_radius as i32,
+        cursor: center,
+        selected: HashSet::new(),
+        statuses: HashMap::new(),
+        generate_args: generate_args.to_vec(),
+        run: None,
+        message: "Select tiles to generate".to_string(),
+    };
+    let events = progress::observe();
+
+    let mut terminal = ratatui::init();
+    let result = run_event_loop(&mut terminal, &mut state, &events);
+    ratatui::restore();
+
+    result
+}
+
+fn run_event_loop(
+    terminal: &mut DefaultTerminal,
+    state: &mut TuiState,
+    events: &Receiver<TileEvent>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    loop {
+        while let Ok(event) = events.try_recv() {
+            state.statuses.insert(event.tile, event.status);
+        }
+
+        if state.run.as_ref().is_some_and(|run| run.is_finished()) {
+            state.message = match state.run.take().unwrap().join() {
+                Ok(Ok(summary)) => format!(
+                    "Run finished: {} tiles, {} pending",
+                    summary.tiles, summary.pending_tiles
+                ),
+                Ok(Err(value)) => format!("Run failed: {}", value),
+                Err(_) => "Run panicked".to_string(),
+            };
+        }
+
+        // The run reports to stdout as well, so the screen is repainted fully while it is active
+        if state.run.is_some() {
+            terminal.clear()?;
+        }
+
+        terminal.draw(|frame| draw(frame, state))?;
+
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let (cursor, radius) = (state.cursor, state.view_radius);
+        let within_view = |tile: Point| {
+            (tile.0 - state.center.0).abs() <= radius && (tile.1 - state.center.1).abs() <= radius
+        };
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Left if within_view(Point(cursor.0 - 1, cursor.1)) => state.cursor.0 -= 1,
+            KeyCode::Right if within_view(Point(cursor.0 + 1, cursor.1)) => state.cursor.0 += 1,
+            KeyCode::Up if within_view(Point(cursor.0, cursor.1 + 1)) => state.cursor.1 += 1,
+            KeyCode::Down if within_view(Point(cursor.0, cursor.1 - 1)) => state.cursor.1 -= 1,
+            KeyCode::Char(' ') if state.run.is_none() => {
+                if !state.selected.remove(&cursor) {
+                    state.selected.insert(cursor);
+                }
+            }
+            KeyCode::Enter if state.run.is_none() && !state.selected.is_empty() => {
+                start_run(state)?;
+            }
+            _ => {}
+        }
+    }
+}
+
+// Every selected tile is passed on as a 1x1 rectangle next to the user's generate arguments
+fn start_run(state: &mut TuiState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut args = vec![env!("CARGO_PKG_NAME").to_string()];
+
+    for tile in state.selected.iter() {
+        args.push("--rect".to_string());
+        args.push(format!("{},{},1,1", tile.0, tile.1));
+    }
+
+    args.extend(state.generate_args.iter().cloned());
+
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(value) => {
+            state.message = format!("Invalid arguments: {}", value.kind());
+            return Ok(());
+        }
+    };
+
+    state.statuses.clear();
+    state.message = format!("Running {} tiles", state.selected.len());
+    state.run = Some(thread::spawn(move || crate::generate(&cli)));
+
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, state: &TuiState) {
+    let [grid_area, status_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+    let radius = state.view_radius;
+
+    // North is up, so rows start at the highest tile index
+    let lines = (-radius..=radius)
+        .rev()
+        .map(|delta_y| {
+            Line::from(
+                (-radius..=radius)
+                    .map(|delta_x| {
+                        let tile = Point(state.center.0 + delta_x, state.center.1 + delta_y);
+                        let (symbol, color) = get_cell(state, tile);
+                        let mut style = Style::default().fg(color);
+
+                        if tile == state.cursor {
+                            style = style.add_modifier(Modifier::REVERSED);
+                        }
+
+                        Span::styled(symbol, style)
+                    })
+                    .collect::<Vec<Span>>(),
+            )
+        })
+        .collect::<Vec<Line>>();
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(format!(
+            " Tile {}:{} | {} selected ",
+            state.cursor.0,
+            state.cursor.1,
+            state.selected.len()
+        ))),
+        grid_area,
+    );
+    frame.render_widget(
+        Paragraph::new(state.message.as_str())
+            .block(Block::bordered().title(" arrows move | space toggle | enter run | q quit ")),
+        status_area,
+    );
+}
+
+fn get_cell(state: &TuiState, tile: Point) -> (&'static str, Color) {
+    match state.statuses.get(&tile) {
+        Some(TileStatus::Downloading) => ("[~]", Color::Yellow),
+        Some(TileStatus::Downloaded) => ("[v]", Color::Cyan),
+        Some(TileStatus::Computed) => ("[#]", Color::Green),
+        Some(TileStatus::Postponed) => ("[p]", Color::Magenta),
+        Some(TileStatus::Missing) => ("[-]", Color::DarkGray),
+        Some(TileStatus::Failed) => ("[!]", Color::Red),
+        None if state.selected.contains(&tile) => ("[x]", Color::White),
+        None => (" . ", Color::DarkGray),
+    }
+}
@@ -0,0 +1,199 @@
+use std::{num::NonZero, thread, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::Config,
+    error::TerrainError,
+    pauser,
+    requester::LazData,
+    validator::{self, TileErrors},
+};
+
+const TUNE_SAMPLE_SIZES: [u8; 6] = [1, 2, 4, 6, 8, 12];
+const TUNE_BLUR_KERNEL_SIZES: [u8; 5] = [0, 1, 2, 3, 5];
+// Surveyed tiles the search runs on, spread evenly over the downloaded ones
+const TUNE_TILES: usize = 3;
+
+// Settings picked by --auto-tune and their held-out errors, recorded in config.json
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TuneReport {
+    pub sample_size: u8,
+    pub ann: bool,
+    pub blur_kernel_size: u8,
+    pub rmse_m: f64,
+    pub mae_m: f64,
+    pub candidates: usize,
+    pub tiles: Vec<(i32, i32)>,
+}
+
+// Error sums of one candidate over all tuning tiles
+struct CandidateErrors {
+    sample_size: u8,
+    ann: bool,
+    blur_kernel_size: u8,
+    samples: usize,
+    squared_sum: f64,
+    absolute_sum: f64,
+}
+
+impl CandidateErrors {
+    fn rmse(&self) -> f64 {
+        (self.squared_sum / self.samples as f64).sqrt()
+    }
+}
+
+// Validates every combination of sample size, neighbour search and blur on a few tiles and
+// switches the configuration to the one with the lowest held-out RMSE
+pub fn auto_tune(
+    config: &mut Config,
+    cpus: NonZero<usize>,
+    data: &[LazData],
+) -> Result<(), TerrainError> {
+    let surveyed = data
+        .iter()
+        .filter(|tile| !tile.fallback)
+        .collect::<Vec<&LazData>>();
+
+    if surveyed.is_empty() {
+        println!("Auto-tune found no surveyed tiles, keeping the given settings");
+        return Ok(());
+    }
+
+    let step = surveyed.len().div_ceil(TUNE_TILES);
+    let tiles = surveyed
+        .into_iter()
+        .step_by(step)
+        .collect::<Vec<&LazData>>();
+
+    let candidates = TUNE_SAMPLE_SIZES
+        .iter()
+        .flat_map(|sample_size| [(*sample_size, false), (*sample_size, true)])
+        .collect::<Vec<(u8, bool)>>();
+
+    println!(
+        "Auto-tuning {} settings on {} tiles",
+        candidates.len() * TUNE_BLUR_KERNEL_SIZES.len(),
+        tiles.len()
+    );
+
+    // Every candidate on every tile is one job, spread over the configured workers. Each job
+    // pauses and yields like a tile of the run does.
+    let jobs = candidates
+        .iter()
+        .enumerate()
+        .flat_map(|(candidate, _)| (0..tiles.len()).map(move |tile| (candidate, tile)))
+        .collect::<Vec<(usize, usize)>>();
+    let workers = cpus.get().min(jobs.len());
+
+    let config_ref: &Config = config;
+    let job_errors = thread::scope(
+        |scope| -> Result<Vec<(usize, Vec<TileErrors>)>, TerrainError> {
+            let mut results = vec![];
+            for id in 0..workers {
+                let (jobs, tiles, candidates) = (&jobs, &tiles, &candidates);
+                let result = scope.spawn(
+                    move || -> Result<Vec<(usize, Vec<TileErrors>)>, TerrainError> {
+                        let mut job_errors = vec![];
+
+                        for (candidate, tile) in jobs.iter().skip(id).step_by(workers) {
+                            pauser::wait_while_paused(config_ref);
+
+                            let start = Instant::now();
+                            let (sample_size, ann) = candidates[*candidate];
+                            let tile = tiles[*tile];
+                            let dim = config_ref.tile_resolution(tile.density()) as usize;
+                            let tile_errors = validator::validate_settings(
+                                config_ref,
+                                tile,
+                                dim,
+                                sample_size,
+                                ann,
+                                &TUNE_BLUR_KERNEL_SIZES,
+                            )?;
+
+                            if config_ref.nice {
+                                thread::sleep(start.elapsed());
+                            }
+
+                            job_errors.push((*candidate, tile_errors));
+                        }
+
+                        Ok(job_errors)
+                    },
+                );
+
+                results.push(result);
+            }
+
+            let mut job_errors = vec![];
+            for result in results {
+                job_errors.extend(result.join().unwrap()?);
+            }
+
+            Ok(job_errors)
+        },
+    )?;
+
+    let mut results = candidates
+        .iter()
+        .flat_map(|(sample_size, ann)| {
+            TUNE_BLUR_KERNEL_SIZES.map(|blur_kernel_size| CandidateErrors {
+                sample_size: *sample_size,
+                ann: *ann,
+                blur_kernel_size,
+                samples: 0,
+                squared_sum: 0.0,
+                absolute_sum: 0.0,
+            })
+        })
+        .collect::<Vec<CandidateErrors>>();
+
+    for (candidate, tile_errors) in job_errors {
+        let errors = &mut results[candidate * TUNE_BLUR_KERNEL_SIZES.len()..];
+
+        for (blur_errors, tile_errors) in errors.iter_mut().zip(tile_errors) {
+            blur_errors.samples += tile_errors.samples;
+            blur_errors.squared_sum += tile_errors.rmse_m.powi(2) * tile_errors.samples as f64;
+            blur_errors.absolute_sum += tile_errors.mae_m * tile_errors.samples as f64;
+        }
+    }
+
+    let best = match results
+        .iter()
+        .filter(|candidate| candidate.samples > 0)
+        .min_by(|a, b| a.rmse().total_cmp(&b.rmse()))
+    {
+        Some(best) => best,
+        None => {
+            println!("Auto-tune could not hold out any points, keeping the given settings");
+            return Ok(());
+        }
+    };
+
+    println!(
+        "Auto-tune picked sample size {}, {} neighbour search and blur {} with RMSE {:.3} m",
+        best.sample_size,
+        if best.ann { "approximate" } else { "exact" },
+        best.blur_kernel_size,
+        best.rmse()
+    );
+
+    config.sample_size = best.sample_size;
+    config.ann = best.ann;
+    config.blur_kernel_size = best.blur_kernel_size;
+    config.tuning = Some(TuneReport {
+        sample_size: best.sample_size,
+        ann: best.ann,
+        blur_kernel_size: best.blur_kernel_size,
+        rmse_m: best.rmse(),
+        mae_m: best.absolute_sum / best.samples as f64,
+        candidates: results.len(),
+        tiles: tiles
+            .iter()
+            .map(|tile| (tile.tile.0, tile.tile.1))
+            .collect(),
+    });
+
+    Ok(())
+}
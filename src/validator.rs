@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    computer::{self, BlurTarget},
+    core::Config,
+    error::TerrainError,
+    gridder::{NeighbourSearch, TileIndex},
+    requester::LazData,
+};
+
+// Upper bound on the points held out of a tile for --validate
+const MAX_VALIDATION_POINTS: usize = 10_000;
+
+// Smaller tiles are split into this many folds and each of their points is held out once
+const VALIDATION_FOLDS: usize = 10;
+
+// Height errors in meters of a tile surface at points left out of its gridding
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TileErrors {
+    pub samples: usize,
+    pub rmse_m: f64,
+    pub mae_m: f64,
+    pub max_error_m: f64,
+}
+
+// Grids the tile again without a held-out subset of its points, with the same resolution, sample
+// size, neighbour search and blur as its heightmap, and compares the held-out elevations with
+// the surface at their positions
+pub fn validate_tile(
+    config: &Config,
+    data: &LazData,
+    dim: usize,
+) -> Result<Option<TileErrors>, TerrainError> {
+    let blur_kernel_size = if config.blur_target == BlurTarget::Viz {
+        0
+    } else {
+        config.blur_kernel_size
+    };
+
+    Ok(validate_settings(
+        config,
+        data,
+        dim,
+        config.sample_size,
+        config.ann,
+        &[blur_kernel_size],
+    )?
+    .pop())
+}
+
+// Errors of one sample size and neighbour search for every given blur kernel size, which share
+// a gridding per fold. Tiles too small to spare a sparse subset are validated at every point, one
+// fold at a time. Empty when the tile has too few points to hold any out.
+pub fn validate_settings(
+    config: &Config,
+    data: &LazData,
+    dim: usize,
+    sample_size: u8,
+    ann: bool,
+    blur_kernel_sizes: &[u8],
+) -> Result<Vec<TileErrors>, TerrainError> {
+    let stride = data.points.len() / MAX_VALIDATION_POINTS;
+    let (folds, stride) = if stride < VALIDATION_FOLDS {
+        (VALIDATION_FOLDS, VALIDATION_FOLDS)
+    } else {
+        (1, stride)
+    };
+
+    let mut errors = vec![vec![]; blur_kernel_sizes.len()];
+
+    for fold in 0..folds {
+        // Points are held out by index, so neither subset is copied out of the tile
+        let is_training = |index: usize| index % stride != fold;
+        let held_out_count = data.points.len().saturating_sub(fold).div_ceil(stride);
+
+        if data.points.len() - held_out_count < sample_size as usize || held_out_count == 0 {
+            return Ok(vec![]);
+        }
+
+        let training = move || {
+            data.points
+                .iter()
+                .enumerate()
+                .filter(move |(index, _)| is_training(*index))
+                .map(|(_, point)| point)
+        };
+        let heights = training()
+            .map(|point| config.adjust_height(point[2]) as f32)
+            .collect::<Vec<f32>>();
+
+        let bounds_min = (data.bounds_min.0, data.bounds_min.1);
+        let bounds_max = (data.bounds_max.0, data.bounds_max.1);
+        let index = TileIndex::new(
+            training(),
+            bounds_min,
+            bounds_max,
+            dim,
+            dim,
+            sample_size as usize,
+            NeighbourSearch::new(ann, config.index),
+            config.internal_precision,
+        );
+        let grid = index.interpolate(sample_size as usize, &heights);
+
+        for (blur_kernel_size, errors) in blur_kernel_sizes.iter().zip(errors.iter_mut()) {
+            let mut surface = grid.clone();
+            computer::blur_image(*blur_kernel_size as u32, dim, dim, &mut surface)?;
+
+            // The surface holds scaled heights, the errors are reported in meters of terrain
+            errors.extend(data.points.iter().skip(fold).step_by(stride).map(|point| {
+                let height =
+                    sample_surface(&surface, dim, bounds_min, bounds_max, point[0], point[1]);
+
+                (height as f64 - config.adjust_height(point[2])).abs() / config.z_scale
+            }));
+        }
+    }
+
+    Ok(errors
+        .into_iter()
+        .map(|errors| TileErrors {
+            samples: errors.len(),
+            rmse_m: (errors.iter().map(|error| error * error).sum::<f64>() / errors.len() as f64)
+                .sqrt(),
+            mae_m: errors.iter().sum::<f64>() / errors.len() as f64,
+            max_error_m: errors.iter().fold(0f64, |max, error| max.max(*error)),
+        })
+        .collect())
+}
+
+// Bilinear sample of a gridded surface. Column c lies at min_x + c / dim * width and row r at
+// min_y + (dim - r) / dim * height, as laid out by TileIndex::interpolate.
+fn sample_surface(
+    surface: &[f32],
+    dim: usize,
+    bounds_min: (f64, f64),
+    bounds_max: (f64, f64),
+    geo_x: f64,
+    geo_y: f64,
+) -> f32 {
+    let last = (dim - 1) as f64;
+    let column =
+        ((geo_x - bounds_min.0) / (bounds_max.0 - bounds_min.0) * dim as f64).clamp(0.0, last);
+    let row = (dim as f64 - (geo_y - bounds_min.1) / (bounds_max.1 - bounds_min.1) * dim as f64)
+        .clamp(0.0, last);
+
+    let (column_0, row_0) = (column.floor() as usize, row.floor() as usize);
+    let (column_1, row_1) = ((column_0 + 1).min(dim - 1), (row_0 + 1).min(dim - 1));
+    let (weight_x, weight_y) = (
+        (column - column_0 as f64) as f32,
+        (row - row_0 as f64) as f32,
+    );
+
+    let value = |column: usize, row: usize| surface[row * dim + column];
+    let top = value(column_0, row_0) * (1.0 - weight_x) + value(column_1, row_0) * weight_x;
+    let bottom = value(column_0, row_1) * (1.0 - weight_x) + value(column_1, row_1) * weight_x;
+
+    top * (1.0 - weight_y) + bottom * weight_y
+}
+
+// Prints the errors of every validated tile and the point weighted totals of the run
+pub fn report_errors(tiles: &[(String, TileErrors)]) {
+    if tiles.is_empty() {
+        return;
+    }
+
+    for (file_stem, errors) in tiles {
+        println!(
+            "Validation {}: RMSE {:.3} m, MAE {:.3} m, max {:.3} m over {} points",
+            file_stem, errors.rmse_m, errors.mae_m, errors.max_error_m, errors.samples
+        );
+    }
+
+    let samples = tiles
+        .iter()
+        .map(|(_, errors)| errors.samples)
+        .sum::<usize>();
+    let squared = tiles
+        .iter()
+        .map(|(_, errors)| errors.rmse_m.powi(2) * errors.samples as f64)
+        .sum::<f64>();
+    let absolute = tiles
+        .iter()
+        .map(|(_, errors)| errors.mae_m * errors.samples as f64)
+        .sum::<f64>();
+
+    println!(
+        "Validation total: RMSE {:.3} m, MAE {:.3} m over {} points",
+        (squared / samples as f64).sqrt(),
+        absolute / samples as f64,
+        samples
+    );
+}
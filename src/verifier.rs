@@ -0,0 +1,200 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    computer::ComputeParameters,
+    core::Config,
+    storage::{self, OutputSink},
+    writer::{InputError, OutputError},
+};
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManifestEntry {
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub generator: String,
+    pub parameters: ComputeParameters,
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+// Passes every output through to the wrapped sink and records its checksum for the manifest
+pub struct ChecksumSink<'a> {
+    inner: &'a dyn OutputSink,
+    entries: Mutex<BTreeMap<String, ManifestEntry>>,
+}
+
+impl<'a> ChecksumSink<'a> {
+    pub fn new(inner: &'a dyn OutputSink) -> Self {
+        ChecksumSink {
+            inner,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Files of an earlier run that this run did not rewrite keep their previous entries, except
+    // for resume.json which is gone once a resumed run completes
+    pub fn write_manifest(
+        &self,
+        config: &Config,
+        previous: Option<Manifest>,
+        resume_pending: bool,
+    ) -> Result<(), OutputError> {
+        let mut files = self.merge_entries(previous);
+
+        if !resume_pending {
+            files.remove(&config.get_run_file_name("resume.json"));
+        }
+
+        self.put_manifest(
+            &config.get_run_file_name(MANIFEST_NAME),
+            ComputeParameters::from(config),
+            files,
+        )
+    }
+
+    // Manifest of a merge, listing the files it wrote and, when merging in place, the files of
+    // the earlier manifest it left alone
+    pub fn write_merged_manifest(
+        &self,
+        parameters: ComputeParameters,
+        previous: Option<Manifest>,
+    ) -> Result<(), OutputError> {
+        let files = self.merge_entries(previous);
+
+        self.put_manifest(MANIFEST_NAME, parameters, files)
+    }
+
+    // Entries written by this run replace the previous ones of the same files
+    fn merge_entries(&self, previous: Option<Manifest>) -> BTreeMap<String, ManifestEntry> {
+        let mut files = previous.map(|manifest| manifest.files).unwrap_or_default();
+        files.extend(self.entries.lock().unwrap().clone());
+
+        files
+    }
+
+    fn put_manifest(
+        &self,
+        file_name: &str,
+        parameters: ComputeParameters,
+        files: BTreeMap<String, ManifestEntry>,
+    ) -> Result<(), OutputError> {
+        let manifest = Manifest {
+            generator: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            parameters,
+            files,
+        };
+
+        self.inner.put(
+            file_name,
+            serde_json::to_string_pretty(&manifest)?.as_bytes(),
+        )
+    }
+}
+
+impl OutputSink for ChecksumSink<'_> {
+    fn put(&self, relative_path: &str, bytes: &[u8]) -> Result<(), OutputError> {
+        self.inner.put(relative_path, bytes)?;
+
+        self.entries.lock().unwrap().insert(
+            relative_path.to_string(),
+            ManifestEntry {
+                sha256: get_sha256(bytes),
+                size: bytes.len() as u64,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+pub fn read_manifest(folder: &str) -> Result<Manifest, InputError> {
+    read_manifest_file(folder, MANIFEST_NAME)
+}
+
+pub fn read_manifest_file(folder: &str, file_name: &str) -> Result<Manifest, InputError> {
+    let json = fs::read_to_string(storage::get_local_path(folder, file_name))?;
+
+    Ok(serde_json::from_str(&json)?)
+}
+
+// Checks every file listed in the manifest against its recorded size and checksum. Files in the
+// folder that the manifest does not list are reported but do not fail the verification.
+pub fn verify_folder(folder: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let manifest = read_manifest(folder)?;
+    let mut failures = 0;
+
+    for (relative_path, entry) in manifest.files.iter() {
+        let bytes = match fs::read(storage::get_local_path(folder, relative_path)) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                println!("Missing: {}", relative_path);
+                failures += 1;
+                continue;
+            }
+        };
+
+        if bytes.len() as u64 != entry.size || get_sha256(&bytes) != entry.sha256 {
+            println!("Checksum mismatch: {}", relative_path);
+            failures += 1;
+        }
+    }
+
+    let mut present = vec![];
+    list_files(Path::new(folder), Path::new(folder), &mut present)?;
+
+    for relative_path in present {
+        if relative_path != MANIFEST_NAME && !manifest.files.contains_key(&relative_path) {
+            println!("Not in manifest: {}", relative_path);
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!(
+            "{} of {} files failed verification",
+            failures,
+            manifest.files.len()
+        )
+        .into());
+    }
+
+    println!("All {} files verified.", manifest.files.len());
+
+    Ok(())
+}
+
+fn list_files(
+    root: &Path,
+    folder: &Path,
+    files: &mut Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_files(root, &path, files)?;
+        } else if let Ok(relative_path) = path.strip_prefix(root) {
+            // Manifest keys always use forward slashes, as passed to the output sink
+            files.push(
+                relative_path
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn get_sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
@@ -0,0 +1,83 @@
+use std::error::Error;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    gridder::{self, NeighbourSearch, Precision, SpatialIndex, TileIndex},
+    packer,
+    projection::Crs,
+    writer::{OutputWriter, PngWriter, TileRaster},
+};
+
+// Grids a dropped LAS, LAZ or zipped tile into a 16 bit grayscale PNG heightmap, entirely inside
+// the browser. Heights are normalized to the height range of the tile.
+#[wasm_bindgen]
+pub fn preview_heightmap(
+    tile_bytes: Vec<u8>,
+    resolution: usize,
+    sample_size: usize,
+    blur: u32,
+    approximate: bool,
+) -> Result<Vec<u8>, JsError> {
+    create_preview(tile_bytes, resolution, sample_size, blur, approximate)
+        .map_err(|error| JsError::new(&error.to_string()))
+}
+
+fn create_preview(
+    tile_bytes: Vec<u8>,
+    resolution: usize,
+    sample_size: usize,
+    blur: u32,
+    approximate: bool,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if resolution == 0 || sample_size == 0 {
+        return Err("Resolution and sample size must be greater than 0".into());
+    }
+
+    let (bounds, points) = packer::decode(packer::unwrap_archive(tile_bytes)?)?;
+
+    if points.is_empty() {
+        return Err("The tile does not contain any points".into());
+    }
+
+    let (min_height, max_height) = (bounds.min.z, bounds.max.z);
+    let range = (max_height - min_height).max(f64::EPSILON);
+
+    let points = points
+        .iter()
+        .map(|point| [point.x, point.y, point.z])
+        .collect::<Vec<[f64; 3]>>();
+    let values = points
+        .iter()
+        .map(|point| ((point[2] - min_height) / range) as f32)
+        .collect::<Vec<f32>>();
+
+    let bounds_min = (bounds.min.x, bounds.min.y);
+    let bounds_max = (bounds.max.x, bounds.max.y);
+    let index = TileIndex::new(
+        points.iter().copied(),
+        bounds_min,
+        bounds_max,
+        resolution,
+        resolution,
+        sample_size,
+        NeighbourSearch::new(approximate, SpatialIndex::Kdtree),
+        Precision::F64,
+    );
+
+    let mut heights = index.interpolate(sample_size, &values);
+    gridder::blur_image(blur, resolution, resolution, &mut heights)?;
+
+    let raster = TileRaster {
+        bounds_min,
+        bounds_max,
+        crs: Crs::D96Tm,
+        dim_x: resolution,
+        dim_y: resolution,
+        heights: &heights,
+        min_height,
+        max_height: min_height + range,
+    };
+
+    PngWriter.encode(&raster)
+}
@@ -0,0 +1,802 @@
+use std::{
+    io::{self, Cursor},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use exr::{
+    image::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, SpecificChannels},
+    math::Vec2,
+    meta::attribute::{AttributeValue, Text},
+    prelude::{ChannelDescription, LayerAttributes, WritableImage},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tiff::{
+    encoder::{TiffEncoder, TiffKind, TiffValue, colortype},
+    tags::Tag,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::error::StorageError;
+use crate::projection::Crs;
+
+const MODEL_PIXEL_SCALE_TAG: u16 = 33550;
+const MODEL_TIEPOINT_TAG: u16 = 33922;
+const GEO_KEY_DIRECTORY_TAG: u16 = 34735;
+// Read by GDAL for the scale and offset of integer heights
+const GDAL_METADATA_TAG: u16 = 42112;
+const GDAL_NODATA_TAG: u16 = 42113;
+// 16 bit code of NaN voids in PNG, raw and integer heightmaps, valid heights use 1..=65535
+pub const HEIGHT_NODATA: u16 = 0;
+// Integer steps left for valid heights next to the void code
+const HEIGHT_STEPS: f64 = (u16::MAX - 1) as f64;
+// Room left for the directory and GeoTIFF tags when deciding whether classic offsets suffice
+const TIFF_HEADER_RESERVE: u64 = 1 << 20;
+
+// Failures of encoding a raster or sidecar and handing it to the output sink
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Exr(#[from] exr::error::Error),
+
+    #[error(transparent)]
+    Tiff(#[from] tiff::TiffError),
+
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+
+    #[error(transparent)]
+    Las(#[from] las::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+// Failures of reading heightmaps, manifests and route files given on the command line or left
+// by an earlier run
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Exr(#[from] exr::error::Error),
+
+    #[error(transparent)]
+    Tiff(#[from] tiff::TiffError),
+
+    #[error(transparent)]
+    Png(#[from] png::DecodingError),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    // Files that were read but hold something else than expected
+    #[error("{0}")]
+    Format(String),
+}
+
+pub struct TileRaster<'a> {
+    pub bounds_min: (f64, f64),
+    pub bounds_max: (f64, f64),
+    pub crs: Crs,
+    pub dim_x: usize,
+    pub dim_y: usize,
+    pub heights: &'a [f32],
+    pub min_height: f64,
+    pub max_height: f64,
+}
+
+impl TileRaster<'_> {
+    pub fn pixel_size(&self) -> (f64, f64) {
+        (
+            (self.bounds_max.0 - self.bounds_min.0) / self.dim_x as f64,
+            (self.bounds_max.1 - self.bounds_min.1) / self.dim_y as f64,
+        )
+    }
+
+    pub fn denormalize(&self, value: f32) -> f64 {
+        value as f64 * (self.max_height - self.min_height) + self.min_height
+    }
+}
+
+// Heights written as 16 bit integers, height = value * scale + offset. Without a fixed scale and
+// offset the values 1..=65535 span the normalization range of the run, 0 marks voids.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IntegerHeights {
+    pub scale: Option<f64>,
+    pub offset: Option<f64>,
+}
+
+impl IntegerHeights {
+    // Scale and offset of height = value * scale + offset as written to the file metadata. The
+    // configured offset is the height of the value 1, the first one after the void code.
+    pub fn resolve(&self, raster: &TileRaster) -> (f64, f64) {
+        let offset = self.offset.unwrap_or(raster.min_height);
+        let scale = self
+            .scale
+            .unwrap_or(((raster.max_height - offset) / HEIGHT_STEPS).max(f64::EPSILON));
+
+        (scale, offset - scale)
+    }
+
+    // Heights outside of the 16 bit range are clamped, missing ones become HEIGHT_NODATA
+    fn encode(&self, raster: &TileRaster) -> Vec<u16> {
+        let (scale, offset) = self.resolve(raster);
+
+        raster
+            .heights
+            .iter()
+            .map(|height| {
+                if height.is_nan() {
+                    return HEIGHT_NODATA;
+                }
+
+                ((raster.denormalize(*height) - offset) / scale)
+                    .round()
+                    .clamp(1.0, u16::MAX as f64) as u16
+            })
+            .collect()
+    }
+}
+
+pub trait OutputWriter: Send + Sync {
+    fn extension(&self) -> &'static str;
+
+    fn encode(&self, raster: &TileRaster) -> Result<Vec<u8>, OutputError>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Exr,
+    Png,
+    Tiff,
+    Raw,
+}
+
+impl OutputFormat {
+    pub fn writer(
+        &self,
+        big_tiff: BigTiffMode,
+        integer_heights: Option<IntegerHeights>,
+    ) -> Box<dyn OutputWriter> {
+        match self {
+            OutputFormat::Exr => match integer_heights {
+                Some(integer_heights) => Box::new(IntegerExrWriter { integer_heights }),
+                None => Box::new(ExrWriter),
+            },
+            OutputFormat::Png => Box::new(PngWriter),
+            OutputFormat::Tiff => Box::new(GeoTiffWriter {
+                big_tiff,
+                integer_heights,
+            }),
+            OutputFormat::Raw => Box::new(RawWriter),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BigTiffMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+pub struct ExrWriter;
+
+impl OutputWriter for ExrWriter {
+    fn extension(&self) -> &'static str {
+        "exr"
+    }
+
+    fn encode(&self, raster: &TileRaster) -> Result<Vec<u8>, OutputError> {
+        let image = create_image(
+            raster.dim_x,
+            raster.dim_y,
+            raster.heights,
+            create_tile_attributes(raster),
+        );
+
+        let mut bytes = vec![];
+        image.write().to_buffered(Cursor::new(&mut bytes))?;
+
+        Ok(bytes)
+    }
+}
+
+// A single unsigned integer channel Y holding 16 bit heights, the scale and offset are layer
+// attributes. EXR has no 16 bit integer channel type, only u32, f16 and f32, so the values are
+// stored as u32, which the lossless compression shrinks back to about their 16 bits.
+pub struct IntegerExrWriter {
+    pub integer_heights: IntegerHeights,
+}
+
+impl OutputWriter for IntegerExrWriter {
+    fn extension(&self) -> &'static str {
+        "exr"
+    }
+
+    fn encode(&self, raster: &TileRaster) -> Result<Vec<u8>, OutputError> {
+        let values = self
+            .integer_heights
+            .encode(raster)
+            .into_iter()
+            .map(u32::from)
+            .collect::<Vec<u32>>();
+        let (scale, offset) = self.integer_heights.resolve(raster);
+
+        let mut attributes = create_tile_attributes(raster);
+        for (name, value) in [
+            ("height_scale", scale),
+            ("height_offset", offset),
+            ("nodata", HEIGHT_NODATA as f64),
+        ] {
+            attributes
+                .other
+                .insert(Text::from(name), AttributeValue::F64(value));
+        }
+
+        let channels = vec![AnyChannel::new("Y", FlatSamples::U32(values))];
+        let image = Image::from_layer(Layer::new(
+            (raster.dim_x, raster.dim_y),
+            attributes,
+            Encoding::SMALL_LOSSLESS,
+            AnyChannels::sort(channels.into()),
+        ));
+
+        let mut bytes = vec![];
+        image.write().to_buffered(Cursor::new(&mut bytes))?;
+
+        Ok(bytes)
+    }
+}
+
+pub struct PngWriter;
+
+impl OutputWriter for PngWriter {
+    fn extension(&self) -> &'static str {
+        "png"
+    }
+
+    fn encode(&self, raster: &TileRaster) -> Result<Vec<u8>, OutputError> {
+        encode_gray_png(raster.dim_x, raster.dim_y, raster.heights, 16)
+    }
+}
+
+pub struct RawWriter;
+
+impl OutputWriter for RawWriter {
+    fn extension(&self) -> &'static str {
+        "r16"
+    }
+
+    fn encode(&self, raster: &TileRaster) -> Result<Vec<u8>, OutputError> {
+        let bytes = raster
+            .heights
+            .iter()
+            .flat_map(|height| to_u16(*height).to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        Ok(bytes)
+    }
+}
+
+pub struct GeoTiffWriter {
+    pub big_tiff: BigTiffMode,
+    pub integer_heights: Option<IntegerHeights>,
+}
+
+impl GeoTiffWriter {
+    // Classic TIFF addresses the file with 32 bit offsets, so anything close to 4 GiB needs BigTIFF
+    fn use_big_tiff(&self, raster: &TileRaster) -> bool {
+        let data_size = (raster.dim_x * raster.dim_y * size_of::<f32>()) as u64;
+
+        match self.big_tiff {
+            BigTiffMode::Auto => data_size + TIFF_HEADER_RESERVE > u32::MAX as u64,
+            BigTiffMode::Always => true,
+            BigTiffMode::Never => false,
+        }
+    }
+}
+
+impl OutputWriter for GeoTiffWriter {
+    fn extension(&self) -> &'static str {
+        "tif"
+    }
+
+    fn encode(&self, raster: &TileRaster) -> Result<Vec<u8>, OutputError> {
+        let mut bytes = vec![];
+
+        if let Some(integer_heights) = &self.integer_heights {
+            let heights = integer_heights.encode(raster);
+            let (scale, offset) = integer_heights.resolve(raster);
+            let gdal_metadata = format!(
+                "<GDALMetadata><Item name=\"OFFSET\" sample=\"0\" role=\"offset\">{}</Item>\
+                 <Item name=\"SCALE\" sample=\"0\" role=\"scale\">{}</Item></GDALMetadata>",
+                offset, scale
+            );
+            let nodata = HEIGHT_NODATA.to_string();
+
+            if self.use_big_tiff(raster) {
+                write_geotiff::<_, colortype::Gray16>(
+                    TiffEncoder::new_big(Cursor::new(&mut bytes))?,
+                    raster,
+                    &heights,
+                    Some(&gdal_metadata),
+                    &nodata,
+                )?;
+            } else {
+                write_geotiff::<_, colortype::Gray16>(
+                    TiffEncoder::new(Cursor::new(&mut bytes))?,
+                    raster,
+                    &heights,
+                    Some(&gdal_metadata),
+                    &nodata,
+                )?;
+            }
+
+            return Ok(bytes);
+        }
+
+        let heights = raster
+            .heights
+            .iter()
+            .map(|height| raster.denormalize(*height) as f32)
+            .collect::<Vec<f32>>();
+
+        if self.use_big_tiff(raster) {
+            write_geotiff::<_, colortype::Gray32Float>(
+                TiffEncoder::new_big(Cursor::new(&mut bytes))?,
+                raster,
+                &heights,
+                None,
+                "nan",
+            )?;
+        } else {
+            write_geotiff::<_, colortype::Gray32Float>(
+                TiffEncoder::new(Cursor::new(&mut bytes))?,
+                raster,
+                &heights,
+                None,
+                "nan",
+            )?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn write_geotiff<K: TiffKind, C: colortype::ColorType>(
+    mut tiff: TiffEncoder<Cursor<&mut Vec<u8>>, K>,
+    raster: &TileRaster,
+    heights: &[C::Inner],
+    gdal_metadata: Option<&str>,
+    nodata: &str,
+) -> Result<(), OutputError>
+where
+    [C::Inner]: TiffValue,
+{
+    let (pixel_size_x, pixel_size_y) = raster.pixel_size();
+
+    // GeoKeyDirectory: version header, then (key, location, count, value) entries for
+    // GTModelType = projected or geographic, GTRasterType = pixel is area, and the EPSG code as
+    // ProjectedCSType or GeographicType. The tiepoint is the outer corner of the first pixel.
+    let (model_type, crs_key) = if raster.crs.is_geographic() {
+        (2, 2048)
+    } else {
+        (1, 3072)
+    };
+    let geo_keys = [
+        [1, 1, 0, 3],
+        [1024, 0, 1, model_type],
+        [1025, 0, 1, 1],
+        [crs_key, 0, 1, raster.crs.epsg()],
+    ]
+    .concat();
+    let tiepoint = [0.0, 0.0, 0.0, raster.bounds_min.0, raster.bounds_max.1, 0.0];
+
+    let mut image = tiff.new_image::<C>(raster.dim_x as u32, raster.dim_y as u32)?;
+
+    image.encoder().write_tag(
+        Tag::Unknown(MODEL_PIXEL_SCALE_TAG),
+        &[pixel_size_x, pixel_size_y, 0.0][..],
+    )?;
+    image
+        .encoder()
+        .write_tag(Tag::Unknown(MODEL_TIEPOINT_TAG), &tiepoint[..])?;
+    image
+        .encoder()
+        .write_tag(Tag::Unknown(GEO_KEY_DIRECTORY_TAG), &geo_keys[..])?;
+    if let Some(gdal_metadata) = gdal_metadata {
+        image
+            .encoder()
+            .write_tag(Tag::Unknown(GDAL_METADATA_TAG), gdal_metadata)?;
+    }
+    image
+        .encoder()
+        .write_tag(Tag::Unknown(GDAL_NODATA_TAG), nodata)?;
+    image.write_data(heights)?;
+
+    Ok(())
+}
+
+// Writes several rasters of the tile as channels of one EXR. Channel names follow the
+// layer.channel convention, so Nuke and Houdini list them as separate layers.
+pub fn encode_layered_exr(
+    raster: &TileRaster,
+    channels: Vec<(&str, Vec<f32>)>,
+) -> Result<Vec<u8>, OutputError> {
+    let channels = channels
+        .into_iter()
+        .map(|(name, values)| AnyChannel::new(name, FlatSamples::F32(values)))
+        .collect::<Vec<AnyChannel<FlatSamples>>>();
+
+    let image = Image::from_layer(Layer::new(
+        (raster.dim_x, raster.dim_y),
+        create_tile_attributes(raster),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(channels.into()),
+    ));
+
+    let mut bytes = vec![];
+    image.write().to_buffered(Cursor::new(&mut bytes))?;
+
+    Ok(bytes)
+}
+
+// Grayscale PNG of values in 0..1 at a bit depth of 8 or 16. At 16 bits NaN voids are written as
+// HEIGHT_NODATA, at 8 bits as 0.
+pub fn encode_gray_png(
+    dim_x: usize,
+    dim_y: usize,
+    values: &[f32],
+    bit_depth: u8,
+) -> Result<Vec<u8>, OutputError> {
+    let mut png_bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut png_bytes, dim_x as u32, dim_y as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+
+    let bytes = if bit_depth == 8 {
+        encoder.set_depth(png::BitDepth::Eight);
+        values
+            .iter()
+            .map(|value| (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+            .collect::<Vec<u8>>()
+    } else {
+        encoder.set_depth(png::BitDepth::Sixteen);
+        values
+            .iter()
+            .flat_map(|value| to_u16(*value).to_be_bytes())
+            .collect::<Vec<u8>>()
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&bytes)?;
+    writer.finish()?;
+
+    Ok(png_bytes)
+}
+
+// 16 bit RGBA PNG with one derived map per channel, for engines that sample several maps from
+// a single texture. NaN voids are HEIGHT_NODATA in every channel.
+pub fn encode_packed_png(
+    dim_x: usize,
+    dim_y: usize,
+    channels: &[Vec<f32>; 4],
+) -> Result<Vec<u8>, OutputError> {
+    let mut png_bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut png_bytes, dim_x as u32, dim_y as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Sixteen);
+
+    let bytes = (0..dim_x * dim_y)
+        .flat_map(|index| channels.iter().map(move |channel| channel[index]))
+        .flat_map(|value| to_u16(value).to_be_bytes())
+        .collect::<Vec<u8>>();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&bytes)?;
+    writer.finish()?;
+
+    Ok(png_bytes)
+}
+
+// Values in 0..1 are mapped to 1..=65535, NaN voids to HEIGHT_NODATA
+fn to_u16(height: f32) -> u16 {
+    if height.is_nan() {
+        return HEIGHT_NODATA;
+    }
+
+    1 + (height.clamp(0.0, 1.0) as f64 * HEIGHT_STEPS).round() as u16
+}
+
+// Inverse of to_u16
+fn from_u16(value: u16) -> f32 {
+    match value {
+        HEIGHT_NODATA => f32::NAN,
+        _ => ((value - 1) as f64 / HEIGHT_STEPS) as f32,
+    }
+}
+
+fn create_tile_attributes(raster: &TileRaster) -> LayerAttributes {
+    let mut attributes = LayerAttributes::named("main-rgb-layer");
+    let (pixel_size_x, pixel_size_y) = raster.pixel_size();
+
+    let text_attributes = [
+        ("crs", raster.crs.to_string()),
+        (
+            "generator",
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+    ];
+    let float_attributes = [
+        ("bounds_min_x", raster.bounds_min.0),
+        ("bounds_min_y", raster.bounds_min.1),
+        ("bounds_max_x", raster.bounds_max.0),
+        ("bounds_max_y", raster.bounds_max.1),
+        ("pixel_size_x_m", pixel_size_x),
+        ("pixel_size_y_m", pixel_size_y),
+        ("normalization_min_height", raster.min_height),
+        ("normalization_max_height", raster.max_height),
+    ];
+
+    for (name, value) in text_attributes {
+        attributes.other.insert(
+            Text::from(name),
+            AttributeValue::Text(Text::from(value.as_str())),
+        );
+    }
+
+    for (name, value) in float_attributes {
+        attributes
+            .other
+            .insert(Text::from(name), AttributeValue::F64(value));
+    }
+
+    attributes
+}
+
+pub fn create_image<'a>(
+    dim_x: usize,
+    dim_y: usize,
+    buffer_f32: &'a [f32],
+    attributes: LayerAttributes,
+) -> Image<
+    Layer<
+        SpecificChannels<
+            impl Fn(Vec2<usize>) -> (f32, f32, f32),
+            (ChannelDescription, ChannelDescription, ChannelDescription),
+        >,
+    >,
+> {
+    let channels = SpecificChannels::rgb(move |position: Vec2<usize>| {
+        let data = buffer_f32[position.0 + position.1 * dim_x];
+
+        (data, data, data)
+    });
+
+    let image = exr::prelude::Image::from_layer(exr::prelude::Layer::new(
+        (dim_x, dim_y),
+        attributes,
+        Encoding::SMALL_LOSSLESS,
+        channels,
+    ));
+
+    image
+}
+
+pub fn read_texture(file_path: impl AsRef<Path>) -> Result<(usize, usize, Vec<f32>), InputError> {
+    let file_path = file_path.as_ref();
+    let image = exr::prelude::read_first_flat_layer_from_file(file_path)?;
+
+    // Float heightmaps keep the heights in red, integer ones in their single luminance channel
+    let channel = ["R", "Y"]
+        .into_iter()
+        .find_map(|name| {
+            image
+                .layer_data
+                .channel_data
+                .list
+                .iter()
+                .find(|channel| channel.name == Text::from(name))
+        })
+        .ok_or_else(|| {
+            InputError::Format(format!("{} has no R or Y channel", file_path.display()))
+        })?;
+
+    let attributes = &image.layer_data.attributes.other;
+    let get_attribute = |name: &str| match attributes.get(&Text::from(name)) {
+        Some(AttributeValue::F64(value)) => Some(*value),
+        _ => None,
+    };
+    let size = image.layer_data.size;
+    let (dim_x, dim_y) = (size.width(), size.height());
+    let mut buffer = channel.sample_data.values_as_f32().collect::<Vec<f32>>();
+
+    // Integer heights are brought back to the normalized range of float EXRs, voids to NaN
+    if let (Some(scale), Some(offset), Some(min_height), Some(max_height)) = (
+        get_attribute("height_scale"),
+        get_attribute("height_offset"),
+        get_attribute("normalization_min_height"),
+        get_attribute("normalization_max_height"),
+    ) {
+        let nodata = get_attribute("nodata");
+        let range = (max_height - min_height).max(f64::EPSILON);
+        for value in buffer.iter_mut() {
+            *value = if nodata == Some(*value as f64) {
+                f32::NAN
+            } else {
+                ((*value as f64 * scale + offset - min_height) / range) as f32
+            };
+        }
+    }
+
+    Ok((dim_x, dim_y, buffer))
+}
+
+// Normalized heights of a heightmap in any output format. PNG and raw heightmaps hold the
+// normalized heights as 16 bit integers, raw ones are square. TIFFs hold meters, or integers with
+// the scale and offset in their GDAL metadata. Voids are read back as NaN.
+pub fn read_heightmap(
+    file_path: impl AsRef<Path>,
+    (min_height, max_height): (f64, f64),
+) -> Result<(usize, usize, Vec<f32>), InputError> {
+    let file_path = file_path.as_ref();
+    let extension = file_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let range = (max_height - min_height).max(f64::EPSILON);
+    let normalize = |meters: f64| ((meters - min_height) / range) as f32;
+
+    match extension.as_str() {
+        "exr" => read_texture(file_path),
+        "png" => {
+            let decoder = png::Decoder::new(Cursor::new(std::fs::read(file_path)?));
+            let mut reader = decoder.read_info()?;
+            let mut bytes = vec![0u8; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut bytes)?;
+
+            if info.color_type != png::ColorType::Grayscale
+                || info.bit_depth != png::BitDepth::Sixteen
+            {
+                return Err(InputError::Format(format!(
+                    "{} is not a 16 bit grayscale heightmap",
+                    file_path.display()
+                )));
+            }
+
+            let heights = bytes[..info.buffer_size()]
+                .chunks_exact(2)
+                .map(|pair| from_u16(u16::from_be_bytes([pair[0], pair[1]])))
+                .collect::<Vec<f32>>();
+
+            Ok((info.width as usize, info.height as usize, heights))
+        }
+        "r16" => {
+            let heights = std::fs::read(file_path)?
+                .chunks_exact(2)
+                .map(|pair| from_u16(u16::from_le_bytes([pair[0], pair[1]])))
+                .collect::<Vec<f32>>();
+            let dim = (heights.len() as f64).sqrt().round() as usize;
+
+            if dim * dim != heights.len() {
+                return Err(InputError::Format(format!(
+                    "{} is not a square raw heightmap",
+                    file_path.display()
+                )));
+            }
+
+            Ok((dim, dim, heights))
+        }
+        "tif" => {
+            let mut decoder = tiff::decoder::Decoder::new(Cursor::new(std::fs::read(file_path)?))?;
+            let (dim_x, dim_y) = decoder.dimensions()?;
+            let gdal_metadata = decoder
+                .get_tag_ascii_string(Tag::Unknown(GDAL_METADATA_TAG))
+                .ok();
+
+            let heights = match decoder.read_image()? {
+                tiff::decoder::DecodingResult::F32(values) => values
+                    .into_iter()
+                    .map(|value| normalize(value as f64))
+                    .collect::<Vec<f32>>(),
+                tiff::decoder::DecodingResult::U16(values) => {
+                    let get_item = |role: &str| {
+                        let metadata = gdal_metadata.as_deref()?;
+                        let item_start = format!("role=\"{}\">", role);
+                        let start = metadata.find(&item_start)? + item_start.len();
+                        let end = start + metadata[start..].find('<')?;
+                        metadata[start..end].trim().parse::<f64>().ok()
+                    };
+                    let (Some(scale), Some(offset)) = (get_item("scale"), get_item("offset"))
+                    else {
+                        return Err(InputError::Format(format!(
+                            "{} has integer heights without a scale and offset",
+                            file_path.display()
+                        )));
+                    };
+
+                    values
+                        .into_iter()
+                        .map(|value| match value {
+                            HEIGHT_NODATA => f32::NAN,
+                            _ => normalize(value as f64 * scale + offset),
+                        })
+                        .collect::<Vec<f32>>()
+                }
+                _ => {
+                    return Err(InputError::Format(format!(
+                        "Unsupported sample format in {}",
+                        file_path.display()
+                    )));
+                }
+            };
+
+            Ok((dim_x as usize, dim_y as usize, heights))
+        }
+        _ => Err(InputError::Format(format!(
+            "{} is not a heightmap format",
+            file_path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_raster(heights: &[f32]) -> TileRaster<'_> {
+        TileRaster {
+            bounds_min: (0.0, 0.0),
+            bounds_max: (1.0, heights.len() as f64),
+            crs: Crs::D96Tm,
+            dim_x: 1,
+            dim_y: heights.len(),
+            heights,
+            min_height: 100.0,
+            max_height: 200.0,
+        }
+    }
+
+    #[test]
+    fn voids_keep_their_own_16_bit_code() {
+        assert_eq!(to_u16(f32::NAN), HEIGHT_NODATA);
+        assert_eq!(to_u16(0.0), 1);
+        assert_eq!(to_u16(-1.0), 1);
+        assert_eq!(to_u16(1.0), u16::MAX);
+
+        assert!(from_u16(HEIGHT_NODATA).is_nan());
+        for height in [0.0, 0.25, 0.5, 1.0] {
+            assert!((from_u16(to_u16(height)) - height).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn integer_heights_keep_voids_apart_from_the_lowest_height() {
+        let heights = [0.0, f32::NAN, 1.0];
+        let raster = create_raster(&heights);
+        let integer_heights = IntegerHeights {
+            scale: None,
+            offset: None,
+        };
+
+        let values = integer_heights.encode(&raster);
+        let (scale, offset) = integer_heights.resolve(&raster);
+
+        assert_eq!(values, vec![1, HEIGHT_NODATA, u16::MAX]);
+        assert!((values[0] as f64 * scale + offset - 100.0).abs() < 1e-9);
+        assert!((values[2] as f64 * scale + offset - 200.0).abs() < 1e-9);
+    }
+}
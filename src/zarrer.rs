@@ -0,0 +1,289 @@
+use std::{num::NonZero, thread};
+
+use serde_json::json;
+
+use crate::{
+    mosaic::{self, Mosaic},
+    projection::Crs,
+    storage::OutputSink,
+    writer::OutputError,
+};
+
+const ZARR_CHUNK_SIZE: usize = 256;
+const STORE_NAME: &str = "terrain.zarr";
+
+#[derive(Clone, Copy)]
+enum ZarrArray {
+    Height,
+    Slope,
+}
+
+impl ZarrArray {
+    fn name(&self) -> &'static str {
+        match self {
+            ZarrArray::Height => "height",
+            ZarrArray::Slope => "slope",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            ZarrArray::Height => "metre",
+            ZarrArray::Slope => "degree",
+        }
+    }
+}
+
+// Writes the mosaic as a Zarr v2 store of uncompressed little-endian f32 chunks. Every array is a
+// group of levels 0..n, each the 2x2 NaN-aware mean of the previous one, described with the
+// NGFF multiscales attributes that xarray and Dask readers understand.
+pub fn write_zarr(
+    cpus: NonZero<usize>,
+    sink: &dyn OutputSink,
+    mosaic: &Mosaic,
+    crs: Crs,
+    pixel_size: f64,
+    min_height: f64,
+    max_height: f64,
+) -> Result<(), OutputError> {
+    let (min_x, min_y, max_x, max_y) = mosaic.extent();
+    let width = ((max_x - min_x) / pixel_size).round() as usize;
+    let height = ((max_y - min_y) / pixel_size).round() as usize;
+
+    let mut levels = 1;
+    while (width.max(height) >> (levels - 1)) > ZARR_CHUNK_SIZE {
+        levels += 1;
+    }
+
+    println!(
+        "Writing Zarr store of {}x{} px with {} levels",
+        width, height, levels
+    );
+
+    sink.put(
+        &format!("{}/.zgroup", STORE_NAME),
+        json!({ "zarr_format": 2 }).to_string().as_bytes(),
+    )?;
+    sink.put(
+        &format!("{}/.zattrs", STORE_NAME),
+        serde_json::to_string_pretty(&json!({
+            "crs": crs.to_string(),
+            "bounds_min": [min_x, min_y],
+            "bounds_max": [max_x, max_y],
+            "generator": format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        }))?
+        .as_bytes(),
+    )?;
+
+    for array in [ZarrArray::Height, ZarrArray::Slope] {
+        let group = format!("{}/{}", STORE_NAME, array.name());
+
+        // Zarr axes are (y, x) with row 0 on the northern edge. NGFF only allows positive scales,
+        // so y is the negated northing. A coarser pixel lies at the mean position of the pixels
+        // it averages, half a pixel of each level in from the level-0 origin.
+        let datasets = (0..levels)
+            .map(|level| {
+                let level_pixel_size = pixel_size * (1 << level) as f64;
+                let offset = (level_pixel_size - pixel_size) / 2.0;
+
+                json!({
+                    "path": level.to_string(),
+                    "coordinateTransformations": [
+                        { "type": "scale", "scale": [level_pixel_size, level_pixel_size] },
+                        { "type": "translation", "translation": [offset - max_y, min_x + offset] },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        sink.put(
+            &format!("{}/.zgroup", group),
+            json!({ "zarr_format": 2 }).to_string().as_bytes(),
+        )?;
+        sink.put(
+            &format!("{}/.zattrs", group),
+            serde_json::to_string_pretty(&json!({
+                "multiscales": [{
+                    "version": "0.4",
+                    "name": array.name(),
+                    "type": "mean",
+                    "axes": [
+                        { "name": "y", "type": "space", "unit": "metre" },
+                        { "name": "x", "type": "space", "unit": "metre" },
+                    ],
+                    "datasets": datasets,
+                    "metadata": {
+                        "description": "2x2 NaN-aware mean of the previous level, y is the negated northing",
+                    },
+                }],
+                "units": array.unit(),
+            }))?
+            .as_bytes(),
+        )?;
+
+        let mut shape = (width, height);
+        let mut values = sample_array(
+            cpus,
+            mosaic,
+            array,
+            shape,
+            (min_x, max_y),
+            pixel_size,
+            (min_height, max_height),
+        );
+
+        for level in 0..levels {
+            if level > 0 {
+                (values, shape) = mosaic::average_2x2(&values, shape);
+            }
+
+            sink.put(
+                &format!("{}/{}/.zarray", group, level),
+                serde_json::to_string_pretty(&json!({
+                    "zarr_format": 2,
+                    "shape": [shape.1, shape.0],
+                    "chunks": [ZARR_CHUNK_SIZE, ZARR_CHUNK_SIZE],
+                    "dtype": "<f4",
+                    "compressor": null,
+                    "fill_value": "NaN",
+                    "order": "C",
+                    "filters": null,
+                }))?
+                .as_bytes(),
+            )?;
+
+            write_level(cpus, sink, &format!("{}/{}", group, level), &values, shape)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Samples the finest level of the array row by row, the value of pixel (x, y) is taken at
+// origin + (x, -y) * pixel_size
+fn sample_array(
+    cpus: NonZero<usize>,
+    mosaic: &Mosaic,
+    array: ZarrArray,
+    shape: (usize, usize),
+    origin: (f64, f64),
+    pixel_size: f64,
+    height_range: (f64, f64),
+) -> Vec<f32> {
+    let height_at = |geo_x: f64, geo_y: f64| {
+        mosaic.sample(geo_x, geo_y) as f64 * (height_range.1 - height_range.0) + height_range.0
+    };
+
+    let value_at = |geo_x: f64, geo_y: f64| -> f32 {
+        match array {
+            ZarrArray::Height => height_at(geo_x, geo_y) as f32,
+            ZarrArray::Slope => {
+                let gradient_x = (height_at(geo_x + pixel_size, geo_y)
+                    - height_at(geo_x - pixel_size, geo_y))
+                    / (2.0 * pixel_size);
+                let gradient_y = (height_at(geo_x, geo_y + pixel_size)
+                    - height_at(geo_x, geo_y - pixel_size))
+                    / (2.0 * pixel_size);
+
+                gradient_x.hypot(gradient_y).atan().to_degrees() as f32
+            }
+        }
+    };
+    let value_at = &value_at;
+
+    let rows = thread::scope(|scope| {
+        let mut results = vec![];
+        for id in 0..cpus.get() {
+            results.push(scope.spawn(move || {
+                (id..shape.1)
+                    .step_by(cpus.get())
+                    .map(|ind_y| {
+                        let values = (0..shape.0)
+                            .map(|ind_x| {
+                                value_at(
+                                    origin.0 + ind_x as f64 * pixel_size,
+                                    origin.1 - ind_y as f64 * pixel_size,
+                                )
+                            })
+                            .collect::<Vec<f32>>();
+
+                        (ind_y, values)
+                    })
+                    .collect::<Vec<(usize, Vec<f32>)>>()
+            }));
+        }
+
+        results
+            .into_iter()
+            .flat_map(|result| result.join().unwrap())
+            .collect::<Vec<(usize, Vec<f32>)>>()
+    });
+
+    let mut values = vec![f32::NAN; shape.0 * shape.1];
+    for (ind_y, row) in rows {
+        values[ind_y * shape.0..(ind_y + 1) * shape.0].copy_from_slice(&row);
+    }
+
+    values
+}
+
+// Splits the level into chunks, chunks without any valid value are left out
+fn write_level(
+    cpus: NonZero<usize>,
+    sink: &dyn OutputSink,
+    path: &str,
+    values: &[f32],
+    shape: (usize, usize),
+) -> Result<(), OutputError> {
+    let columns = shape.0.div_ceil(ZARR_CHUNK_SIZE);
+    let rows = shape.1.div_ceil(ZARR_CHUNK_SIZE);
+
+    thread::scope(|scope| -> Result<(), OutputError> {
+        let mut results = vec![];
+        for id in 0..cpus.get() {
+            let result = scope.spawn(move || -> Result<(), OutputError> {
+                let mut chunk = vec![f32::NAN; ZARR_CHUNK_SIZE * ZARR_CHUNK_SIZE];
+
+                for chunk_index in (id..columns * rows).step_by(cpus.get()) {
+                    let (column, row) = (chunk_index % columns, chunk_index / columns);
+
+                    for (linear_index, value) in chunk.iter_mut().enumerate() {
+                        let (ind_x, ind_y) = (
+                            column * ZARR_CHUNK_SIZE + linear_index % ZARR_CHUNK_SIZE,
+                            row * ZARR_CHUNK_SIZE + linear_index / ZARR_CHUNK_SIZE,
+                        );
+
+                        // Edge chunks are stored whole, the part outside the array is padding
+                        *value = if ind_x < shape.0 && ind_y < shape.1 {
+                            values[ind_y * shape.0 + ind_x]
+                        } else {
+                            f32::NAN
+                        };
+                    }
+
+                    // Missing chunks read back as the NaN fill value
+                    if chunk.iter().all(|value| value.is_nan()) {
+                        continue;
+                    }
+
+                    let bytes = chunk
+                        .iter()
+                        .flat_map(|value| value.to_le_bytes())
+                        .collect::<Vec<u8>>();
+
+                    sink.put(&format!("{}/{}.{}", path, row, column), &bytes)?;
+                }
+
+                Ok(())
+            });
+
+            results.push(result);
+        }
+
+        for result in results {
+            result.join().unwrap()?;
+        }
+
+        Ok(())
+    })
+}
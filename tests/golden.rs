@@ -0,0 +1,277 @@
+#![cfg(feature = "golden-tests")]
+
+// Runs the generator against a local mock of the ARSO tile server and compares the produced
+// rasters with the golden outputs in tests/golden, one folder per scenario. A missing or extra
+// golden file fails the test; set LTG_BLESS=1 to write all of them after an intended output
+// change and commit the result.
+//
+//     cargo test --features golden-tests --test golden
+//     LTG_BLESS=1 cargo test --features golden-tests --test golden
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, Cursor, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+};
+
+use las_terrain_generator::writer;
+
+const BLOCK: u8 = 1;
+const FIXTURE_TILES: [(i16, i16); 2] = [(400, 100), (401, 100)];
+const POINTS_PER_SIDE: usize = 60;
+const EXR_TOLERANCE: f32 = 1e-3;
+const PNG_TOLERANCE: u16 = 64;
+// Prefixes of the heightmap tiles and of the chunked outputs
+const COMPARED_PREFIXES: [&str; 2] = ["img_", "chunk_"];
+
+type TestResult = Result<(), Box<dyn Error>>;
+
+#[test]
+fn golden_exr_and_png_outputs_match() -> TestResult {
+    run_scenario("default", &["--format", "exr", "png"])
+}
+
+#[test]
+fn golden_integer_height_outputs_match() -> TestResult {
+    run_scenario(
+        "integer-heights",
+        &["--format", "exr", "png", "--integer-heights"],
+    )
+}
+
+#[test]
+fn golden_chunked_outputs_match() -> TestResult {
+    run_scenario("chunk-size", &["--format", "exr", "--chunk-size", "32"])
+}
+
+// Generates the fixture area with the scenario arguments and compares every heightmap with the
+// golden file of the same name in tests/golden/<scenario>
+fn run_scenario(scenario: &str, arguments: &[&str]) -> TestResult {
+    let server = serve_fixture_tiles()?;
+    let output = temp_folder(scenario)?;
+
+    let status = Command::new(env!("CARGO_BIN_EXE_las-terrain-generator"))
+        .args(["-p", "401,101", "-r", "1", "--possible-blocks", "1"])
+        .args(["--resolution", "64", "-b", "2", "-s", "3"])
+        .args(arguments)
+        .args(["--source", &format!("arso:{}", server)])
+        .args(["-d", output.to_str().unwrap()])
+        .status()?;
+    assert!(status.success(), "generator exited with {}", status);
+
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(scenario);
+    let bless = std::env::var("LTG_BLESS").is_ok_and(|value| value == "1");
+    let produced = list_compared_files(&output)?;
+
+    assert!(!produced.is_empty(), "{} produced no outputs", scenario);
+
+    if bless {
+        if golden.exists() {
+            fs::remove_dir_all(&golden)?;
+        }
+        fs::create_dir_all(&golden)?;
+    } else {
+        assert_eq!(
+            list_compared_files(&golden).unwrap_or_default(),
+            produced,
+            "{} outputs differ from the golden files, run with LTG_BLESS=1 to write them",
+            scenario
+        );
+    }
+
+    for file_name in produced.iter() {
+        let (path, golden_path) = (output.join(file_name), golden.join(file_name));
+
+        if bless {
+            fs::copy(&path, &golden_path)?;
+            println!("Blessed {}", golden_path.display());
+            continue;
+        }
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            // Float and integer EXRs are both read back as normalized heights
+            Some("exr") => compare(
+                file_name,
+                writer::read_texture(&path)?,
+                writer::read_texture(&golden_path)?,
+                EXR_TOLERANCE,
+            )?,
+            Some("png") => compare(
+                file_name,
+                read_png(&path)?,
+                read_png(&golden_path)?,
+                PNG_TOLERANCE,
+            )?,
+            _ => {}
+        }
+    }
+
+    fs::remove_dir_all(&output)?;
+
+    Ok(())
+}
+
+// Sorted names of the EXR and PNG heightmaps in the folder
+fn list_compared_files(folder: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut file_names = vec![];
+
+    for entry in fs::read_dir(folder)? {
+        let file_name = entry?.file_name().to_string_lossy().to_string();
+        let is_raster = file_name.ends_with(".exr") || file_name.ends_with(".png");
+
+        if is_raster
+            && COMPARED_PREFIXES
+                .iter()
+                .any(|prefix| file_name.starts_with(prefix))
+        {
+            file_names.push(file_name);
+        }
+    }
+
+    file_names.sort();
+
+    Ok(file_names)
+}
+
+fn compare<T: Copy + PartialOrd + std::ops::Sub<Output = T> + std::fmt::Debug>(
+    file_name: &str,
+    (dim_x, dim_y, actual): (usize, usize, Vec<T>),
+    (golden_x, golden_y, expected): (usize, usize, Vec<T>),
+    tolerance: T,
+) -> TestResult {
+    assert_eq!(
+        (dim_x, dim_y),
+        (golden_x, golden_y),
+        "{} changed size",
+        file_name
+    );
+
+    for (index, (actual, expected)) in actual.iter().zip(expected.iter()).enumerate() {
+        let delta = if actual > expected {
+            *actual - *expected
+        } else {
+            *expected - *actual
+        };
+
+        assert!(
+            delta <= tolerance,
+            "{} differs at pixel {}: {:?} vs golden {:?}",
+            file_name,
+            index,
+            actual,
+            expected
+        );
+    }
+
+    Ok(())
+}
+
+fn read_png(path: &Path) -> Result<(usize, usize, Vec<u16>), Box<dyn Error>> {
+    let mut reader = png::Decoder::new(fs::File::open(path)?).read_info()?;
+    let mut bytes = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut bytes)?;
+
+    let pixels = bytes[..info.buffer_size()]
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+// Serves the fixture tiles in the ARSO layout and returns the base URL of the server
+fn serve_fixture_tiles() -> Result<String, Box<dyn Error>> {
+    let mut tiles = HashMap::new();
+
+    for (x, y) in FIXTURE_TILES {
+        tiles.insert(
+            format!("/b_{}/D96TM/TMR_{}_{}.laz", BLOCK, x, y),
+            create_fixture_tile(x, y)?,
+        );
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(&stream);
+
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+
+            // Drain the request headers
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+                header.clear();
+            }
+
+            let mut parts = request_line.split_whitespace();
+            let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+            let _ = match tiles.get(path) {
+                Some(body) => {
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"fixture\"\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+
+                    if method == "GET" {
+                        stream.write_all(body)
+                    } else {
+                        Ok(())
+                    }
+                }
+                None => stream.write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                ),
+            };
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+// A deterministic surface sampled on a regular grid, compressed like the survey tiles
+fn create_fixture_tile(tile_x: i16, tile_y: i16) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut builder = las::Builder::from((1, 4));
+    builder.point_format.is_compressed = true;
+
+    let mut writer = las::Writer::new(Cursor::new(Vec::new()), builder.into_header()?)?;
+    let spacing = 1000.0 / POINTS_PER_SIDE as f64;
+
+    for row in 0..POINTS_PER_SIDE {
+        for column in 0..POINTS_PER_SIDE {
+            let x = tile_x as f64 * 1000.0 + (column as f64 + 0.5) * spacing;
+            let y = tile_y as f64 * 1000.0 + (row as f64 + 0.5) * spacing;
+            let z = 300.0 + 40.0 * (x / 120.0).sin() + 25.0 * (y / 70.0).cos();
+
+            writer.write_point(las::Point {
+                x,
+                y,
+                z,
+                ..Default::default()
+            })?;
+        }
+    }
+
+    Ok(writer.into_inner()?.into_inner())
+}
+
+fn temp_folder(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let folder = std::env::temp_dir().join(format!("ltg-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&folder)?;
+
+    Ok(folder)
+}
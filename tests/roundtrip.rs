@@ -0,0 +1,101 @@
+// Encodes a heightmap with every output writer, reads it back with read_heightmap and compares
+// the normalized heights with the ones written.
+//
+//     cargo test --test roundtrip
+
+use std::{error::Error, fs};
+
+use las_terrain_generator::{
+    projection::Crs,
+    writer::{self, BigTiffMode, IntegerHeights, OutputFormat, TileRaster},
+};
+
+const DIM: usize = 16;
+const MIN_HEIGHT: f64 = 300.0;
+const MAX_HEIGHT: f64 = 420.0;
+const FLOAT_TOLERANCE: f32 = 1e-5;
+// Half a step of the 16 bit formats plus rounding of the scale
+const INTEGER_TOLERANCE: f32 = 1e-4;
+
+type TestResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+#[test]
+fn float_formats_round_trip() -> TestResult {
+    round_trip(OutputFormat::Exr, None, FLOAT_TOLERANCE)?;
+    round_trip(OutputFormat::Tiff, None, FLOAT_TOLERANCE)
+}
+
+#[test]
+fn sixteen_bit_formats_round_trip() -> TestResult {
+    round_trip(OutputFormat::Png, None, INTEGER_TOLERANCE)?;
+    round_trip(OutputFormat::Raw, None, INTEGER_TOLERANCE)
+}
+
+#[test]
+fn integer_heights_round_trip() -> TestResult {
+    for integer_heights in [
+        IntegerHeights {
+            scale: None,
+            offset: None,
+        },
+        IntegerHeights {
+            scale: Some(0.01),
+            offset: Some(250.0),
+        },
+    ] {
+        round_trip(OutputFormat::Exr, Some(integer_heights), INTEGER_TOLERANCE)?;
+        round_trip(OutputFormat::Tiff, Some(integer_heights), INTEGER_TOLERANCE)?;
+    }
+
+    Ok(())
+}
+
+fn round_trip(
+    format: OutputFormat,
+    integer_heights: Option<IntegerHeights>,
+    tolerance: f32,
+) -> TestResult {
+    let heights = (0..DIM * DIM)
+        .map(|index| {
+            let (column, row) = ((index % DIM) as f32, (index / DIM) as f32);
+            (column * 0.7 + row * 0.3) / (DIM - 1) as f32
+        })
+        .collect::<Vec<f32>>();
+    let raster = TileRaster {
+        bounds_min: (500_000.0, 100_000.0),
+        bounds_max: (501_000.0, 101_000.0),
+        crs: Crs::D96Tm,
+        dim_x: DIM,
+        dim_y: DIM,
+        heights: &heights,
+        min_height: MIN_HEIGHT,
+        max_height: MAX_HEIGHT,
+    };
+
+    let name = format!("{:?}-{}", format, integer_heights.is_some()).to_lowercase();
+    let writer = format.writer(BigTiffMode::Auto, integer_heights);
+    let folder = std::env::temp_dir().join(format!("ltg-roundtrip-{}", std::process::id()));
+    fs::create_dir_all(&folder)?;
+    let path = folder.join(format!("{}.{}", name, writer.extension()));
+    fs::write(&path, writer.encode(&raster)?)?;
+
+    let (dim_x, dim_y, read) = writer::read_heightmap(&path, (MIN_HEIGHT, MAX_HEIGHT))?;
+    fs::remove_file(&path)?;
+
+    assert_eq!((dim_x, dim_y), (DIM, DIM), "{} changed its size", name);
+
+    let max_difference = heights
+        .iter()
+        .zip(read.iter())
+        .map(|(written, read)| (written - read).abs())
+        .fold(0f32, f32::max);
+
+    assert!(
+        max_difference <= tolerance,
+        "{} differs by {} from the written heights",
+        name,
+        max_difference
+    );
+
+    Ok(())
+}